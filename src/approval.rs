@@ -0,0 +1,71 @@
+use std::io::{self, BufRead, Write};
+
+// Summary reason of a repository whose push was declined in --interactive
+pub const SKIPPED_BY_USER: &str = "skipped: declined in --interactive";
+
+// Answer to the prompt shown before pushing a repository
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Approval {
+    Push,
+    Skip,
+    // Stop the run, the same as Ctrl-C
+    Quit,
+}
+
+// Asked before the changes of a repository are pushed, --interactive
+pub trait Approver: Send + Sync {
+    fn approve(&self, repository: &str, diff: &str) -> Approval;
+}
+
+// Shows the diff on stderr and reads the answer from stdin, asking again until the
+// answer is understood. The end of stdin quits.
+pub struct TerminalApprover;
+
+impl Approver for TerminalApprover {
+    fn approve(&self, repository: &str, diff: &str) -> Approval {
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\n{}\n", diff);
+        let mut line = String::new();
+        loop {
+            let _ = write!(
+                stderr,
+                "Push {}? [y]es push / [s]kip / [q]uit run: ",
+                repository
+            );
+            let _ = stderr.flush();
+            line.clear();
+            match io::stdin().lock().read_line(&mut line) {
+                Ok(0) | Err(_) => return Approval::Quit,
+                Ok(_) => {
+                    if let Some(approval) = parse_answer(&line) {
+                        return approval;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn parse_answer(line: &str) -> Option<Approval> {
+    match line.trim().to_lowercase().as_str() {
+        "y" | "yes" => Some(Approval::Push),
+        "s" | "skip" => Some(Approval::Skip),
+        "q" | "quit" => Some(Approval::Quit),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_answer() {
+        assert_eq!(parse_answer("y\n"), Some(Approval::Push));
+        assert_eq!(parse_answer(" Yes "), Some(Approval::Push));
+        assert_eq!(parse_answer("s\n"), Some(Approval::Skip));
+        assert_eq!(parse_answer("QUIT\n"), Some(Approval::Quit));
+        assert_eq!(parse_answer("\n"), None);
+        assert_eq!(parse_answer("no\n"), None);
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    env,
+    error::Error,
+    fmt, fs, io,
+    process::Command,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::EncodingKey;
+use log::info;
+#[cfg(test)]
+use mockall::automock;
+use octocrab::{auth::AppAuth, models::AppId, AuthState, Octocrab};
+use secrecy::{ExposeSecret, Secret};
+
+use crate::{
+    github::{GitHubClient, GitHubUrls, SourceStatus, TagLookup, TagRef},
+    proxy::proxied_octocrab,
+    redact::redact,
+    Args,
+};
+
+// The GitHub CLI, asked for its token by --token-from-gh
+const GH_BIN: &str = "gh";
+
+// Installation tokens are refreshed this long before they expire, so a push
+// started right before the expiry does not fail halfway
+pub const TOKEN_REFRESH_MARGIN_MINUTES: i64 = 5;
+
+// Lifetime GitHub gives installation tokens, used when the response has no expiry
+const DEFAULT_TOKEN_LIFETIME_MINUTES: i64 = 60;
+
+// Source of the current time, replaced by a mock clock in tests
+pub trait Clock: Send + Sync {
+    fn now(&self) -> DateTime<Utc>;
+}
+
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> DateTime<Utc> {
+        Utc::now()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstallationToken {
+    pub token: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+// Creates new installation tokens
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait TokenSource {
+    async fn create_token(&self) -> Result<InstallationToken, Box<dyn Error>>;
+}
+
+// Creates installation tokens of a GitHub App, authenticated with a JWT signed by the app key
+pub struct AppTokenSource {
+    octocrab: Octocrab,
+    installation_id: u64,
+}
+
+impl AppTokenSource {
+    pub fn new(
+        app_id: u64,
+        private_key_pem: &[u8],
+        installation_id: u64,
+        urls: &GitHubUrls,
+    ) -> Result<Self, Box<dyn Error>> {
+        let key = EncodingKey::from_rsa_pem(private_key_pem)
+            .map_err(|e| format!("Invalid GitHub App private key: {}", e))?;
+        let octocrab = match &urls.proxy {
+            Some(proxy) => proxied_octocrab(
+                proxy,
+                &urls.api_base,
+                None,
+                AuthState::App(AppAuth {
+                    app_id: AppId(app_id),
+                    key,
+                }),
+            )?,
+            None => Octocrab::builder()
+                .base_uri(urls.api_base.as_str())?
+                .app(AppId(app_id), key)
+                .build()?,
+        };
+        Ok(AppTokenSource {
+            octocrab,
+            installation_id,
+        })
+    }
+}
+
+#[derive(serde::Deserialize)]
+struct AccessTokenResponse {
+    token: String,
+    expires_at: Option<DateTime<Utc>>,
+}
+
+#[async_trait]
+impl TokenSource for AppTokenSource {
+    // Make a request to the GitHub API to create an installation access token
+    async fn create_token(&self) -> Result<InstallationToken, Box<dyn Error>> {
+        let response: AccessTokenResponse = self
+            .octocrab
+            .post(
+                format!("/app/installations/{}/access_tokens", self.installation_id),
+                None::<&()>,
+            )
+            .await?;
+        Ok(InstallationToken {
+            token: response.token,
+            expires_at: response
+                .expires_at
+                .unwrap_or_else(|| Utc::now() + Duration::minutes(DEFAULT_TOKEN_LIFETIME_MINUTES)),
+        })
+    }
+}
+
+// Keeps an installation token valid during long runs: a new token is created
+// when there is none yet or when the current one expires within the margin
+pub struct TokenManager {
+    source: Box<dyn TokenSource + Send + Sync>,
+    clock: Box<dyn Clock>,
+    margin: Duration,
+    current: tokio::sync::Mutex<Option<InstallationToken>>,
+}
+
+impl TokenManager {
+    pub fn new(source: Box<dyn TokenSource + Send + Sync>, clock: Box<dyn Clock>) -> Self {
+        TokenManager {
+            source,
+            clock,
+            margin: Duration::minutes(TOKEN_REFRESH_MARGIN_MINUTES),
+            current: tokio::sync::Mutex::new(None),
+        }
+    }
+
+    pub async fn token(&self) -> Result<String, Box<dyn Error>> {
+        let mut current = self.current.lock().await;
+        let now = self.clock.now();
+        match current.as_ref() {
+            Some(token) if now + self.margin < token.expires_at => Ok(token.token.clone()),
+            _ => {
+                let token = self
+                    .source
+                    .create_token()
+                    .await
+                    .map_err(|e| format!("Failed to create installation token: {}", e))?;
+                info!(
+                    "Created installation token, expires at {}",
+                    token.expires_at
+                );
+                let value = token.token.clone();
+                *current = Some(token);
+                Ok(value)
+            }
+        }
+    }
+}
+
+// How the run authenticates, both against the API and for git operations
+pub enum Credentials {
+    // Personal access token from resolve_token, used as is
+    Token(Secret<String>),
+    // GitHub App installation, its token is refreshed before it expires
+    App(TokenManager),
+}
+
+impl Credentials {
+    // Token to use right now, call it again before every batch of requests or pushes
+    pub async fn token(&self) -> Result<String, Box<dyn Error>> {
+        match self {
+            Credentials::Token(token) => Ok(token.expose_secret().clone()),
+            Credentials::App(manager) => manager.token().await,
+        }
+    }
+}
+
+// Where the personal access token of the run came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenOrigin {
+    File,
+    GhCli,
+    Env,
+}
+
+impl fmt::Display for TokenOrigin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenOrigin::File => write!(f, "--token-file"),
+            TokenOrigin::GhCli => write!(f, "gh auth token"),
+            TokenOrigin::Env => write!(f, "GITHUB_TOKEN"),
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum TokenError {
+    #[error("GITHUB_TOKEN environment variable is not set, pass --token-file <path> or --token-from-gh instead")]
+    Missing,
+    #[error("Failed to read token file {0}: {1}")]
+    File(String, io::Error),
+    #[error("Token file {0} is empty")]
+    EmptyFile(String),
+    #[error("Failed to get a token from `gh auth token`: {0}")]
+    GhCli(String),
+}
+
+// The personal access token of the run: the content of --token-file, the token of the
+// gh CLI with --token-from-gh, or GITHUB_TOKEN (also read from .env), in that order.
+// Only the source is logged.
+pub fn resolve_token(args: &Args) -> Result<Secret<String>, TokenError> {
+    dotenv::dotenv().ok();
+    let gh = args.token_from_gh.then_some(GH_BIN);
+    let (token, origin) = find_token(
+        args.token_file.as_deref(),
+        gh,
+        env::var("GITHUB_TOKEN").ok(),
+    )?;
+    info!(phase = "startup"; "Using the GitHub token from {}", origin);
+    Ok(token)
+}
+
+fn find_token(
+    token_file: Option<&str>,
+    gh: Option<&str>,
+    env_token: Option<String>,
+) -> Result<(Secret<String>, TokenOrigin), TokenError> {
+    if let Some(path) = token_file {
+        let content =
+            fs::read_to_string(path).map_err(|e| TokenError::File(path.to_string(), e))?;
+        let token = content.trim();
+        if token.is_empty() {
+            return Err(TokenError::EmptyFile(path.to_string()));
+        }
+        return Ok((Secret::new(token.to_string()), TokenOrigin::File));
+    }
+    if let Some(gh) = gh {
+        return gh_token(gh).map(|token| (token, TokenOrigin::GhCli));
+    }
+    env_token
+        .map(|token| (Secret::new(token), TokenOrigin::Env))
+        .ok_or(TokenError::Missing)
+}
+
+// Output of `<gh> auth token`, the token of the account gh is logged in with
+fn gh_token(gh: &str) -> Result<Secret<String>, TokenError> {
+    let output = Command::new(gh)
+        .args(["auth", "token"])
+        .output()
+        .map_err(|e| TokenError::GhCli(e.to_string()))?;
+    if !output.status.success() {
+        let stderr = redact(&String::from_utf8_lossy(&output.stderr));
+        return Err(TokenError::GhCli(format!(
+            "{}, {}",
+            output.status,
+            stderr.trim()
+        )));
+    }
+    let token = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if token.is_empty() {
+        return Err(TokenError::GhCli(String::from("gh printed no token")));
+    }
+    Ok(Secret::new(token))
+}
+
+// Tag lookups that always use the current token of <credentials>.
+// The client is rebuilt whenever the token changed.
+pub struct CredentialedTagLookup {
+    credentials: Arc<Credentials>,
+    urls: GitHubUrls,
+    client: Mutex<Option<(String, GitHubClient)>>,
+}
+
+impl CredentialedTagLookup {
+    pub fn new(credentials: Arc<Credentials>, urls: GitHubUrls) -> Self {
+        CredentialedTagLookup {
+            credentials,
+            urls,
+            client: Mutex::new(None),
+        }
+    }
+
+    async fn client(&self) -> Result<GitHubClient, Box<dyn Error>> {
+        let token = self.credentials.token().await?;
+        let mut client = self.client.lock().unwrap();
+        match client.as_ref() {
+            Some((current, client)) if *current == token => Ok(client.clone()),
+            _ => {
+                let fresh = GitHubClient::for_org(String::new(), token.clone(), &self.urls);
+                *client = Some((token, fresh.clone()));
+                Ok(fresh)
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl TagLookup for CredentialedTagLookup {
+    async fn list_tags(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<Option<Vec<TagRef>>, Box<dyn Error>> {
+        let client = self.client().await?;
+        client.list_tags(owner, repo).await
+    }
+
+    async fn is_reachable_from(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        tag: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let client = self.client().await?;
+        client.is_reachable_from(owner, repo, sha, tag).await
+    }
+
+    async fn resolve_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        reference: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let client = self.client().await?;
+        client.resolve_ref(owner, repo, reference).await
+    }
+
+    async fn commit_date(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Option<DateTime<Utc>>, Box<dyn Error>> {
+        let client = self.client().await?;
+        client.commit_date(owner, repo, sha).await
+    }
+
+    async fn repository_status(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<SourceStatus, Box<dyn Error>> {
+        let client = self.client().await?;
+        client.repository_status(owner, repo).await
+    }
+}
+
+// Clock that only moves when told to
+#[cfg(test)]
+#[derive(Clone)]
+pub struct MockClock(Arc<Mutex<DateTime<Utc>>>);
+
+#[cfg(test)]
+impl MockClock {
+    pub fn new(now: DateTime<Utc>) -> Self {
+        MockClock(Arc::new(Mutex::new(now)))
+    }
+
+    pub fn advance(&self, duration: Duration) {
+        *self.0.lock().unwrap() += duration;
+    }
+}
+
+#[cfg(test)]
+impl Clock for MockClock {
+    fn now(&self) -> DateTime<Utc> {
+        *self.0.lock().unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{header_regex, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const TEST_APP_KEY: &[u8] = include_bytes!("../resources/test_app_key.pem");
+
+    fn source(start: DateTime<Utc>, tokens: &[&str]) -> MockTokenSource {
+        let mut source = MockTokenSource::new();
+        let mut sequence = mockall::Sequence::new();
+        for (index, token) in tokens.iter().enumerate() {
+            let token = token.to_string();
+            source
+                .expect_create_token()
+                .times(1)
+                .in_sequence(&mut sequence)
+                .returning(move || {
+                    Ok(InstallationToken {
+                        token: token.clone(),
+                        expires_at: start + Duration::minutes(60 * (index as i64 + 1)),
+                    })
+                });
+        }
+        source
+    }
+
+    #[tokio::test]
+    async fn test_token_is_reused_until_refresh_margin() {
+        let start = Utc::now();
+        let clock = MockClock::new(start);
+        let manager = TokenManager::new(
+            Box::new(source(start, &["t1", "t2"])),
+            Box::new(clock.clone()),
+        );
+
+        assert_eq!(manager.token().await.unwrap(), "t1");
+        clock.advance(Duration::minutes(54));
+        assert_eq!(manager.token().await.unwrap(), "t1");
+        // Within the margin of the expiry at 60 minutes
+        clock.advance(Duration::minutes(1) + Duration::seconds(1));
+        assert_eq!(manager.token().await.unwrap(), "t2");
+        clock.advance(Duration::minutes(30));
+        assert_eq!(manager.token().await.unwrap(), "t2");
+    }
+
+    #[tokio::test]
+    async fn test_refresh_failure_is_reported() {
+        let mut source = MockTokenSource::new();
+        source
+            .expect_create_token()
+            .returning(|| Err(Box::from("401 Bad credentials")));
+        let manager = TokenManager::new(Box::new(source), Box::new(MockClock::new(Utc::now())));
+
+        let err = manager.token().await.unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("Failed to create installation token"));
+    }
+
+    #[tokio::test]
+    async fn test_personal_token_is_unaffected() {
+        let credentials = Credentials::Token(Secret::new(String::from("pat")));
+        assert_eq!(credentials.token().await.unwrap(), "pat");
+    }
+
+    #[tokio::test]
+    async fn test_app_token_source_creates_installation_token() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/app/installations/42/access_tokens"))
+            .and(header_regex("authorization", "^Bearer .+"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(serde_json::json!({
+                "token": "ghs_installation",
+                "expires_at": "2030-01-01T00:00:00Z"
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let source =
+            AppTokenSource::new(7, TEST_APP_KEY, 42, &GitHubUrls::new(&server.uri())).unwrap();
+        let token = source.create_token().await.unwrap();
+        assert_eq!(token.token, "ghs_installation");
+        assert_eq!(token.expires_at.to_rfc3339(), "2030-01-01T00:00:00+00:00");
+    }
+
+    fn fake_gh(dir: &std::path::Path, script: &str) -> String {
+        use std::os::unix::fs::PermissionsExt;
+        let gh = dir.join("gh");
+        fs::write(&gh, script).unwrap();
+        fs::set_permissions(&gh, fs::Permissions::from_mode(0o755)).unwrap();
+        gh.display().to_string()
+    }
+
+    #[test]
+    fn test_token_from_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        fs::write(&path, "ghp_file\n").unwrap();
+        let path = path.to_str().unwrap();
+
+        // The file wins over the environment
+        let (token, origin) = find_token(Some(path), None, Some(String::from("ghp_env"))).unwrap();
+        assert_eq!(token.expose_secret(), "ghp_file");
+        assert_eq!(origin, TokenOrigin::File);
+
+        fs::write(path, " \n").unwrap();
+        let err = find_token(Some(path), None, None).unwrap_err();
+        assert!(matches!(err, TokenError::EmptyFile(_)));
+        let missing = dir.path().join("missing");
+        let err = find_token(missing.to_str(), None, None).unwrap_err();
+        assert!(matches!(err, TokenError::File(_, _)));
+    }
+
+    #[test]
+    fn test_token_from_gh() {
+        let dir = tempfile::tempdir().unwrap();
+        let gh = fake_gh(
+            dir.path(),
+            "#!/bin/sh\n[ \"$1 $2\" = \"auth token\" ] && echo gho_cli && exit 0\nexit 1\n",
+        );
+        let (token, origin) = find_token(None, Some(&gh), Some(String::from("ghp_env"))).unwrap();
+        assert_eq!(token.expose_secret(), "gho_cli");
+        assert_eq!(origin, TokenOrigin::GhCli);
+
+        let gh = fake_gh(
+            dir.path(),
+            "#!/bin/sh\necho 'You are not logged into any GitHub hosts' >&2\nexit 1\n",
+        );
+        let err = find_token(None, Some(&gh), None).unwrap_err();
+        assert!(err.to_string().contains("not logged into"), "{}", err);
+        let missing = dir.path().join("missing");
+        let err = find_token(None, missing.to_str(), None).unwrap_err();
+        assert!(matches!(err, TokenError::GhCli(_)));
+    }
+
+    #[test]
+    fn test_token_from_env() {
+        let (token, origin) = find_token(None, None, Some(String::from("ghp_env"))).unwrap();
+        assert_eq!(token.expose_secret(), "ghp_env");
+        assert_eq!(origin, TokenOrigin::Env);
+        assert!(matches!(
+            find_token(None, None, None).unwrap_err(),
+            TokenError::Missing
+        ));
+    }
+}
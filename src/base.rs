@@ -0,0 +1,155 @@
+use log::{info, warn};
+
+use crate::github::{BranchRefs, CreateRefOutcome};
+
+// Make sure <base> exists on the remote, creating it from <default_branch> when missing.
+// Returns the branch the PR should target: <base>, or <default_branch> when the
+// branch could not be created because of missing permissions and <strict> is not set.
+pub async fn ensure_base_branch<C: BranchRefs + Sync + ?Sized>(
+    client: &C,
+    base: &str,
+    default_branch: &str,
+    strict: bool,
+) -> Result<String, Box<dyn std::error::Error>> {
+    if client.branch_sha(base).await?.is_some() {
+        info!("Base branch {} already exists, not creating it", base);
+        return Ok(base.to_string());
+    }
+
+    let sha = client
+        .branch_sha(default_branch)
+        .await?
+        .ok_or_else(|| format!("Default branch {} not found", default_branch))?;
+
+    match client.create_branch_ref(base, &sha).await? {
+        CreateRefOutcome::Created => {
+            info!("Created base branch {} from {}", base, default_branch);
+            Ok(base.to_string())
+        }
+        CreateRefOutcome::PermissionDenied if strict => Err(Box::from(format!(
+            "Not allowed to create base branch {}",
+            base
+        ))),
+        CreateRefOutcome::PermissionDenied => {
+            warn!(
+                "Not allowed to create base branch {}, targeting {} instead",
+                base, default_branch
+            );
+            Ok(default_branch.to_string())
+        }
+    }
+}
+
+// --base-branch: <base> when it exists on the remote, None when the default branch has
+// to be targeted instead. Fails with <strict> when it is missing.
+pub async fn existing_base_branch<C: BranchRefs + Sync + ?Sized>(
+    client: &C,
+    base: &str,
+    strict: bool,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if client.branch_sha(base).await?.is_some() {
+        return Ok(Some(base.to_string()));
+    }
+    if strict {
+        return Err(Box::from(format!("Base branch {} does not exist", base)));
+    }
+    warn!(
+        "Base branch {} does not exist, targeting the default branch instead",
+        base
+    );
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::MockBranchRefs;
+    use mockall::predicate::*;
+
+    const SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+
+    fn missing_base(outcome: CreateRefOutcome) -> MockBranchRefs {
+        let mut client = MockBranchRefs::new();
+        client
+            .expect_branch_sha()
+            .with(eq("automation"))
+            .returning(|_| Ok(None));
+        client
+            .expect_branch_sha()
+            .with(eq("main"))
+            .returning(|_| Ok(Some(SHA.to_string())));
+        client
+            .expect_create_branch_ref()
+            .with(eq("automation"), eq(SHA))
+            .times(1)
+            .returning(move |_, _| Ok(outcome));
+        client
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_branch_creates_missing_branch() {
+        let client = missing_base(CreateRefOutcome::Created);
+        let base = ensure_base_branch(&client, "automation", "main", false)
+            .await
+            .unwrap();
+        assert_eq!(base, "automation");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_branch_existing_branch() {
+        let mut client = MockBranchRefs::new();
+        client
+            .expect_branch_sha()
+            .with(eq("automation"))
+            .returning(|_| Ok(Some(SHA.to_string())));
+        client.expect_create_branch_ref().never();
+
+        let base = ensure_base_branch(&client, "automation", "main", false)
+            .await
+            .unwrap();
+        assert_eq!(base, "automation");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_branch_permission_denied_falls_back() {
+        let client = missing_base(CreateRefOutcome::PermissionDenied);
+        let base = ensure_base_branch(&client, "automation", "main", false)
+            .await
+            .unwrap();
+        assert_eq!(base, "main");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_base_branch_permission_denied_strict() {
+        let client = missing_base(CreateRefOutcome::PermissionDenied);
+        let result = ensure_base_branch(&client, "automation", "main", true).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_existing_base_branch() {
+        let mut client = MockBranchRefs::new();
+        client
+            .expect_branch_sha()
+            .with(eq("develop"))
+            .returning(|_| Ok(Some(SHA.to_string())));
+        client
+            .expect_branch_sha()
+            .with(eq("missing"))
+            .returning(|_| Ok(None));
+        client.expect_create_branch_ref().never();
+
+        let base = existing_base_branch(&client, "develop", true)
+            .await
+            .unwrap();
+        assert_eq!(base.as_deref(), Some("develop"));
+        let base = existing_base_branch(&client, "missing", false)
+            .await
+            .unwrap();
+        assert_eq!(base, None);
+        let err = existing_base_branch(&client, "missing", true)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Base branch missing does not exist");
+    }
+}
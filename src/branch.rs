@@ -0,0 +1,230 @@
+use std::collections::HashSet;
+
+use git2::Reference;
+
+pub const DEFAULT_BRANCH_TEMPLATE: &str = "automated-ratchet-dispatcher-pin";
+
+// Placeholders a branch name template may use, see BranchContext
+const PLACEHOLDERS: [&str; 4] = ["owner", "repo", "base", "date"];
+
+// --branch, rejects a template with a placeholder BranchNamer does not fill in, before
+// any repository is cloned
+pub fn parse_branch_template(template: &str) -> Result<String, String> {
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        rest = &rest[start + 1..];
+        let Some(end) = rest.find('}') else {
+            return Err(format!("unterminated placeholder {{{}", rest));
+        };
+        let name = &rest[..end];
+        if !PLACEHOLDERS.contains(&name) {
+            return Err(format!(
+                "unknown placeholder {{{}}}, supported are {}",
+                name,
+                PLACEHOLDERS.map(|name| format!("{{{}}}", name)).join(", ")
+            ));
+        }
+        rest = &rest[end + 1..];
+    }
+    Ok(template.to_string())
+}
+
+// Everything a branch name template can refer to
+#[derive(Debug, Clone, Copy)]
+pub struct BranchContext<'a> {
+    pub owner: &'a str,
+    pub repo: &'a str,
+    pub base: &'a str,
+    pub date: &'a str,
+}
+
+// Builds branch names from a template and guarantees they are valid git
+// branch names and unique per repository within a run.
+// Supported placeholders: {owner}, {repo}, {base} and {date}.
+pub struct BranchNamer {
+    template: String,
+    prefix: Option<String>,
+    used: HashSet<(String, String)>,
+}
+
+impl BranchNamer {
+    pub fn new(template: &str, prefix: Option<&str>) -> Self {
+        BranchNamer {
+            template: template.to_string(),
+            prefix: prefix.map(String::from),
+            used: HashSet::new(),
+        }
+    }
+
+    pub fn name(&mut self, context: &BranchContext) -> Result<String, Box<dyn std::error::Error>> {
+        let rendered = self
+            .template
+            .replace("{owner}", context.owner)
+            .replace("{repo}", context.repo)
+            .replace("{base}", context.base)
+            .replace("{date}", context.date);
+        let rendered = match &self.prefix {
+            Some(prefix) => format!("{}/{}", prefix, rendered),
+            None => rendered,
+        };
+
+        let mut name = sanitize(&rendered);
+        if !Reference::is_valid_name(&format!("refs/heads/{}", name)) {
+            return Err(Box::from(format!(
+                "Invalid branch name {:?} rendered from template {:?}",
+                name, self.template
+            )));
+        }
+
+        let repository = format!("{}/{}", context.owner, context.repo);
+        if self.used.contains(&(repository.clone(), name.clone())) {
+            let hashed = format!("{}-{}", name, short_hash(&format!("{:?}", context)));
+            name = hashed.clone();
+            let mut attempt = 2;
+            while self.used.contains(&(repository.clone(), name.clone())) {
+                name = format!("{}-{}", hashed, attempt);
+                attempt += 1;
+            }
+        }
+        self.used.insert((repository, name.clone()));
+        Ok(name)
+    }
+}
+
+// Replace everything git does not allow in a branch name
+fn sanitize(name: &str) -> String {
+    let replaced: String = name
+        .chars()
+        .map(|c| match c {
+            ' ' | '~' | '^' | ':' | '?' | '*' | '[' | '\\' | '@' | '{' | '}' => '-',
+            c if c.is_control() => '-',
+            c => c,
+        })
+        .collect();
+
+    let components: Vec<String> = replaced
+        .split('/')
+        .map(|component| {
+            let component = component.replace("..", "-");
+            let component = component.trim_start_matches('.');
+            let component = component.strip_suffix(".lock").unwrap_or(component);
+            component.trim_end_matches('.').to_string()
+        })
+        .filter(|component| !component.is_empty())
+        .collect();
+
+    let name = components.join("/");
+    if name.is_empty() {
+        DEFAULT_BRANCH_TEMPLATE.to_string()
+    } else {
+        name
+    }
+}
+
+// FNV-1a, stable across runs and Rust versions unlike DefaultHasher
+fn short_hash(value: &str) -> String {
+    let mut hash: u32 = 0x811c9dc5;
+    for byte in value.bytes() {
+        hash ^= u32::from(byte);
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    format!("{:08x}", hash)[..7].to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context<'a>(repo: &'a str, base: &'a str) -> BranchContext<'a> {
+        BranchContext {
+            owner: "org",
+            repo,
+            base,
+            date: "20240629",
+        }
+    }
+
+    #[test]
+    fn test_default_branch_name_is_unchanged() {
+        let mut namer = BranchNamer::new(DEFAULT_BRANCH_TEMPLATE, None);
+        assert_eq!(
+            namer.name(&context("repo", "main")).unwrap(),
+            "automated-ratchet-dispatcher-pin"
+        );
+        // Other repositories get the same name
+        assert_eq!(
+            namer.name(&context("other", "main")).unwrap(),
+            "automated-ratchet-dispatcher-pin"
+        );
+    }
+
+    #[test]
+    fn test_template_placeholders() {
+        let mut namer = BranchNamer::new("pin/{base}/{repo}-{date}", Some("bot"));
+        assert_eq!(
+            namer.name(&context("app", "release/1.x")).unwrap(),
+            "bot/pin/release/1.x/app-20240629"
+        );
+    }
+
+    #[test]
+    fn test_parse_branch_template() {
+        for template in [
+            DEFAULT_BRANCH_TEMPLATE,
+            "ratchet/{owner}-{repo}/{date}",
+            "pin-{base}",
+        ] {
+            assert_eq!(parse_branch_template(template).unwrap(), template);
+        }
+        assert_eq!(
+            parse_branch_template("pin-{repo}-{day}").unwrap_err(),
+            "unknown placeholder {day}, supported are {owner}, {repo}, {base}, {date}"
+        );
+        assert!(parse_branch_template("pin-{}").is_err());
+        assert!(parse_branch_template("pin-{workflow}").is_err());
+        assert_eq!(
+            parse_branch_template("pin-{repo").unwrap_err(),
+            "unterminated placeholder {repo"
+        );
+    }
+
+    #[test]
+    fn test_collisions_within_a_repository_get_a_hash() {
+        // Both bases of the repository render to the same name
+        let mut namer = BranchNamer::new("pin-{repo}", None);
+        let first = namer.name(&context("repo", "main")).unwrap();
+        let second = namer.name(&context("repo", "develop")).unwrap();
+        assert_eq!(first, "pin-repo");
+        assert_ne!(first, second);
+        assert!(second.starts_with("pin-repo-"));
+        assert_eq!(second.len(), "pin-repo-".len() + 7);
+    }
+
+    #[test]
+    fn test_randomized_names_are_valid_and_unique() {
+        const CHARS: &[u8] = b"abcXYZ019-_./ ~^:?*[\\@{}\t";
+        // Small linear congruential generator, deterministic between runs
+        let mut seed: u64 = 42;
+        let mut next = move || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            (seed >> 33) as usize
+        };
+
+        let mut namer = BranchNamer::new("{base}-{base}", None);
+        let mut names = HashSet::new();
+        for _ in 0..500 {
+            let base: String = (0..next() % 12)
+                .map(|_| CHARS[next() % CHARS.len()] as char)
+                .collect();
+            let name = namer.name(&context("repo", &base)).unwrap();
+            assert!(
+                Reference::is_valid_name(&format!("refs/heads/{}", name)),
+                "{:?} is not valid",
+                name
+            );
+            assert!(names.insert(name.clone()), "{:?} is not unique", name);
+        }
+    }
+}
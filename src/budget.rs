@@ -0,0 +1,118 @@
+// Limits how many pull requests a single run creates.
+// Repositories consume the budget in input order, once it is exhausted the
+// remaining repositories with changes are deferred instead of pushed.
+pub const DEFERRED_BUDGET_EXHAUSTED: &str = "deferred: PR budget exhausted";
+
+pub struct PrBudget {
+    max_prs: Option<usize>,
+    count_updates: bool,
+    used: usize,
+    deferred: Vec<(String, String)>,
+}
+
+impl PrBudget {
+    pub fn new(max_prs: Option<usize>, count_updates: bool) -> Self {
+        PrBudget {
+            max_prs,
+            count_updates,
+            used: 0,
+            deferred: Vec::new(),
+        }
+    }
+
+    // Take one PR from the budget, returns false when the push has to be deferred.
+    // Updates to existing PRs are free unless count_updates is set.
+    pub fn try_consume(&mut self, is_update: bool) -> bool {
+        if is_update && !self.count_updates {
+            return true;
+        }
+        match self.max_prs {
+            Some(max) if self.used >= max => false,
+            _ => {
+                self.used += 1;
+                true
+            }
+        }
+    }
+
+    // Give back what try_consume(<is_update>) took, for a repository that ended
+    // without a PR after all
+    pub fn refund(&mut self, is_update: bool) {
+        if is_update && !self.count_updates {
+            return;
+        }
+        self.used = self.used.saturating_sub(1);
+    }
+
+    // Remember <repository> as deferred, <reason> is listed at the end of the run
+    pub fn defer(&mut self, repository: &str, reason: &str) {
+        self.deferred
+            .push((repository.to_string(), reason.to_string()));
+    }
+
+    pub fn deferred(&self) -> &[(String, String)] {
+        &self.deferred
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_budget() {
+        let mut budget = PrBudget::new(None, true);
+        assert!((0..1000).all(|_| budget.try_consume(false)));
+    }
+
+    #[test]
+    fn test_budget_is_consumed_in_order() {
+        let mut budget = PrBudget::new(Some(2), false);
+        let repositories = ["org/a", "org/b", "org/c", "org/d"];
+        for repository in repositories {
+            if !budget.try_consume(false) {
+                budget.defer(repository, DEFERRED_BUDGET_EXHAUSTED);
+            }
+        }
+        let deferred: Vec<&str> = budget
+            .deferred()
+            .iter()
+            .map(|(repository, _)| repository.as_str())
+            .collect();
+        assert_eq!(deferred, ["org/c", "org/d"]);
+    }
+
+    #[test]
+    fn test_updates_do_not_count_by_default() {
+        let mut budget = PrBudget::new(Some(1), false);
+        assert!(budget.try_consume(true));
+        assert!(budget.try_consume(false));
+        assert!(budget.try_consume(true));
+        assert!(!budget.try_consume(false));
+    }
+
+    #[test]
+    fn test_count_updates() {
+        let mut budget = PrBudget::new(Some(1), true);
+        assert!(budget.try_consume(true));
+        assert!(!budget.try_consume(true));
+        assert!(!budget.try_consume(false));
+    }
+
+    #[test]
+    fn test_refund() {
+        let mut budget = PrBudget::new(Some(1), false);
+        assert!(budget.try_consume(false));
+        budget.refund(true);
+        assert!(!budget.try_consume(false));
+        budget.refund(false);
+        assert!(budget.try_consume(false));
+    }
+
+    #[test]
+    fn test_zero_budget_defers_everything() {
+        let mut budget = PrBudget::new(Some(0), false);
+        assert!(!budget.try_consume(false));
+        assert!(budget.try_consume(true));
+    }
+}
@@ -0,0 +1,112 @@
+use std::path::Path;
+
+use crate::scan::{find_unpinned_actions, ActionUse};
+
+// Exit codes of --check, so it can be used as a required status check
+pub const CHECK_PINNED: i32 = 0;
+pub const CHECK_UNPINNED: i32 = 1;
+pub const CHECK_ERROR: i32 = 2;
+
+// Scan the checkout at <local_path> without changing anything. Returns the report to
+// print and the exit code: CHECK_UNPINNED when an action is not pinned to a commit SHA,
+// CHECK_ERROR when the checkout can not be scanned. Actions excluded with a
+// `ratchet:exclude` comment are not violations.
+pub fn check_local_path(local_path: &str, max_size: u64) -> (String, i32) {
+    if !Path::new(local_path).is_dir() {
+        return (format!("{} is not a directory\n", local_path), CHECK_ERROR);
+    }
+    match find_unpinned_actions(local_path, max_size) {
+        Ok(unpinned) => {
+            let violations: Vec<ActionUse> = unpinned
+                .into_iter()
+                .filter(|action| !action.excluded)
+                .collect();
+            let code = if violations.is_empty() {
+                CHECK_PINNED
+            } else {
+                CHECK_UNPINNED
+            };
+            (render_check_report(&violations), code)
+        }
+        Err(e) => (
+            format!("Failed to scan {}: {}\n", local_path, e),
+            CHECK_ERROR,
+        ),
+    }
+}
+
+// Unpinned actions grouped per file, in the order they were found
+fn render_check_report(unpinned: &[ActionUse]) -> String {
+    if unpinned.is_empty() {
+        return String::from("All actions are pinned\n");
+    }
+    let mut report = String::new();
+    let mut files = 0;
+    for (index, action) in unpinned.iter().enumerate() {
+        if index == 0 || unpinned[index - 1].file != action.file {
+            report.push_str(&format!("{}\n", action.file));
+            files += 1;
+        }
+        report.push_str(&format!("  line {}: {}\n", action.line, action.uses));
+    }
+    report.push_str(&format!(
+        "\n{} unpinned action(s) in {} file(s)\n",
+        unpinned.len(),
+        files
+    ));
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratchet::DEFAULT_MAX_WORKFLOW_SIZE;
+    use std::fs;
+    use tempfile::tempdir;
+
+    const UNPINNED_WORKFLOW: &str = include_str!("../resources/ci_unpinned.yml");
+    const PINNED_WORKFLOW: &str = include_str!("../resources/ci_pinned.yml");
+
+    fn checkout(workflows: &[(&str, &str)]) -> tempfile::TempDir {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join(".github/workflows");
+        fs::create_dir_all(&path).unwrap();
+        for (name, content) in workflows {
+            fs::write(path.join(name), content).unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_check_reports_unpinned_actions() {
+        let dir = checkout(&[
+            ("ci.yml", UNPINNED_WORKFLOW),
+            ("release.yml", PINNED_WORKFLOW),
+        ]);
+        let (report, code) =
+            check_local_path(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE);
+        assert_eq!(code, CHECK_UNPINNED);
+        assert_eq!(
+            report,
+            ".github/workflows/ci.yml\n  line 17: actions/checkout@v3\n  line 19: actions/setup-node@v3\n\n2 unpinned action(s) in 1 file(s)\n"
+        );
+    }
+
+    #[test]
+    fn test_check_pinned_and_excluded_actions() {
+        let excluded = UNPINNED_WORKFLOW
+            .replace("checkout@v3", "checkout@v3 # ratchet:exclude")
+            .replace("setup-node@v3", "setup-node@v3 # ratchet:exclude");
+        let dir = checkout(&[("ci.yml", PINNED_WORKFLOW), ("lint.yml", &excluded)]);
+        assert_eq!(
+            check_local_path(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE),
+            (String::from("All actions are pinned\n"), CHECK_PINNED)
+        );
+    }
+
+    #[test]
+    fn test_check_missing_directory() {
+        let (_, code) = check_local_path("/nonexistent/checkout", DEFAULT_MAX_WORKFLOW_SIZE);
+        assert_eq!(code, CHECK_ERROR);
+    }
+}
@@ -0,0 +1,619 @@
+use std::{error::Error, fs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{branch::DEFAULT_BRANCH_TEMPLATE, ratchet::DEFAULT_MAX_WORKFLOW_SIZE};
+
+// verify_pins = true like the other flags, or the mode: verify_pins = "warn"
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VerifyPins {
+    Enabled(bool),
+    Mode(String),
+}
+
+// Run settings loaded with --config, every field mirrors a command line flag.
+// The file is turned into command line arguments placed before the real ones,
+// so flags given on the command line take precedence and clap validates both. Lists
+// and booleans would add up, those of the file are left out when the command line
+// has the flag.
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub repos: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub org: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub github_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub https_proxy: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_retries: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_from_gh: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub app_private_key: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub installation_id: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kill_switch_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary_json: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub changes_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_webhook: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub notify_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_archived: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_forks: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_templates: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub visibility: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub branch_prefix: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verbose: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub log_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub progress: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub clone_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_body_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_change_table: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub only_report_unpinned: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub check: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub local_path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub push: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reproducible_commits: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub commit_date: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_pins: Option<VerifyPins>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verify_sources: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_workflow_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_comment_only_changes: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ensure_base_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub base_branch: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_base: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_prs: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count_updates: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub defer_if_workflow_prs_open: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub defer_max_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update_pins: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub update: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_pin_age: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deprecated_pins: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_paths: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub include_extensions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub only_actions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exclude_actions: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratchet_bin: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratchet_args: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_ratchet_token: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub install_ratchet: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratchet_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_ratchet_version: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub subprocess_timeout: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratchet_batch_size: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub comment_style: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backend: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unclean: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub labels: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub create_missing_labels: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub draft: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ready: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reopen_closed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune_branches: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prune_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub overwrite_existing_branch: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub force_push_always: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub shallow: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sparse: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reuse_clones: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_clones_on_error: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub keep_clones: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub strict_yaml: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_on_branch_refs: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_fast: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_fail_on_error: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preserve_newline: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fail_on_missing_workflows: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auto_merge: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_preview_dir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dry_run_output: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code_on_changes: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interactive: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub interactive_stdin: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub state_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_cache: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_cache_ttl: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ignore_state: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ledger: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub resume: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fork_org: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_preflight: Option<bool>,
+}
+
+impl Config {
+    pub fn from_file(path: &str) -> Result<Self, Box<dyn Error>> {
+        let content =
+            fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        Self::parse(&content).map_err(|e| Box::from(format!("Invalid config {}: {}", path, e)))
+    }
+
+    pub fn parse(content: &str) -> Result<Self, toml::de::Error> {
+        toml::from_str(content)
+    }
+
+    // Example configuration printed by --print-default-config
+    pub fn example() -> Self {
+        Config {
+            repos: Some(vec![
+                String::from("owner/repo1"),
+                String::from("owner/repo2"),
+            ]),
+            skip_forks: Some(true),
+            skip_templates: Some(true),
+            visibility: Some(String::from("all")),
+            branch: Some(String::from(DEFAULT_BRANCH_TEMPLATE)),
+            clone_dir: Some(String::from("temp_clones")),
+            only_report_unpinned: Some(false),
+            reproducible_commits: Some(false),
+            verify_pins: Some(VerifyPins::Enabled(false)),
+            max_workflow_size: Some(DEFAULT_MAX_WORKFLOW_SIZE),
+            ..Config::default()
+        }
+    }
+
+    pub fn to_toml(&self) -> Result<String, toml::ser::Error> {
+        toml::to_string(self)
+    }
+
+    // Command line arguments equivalent to this configuration.
+    // --repos and --org conflict, the one from <cli> wins over the file.
+    fn to_args(&self, cli: &[String]) -> Vec<String> {
+        let cli_selects_repos = has_flag(cli, "--repos") || has_flag(cli, "--org");
+        let mut args = Vec::new();
+        let mut value = |flag: &str, value: Option<String>| {
+            if let Some(value) = value {
+                args.push(format!("--{}", flag));
+                args.push(value);
+            }
+        };
+        // A list given on the command line replaces the list of the file
+        let list = |flag: &str, values: &Option<Vec<String>>| -> Vec<String> {
+            if has_flag(cli, &format!("--{}", flag)) {
+                Vec::new()
+            } else {
+                values.clone().unwrap_or_default()
+            }
+        };
+
+        if !cli_selects_repos {
+            value("repos", self.repos.as_ref().map(|repos| repos.join(",")));
+            value("org", self.org.clone());
+        }
+        value("github-url", self.github_url.clone());
+        value("https-proxy", self.https_proxy.clone());
+        value("local-path", self.local_path.clone());
+        value("max-retries", self.max_retries.map(|v| v.to_string()));
+        value("token-file", self.token_file.clone());
+        value("app-id", self.app_id.map(|v| v.to_string()));
+        value("app-private-key", self.app_private_key.clone());
+        value(
+            "installation-id",
+            self.installation_id.map(|v| v.to_string()),
+        );
+        value("kill-switch-url", self.kill_switch_url.clone());
+        value("summary-json", self.summary_json.clone());
+        value("changes-output", self.changes_output.clone());
+        value("notify-webhook", self.notify_webhook.clone());
+        value("notify-format", self.notify_format.clone());
+        value("skip-forks", self.skip_forks.map(|v| v.to_string()));
+        value("skip-templates", self.skip_templates.map(|v| v.to_string()));
+        value("visibility", self.visibility.clone());
+        value("branch", self.branch.clone());
+        value("branch-prefix", self.branch_prefix.clone());
+        value("color", self.color.clone());
+        value("log-format", self.log_format.clone());
+        value("clone-dir", self.clone_dir.clone());
+        value("remote-name", self.remote_name.clone());
+        value("pr-body-path", self.pr_body_path.clone());
+        value("commit-date", self.commit_date.map(|v| v.to_string()));
+        value(
+            "max-workflow-size",
+            self.max_workflow_size.map(|v| v.to_string()),
+        );
+        value("ensure-base-branch", self.ensure_base_branch.clone());
+        value("base-branch", self.base_branch.clone());
+        value("state-file", self.state_file.clone());
+        value("pin-cache", self.pin_cache.clone());
+        value("pin-cache-ttl", self.pin_cache_ttl.map(|v| v.to_string()));
+        value("ledger", self.ledger.clone());
+        value("resume", self.resume.clone());
+        value("fork-org", self.fork_org.clone());
+        value("max-prs", self.max_prs.map(|v| v.to_string()));
+        value("defer-max-age", self.defer_max_age.map(|v| v.to_string()));
+        value("min-pin-age", self.min_pin_age.map(|v| v.to_string()));
+        for pin in list("deprecated-pin", &self.deprecated_pins) {
+            value("deprecated-pin", Some(pin));
+        }
+        value("auto-merge", self.auto_merge.clone());
+        value("pr-preview-dir", self.pr_preview_dir.clone());
+        value("dry-run-output", self.dry_run_output.clone());
+        for pin_path in list("pin-paths", &self.pin_paths) {
+            value("pin-paths", Some(pin_path));
+        }
+        for extension in list("include-extension", &self.include_extensions) {
+            value("include-extension", Some(extension));
+        }
+        for action in list("only-actions", &self.only_actions) {
+            value("only-actions", Some(action));
+        }
+        for action in list("exclude-actions", &self.exclude_actions) {
+            value("exclude-actions", Some(action));
+        }
+        value("ratchet-bin", self.ratchet_bin.clone());
+        value("ratchet-version", self.ratchet_version.clone());
+        value("min-ratchet-version", self.min_ratchet_version.clone());
+        value(
+            "subprocess-timeout",
+            self.subprocess_timeout.map(|v| v.to_string()),
+        );
+        value(
+            "ratchet-batch-size",
+            self.ratchet_batch_size.map(|v| v.to_string()),
+        );
+        value("comment-style", self.comment_style.clone());
+        value("backend", self.backend.clone());
+        for arg in list("ratchet-arg", &self.ratchet_args) {
+            value("ratchet-arg", Some(arg));
+        }
+        for label in list("label", &self.labels) {
+            value("label", Some(label));
+        }
+
+        for (flag, enabled) in self.flags() {
+            // --<flag>=false on the command line turns off a flag of the file
+            if enabled == Some(true) && !has_flag(cli, &format!("--{}", flag)) {
+                args.push(format!("--{}", flag));
+            }
+        }
+        match &self.verify_pins {
+            Some(VerifyPins::Enabled(true)) => args.push(String::from("--verify-pins")),
+            Some(VerifyPins::Mode(mode)) => args.push(format!("--verify-pins={}", mode)),
+            _ => {}
+        }
+        for _ in 0..self.verbose.unwrap_or(0) {
+            args.push(String::from("-v"));
+        }
+        args
+    }
+
+    // The boolean flags and their keys
+    fn flags(&self) -> Vec<(&'static str, Option<bool>)> {
+        vec![
+            ("token-from-gh", self.token_from_gh),
+            ("no-ratchet-token", self.no_ratchet_token),
+            ("include-archived", self.include_archived),
+            ("only-report-unpinned", self.only_report_unpinned),
+            ("check", self.check),
+            ("no-change-table", self.no_change_table),
+            ("push", self.push),
+            ("install-ratchet", self.install_ratchet),
+            ("reproducible-commits", self.reproducible_commits),
+            ("verify-sources", self.verify_sources),
+            (
+                "include-comment-only-changes",
+                self.include_comment_only_changes,
+            ),
+            ("strict-base", self.strict_base),
+            ("count-updates", self.count_updates),
+            (
+                "defer-if-workflow-prs-open",
+                self.defer_if_workflow_prs_open,
+            ),
+            ("update-pins", self.update_pins),
+            ("update", self.update),
+            ("create-missing-labels", self.create_missing_labels),
+            ("draft", self.draft),
+            ("ready", self.ready),
+            ("dry-run", self.dry_run),
+            ("reopen-closed", self.reopen_closed),
+            ("prune-branches", self.prune_branches),
+            ("prune-only", self.prune_only),
+            ("overwrite-existing-branch", self.overwrite_existing_branch),
+            ("force-push-always", self.force_push_always),
+            ("shallow", self.shallow),
+            ("sparse", self.sparse),
+            ("reuse-clones", self.reuse_clones),
+            ("keep-clones-on-error", self.keep_clones_on_error),
+            ("keep-clones", self.keep_clones),
+            ("strict-yaml", self.strict_yaml),
+            ("fail-on-branch-refs", self.fail_on_branch_refs),
+            ("unclean", self.unclean),
+            ("fail-fast", self.fail_fast),
+            ("no-fail-on-error", self.no_fail_on_error),
+            ("exit-code-on-changes", self.exit_code_on_changes),
+            ("interactive", self.interactive),
+            ("interactive-stdin", self.interactive_stdin),
+            ("ignore-state", self.ignore_state),
+            ("fork", self.fork),
+            ("skip-preflight", self.skip_preflight),
+            ("progress", self.progress),
+            ("preserve-newline", self.preserve_newline),
+            ("fail-on-missing-workflows", self.fail_on_missing_workflows),
+        ]
+    }
+}
+
+fn has_flag(args: &[String], flag: &str) -> bool {
+    args.iter()
+        .any(|arg| arg == flag || arg.starts_with(&format!("{}=", flag)))
+}
+
+// Value of --config in the raw command line arguments
+fn config_path(args: &[String]) -> Option<String> {
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        if arg == "--config" {
+            return iter.next().cloned();
+        }
+        if let Some(path) = arg.strip_prefix("--config=") {
+            return Some(path.to_string());
+        }
+    }
+    None
+}
+
+// <args> with --<flag>=true of a boolean flag turned into --<flag>, and --<flag>=false
+// dropped. Only the file sees --<flag>=false: it keeps a flag of the file off.
+fn normalize_flags(args: Vec<String>) -> Vec<String> {
+    let flags = Config::default().flags();
+    args.into_iter()
+        .filter_map(|arg| {
+            for (flag, _) in &flags {
+                match arg.strip_prefix(&format!("--{}=", flag)) {
+                    Some("true") => return Some(format!("--{}", flag)),
+                    Some("false") => return None,
+                    _ => {}
+                }
+            }
+            Some(arg)
+        })
+        .collect()
+}
+
+// Insert the settings of the --config file, if any, between the program name
+// and the command line arguments
+pub fn merge_config_args(args: Vec<String>) -> Result<Vec<String>, Box<dyn Error>> {
+    let path = match config_path(&args) {
+        Some(path) => path,
+        None => return Ok(normalize_flags(args)),
+    };
+    let config = Config::from_file(&path)?;
+    let mut merged: Vec<String> = args.iter().take(1).cloned().collect();
+    merged.extend(config.to_args(&args));
+    merged.extend(normalize_flags(args).into_iter().skip(1));
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{verify::PinVerification, Args};
+    use clap::Parser;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    fn argv(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    fn parse_with_config(config: &str, args: &[&str]) -> Args {
+        let mut file = NamedTempFile::new().unwrap();
+        file.write_all(config.as_bytes()).unwrap();
+        let mut cli = argv(&[
+            "ratchet-dispatcher",
+            "--config",
+            file.path().to_str().unwrap(),
+        ]);
+        cli.extend(argv(args));
+        Args::try_parse_from(merge_config_args(cli).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_config_values_are_used() {
+        let args = parse_with_config(
+            "repos = [\"org/a\", \"org/b\"]\nclone_dir = \"clones\"\nverify_pins = true\nmax_prs = 3\n",
+            &[],
+        );
+        assert_eq!(args.repos.as_deref(), Some("org/a,org/b"));
+        assert_eq!(args.clone_dir, "clones");
+        assert_eq!(args.verify_pins, Some(PinVerification::Fail));
+        assert_eq!(args.max_prs, Some(3));
+        assert_eq!(args.branch, DEFAULT_BRANCH_TEMPLATE);
+    }
+
+    #[test]
+    fn test_verify_pins_mode() {
+        let args = parse_with_config("repos = [\"org/a\"]\nverify_pins = \"warn\"\n", &[]);
+        assert_eq!(args.verify_pins, Some(PinVerification::Warn));
+        let args = parse_with_config("repos = [\"org/a\"]\nverify_pins = false\n", &[]);
+        assert_eq!(args.verify_pins, None);
+        let args = parse_with_config("repos = [\"org/a\"]\n", &["--verify-pins=warn"]);
+        assert_eq!(args.verify_pins, Some(PinVerification::Warn));
+    }
+
+    #[test]
+    fn test_cli_takes_precedence() {
+        let args = parse_with_config(
+            "repos = [\"org/a\"]\nclone_dir = \"clones\"\nskip_forks = true\n",
+            &[
+                "--clone-dir",
+                "other",
+                "--skip-forks",
+                "false",
+                "--org",
+                "org",
+            ],
+        );
+        assert_eq!(args.clone_dir, "other");
+        assert_eq!(args.skip_forks, Some(false));
+        assert_eq!(args.repos, None);
+        assert_eq!(args.org.as_deref(), Some("org"));
+    }
+
+    #[test]
+    fn test_cli_lists_and_flags_take_precedence() {
+        let config =
+            "repos = [\"org/a\"]\npin_paths = [\".circleci\", \".gitlab-ci.yml\"]\ndraft = true\n";
+        let args = parse_with_config(config, &[]);
+        assert_eq!(args.pin_paths, [".circleci", ".gitlab-ci.yml"]);
+        assert!(args.draft);
+
+        let args = parse_with_config(config, &["--pin-paths", "cloudbuild.yaml", "--draft=false"]);
+        assert_eq!(args.pin_paths, ["cloudbuild.yaml"]);
+        assert!(!args.draft);
+
+        let args = parse_with_config("repos = [\"org/a\"]\n", &["--draft=true"]);
+        assert!(args.draft);
+    }
+
+    #[test]
+    fn test_deprecated_pins_are_repeated() {
+        let config = "repos = [\"org/a\"]\nupdate_pins = true\nmin_pin_age = 14\ndeprecated_pins = [\"actions/cache\", \"actions/checkout@abc\"]\n";
+        let args = parse_with_config(config, &[]);
+        assert!(args.update_pins);
+        assert_eq!(args.min_pin_age, Some(14));
+        assert_eq!(
+            args.deprecated_pin,
+            ["actions/cache", "actions/checkout@abc"]
+        );
+
+        let args = parse_with_config(config, &["--deprecated-pin", "actions/setup-node"]);
+        assert_eq!(args.deprecated_pin, ["actions/setup-node"]);
+    }
+
+    #[test]
+    fn test_ratchet_bin_and_args() {
+        let args = parse_with_config(
+            "repos = [\"org/a\"]\nratchet_bin = \"/opt/bin/ratchet\"\nratchet_args = [\"-experimental-keep-newlines\"]\n",
+            &["--ratchet-arg", "-out=pinned.yml"],
+        );
+        assert_eq!(args.ratchet_bin, "/opt/bin/ratchet");
+        assert_eq!(args.ratchet_arg, ["-out=pinned.yml"]);
+    }
+
+    #[test]
+    fn test_unknown_keys_are_rejected() {
+        let err = Config::parse("repos = [\"org/a\"]\nbranches = \"x\"\n").unwrap_err();
+        assert!(err.to_string().contains("unknown field `branches`"));
+    }
+
+    #[test]
+    fn test_missing_config_file() {
+        let err =
+            merge_config_args(argv(&["ratchet-dispatcher", "--config=missing.toml"])).unwrap_err();
+        assert!(err.to_string().contains("missing.toml"));
+    }
+
+    #[test]
+    fn test_example_config_round_trips() {
+        let example = Config::example();
+        let content = example.to_toml().unwrap();
+        assert_eq!(Config::parse(&content).unwrap(), example);
+        let args = parse_with_config(&content, &[]);
+        assert_eq!(args.repos.as_deref(), Some("owner/repo1,owner/repo2"));
+    }
+}
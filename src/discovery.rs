@@ -0,0 +1,245 @@
+use std::collections::BTreeMap;
+
+use clap::ValueEnum;
+use log::info;
+
+use crate::github::RepositoryLister;
+
+// Summary reasons of repositories skipped before cloning, for the same reasons as
+// discovery skips them
+pub const SKIPPED_ARCHIVED: &str = "skipped: archived repository";
+pub const SKIPPED_FORK: &str = "skipped: fork (--skip-forks)";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Visibility {
+    Public,
+    Private,
+    Internal,
+    All,
+}
+
+// A repository as returned by the organization repository listing
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoListing {
+    pub full_name: String,
+    pub fork: bool,
+    pub is_template: bool,
+    pub archived: bool,
+    pub disabled: bool,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DiscoveryFilters {
+    pub skip_forks: bool,
+    pub skip_templates: bool,
+    pub include_archived: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    Archived,
+    Disabled,
+    Fork,
+    Template,
+}
+
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Discovery {
+    pub repositories: Vec<String>,
+    pub skipped: BTreeMap<SkipReason, usize>,
+}
+
+// List all repositories of <org> with the given visibility and drop the ones
+// excluded by <filters>, counting every excluded repository by reason
+pub async fn discover_repositories(
+    lister: &(dyn RepositoryLister + Sync),
+    org: &str,
+    visibility: Visibility,
+    filters: DiscoveryFilters,
+) -> Result<Discovery, Box<dyn std::error::Error>> {
+    let listings = lister.list_org_repositories(org, visibility).await?;
+
+    let mut discovery = Discovery::default();
+    for listing in listings {
+        match skip_reason(&listing, filters) {
+            Some(reason) => *discovery.skipped.entry(reason).or_default() += 1,
+            None => discovery.repositories.push(listing.full_name),
+        }
+    }
+
+    info!(
+        "Discovered {} repositories in {} (skipped: {:?})",
+        discovery.repositories.len(),
+        org,
+        discovery.skipped
+    );
+    for repository in &discovery.repositories {
+        info!("  {}", repository);
+    }
+    Ok(discovery)
+}
+
+fn skip_reason(listing: &RepoListing, filters: DiscoveryFilters) -> Option<SkipReason> {
+    // Disabled repositories can not be cloned or pushed to, they are always skipped
+    if listing.disabled {
+        Some(SkipReason::Disabled)
+    } else if !filters.include_archived && listing.archived {
+        Some(SkipReason::Archived)
+    } else if filters.skip_forks && listing.fork {
+        Some(SkipReason::Fork)
+    } else if filters.skip_templates && listing.is_template {
+        Some(SkipReason::Template)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::MockRepositoryLister;
+    use mockall::predicate::*;
+
+    fn listing(name: &str, fork: bool, is_template: bool) -> RepoListing {
+        RepoListing {
+            full_name: format!("org/{}", name),
+            fork,
+            is_template,
+            archived: false,
+            disabled: false,
+        }
+    }
+
+    fn lister() -> MockRepositoryLister {
+        let mut lister = MockRepositoryLister::new();
+        lister
+            .expect_list_org_repositories()
+            .with(eq("org"), eq(Visibility::All))
+            .returning(|_, _| {
+                Ok(vec![
+                    listing("app", false, false),
+                    listing("forked", true, false),
+                    listing("template", false, true),
+                    listing("forked-template", true, true),
+                ])
+            });
+        lister
+    }
+
+    async fn discover(skip_forks: bool, skip_templates: bool) -> Discovery {
+        let filters = DiscoveryFilters {
+            skip_forks,
+            skip_templates,
+            include_archived: false,
+        };
+        discover_repositories(&lister(), "org", Visibility::All, filters)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_discover_skips_forks() {
+        let discovery = discover(true, false).await;
+        assert_eq!(discovery.repositories, vec!["org/app", "org/template"]);
+        assert_eq!(discovery.skipped, BTreeMap::from([(SkipReason::Fork, 2)]));
+    }
+
+    #[tokio::test]
+    async fn test_discover_skips_templates() {
+        let discovery = discover(false, true).await;
+        assert_eq!(discovery.repositories, vec!["org/app", "org/forked"]);
+        assert_eq!(
+            discovery.skipped,
+            BTreeMap::from([(SkipReason::Template, 2)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discover_skips_forks_and_templates() {
+        let discovery = discover(true, true).await;
+        assert_eq!(discovery.repositories, vec!["org/app"]);
+        assert_eq!(
+            discovery.skipped,
+            BTreeMap::from([(SkipReason::Fork, 2), (SkipReason::Template, 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discover_without_filters() {
+        let discovery = discover(false, false).await;
+        assert_eq!(discovery.repositories.len(), 4);
+        assert!(discovery.skipped.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discover_passes_visibility() {
+        let mut lister = MockRepositoryLister::new();
+        lister
+            .expect_list_org_repositories()
+            .with(eq("org"), eq(Visibility::Private))
+            .times(1)
+            .returning(|_, _| Ok(vec![listing("secret", false, false)]));
+        let filters = DiscoveryFilters {
+            skip_forks: true,
+            skip_templates: true,
+            include_archived: false,
+        };
+
+        let discovery = discover_repositories(&lister, "org", Visibility::Private, filters)
+            .await
+            .unwrap();
+        assert_eq!(discovery.repositories, vec!["org/secret"]);
+    }
+
+    fn archived_lister() -> MockRepositoryLister {
+        let mut lister = MockRepositoryLister::new();
+        lister.expect_list_org_repositories().returning(|_, _| {
+            Ok(vec![
+                listing("app", false, false),
+                RepoListing {
+                    archived: true,
+                    ..listing("old", false, false)
+                },
+                RepoListing {
+                    disabled: true,
+                    ..listing("disabled", false, false)
+                },
+            ])
+        });
+        lister
+    }
+
+    #[tokio::test]
+    async fn test_discover_skips_archived_and_disabled_by_default() {
+        let filters = DiscoveryFilters {
+            skip_forks: true,
+            skip_templates: true,
+            include_archived: false,
+        };
+        let discovery = discover_repositories(&archived_lister(), "org", Visibility::All, filters)
+            .await
+            .unwrap();
+        assert_eq!(discovery.repositories, vec!["org/app"]);
+        assert_eq!(
+            discovery.skipped,
+            BTreeMap::from([(SkipReason::Archived, 1), (SkipReason::Disabled, 1)])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_discover_include_archived() {
+        let filters = DiscoveryFilters {
+            skip_forks: true,
+            skip_templates: true,
+            include_archived: true,
+        };
+        let discovery = discover_repositories(&archived_lister(), "org", Visibility::All, filters)
+            .await
+            .unwrap();
+        assert_eq!(discovery.repositories, vec!["org/app", "org/old"]);
+        assert_eq!(
+            discovery.skipped,
+            BTreeMap::from([(SkipReason::Disabled, 1)])
+        );
+    }
+}
@@ -0,0 +1,1462 @@
+use std::{
+    error::Error,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{error, info, warn};
+
+use crate::{
+    approval::{Approval, Approver, SKIPPED_BY_USER},
+    auth::{CredentialedTagLookup, Credentials},
+    base::{ensure_base_branch, existing_base_branch},
+    branch::{BranchContext, BranchNamer},
+    budget::{PrBudget, DEFERRED_BUDGET_EXHAUSTED},
+    discovery::{SKIPPED_ARCHIVED, SKIPPED_FORK},
+    dry_run::{patch_file_name, write_patch},
+    fork::{ensure_fork, pr_head, FORK_POLL_INTERVAL, FORK_READY_TIMEOUT},
+    freshness::{age_distribution, hold_back, restore_held_pins, young_pins, PinAges},
+    git::{CloneOptions, CorruptedWorkflow, GitError, GitRepository, FORK_REMOTE},
+    github::{
+        api_context, backoff_delay, ForkRef, GitHubClient, GitHubUrls, RepositoryClient, TagLookup,
+        RETRY_BASE_DELAY,
+    },
+    interrupt::{Cancellation, DEFERRED_INTERRUPTED},
+    io::{cleanup_clone_dir, DEFAULT_PR_BODY},
+    ledger::{Ledger, SKIPPED_RESUMED},
+    output::with_log_repository,
+    pin_cache::{PinCache, PinCacheStats},
+    pr_content::{build_pr_content, write_preview, StagedChanges},
+    preflight::find_open_workflow_prs,
+    progress::{NoProgress, Phase, Progress},
+    proxy::ProxyConfig,
+    prune::{prune_branch, PruneOutcome},
+    ratchet::{
+        has_pin_files, restore_ratchet_comments_in, upgrade_workflows, BranchPin, PinBackend,
+        PinCounts, PinStats, RatchetMode, RatchetOptions, ALREADY_PINNED, NOTHING_TO_COMMIT,
+        NO_WORKFLOWS,
+    },
+    repo_config::{RepoConfig, REPO_CONFIG_PATH, SKIPPED_BY_REPO_CONFIG},
+    report::{parse_pinned_actions, report_unpinned_actions},
+    scan::{find_actions, find_pinned_actions, find_unpinned_actions},
+    state::{StateFile, SKIPPED_UNCHANGED},
+    summary::{
+        KeptClone, RepoStatus, RepoSummary, RunSummary, KEPT_ALWAYS, KEPT_FOR_REUSE, KEPT_ON_ERROR,
+    },
+    verify::{
+        render_floating_pins, PinVerification, PinVerifier, SourceIssue, SourceVerifier,
+        TagMismatch,
+    },
+    workflow::is_action_selected,
+    Args,
+};
+
+// Summary reason of the repositories left alone after a failure with --fail-fast
+pub const DEFERRED_FAIL_FAST: &str = "deferred: an earlier repository failed (--fail-fast)";
+
+// Creates the client for the <owner>/<repo> repository, authenticated with <token>
+pub type ClientFactory = Box<dyn Fn(&str, &str, &str) -> Box<dyn RepositoryClient>>;
+
+// Clients of the GitHub instance of <urls>, set up as --max-retries and --label ask
+fn github_clients(args: &Args, urls: GitHubUrls) -> ClientFactory {
+    let max_retries = args.max_retries;
+    let labels = args.label.clone();
+    let create_missing_labels = args.create_missing_labels;
+    Box::new(move |owner, repo, token| {
+        Box::new(
+            GitHubClient::new(
+                owner.to_string(),
+                repo.to_string(),
+                token.to_string(),
+                &urls,
+            )
+            .with_max_retries(max_retries)
+            .with_labels(labels.clone(), create_missing_labels),
+        )
+    })
+}
+
+// Where a single repository is cloned from and which branches are used
+#[derive(Debug, Clone, Copy)]
+struct RepoTarget<'a> {
+    repository: &'a str,
+    repo_url: &'a str,
+    local_path: &'a str,
+    default_branch: &'a str,
+    branch: &'a str,
+    // --fork, the automation branch is pushed there
+    fork: Option<&'a ForkRef>,
+    token: &'a str,
+    run_date: &'a str,
+}
+
+// What the pin run changed in a repository, reported alike by every status it can end in
+struct Outcome<'a> {
+    files_changed: usize,
+    actions_pinned: usize,
+    comment_only_changes: usize,
+    source_issues: &'a [SourceIssue],
+    tag_mismatches: &'a [TagMismatch],
+    branch_pins: &'a [BranchPin],
+    corrupted: &'a [CorruptedWorkflow],
+}
+
+impl Outcome<'_> {
+    fn summary(&self, repository: &str, status: RepoStatus, pins: PinStats) -> RepoSummary {
+        RepoSummary::new(repository, status)
+            .with_files_changed(self.files_changed)
+            .with_actions_pinned(self.actions_pinned)
+            .with_pins(pins)
+            .with_comment_only_changes(self.comment_only_changes)
+            .with_source_issues(self.source_issues)
+            .with_tag_mismatches(self.tag_mismatches)
+            .with_branch_pins(self.branch_pins)
+            .with_corrupted_workflows(self.corrupted)
+    }
+}
+
+// With --reopen-closed a branch whose last PR was merged is not reused, the first
+// <branch>-<n> without a merged PR gets a brand-new PR instead
+async fn unmerged_branch(
+    github_client: &dyn RepositoryClient,
+    branch: String,
+    fork: Option<&ForkRef>,
+) -> Result<String, Box<dyn Error>> {
+    let mut candidate = branch.clone();
+    let mut suffix = 1;
+    while github_client
+        .find_existing_pr(&pr_head(fork, &candidate))
+        .await?
+        .is_none()
+    {
+        match github_client
+            .find_closed_pr(&pr_head(fork, &candidate))
+            .await?
+        {
+            Some(closed) if closed.merged => {
+                suffix += 1;
+                let next = format!("{}-{}", branch, suffix);
+                info!(
+                    "PR #{} of branch {} was merged, using branch {}",
+                    closed.pr.number, candidate, next
+                );
+                candidate = next;
+            }
+            _ => break,
+        }
+    }
+    Ok(candidate)
+}
+
+// Push <branch>, retrying up to <max_retries> times with backoff while the remote
+// rejects the update. Other push errors are returned immediately.
+async fn push_with_retry(
+    git_repo: &GitRepository,
+    branch: &str,
+    max_retries: u32,
+) -> Result<(), GitError> {
+    let mut attempt = 0;
+    loop {
+        match git_repo.push_changes(branch, true) {
+            Err(e @ GitError::PushRejected { .. }) if attempt < max_retries => {
+                attempt += 1;
+                let delay = backoff_delay(RETRY_BASE_DELAY, attempt);
+                warn!("{}, retry {}/{} in {:?}", e, attempt, max_retries, delay);
+                tokio::time::sleep(delay).await;
+            }
+            result => return result,
+        }
+    }
+}
+
+// Why the clone of a repository stays in --clone-dir, None when it is removed. A failure
+// with --keep-clones-on-error wins, it tells the most about why the clone is there.
+fn clone_retention(args: &Args, failed: bool) -> Option<&'static str> {
+    if failed && args.keep_clones_on_error {
+        Some(KEPT_ON_ERROR)
+    } else if args.keep_clones {
+        Some(KEPT_ALWAYS)
+    } else if args.reuse_clones {
+        Some(KEPT_FOR_REUSE)
+    } else {
+        None
+    }
+}
+
+// State shared by all repositories of a single run
+struct RunState<'a> {
+    verifier: PinVerifier<'a>,
+    sources: SourceVerifier<'a>,
+    pin_ages: PinAges<'a>,
+    branch_namer: BranchNamer,
+    budget: PrBudget,
+    // Date of the run for branch names (YYYYMMDD) and PR bodies (YYYY-MM-DD)
+    date: String,
+    run_date: String,
+    // --state-file, saved at the end of the run
+    heads: Option<StateFile>,
+}
+
+// Runs the whole pipeline (clone, ratchet, stage, commit, push, PR) over a list of
+// repositories. Everything that talks to the outside world can be swapped, which
+// allows running it against local repositories (see the test-harness feature).
+pub struct Dispatcher {
+    args: Args,
+    clone_url_base: String,
+    ratchet_path: String,
+    // Template of the PR body, --pr-body-path as read by main
+    pr_body: String,
+    credentials: Arc<Credentials>,
+    clients: ClientFactory,
+    tag_lookup: Box<dyn TagLookup + Sync>,
+    // Ctrl-C, checked between repositories and before pushing
+    cancellation: Cancellation,
+    // Told which repository and step the run is at
+    progress: Arc<dyn Progress>,
+    // --interactive, asked before every push
+    approver: Option<Box<dyn Approver>>,
+    // --ledger or --resume, written after every repository
+    ledger: Option<Mutex<Ledger>>,
+    // --https-proxy or the proxy environment variables, and what ratchet is told of it
+    proxy: Option<ProxyConfig>,
+    proxy_env: Vec<(String, String)>,
+    // --pin-cache, shared by every repository and saved at the end of the run
+    pin_cache: Option<Mutex<PinCache>>,
+}
+
+impl Dispatcher {
+    // Dispatcher that works against the GitHub instance of --github-url
+    pub fn new(args: Args, credentials: Arc<Credentials>) -> Self {
+        let urls = GitHubUrls::new(&args.github_url);
+        let ratchet_path = args.ratchet_bin.clone();
+        let pin_cache = args.pin_cache.as_deref().map(|path| {
+            let now = chrono::Utc::now().timestamp();
+            let cache = PinCache::load(path, args.pin_cache_ttl, now).unwrap_or_else(|e| {
+                warn!("{}, starting with an empty pin cache", e);
+                PinCache::new(path, args.pin_cache_ttl)
+            });
+            Mutex::new(cache)
+        });
+        Dispatcher {
+            clone_url_base: urls.clone_base.clone(),
+            ratchet_path,
+            pr_body: String::from(DEFAULT_PR_BODY),
+            credentials: credentials.clone(),
+            clients: github_clients(&args, urls.clone()),
+            tag_lookup: Box::new(CredentialedTagLookup::new(credentials, urls)),
+            args,
+            cancellation: Cancellation::default(),
+            progress: Arc::new(NoProgress),
+            approver: None,
+            ledger: None,
+            proxy: None,
+            proxy_env: Vec::new(),
+            pin_cache,
+        }
+    }
+
+    // Talk to GitHub, clone and run ratchet through <proxy>
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        let urls = GitHubUrls::new(&self.args.github_url).with_proxy(proxy.clone());
+        self.clients = github_clients(&self.args, urls.clone());
+        self.tag_lookup = Box::new(CredentialedTagLookup::new(self.credentials.clone(), urls));
+        self.proxy_env = proxy
+            .as_ref()
+            .map(ProxyConfig::env_vars)
+            .unwrap_or_default();
+        self.proxy = proxy;
+        self
+    }
+
+    #[cfg(all(test, feature = "test-harness"))]
+    // Clone from <clone_url_base>/<owner>/<repo>.git instead of --github-url
+    pub fn with_clone_url_base(mut self, clone_url_base: &str) -> Self {
+        self.clone_url_base = clone_url_base.to_string();
+        self
+    }
+
+    // Use <pr_body> as the template of the PR body instead of DEFAULT_PR_BODY
+    pub fn with_pr_body(mut self, pr_body: String) -> Self {
+        self.pr_body = pr_body;
+        self
+    }
+
+    // Report the repositories and their steps to <progress>
+    pub fn with_progress(mut self, progress: Arc<dyn Progress>) -> Self {
+        self.progress = progress;
+        self
+    }
+
+    // Ask <approver> before pushing a repository
+    pub fn with_approver(mut self, approver: Box<dyn Approver>) -> Self {
+        self.approver = Some(approver);
+        self
+    }
+
+    // Record every finished repository in <ledger>, and skip the ones it already has
+    pub fn with_ledger(mut self, ledger: Ledger) -> Self {
+        self.ledger = Some(Mutex::new(ledger));
+        self
+    }
+
+    #[cfg(all(test, feature = "test-harness"))]
+    pub fn with_ratchet_path(mut self, ratchet_path: &str) -> Self {
+        self.ratchet_path = ratchet_path.to_string();
+        self
+    }
+
+    #[cfg(all(test, feature = "test-harness"))]
+    pub fn with_clients(mut self, clients: ClientFactory) -> Self {
+        self.clients = clients;
+        self
+    }
+
+    #[cfg(all(test, feature = "test-harness"))]
+    pub fn with_tag_lookup(mut self, tag_lookup: Box<dyn TagLookup + Sync>) -> Self {
+        self.tag_lookup = tag_lookup;
+        self
+    }
+
+    // Cancelling it stops the run after the repository in flight
+    pub fn cancellation(&self) -> Cancellation {
+        self.cancellation.clone()
+    }
+
+    fn run_state(&self) -> RunState<'_> {
+        let args = &self.args;
+        let now = chrono::Utc::now();
+        RunState {
+            verifier: PinVerifier::new(self.tag_lookup.as_ref()),
+            sources: SourceVerifier::new(self.tag_lookup.as_ref()),
+            pin_ages: PinAges::new(self.tag_lookup.as_ref(), now),
+            branch_namer: BranchNamer::new(&args.branch, args.branch_prefix.as_deref()),
+            budget: PrBudget::new(args.max_prs, args.count_updates),
+            date: now.format("%Y%m%d").to_string(),
+            run_date: now.format("%Y-%m-%d").to_string(),
+            heads: args.state_file.as_deref().map(|path| {
+                StateFile::load(path).unwrap_or_else(|e| {
+                    warn!("{}, processing every repository", e);
+                    StateFile::default()
+                })
+            }),
+        }
+    }
+
+    // --local-path without --push: pin the checkout at <local_path> and commit to a new
+    // branch there. Nothing is pushed, so no GitHub client is created. <repository>
+    // (owner/repo) names the branch, the directory name is used when it is not given.
+    pub async fn run_local(&self, local_path: &str, repository: Option<&str>) -> RunSummary {
+        let mut state = self.run_state();
+        let repository = match repository {
+            Some(repository) => repository.to_string(),
+            None => Path::new(local_path)
+                .canonicalize()
+                .ok()
+                .and_then(|path| path.file_name().map(|name| name.to_string_lossy().into()))
+                .unwrap_or_else(|| local_path.to_string()),
+        };
+        self.progress.start(1, 1, &repository);
+        let entry = with_log_repository(&repository, async {
+            match self
+                .run_local_repository(&repository, local_path, &mut state)
+                .await
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    error!("Failed to process checkout {}: {}", local_path, e);
+                    RepoSummary::failed(&repository, e.as_ref())
+                }
+            }
+        })
+        .await;
+        self.progress.finish(&entry);
+        let mut summary = RunSummary::default();
+        summary.record(entry);
+        summary.pin_cache = self.save_pin_cache();
+        summary
+    }
+
+    async fn run_local_repository(
+        &self,
+        repository: &str,
+        local_path: &str,
+        state: &mut RunState<'_>,
+    ) -> Result<RepoSummary, Box<dyn Error>> {
+        let args = &self.args;
+        let (owner, repo_name) = repository.split_once('/').unwrap_or(("", repository));
+        let base_branch = GitRepository::open_repo(local_path, None, &CloneOptions::default())?
+            .current_branch()?;
+        let branch = state
+            .branch_namer
+            .name(&BranchContext {
+                owner,
+                repo: repo_name,
+                base: &base_branch,
+                date: &state.date,
+            })
+            .map_err(|e| format!("Failed to name branch: {}", e))?;
+        let target = RepoTarget {
+            repository,
+            repo_url: local_path,
+            local_path,
+            default_branch: &base_branch,
+            branch: &branch,
+            fork: None,
+            token: "",
+            run_date: &state.run_date,
+        };
+        self.process_single_repository(
+            &target,
+            None,
+            args.verify_pins.is_some().then_some(&mut state.verifier),
+            args.verify_sources.then_some(&mut state.sources),
+            args.update_pins.then_some(&mut state.pin_ages),
+            &mut state.budget,
+        )
+        .await
+    }
+
+    pub async fn run(&self, repos: &[String]) -> RunSummary {
+        let mut state = self.run_state();
+        let mut summary = RunSummary::default();
+        let mut failed = false;
+        for (index, repo) in repos.iter().enumerate() {
+            self.progress.start(index + 1, repos.len(), repo);
+            if self.is_resumed(repo) {
+                info!("{}: {}", repo, SKIPPED_RESUMED);
+                let entry =
+                    RepoSummary::new(repo, RepoStatus::Skipped).with_reason(SKIPPED_RESUMED);
+                self.progress.finish(&entry);
+                summary.record(entry);
+                continue;
+            }
+            let entry = if self.cancellation.is_cancelled() {
+                RepoSummary::new(repo, RepoStatus::Deferred).with_reason(DEFERRED_INTERRUPTED)
+            } else if failed && self.args.fail_fast {
+                RepoSummary::new(repo, RepoStatus::Deferred).with_reason(DEFERRED_FAIL_FAST)
+            } else {
+                with_log_repository(repo, async {
+                    match self.run_single_repository(repo, &mut state).await {
+                        Ok(entry) => entry,
+                        Err(e) => {
+                            error!("Failed to process repository {}: {}", repo, e);
+                            RepoSummary::failed(repo, e.as_ref())
+                        }
+                    }
+                })
+                .await
+            };
+            if entry.status == RepoStatus::Deferred {
+                let reason = entry.reason.as_deref().unwrap_or(DEFERRED_BUDGET_EXHAUSTED);
+                state.budget.defer(repo, reason);
+            }
+            failed |= entry.status == RepoStatus::Failed;
+            if let Some(Ok(mut ledger)) = self.ledger.as_ref().map(Mutex::lock) {
+                ledger.append(&entry);
+            }
+            self.progress.finish(&entry);
+            summary.record(entry);
+        }
+
+        if let (Some(path), Some(heads)) = (&self.args.state_file, &state.heads) {
+            match heads.save(path) {
+                Ok(()) => info!("Saved the state of the run to {}", path),
+                Err(e) => warn!("Failed to save the state of the run: {}", e),
+            }
+        }
+        summary.pin_cache = self.save_pin_cache();
+        let deferred = state.budget.deferred();
+        if !deferred.is_empty() {
+            warn!("{} repositories deferred:", deferred.len());
+            for (repo, reason) in deferred {
+                warn!("  {}: {}", repo, reason);
+            }
+        }
+        if !summary.pins.is_empty() {
+            for line in summary.render_pin_table().lines() {
+                info!("{}", line);
+            }
+        }
+        let kept = summary.kept_clones();
+        if !kept.is_empty() {
+            info!("{} clone(s) kept:", kept.len());
+            for (repo, clone) in kept {
+                info!("  {} at {}: {}", repo, clone.path, clone.reason);
+            }
+        }
+        summary
+    }
+
+    // Save --pin-cache for the next run, returns its hits and misses of this run
+    fn save_pin_cache(&self) -> Option<PinCacheStats> {
+        let cache = self.pin_cache.as_ref()?.lock().ok()?;
+        let stats = cache.stats();
+        match cache.save() {
+            Ok(()) => info!(
+                "Pin cache {}: {} hits, {} misses",
+                cache.path(),
+                stats.hits,
+                stats.misses
+            ),
+            Err(e) => warn!("Failed to save the pin cache: {}", e),
+        }
+        Some(stats)
+    }
+
+    // Whether the ledger of --resume has <repo> as finished
+    fn is_resumed(&self, repo: &str) -> bool {
+        self.ledger
+            .as_ref()
+            .and_then(|ledger| ledger.lock().ok())
+            .is_some_and(|ledger| ledger.is_done(repo))
+    }
+
+    async fn run_single_repository(
+        &self,
+        repo: &str,
+        state: &mut RunState<'_>,
+    ) -> Result<RepoSummary, Box<dyn Error>> {
+        let args = &self.args;
+        let (owner, repo_name) = repo
+            .split_once('/')
+            .filter(|(_, name)| !name.contains('/'))
+            .ok_or_else(|| format!("Invalid repository format: {}", repo))?;
+        let repo_url = format!("{}/{}/{}.git", self.clone_url_base, owner, repo_name);
+        let local_path = match &args.local_path {
+            Some(local_path) => local_path.clone(),
+            None => format!("{}/{}_{}", args.clone_dir, owner, repo_name),
+        };
+        let token = self.credentials.token().await?;
+        let github_client = (self.clients)(owner, repo_name, &token);
+        // Archived repositories can not be pushed to, checked before anything is cloned
+        let info = github_client
+            .get_repo_info()
+            .await
+            .map_err(|e| api_context("Failed to get repository info", e))?;
+        let skipped = if info.archived && !args.include_archived {
+            Some(SKIPPED_ARCHIVED)
+        } else if info.fork && args.skip_forks == Some(true) {
+            Some(SKIPPED_FORK)
+        } else {
+            None
+        };
+        if let Some(reason) = skipped {
+            info!("{}: {}", repo, reason);
+            return Ok(RepoSummary::new(repo, RepoStatus::Skipped).with_reason(reason));
+        }
+        // --fork: dry runs and reports push nothing and do not fork
+        let fork = if args.fork && !args.dry_run && !args.only_report_unpinned {
+            let fork = ensure_fork(
+                github_client.as_ref(),
+                args.fork_org.as_deref(),
+                &info.default_branch,
+                FORK_POLL_INTERVAL,
+                FORK_READY_TIMEOUT,
+            )
+            .await
+            .map_err(|e| api_context(&format!("Failed to fork {}", repo), e))?;
+            info!("Pushing {} to fork {}/{}", repo, fork.owner, fork.repo);
+            Some(fork)
+        } else {
+            None
+        };
+        let existing_base = match &args.base_branch {
+            Some(base) => existing_base_branch(github_client.as_ref(), base, args.strict_base)
+                .await
+                .map_err(|e| api_context(&format!("Failed to check base branch {}", base), e))?,
+            None => None,
+        };
+        let base_branch = match existing_base {
+            Some(base) => base,
+            None => {
+                let default_branch = info.default_branch;
+                match &args.ensure_base_branch {
+                    // Dry runs and pruning do not create branches
+                    Some(base)
+                        if !args.only_report_unpinned && !args.dry_run && !args.prune_only =>
+                    {
+                        ensure_base_branch(
+                            github_client.as_ref(),
+                            base,
+                            &default_branch,
+                            args.strict_base,
+                        )
+                        .await
+                        .map_err(|e| {
+                            api_context(&format!("Failed to ensure base branch {}", base), e)
+                        })?
+                    }
+                    _ => default_branch,
+                }
+            }
+        };
+        let branch = state
+            .branch_namer
+            .name(&BranchContext {
+                owner,
+                repo: repo_name,
+                base: &base_branch,
+                date: &state.date,
+            })
+            .map_err(|e| format!("Failed to name branch: {}", e))?;
+        if args.prune_branches && !args.only_report_unpinned {
+            let client = github_client.as_ref();
+            let outcome = prune_branch(client, client, &branch, args.reopen_closed, args.dry_run)
+                .await
+                .map_err(|e| api_context(&format!("Failed to prune branch {}", branch), e))?;
+            info!("{}: {}", repo, outcome.describe(&branch));
+            if args.prune_only {
+                let status = match outcome {
+                    PruneOutcome::Deleted { .. } => RepoStatus::Pruned,
+                    PruneOutcome::WouldDelete { .. } => RepoStatus::DryRun,
+                    _ => RepoStatus::Skipped,
+                };
+                return Ok(RepoSummary::new(repo, status).with_reason(&outcome.describe(&branch)));
+            }
+        }
+        let branch = if args.reopen_closed && !args.only_report_unpinned {
+            unmerged_branch(github_client.as_ref(), branch, fork.as_ref())
+                .await
+                .map_err(|e| api_context("Failed to look up closed PRs", e))?
+        } else {
+            branch
+        };
+        // --state-file: nothing to do when the base branch did not move since the last
+        // run, unless that run left a PR open
+        let head = match &state.heads {
+            Some(_) if !args.only_report_unpinned => {
+                github_client.branch_sha(&base_branch).await.map_err(|e| {
+                    api_context(&format!("Failed to get the head of {}", base_branch), e)
+                })?
+            }
+            _ => None,
+        };
+        if let (Some(heads), Some(sha)) = (&state.heads, &head) {
+            if !args.ignore_state
+                && heads.is_unchanged(repo, &base_branch, sha)
+                && github_client
+                    .find_existing_pr(&pr_head(fork.as_ref(), &branch))
+                    .await?
+                    .is_none()
+            {
+                info!("{}: {}", repo, SKIPPED_UNCHANGED);
+                return Ok(
+                    RepoSummary::new(repo, RepoStatus::Skipped).with_reason(SKIPPED_UNCHANGED)
+                );
+            }
+        }
+        if args.defer_if_workflow_prs_open && !args.only_report_unpinned {
+            let max_age = args
+                .defer_max_age
+                .map(|days| chrono::Duration::days(days as i64));
+            let deferral =
+                find_open_workflow_prs(github_client.as_ref(), &base_branch, &branch, max_age)
+                    .await
+                    .map_err(|e| api_context("Failed to list open workflow PRs", e))?;
+            if let Some(reason) = deferral {
+                warn!("{}: {}", repo, reason);
+                return Ok(RepoSummary::new(repo, RepoStatus::Deferred).with_reason(&reason));
+            }
+        }
+
+        let result = if args.only_report_unpinned {
+            self.report_single_repository(
+                &repo_url,
+                &local_path,
+                &base_branch,
+                &token,
+                github_client.as_ref(),
+            )
+            .await
+            .map(|_| RepoSummary::new(repo, RepoStatus::Reported))
+        } else {
+            let target = RepoTarget {
+                repository: repo,
+                repo_url: &repo_url,
+                local_path: &local_path,
+                default_branch: &base_branch,
+                branch: &branch,
+                fork: fork.as_ref(),
+                token: &token,
+                run_date: &state.run_date,
+            };
+            self.process_single_repository(
+                &target,
+                Some(github_client.as_ref()),
+                args.verify_pins.is_some().then_some(&mut state.verifier),
+                args.verify_sources.then_some(&mut state.sources),
+                args.update_pins.then_some(&mut state.pin_ages),
+                &mut state.budget,
+            )
+            .await
+        };
+        // Only a repository that is done with this head is skipped by the next run
+        if let (Some(heads), Some(sha), Ok(entry)) = (&mut state.heads, &head, &result) {
+            if matches!(
+                entry.status,
+                RepoStatus::Created | RepoStatus::Updated | RepoStatus::Skipped
+            ) {
+                heads.record(repo, &base_branch, sha);
+            }
+        }
+        // A --local-path checkout is not a clone and is never removed
+        if args.local_path.is_some() {
+            return result;
+        }
+        let kept = match clone_retention(args, result.is_err()) {
+            Some(reason) if Path::new(&local_path).exists() => Some(KeptClone {
+                path: local_path.clone(),
+                reason,
+            }),
+            _ => {
+                cleanup_clone_dir(&local_path);
+                None
+            }
+        };
+        match (result, kept) {
+            (Ok(entry), kept) => Ok(entry.with_kept_clone(kept)),
+            (Err(e), None) => Err(e),
+            // The clone is only useful with the error next to it
+            (Err(e), Some(kept)) => {
+                error!("Failed to process repository {}: {}", repo, e);
+                warn!("Kept the clone of {} at {} for debugging", repo, kept.path);
+                Ok(RepoSummary::failed(repo, e.as_ref()).with_kept_clone(Some(kept)))
+            }
+        }
+    }
+
+    // Clone <repo_url> to <local_path>, or with --reuse-clones bring the clone of an
+    // earlier run up to date. --shallow clones only fetch <default_branch>, the base of
+    // the automation branch. A --local-path checkout is opened as it is.
+    fn clone(
+        &self,
+        repo_url: &str,
+        local_path: &str,
+        default_branch: &str,
+        token: &str,
+    ) -> Result<GitRepository, GitError> {
+        let options = CloneOptions {
+            shallow_branch: self.args.shallow.then_some(default_branch),
+            sparse: self.args.sparse,
+            pin_paths: &self.args.pin_paths,
+            proxy: self
+                .proxy
+                .as_ref()
+                .and_then(|proxy| proxy.proxy_for(repo_url))
+                .map(|proxy| proxy.url.as_str()),
+        };
+        if self.args.local_path.is_some() {
+            GitRepository::open_repo(local_path, Some(token), &options)
+        } else if self.args.reuse_clones {
+            GitRepository::open_or_clone_repo(
+                repo_url,
+                local_path,
+                Some(token),
+                &options,
+                default_branch,
+            )
+        } else {
+            GitRepository::clone_repo(repo_url, local_path, Some(token), &options)
+        }
+    }
+
+    // Commit the staged changes with the message of <mode>, with --reproducible-commits
+    // as the bot at a fixed date
+    fn commit(&self, git_repo: &GitRepository, mode: RatchetMode) -> Result<(), GitError> {
+        git_repo.commit_changes(mode.commit_message(), self.commit_time(git_repo)?)
+    }
+
+    fn commit_time(&self, git_repo: &GitRepository) -> Result<Option<i64>, GitError> {
+        if !self.args.reproducible_commits {
+            return Ok(None);
+        }
+        match self.args.commit_date {
+            Some(date) => Ok(Some(date)),
+            None => Ok(Some(git_repo.head_commit_time()?)),
+        }
+    }
+
+    // --dry-run-output: write the commit the run would push as a patch, returns the
+    // file name of the patch
+    fn write_dry_run_patch(
+        &self,
+        git_repo: &GitRepository,
+        mode: RatchetMode,
+        repository: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        let Some(dir) = &self.args.dry_run_output else {
+            return Ok(None);
+        };
+        let patch = match git_repo.format_patch(mode.commit_message(), self.commit_time(git_repo)?)
+        {
+            Ok(patch) => patch,
+            Err(GitError::NothingToStage) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let path = write_patch(dir, repository, &patch)?;
+        info!("Wrote the patch of {} to {}", repository, path.display());
+        Ok(Some(patch_file_name(repository)))
+    }
+
+    async fn report_single_repository(
+        &self,
+        repo_url: &str,
+        local_path: &str,
+        default_branch: &str,
+        token: &str,
+        github_client: &dyn RepositoryClient,
+    ) -> Result<(), Box<dyn Error>> {
+        if let Err(e) = self.clone(repo_url, local_path, default_branch, token) {
+            error!("Failed to clone repository: {}", e);
+            return Err(e.into());
+        }
+
+        let unpinned = find_unpinned_actions(local_path, self.args.max_workflow_size)?;
+        info!(
+            "Found {} unpinned action(s) in {}",
+            unpinned.len(),
+            repo_url
+        );
+
+        let report = report_unpinned_actions(github_client, &unpinned).await?;
+        info!(
+            "Issue report for {}: {:?} {}",
+            repo_url,
+            report.transition,
+            report.url.unwrap_or_default()
+        );
+        Ok(())
+    }
+
+    async fn process_single_repository(
+        &self,
+        target: &RepoTarget<'_>,
+        github_client: Option<&dyn RepositoryClient>,
+        mut verifier: Option<&mut PinVerifier<'_>>,
+        sources: Option<&mut SourceVerifier<'_>>,
+        pin_ages: Option<&mut PinAges<'_>>,
+        budget: &mut PrBudget,
+    ) -> Result<RepoSummary, Box<dyn Error>> {
+        let RepoTarget {
+            repository,
+            repo_url,
+            local_path,
+            default_branch,
+            branch,
+            fork,
+            token,
+            run_date,
+        } = *target;
+        let started = Instant::now();
+        self.progress.phase(Phase::Clone);
+        let mut git_repo = match self.clone(repo_url, local_path, default_branch, token) {
+            Ok(repo) => repo,
+            Err(e) => {
+                error!("Failed to clone repository: {}", e);
+                return Err(e.into());
+            }
+        };
+        // The base branch is still fetched from the upstream, origin
+        if let Some(fork) = fork {
+            let fork_url = format!("{}/{}/{}.git", self.clone_url_base, fork.owner, fork.repo);
+            git_repo.add_push_remote(FORK_REMOTE, &fork_url)?;
+        }
+        // The repository may opt out of or tune the run with a config file of its own
+        let repo_config = match RepoConfig::load(local_path) {
+            Ok(config) => config,
+            Err(e) => {
+                warn!("Skipping {}: {}", repository, e);
+                return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                    .with_reason(&format!("skipped: {}", e)));
+            }
+        };
+        if !repo_config.is_enabled() {
+            info!(
+                "{} is disabled by {}, skipping",
+                repository, REPO_CONFIG_PATH
+            );
+            return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                .with_reason(SKIPPED_BY_REPO_CONFIG));
+        }
+        let args = &repo_config.apply(&self.args);
+        let branch = repo_config.branch.as_deref().unwrap_or(branch);
+        info!("Using branch {} for {}", branch, repo_url);
+        if !has_pin_files(local_path, &args.pin_paths, &args.include_extension) {
+            if args.fail_on_missing_workflows {
+                return Err(Box::from("No workflows to pin found"));
+            }
+            info!("No workflows to pin in {}, skipping", repo_url);
+            return Ok(RepoSummary::new(repository, RepoStatus::Skipped).with_reason(NO_WORKFLOWS));
+        }
+        // Without a client nothing is pushed, so no remote is needed
+        if github_client.is_some() {
+            match git_repo.resolve_remote(repo_url, args.remote_name.as_deref()) {
+                Ok(remote) => info!("Using remote {} for {}", remote, repository),
+                Err(e) => {
+                    error!("Failed to resolve remote: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
+
+        if args.local_path.is_some() {
+            // Branch off whatever is checked out, keeping uncommitted changes
+            if git_repo.checkout_branch(branch).is_err() {
+                if let Err(e) = git_repo.create_branch_at_head(branch) {
+                    error!("Failed to create branch: {}", e);
+                    return Err(e.into());
+                }
+            }
+        } else if git_repo.checkout_branch(branch).is_err() {
+            if let Err(e) = git_repo.create_branch(branch, default_branch) {
+                error!("Failed to create branch: {}", e);
+                return Err(e.into());
+            }
+        } else if args.overwrite_existing_branch {
+            if let Err(e) = git_repo.reset_branch_to_base(branch, default_branch) {
+                error!("Failed to reset branch: {}", e);
+                return Err(e.into());
+            }
+            info!(
+                "Reset branch {} of {} to {}",
+                branch, repository, default_branch
+            );
+        }
+
+        let actions_before = find_actions(local_path, args.max_workflow_size)?;
+        let unpinned_before = actions_before
+            .iter()
+            .filter(|action| !action.is_pinned())
+            .count();
+        let pins_before = PinCounts::of(actions_before.iter().map(|action| action.uses.as_str()));
+
+        // In update mode, pins younger than --min-pin-age keep their current commit
+        let mut aged = Vec::new();
+        let mut held = Vec::new();
+        if let Some(pin_ages) = pin_ages {
+            let pinned = find_pinned_actions(local_path, args.max_workflow_size)?;
+            aged = pin_ages
+                .age_all(&pinned)
+                .await
+                .map_err(|e| api_context("Failed to look up pin ages", e))?;
+            info!("Pin ages in {}: {:?}", repo_url, age_distribution(&aged));
+            if let Some(min_age) = args.min_pin_age {
+                let young = young_pins(&aged, min_age as i64, &args.deprecated_pin);
+                held = hold_back(local_path, &young)?;
+            }
+        }
+
+        let mode = RatchetMode::new(args.update, args.update_pins);
+        self.progress.phase(Phase::Pin);
+        let upgrade = match upgrade_workflows(
+            local_path,
+            &RatchetOptions {
+                path: &self.ratchet_path,
+                args: &args.ratchet_arg,
+                timeout: Duration::from_secs(args.subprocess_timeout),
+                comment_style: args.comment_style,
+                env: &self.proxy_env,
+                // Empty for --local-path runs that push nothing
+                token: (!args.no_ratchet_token && !token.is_empty()).then_some(token),
+                native: (args.backend == PinBackend::Native).then_some(self.tag_lookup.as_ref()),
+                batch_size: args.ratchet_batch_size,
+            },
+            mode,
+            args.max_workflow_size,
+            &args.pin_paths,
+            &args.include_extension,
+            self.pin_cache.as_ref(),
+        )
+        .await
+        {
+            Ok(upgrade) => upgrade,
+            Err(e) => {
+                error!("Failed to upgrade workflows: {}", e);
+                return Err(e);
+            }
+        };
+        let held_back = restore_held_pins(local_path, &held)?;
+        for workflow in &upgrade.skipped {
+            warn!("{}: {}", workflow.path.display(), workflow.reason);
+        }
+        if args.fail_on_branch_refs && !upgrade.branch_pins.is_empty() {
+            return Err(format!(
+                "{} action(s) pinned from a branch: {}",
+                upgrade.branch_pins.len(),
+                upgrade
+                    .branch_pins
+                    .iter()
+                    .map(|pin| format!(
+                        "{}@{} in {} line {}",
+                        pin.action, pin.branch, pin.file, pin.line
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+        // Nothing to pin: skip the pin verification, staging and the PR lookup
+        if !args.unclean && held_back == 0 && git_repo.changed_workflow_files()? == 0 {
+            info!(
+                "{} is already pinned, skipping after {:.1?}",
+                repo_url,
+                started.elapsed()
+            );
+            return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                .with_reason(ALREADY_PINNED)
+                .with_pins(PinStats::unchanged(pins_before))
+                .with_branch_pins(&upgrade.branch_pins));
+        }
+
+        let mut floating_pins = String::new();
+        if let Some(verifier) = verifier.as_mut() {
+            match find_pinned_actions(local_path, args.max_workflow_size) {
+                Ok(pinned) => match verifier.find_floating_pins(&pinned).await {
+                    Ok(floating) => floating_pins = render_floating_pins(&floating),
+                    Err(e) => warn!("Failed to verify pins: {}", e),
+                },
+                Err(e) => warn!("Failed to scan pinned actions: {}", e),
+            }
+        }
+
+        let mut source_issues = Vec::new();
+        if let Some(sources) = sources {
+            match find_actions(local_path, args.max_workflow_size) {
+                Ok(actions) => match sources.find_source_issues(&actions).await {
+                    Ok(issues) => source_issues = issues,
+                    Err(e) => warn!("Failed to verify action sources: {}", e),
+                },
+                Err(e) => warn!("Failed to scan actions: {}", e),
+            }
+        }
+
+        self.progress.phase(Phase::Stage);
+        let comment_only_changes = if args.include_comment_only_changes {
+            0
+        } else {
+            git_repo.revert_comment_only_changes()?
+        };
+        if comment_only_changes > 0 {
+            info!(
+                "Skipped {} comment-only change(s) in {}",
+                comment_only_changes, repo_url
+            );
+        }
+
+        if args.unclean {
+            let restored = restore_ratchet_comments_in(
+                local_path,
+                &args.pin_paths,
+                &args.include_extension,
+                args.max_workflow_size,
+            );
+            if restored > 0 {
+                info!("Restored {} ratchet comment(s) in {}", restored, repo_url);
+            }
+        }
+
+        let excluded_pins =
+            git_repo.revert_excluded_actions(&args.only_actions, &args.exclude_actions)?;
+        if excluded_pins > 0 {
+            info!(
+                "Left {} pin(s) of excluded actions out of {}",
+                excluded_pins, repo_url
+            );
+        }
+
+        let excluded_lines = git_repo.revert_ratchet_excluded_lines()?;
+        if excluded_lines > 0 {
+            info!(
+                "Left {} changed ratchet:exclude line(s) out of {}",
+                excluded_lines, repo_url
+            );
+        }
+
+        // Remove blank line changes from the changes
+        if let Err(e) =
+            git_repo.remove_blank_line_changes(args.preserve_newline, args.max_workflow_size)
+        {
+            error!("Failed to remove blank line changes: {}", e);
+            git_repo.stage_changes(args.preserve_newline, args.max_workflow_size)?;
+        }
+
+        let corrupted = git_repo.revert_corrupted_workflows()?;
+        for workflow in &corrupted {
+            warn!(
+                "{}: not committing {}, {}",
+                repository, workflow.path, workflow.reason
+            );
+        }
+        if args.strict_yaml && !corrupted.is_empty() {
+            return Err(format!(
+                "Ratchet corrupted {} workflow(s): {}",
+                corrupted.len(),
+                corrupted
+                    .iter()
+                    .map(|workflow| format!("{} ({})", workflow.path, workflow.reason))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+            .into());
+        }
+
+        let pinned_actions: Vec<_> = parse_pinned_actions(&git_repo.staged_diff()?)
+            .into_iter()
+            .filter(|pinned| {
+                is_action_selected(&pinned.action, &args.only_actions, &args.exclude_actions)
+            })
+            .collect();
+        let mut tag_mismatches = Vec::new();
+        if let (Some(verifier), Some(verification)) = (verifier, args.verify_pins) {
+            let checked = verifier.find_tag_mismatches(&pinned_actions).await;
+            if !checked.errors.is_empty() {
+                // An unchecked pin may not match its tag either
+                if verification == PinVerification::Fail {
+                    return Err(format!(
+                        "Failed to verify {} pin(s) against their tag: {}",
+                        checked.errors.len(),
+                        checked.errors.join(", ")
+                    )
+                    .into());
+                }
+                for error in &checked.errors {
+                    warn!("Failed to verify pin against its tag: {}", error);
+                }
+            }
+            tag_mismatches = checked.mismatches;
+            if verification == PinVerification::Fail && !tag_mismatches.is_empty() {
+                return Err(format!(
+                    "{} pin(s) do not match their tag: {}",
+                    tag_mismatches.len(),
+                    tag_mismatches
+                        .iter()
+                        .map(|mismatch| format!(
+                            "{}@{} in {} ({} is {})",
+                            mismatch.action,
+                            mismatch.sha,
+                            mismatch.file,
+                            mismatch.tag,
+                            mismatch.tag_sha
+                        ))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+                .into());
+            }
+        }
+
+        let actions_after = find_actions(local_path, args.max_workflow_size)?;
+        let pins = PinStats {
+            before: pins_before,
+            after: PinCounts::of(actions_after.iter().map(|action| action.uses.as_str())),
+        };
+        let remaining: Vec<_> = actions_after
+            .into_iter()
+            .filter(|action| !action.is_pinned())
+            .collect();
+        let actions_pinned = unpinned_before.saturating_sub(remaining.len());
+        info!(
+            "Unpinned actions in {}: {} -> {}",
+            repo_url,
+            unpinned_before,
+            remaining.len()
+        );
+        let files_changed = git_repo.changed_workflow_files()?;
+        if files_changed == 0 {
+            info!("No workflow changes left in {}, skipping", repo_url);
+            return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                .with_reason(NOTHING_TO_COMMIT)
+                .with_comment_only_changes(comment_only_changes)
+                .with_pins(pins)
+                .with_source_issues(&source_issues)
+                .with_tag_mismatches(&tag_mismatches)
+                .with_branch_pins(&upgrade.branch_pins)
+                .with_corrupted_workflows(&corrupted));
+        }
+        let outcome = Outcome {
+            files_changed,
+            actions_pinned,
+            comment_only_changes,
+            source_issues: &source_issues,
+            tag_mismatches: &tag_mismatches,
+            branch_pins: &upgrade.branch_pins,
+            corrupted: &corrupted,
+        };
+
+        let Some(github_client) = github_client else {
+            if args.dry_run {
+                info!("Dry run: would commit to branch {} of {}", branch, repo_url);
+                let patch = self.write_dry_run_patch(&git_repo, mode, repository)?;
+                return Ok(outcome
+                    .summary(repository, RepoStatus::DryRun, pins)
+                    .with_changed_files(git_repo.changed_workflow_paths()?)
+                    .with_patch(patch));
+            }
+            self.progress.phase(Phase::Push);
+            return match self.commit(&git_repo, mode) {
+                Ok(()) => {
+                    info!("Committed the changes to branch {} of {}", branch, repo_url);
+                    Ok(outcome.summary(repository, RepoStatus::Committed, pins))
+                }
+                Err(GitError::NothingToStage) => {
+                    info!("No changes to commit for {}", repo_url);
+                    Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                        .with_reason(&GitError::NothingToStage.to_string())
+                        .with_pins(PinStats::unchanged(pins_before))
+                        .with_corrupted_workflows(&corrupted))
+                }
+                Err(e) => {
+                    error!("Failed to commit changes: {}", e);
+                    Err(e.into())
+                }
+            };
+        };
+
+        let pin_changes = git_repo.staged_pin_changes()?;
+        let head = pr_head(fork, branch);
+        let content = build_pr_content(
+            args,
+            &self.pr_body,
+            &head,
+            default_branch,
+            &StagedChanges {
+                repository,
+                run_date,
+                files_changed,
+                pin_changes: &pin_changes,
+                pinned_actions: &pinned_actions,
+                unpinned_before,
+                remaining: &remaining,
+                floating_pins: &floating_pins,
+                tag_mismatches: &tag_mismatches,
+                source_issues: &source_issues,
+                comment_only_changes,
+                aged: &aged,
+                held_back,
+                upgrade: &upgrade,
+            },
+        );
+
+        let mut existing_pr = match github_client.find_existing_pr(&head).await {
+            Ok(pr) => pr,
+            Err(e) => {
+                error!("Failed to check existing PR: {}", e);
+                return Err(e);
+            }
+        };
+        // A PR that was closed without merging is reopened instead of opening a duplicate
+        let closed_pr = match existing_pr {
+            None if args.reopen_closed => github_client
+                .find_closed_pr(&head)
+                .await?
+                .filter(|closed| !closed.merged)
+                .map(|closed| closed.pr),
+            _ => None,
+        };
+        if args.dry_run {
+            let action = match (&existing_pr, &closed_pr) {
+                (Some(pr), _) => format!("update PR #{}", pr.number),
+                (None, Some(pr)) => format!("reopen PR #{}", pr.number),
+                (None, None) if content.draft => String::from("create a draft PR"),
+                (None, None) => String::from("create a PR"),
+            };
+            let action = match (&existing_pr, content.auto_merge) {
+                (None, Some(method)) if closed_pr.is_none() => {
+                    format!("{} and enable {} auto-merge", action, method)
+                }
+                _ => action,
+            };
+            info!(
+                "Dry run: would {} for {} from branch {}",
+                action, repository, branch
+            );
+            if let Some(dir) = &args.pr_preview_dir {
+                let existing = existing_pr.as_ref().map(|pr| pr.number);
+                let path = write_preview(dir, repository, &content, existing)?;
+                info!("Wrote PR preview of {} to {}", repository, path.display());
+            }
+            let patch = self.write_dry_run_patch(&git_repo, mode, repository)?;
+            return Ok(outcome
+                .summary(repository, RepoStatus::DryRun, pins)
+                .with_changed_files(git_repo.changed_workflow_paths()?)
+                .with_patch(patch));
+        }
+
+        if let Some(approver) = &self.approver {
+            match approver.approve(repository, &git_repo.staged_diff()?) {
+                Approval::Push => {}
+                Approval::Skip => {
+                    info!("{}: {}", repo_url, SKIPPED_BY_USER);
+                    return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                        .with_reason(SKIPPED_BY_USER)
+                        .with_pins(PinStats::unchanged(pins_before))
+                        .with_corrupted_workflows(&corrupted));
+                }
+                // Left to the cancellation check below, like Ctrl-C
+                Approval::Quit => self.cancellation.cancel(),
+            }
+        }
+
+        // Never push a branch the run may not get to open the PR for
+        if self.cancellation.is_cancelled() {
+            warn!("{}: {}, not pushing", repo_url, DEFERRED_INTERRUPTED);
+            return Ok(outcome
+                .summary(
+                    repository,
+                    RepoStatus::Deferred,
+                    PinStats::unchanged(pins_before),
+                )
+                .with_reason(DEFERRED_INTERRUPTED));
+        }
+
+        // Taken before pushing and given back when no PR comes out of it
+        let mut is_update = existing_pr.is_some() || closed_pr.is_some();
+        if !budget.try_consume(is_update) {
+            warn!("{}: {}", repo_url, DEFERRED_BUDGET_EXHAUSTED);
+            return Ok(outcome
+                .summary(
+                    repository,
+                    RepoStatus::Deferred,
+                    PinStats::unchanged(pins_before),
+                )
+                .with_reason(DEFERRED_BUDGET_EXHAUSTED));
+        }
+
+        self.progress.phase(Phase::Push);
+        match self.commit(&git_repo, mode) {
+            Ok(()) => {}
+            // The workflows ended up identical to HEAD after all
+            Err(GitError::NothingToStage) => {
+                info!("No changes to commit for {}", repo_url);
+                budget.refund(is_update);
+                return Ok(RepoSummary::new(repository, RepoStatus::Skipped)
+                    .with_reason(&GitError::NothingToStage.to_string())
+                    .with_pins(PinStats::unchanged(pins_before))
+                    .with_corrupted_workflows(&corrupted));
+            }
+            Err(e) => {
+                error!("Failed to commit changes: {}", e);
+                budget.refund(is_update);
+                return Err(e.into());
+            }
+        }
+
+        // GitHub refuses to reopen a PR once its branch was force-pushed, so reopen first
+        if let Some(closed) = closed_pr {
+            match github_client.reopen_pull_request(closed.number).await {
+                Ok(pr) => {
+                    info!("Reopened closed PR #{} for {}", pr.number, repo_url);
+                    existing_pr = Some(pr);
+                }
+                Err(e) => {
+                    warn!(
+                        "Failed to reopen PR #{}, creating a new PR instead: {}",
+                        closed.number, e
+                    );
+                    // The new PR counts against the budget, the reopen did not
+                    budget.refund(is_update);
+                    is_update = false;
+                    if !budget.try_consume(is_update) {
+                        warn!("{}: {}", repo_url, DEFERRED_BUDGET_EXHAUSTED);
+                        return Ok(outcome
+                            .summary(
+                                repository,
+                                RepoStatus::Deferred,
+                                PinStats::unchanged(pins_before),
+                            )
+                            .with_reason(DEFERRED_BUDGET_EXHAUSTED));
+                    }
+                }
+            }
+        }
+
+        let pushed: Result<bool, Box<dyn Error>> = async {
+            // Pin updates of large repositories can take long enough for an
+            // installation token to expire, refresh it right before pushing
+            git_repo.set_token(&self.credentials.token().await?);
+            // Pushing an identical tree to the branch of an open PR only retriggers its CI
+            let unchanged = existing_pr.is_some()
+                && !args.force_push_always
+                && git_repo.remote_branch_matches(branch)?;
+            if unchanged {
+                info!(
+                    "Branch {} of {} already has these changes, skipping push",
+                    branch, repo_url
+                );
+            } else if let Err(e) = push_with_retry(&git_repo, branch, args.max_retries).await {
+                error!("Failed to push changes to branch {}: {}", branch, e);
+                return Err(e.into());
+            }
+            Ok(unchanged)
+        }
+        .await;
+        let unchanged = match pushed {
+            Ok(unchanged) => unchanged,
+            Err(e) => {
+                budget.refund(is_update);
+                return Err(e);
+            }
+        };
+
+        self.progress.phase(Phase::PullRequest);
+        match existing_pr {
+            None => match github_client.create_pull_request(&content).await {
+                Ok(pr) => {
+                    if pr.draft {
+                        info!("Created draft PR for {}: {}", repo_url, pr.html_url);
+                    } else {
+                        info!("Created PR for {}: {}", repo_url, pr.html_url);
+                    }
+                    if let Some(method) = content.auto_merge {
+                        match github_client.enable_auto_merge(pr.number, method).await {
+                            Ok(()) => info!("Enabled {} auto-merge on PR #{}", method, pr.number),
+                            Err(e) => warn!(
+                                "Could not enable {} auto-merge on PR #{}, the repository may not allow auto-merge or this merge method: {}",
+                                method, pr.number, e
+                            ),
+                        }
+                    }
+                    Ok(outcome
+                        .summary(repository, RepoStatus::Created, pins)
+                        .with_pr(&pr))
+                }
+                Err(e) => {
+                    error!("Failed to create PR: {}", e);
+                    budget.refund(is_update);
+                    Err(e)
+                }
+            },
+            Some(pr) => {
+                if !unchanged {
+                    info!("Updated existing PR for {}", repo_url);
+                }
+                match github_client
+                    .update_pull_request(pr.number, &content.title, &content.body)
+                    .await
+                {
+                    Ok(true) => info!("Refreshed the description of PR #{}", pr.number),
+                    Ok(false) => info!("Description of PR #{} is up to date", pr.number),
+                    Err(e) => warn!(
+                        "Failed to update the description of PR #{}: {}",
+                        pr.number, e
+                    ),
+                }
+                if let Err(e) = github_client.add_missing_labels(pr.number).await {
+                    warn!("Failed to label PR #{}: {}", pr.number, e);
+                }
+                if pr.draft && args.ready {
+                    match github_client.mark_ready_for_review(pr.number).await {
+                        Ok(()) => info!("Marked PR #{} ready for review", pr.number),
+                        Err(e) => warn!("Failed to mark PR #{} ready for review: {}", pr.number, e),
+                    }
+                } else if pr.draft {
+                    info!("PR #{} is a draft, leaving it as draft", pr.number);
+                }
+                Ok(outcome
+                    .summary(repository, RepoStatus::Updated, pins)
+                    .with_pr(&pr))
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+use std::{
+    collections::BTreeMap,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Serialize;
+
+use crate::{io::write_atomically, summary::RunSummary};
+
+// Written next to the patches, maps every repository to its patch
+pub const DRY_RUN_INDEX: &str = "index.json";
+
+// Entry of index.json
+#[derive(Debug, PartialEq, Eq, Serialize)]
+struct IndexEntry<'a> {
+    patch: &'a str,
+    files_changed: usize,
+    actions_pinned: usize,
+}
+
+// Name of the patch of <repository> (owner/repo) in --dry-run-output: owner_repo.patch
+pub fn patch_file_name(repository: &str) -> String {
+    format!("{}.patch", repository.replace('/', "_"))
+}
+
+// Write <patch> of <repository> to <dir>, creating <dir> when it is missing
+pub fn write_patch(dir: &str, repository: &str, patch: &str) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+    let path = Path::new(dir).join(patch_file_name(repository));
+    write_atomically(&path, patch)?;
+    Ok(path)
+}
+
+// Write <dir>/index.json with the repositories of <summary> that got a patch
+pub fn write_index(dir: &str, summary: &RunSummary) -> Result<(), Box<dyn Error>> {
+    let index: BTreeMap<&str, IndexEntry> = summary
+        .repositories
+        .iter()
+        .filter_map(|entry| {
+            let patch = entry.patch.as_deref()?;
+            Some((
+                entry.repository.as_str(),
+                IndexEntry {
+                    patch,
+                    files_changed: entry.files_changed,
+                    actions_pinned: entry.actions_pinned,
+                },
+            ))
+        })
+        .collect();
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+    let json = serde_json::to_string_pretty(&index)? + "\n";
+    write_atomically(&Path::new(dir).join(DRY_RUN_INDEX), &json)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::summary::{RepoStatus, RepoSummary};
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_write_patches_and_index() {
+        let dir = tempdir().unwrap();
+        let out = dir.path().join("patches");
+        let out = out.to_str().unwrap();
+
+        let path = write_patch(out, "org/app", "old").unwrap();
+        assert_eq!(path, dir.path().join("patches/org_app.patch"));
+        write_patch(out, "org/app", "new").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "new");
+
+        let mut summary = RunSummary::default();
+        summary.record(
+            RepoSummary::new("org/app", RepoStatus::DryRun)
+                .with_files_changed(1)
+                .with_actions_pinned(2)
+                .with_patch(Some(patch_file_name("org/app"))),
+        );
+        summary.record(RepoSummary::new("org/pinned", RepoStatus::Skipped));
+        write_index(out, &summary).unwrap();
+        assert_eq!(
+            fs::read_to_string(dir.path().join("patches/index.json")).unwrap(),
+            "{\n  \"org/app\": {\n    \"patch\": \"org_app.patch\",\n    \"files_changed\": 1,\n    \"actions_pinned\": 2\n  }\n}\n"
+        );
+        let mut files: Vec<_> = fs::read_dir(out)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["index.json", "org_app.patch"]);
+    }
+}
@@ -0,0 +1,109 @@
+use std::time::Duration;
+
+use log::info;
+use tokio::time::Instant;
+
+use crate::github::{ForkRef, Forks};
+
+// How often --fork checks whether a new fork can be cloned, and for how long
+pub const FORK_POLL_INTERVAL: Duration = Duration::from_secs(2);
+pub const FORK_READY_TIMEOUT: Duration = Duration::from_secs(300);
+
+// Fork the repository of <client> into <organization>, or the account of the token,
+// and wait until <branch> (the default branch) can be fetched from the fork. An
+// existing fork is reused, it is ready right away.
+pub async fn ensure_fork<C: Forks + Sync + ?Sized>(
+    client: &C,
+    organization: Option<&str>,
+    branch: &str,
+    interval: Duration,
+    timeout: Duration,
+) -> Result<ForkRef, Box<dyn std::error::Error>> {
+    let fork = client.create_fork(organization).await?;
+    let deadline = Instant::now() + timeout;
+    while !client.fork_has_branch(&fork, branch).await? {
+        if Instant::now() >= deadline {
+            return Err(Box::from(format!(
+                "Fork {}/{} is not ready after {:?}",
+                fork.owner, fork.repo, timeout
+            )));
+        }
+        info!("Waiting for fork {}/{} to be ready", fork.owner, fork.repo);
+        tokio::time::sleep(interval).await;
+    }
+    Ok(fork)
+}
+
+// Head of the pull request of <branch>: <fork owner>:<branch> when the branch is
+// pushed to <fork>, which GitHub needs to find it in the fork
+pub fn pr_head(fork: Option<&ForkRef>, branch: &str) -> String {
+    match fork {
+        Some(fork) => format!("{}:{}", fork.owner, branch),
+        None => branch.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::MockForks;
+    use mockall::{predicate::*, Sequence};
+
+    fn fork() -> ForkRef {
+        ForkRef {
+            owner: String::from("bot"),
+            repo: String::from("app"),
+        }
+    }
+
+    fn forks(ready_after: usize) -> MockForks {
+        let mut client = MockForks::new();
+        client
+            .expect_create_fork()
+            .withf(|organization| *organization == Some("bots"))
+            .times(1)
+            .returning(|_| Ok(fork()));
+        let mut sequence = Sequence::new();
+        client
+            .expect_fork_has_branch()
+            .with(eq(fork()), eq("main"))
+            .times(ready_after)
+            .in_sequence(&mut sequence)
+            .returning(|_, _| Ok(false));
+        client.expect_fork_has_branch().returning(|_, _| Ok(true));
+        client
+    }
+
+    #[tokio::test]
+    async fn test_ensure_fork_waits_until_ready() {
+        let fork = ensure_fork(
+            &forks(2),
+            Some("bots"),
+            "main",
+            Duration::from_millis(1),
+            Duration::from_secs(5),
+        )
+        .await
+        .unwrap();
+        assert_eq!(pr_head(Some(&fork), "ratchet-pin"), "bot:ratchet-pin");
+        assert_eq!(pr_head(None, "ratchet-pin"), "ratchet-pin");
+    }
+
+    #[tokio::test]
+    async fn test_ensure_fork_gives_up() {
+        let mut client = MockForks::new();
+        client.expect_create_fork().returning(|_| Ok(fork()));
+        client.expect_fork_has_branch().returning(|_, _| Ok(false));
+
+        let err = ensure_fork(
+            &client,
+            None,
+            "main",
+            Duration::from_millis(1),
+            Duration::from_millis(10),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(err.to_string(), "Fork bot/app is not ready after 10ms");
+    }
+}
@@ -0,0 +1,356 @@
+use std::{collections::HashMap, error::Error, fs, path::Path};
+
+use chrono::{DateTime, Utc};
+use log::{debug, info, warn};
+
+use crate::{
+    github::TagLookup,
+    scan::ActionUse,
+    workflow::{ActionRef, UsesLine},
+};
+
+// Age buckets of the PR body, upper bounds in days (exclusive)
+const AGE_BUCKETS: &[(i64, &str)] = &[
+    (30, "< 30 days"),
+    (90, "30-90 days"),
+    (365, "90-365 days"),
+    (i64::MAX, "> 365 days"),
+];
+const UNKNOWN_AGE: &str = "unknown";
+
+// A pinned action and the age of its pinned commit, None when the date is unknown
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgedPin {
+    pub action: ActionUse,
+    pub age_days: Option<i64>,
+}
+
+// Looks up the commit dates of pinned SHAs.
+// Dates are cached per action repository and SHA, the same pin found in
+// several files or repositories is only looked up once.
+pub struct PinAges<'a> {
+    lookup: &'a (dyn TagLookup + Sync),
+    now: DateTime<Utc>,
+    cache: HashMap<String, Option<i64>>,
+}
+
+impl<'a> PinAges<'a> {
+    pub fn new(lookup: &'a (dyn TagLookup + Sync), now: DateTime<Utc>) -> Self {
+        PinAges {
+            lookup,
+            now,
+            cache: HashMap::new(),
+        }
+    }
+
+    pub async fn age_days(&mut self, action: &ActionUse) -> Result<Option<i64>, Box<dyn Error>> {
+        let (owner, repo, sha) = action
+            .parts()
+            .ok_or_else(|| format!("{} is not a pinned action", action.uses))?;
+        let key = format!("{}/{}@{}", owner, repo, sha);
+        if let Some(age) = self.cache.get(&key) {
+            return Ok(*age);
+        }
+
+        let age = self
+            .lookup
+            .commit_date(owner, repo, sha)
+            .await?
+            .map(|date| (self.now - date).num_days());
+        debug!("Pin {} is {:?} days old", key, age);
+        self.cache.insert(key, age);
+        Ok(age)
+    }
+
+    pub async fn age_all(&mut self, actions: &[ActionUse]) -> Result<Vec<AgedPin>, Box<dyn Error>> {
+        let mut pins = Vec::new();
+        for action in actions {
+            pins.push(AgedPin {
+                action: action.clone(),
+                age_days: self.age_days(action).await?,
+            });
+        }
+        Ok(pins)
+    }
+}
+
+// Whether <action> matches --deprecated-pin: "owner/repo" matches every pin
+// of the action, "owner/repo@sha" a single commit
+pub fn is_deprecated(action: &ActionUse, deprecated: &[String]) -> bool {
+    let (owner, repo, sha) = match action.parts() {
+        Some(parts) => parts,
+        None => return false,
+    };
+    let name = format!("{}/{}", owner, repo);
+    deprecated.iter().any(|entry| match entry.split_once('@') {
+        Some((entry_name, entry_sha)) => entry_name == name && entry_sha == sha,
+        None => *entry == name,
+    })
+}
+
+// Pins that must keep their current commit: younger than <min_age_days> and
+// not deprecated. Pins of unknown age are not held back.
+pub fn young_pins(pins: &[AgedPin], min_age_days: i64, deprecated: &[String]) -> Vec<ActionUse> {
+    pins.iter()
+        .filter(|pin| matches!(pin.age_days, Some(age) if age < min_age_days))
+        .filter(|pin| !is_deprecated(&pin.action, deprecated))
+        .map(|pin| pin.action.clone())
+        .collect()
+}
+
+// A workflow line whose update is held back, as it was before ratchet ran
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeldPin {
+    pub action: ActionUse,
+    original: String,
+}
+
+// Remember the current lines of <young> pins so their update can be undone
+pub fn hold_back(local_path: &str, young: &[ActionUse]) -> Result<Vec<HeldPin>, Box<dyn Error>> {
+    let mut held = Vec::new();
+    for action in young {
+        let content = fs::read_to_string(Path::new(local_path).join(&action.file))?;
+        if let Some(original) = content.split_inclusive('\n').nth(action.line - 1) {
+            held.push(HeldPin {
+                action: action.clone(),
+                original: original.to_string(),
+            });
+        }
+    }
+    Ok(held)
+}
+
+// Put the held back SHAs back after `ratchet update` rewrote them.
+// A line is only restored when it still references the same action,
+// returns the number of restored pins.
+pub fn restore_held_pins(local_path: &str, held: &[HeldPin]) -> Result<usize, Box<dyn Error>> {
+    let mut by_file: HashMap<&str, Vec<&HeldPin>> = HashMap::new();
+    for pin in held {
+        by_file.entry(&pin.action.file).or_default().push(pin);
+    }
+
+    let mut restored = 0;
+    for (file, pins) in by_file {
+        let path = Path::new(local_path).join(file);
+        let content = fs::read_to_string(&path)?;
+        let mut lines: Vec<String> = content
+            .split_inclusive('\n')
+            .map(|line| line.to_string())
+            .collect();
+        for pin in pins {
+            let index = pin.action.line - 1;
+            let held = match lines.get(index) {
+                Some(line) if *line == pin.original => continue,
+                Some(line) => held_line(line, &pin.action),
+                None => None,
+            };
+            match held {
+                Some(held) => {
+                    lines[index] = held;
+                    restored += 1;
+                }
+                None => warn!(
+                    "Could not hold back {} in {}:{}, the line was moved",
+                    pin.action.uses, file, pin.action.line
+                ),
+            }
+        }
+        fs::write(&path, lines.concat())?;
+    }
+    if restored > 0 {
+        info!("Held back {} pin update(s) in {}", restored, local_path);
+    }
+    Ok(restored)
+}
+
+// <line> with the SHA of <action> put back, None when it references another action
+fn held_line(line: &str, action: &ActionUse) -> Option<String> {
+    let held = ActionRef::parse(&action.uses)?;
+    let updated = UsesLine::parse(line, action.line)?;
+    if updated.action_ref()?.action() != held.action() {
+        return None;
+    }
+    Some(updated.with_ref(held.reference?))
+}
+
+// Number of pins per age bucket, buckets without pins are left out
+pub fn age_distribution(pins: &[AgedPin]) -> Vec<(&'static str, usize)> {
+    let mut counts: Vec<(&'static str, usize)> = AGE_BUCKETS
+        .iter()
+        .map(|(_, label)| (*label, 0))
+        .chain(std::iter::once((UNKNOWN_AGE, 0)))
+        .collect();
+    for pin in pins {
+        let index = match pin.age_days {
+            Some(age) => AGE_BUCKETS
+                .iter()
+                .position(|(max, _)| age < *max)
+                .unwrap_or(AGE_BUCKETS.len() - 1),
+            None => AGE_BUCKETS.len(),
+        };
+        counts[index].1 += 1;
+    }
+    counts.retain(|(_, count)| *count > 0);
+    counts
+}
+
+// Section appended to the PR body with the age of the pins before this PR
+pub fn render_age_distribution(pins: &[AgedPin], held_back: usize) -> String {
+    if pins.is_empty() {
+        return String::new();
+    }
+    let mut section =
+        String::from("\n\n### Pin ages\n\n| Age of the pinned commit | Pins |\n| --- | --- |\n");
+    for (label, count) in age_distribution(pins) {
+        section.push_str(&format!("| {} | {} |\n", label, count));
+    }
+    if held_back > 0 {
+        section.push_str(&format!(
+            "\n{} pin update(s) held back until the pinned commit is older than `--min-pin-age`.\n",
+            held_back
+        ));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::MockTagLookup;
+    use chrono::Duration;
+    use mockall::predicate::*;
+    use tempfile::tempdir;
+
+    const OLD_SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+    const YOUNG_SHA: &str = "0ad4b8fadaa221de15dcec353f45205ec38ea70b";
+    const NEW_SHA: &str = "11bd71901bbe5b1630ceea73d27597364c9af683";
+
+    fn pinned(line: usize, uses: &str) -> ActionUse {
+        ActionUse {
+            file: ".github/workflows/ci.yml".to_string(),
+            line,
+            uses: uses.to_string(),
+            excluded: false,
+        }
+    }
+
+    fn lookup(now: DateTime<Utc>) -> MockTagLookup {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_commit_date()
+            .with(eq("actions"), eq("checkout"), eq(OLD_SHA))
+            .times(1)
+            .returning(move |_, _, _| Ok(Some(now - Duration::days(120))));
+        lookup
+            .expect_commit_date()
+            .with(eq("actions"), eq("setup-node"), eq(YOUNG_SHA))
+            .times(1)
+            .returning(move |_, _, _| Ok(Some(now - Duration::days(5))));
+        lookup
+            .expect_commit_date()
+            .with(eq("actions"), eq("cache"), always())
+            .returning(|_, _, _| Ok(None));
+        lookup
+    }
+
+    fn actions() -> Vec<ActionUse> {
+        vec![
+            pinned(5, &format!("actions/checkout@{}", OLD_SHA)),
+            pinned(6, &format!("actions/setup-node@{}", YOUNG_SHA)),
+            pinned(7, &format!("actions/cache@{}", YOUNG_SHA)),
+            // Served from the cache
+            pinned(9, &format!("actions/checkout@{}", OLD_SHA)),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_young_pins_honor_threshold() {
+        let now = Utc::now();
+        let lookup = lookup(now);
+        let pins = PinAges::new(&lookup, now)
+            .age_all(&actions())
+            .await
+            .unwrap();
+        assert_eq!(
+            pins.iter().map(|pin| pin.age_days).collect::<Vec<_>>(),
+            [Some(120), Some(5), None, Some(120)]
+        );
+
+        let held: Vec<usize> = young_pins(&pins, 30, &[])
+            .iter()
+            .map(|action| action.line)
+            .collect();
+        assert_eq!(held, [6]);
+        assert!(young_pins(&pins, 3, &[]).is_empty());
+        assert_eq!(young_pins(&pins, 365, &[]).len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_deprecated_pins_always_update() {
+        let now = Utc::now();
+        let lookup = lookup(now);
+        let pins = PinAges::new(&lookup, now)
+            .age_all(&actions())
+            .await
+            .unwrap();
+
+        assert!(young_pins(&pins, 30, &[String::from("actions/setup-node")]).is_empty());
+        assert!(young_pins(&pins, 30, &[format!("actions/setup-node@{}", YOUNG_SHA)]).is_empty());
+        assert_eq!(
+            young_pins(&pins, 30, &[format!("actions/setup-node@{}", OLD_SHA)]).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_restore_held_pins() {
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        let path = workflows.join("ci.yml");
+        let before = format!(
+            "steps:\n  - uses: actions/checkout@{} # ratchet:actions/checkout@v4\n  - uses: actions/setup-node@{} # ratchet:actions/setup-node@v4\n",
+            OLD_SHA, YOUNG_SHA
+        );
+        fs::write(&path, &before).unwrap();
+        let local_path = dir.path().to_str().unwrap();
+
+        let young = [pinned(3, &format!("actions/setup-node@{}", YOUNG_SHA))];
+        let held = hold_back(local_path, &young).unwrap();
+        fs::write(
+            &path,
+            before.replace(OLD_SHA, NEW_SHA).replace(YOUNG_SHA, NEW_SHA),
+        )
+        .unwrap();
+
+        assert_eq!(restore_held_pins(local_path, &held).unwrap(), 1);
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            before.replace(OLD_SHA, NEW_SHA)
+        );
+    }
+
+    #[test]
+    fn test_render_age_distribution() {
+        let pins: Vec<AgedPin> = [Some(2), Some(45), Some(45), Some(400), None]
+            .into_iter()
+            .map(|age_days| AgedPin {
+                action: pinned(1, &format!("actions/checkout@{}", OLD_SHA)),
+                age_days,
+            })
+            .collect();
+        assert_eq!(
+            age_distribution(&pins),
+            [
+                ("< 30 days", 1),
+                ("30-90 days", 2),
+                ("> 365 days", 1),
+                ("unknown", 1)
+            ]
+        );
+        let section = render_age_distribution(&pins, 1);
+        assert!(section.contains("| 30-90 days | 2 |"));
+        assert!(section.contains("1 pin update(s) held back"));
+        assert!(render_age_distribution(&[], 0).is_empty());
+    }
+}
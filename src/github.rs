@@ -1,64 +1,2249 @@
-use octocrab::{models::pulls::PullRequest, Octocrab};
+use std::{
+    future::Future,
+    time::{Duration, SystemTime},
+};
 
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use clap::ValueEnum;
+use http::StatusCode;
+use log::{info, warn};
+#[cfg(test)]
+use mockall::automock;
+use octocrab::{
+    models::{commits::GithubCommitStatus, pulls::PullRequest, repos::Object},
+    params::{
+        repos::{Reference, Type},
+        State,
+    },
+    service::middleware::retry::RetryConfig,
+    AuthState, Octocrab,
+};
+use serde::Serialize;
+
+use crate::{
+    discovery::{RepoListing, Visibility},
+    pr_content::PullRequestContent,
+    proxy::{proxied_octocrab, ProxyConfig},
+};
+
+pub const DEFAULT_GITHUB_URL: &str = "https://github.com";
+
+// Default for --max-retries
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+
+// Delay before the first retry, doubled for every next attempt
+pub const RETRY_BASE_DELAY: Duration = Duration::from_millis(500);
+
+// Clone and API base URLs of a GitHub instance, all derived from --github-url
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHubUrls {
+    pub clone_base: String,
+    pub api_base: String,
+    // The GraphQL endpoint is <graphql_base>/graphql
+    pub graphql_base: String,
+    // --https-proxy or the proxy environment variables, every API client connects
+    // through it
+    pub proxy: Option<ProxyConfig>,
+}
+
+impl GitHubUrls {
+    // github.com uses api.github.com, GitHub Enterprise Server serves the API under /api/v3
+    // and GraphQL under /api/graphql
+    pub fn new(github_url: &str) -> Self {
+        let clone_base = github_url.trim_end_matches('/').to_string();
+        let (api_base, graphql_base) = if clone_base == DEFAULT_GITHUB_URL {
+            (
+                String::from("https://api.github.com"),
+                String::from("https://api.github.com"),
+            )
+        } else {
+            (
+                format!("{}/api/v3", clone_base),
+                format!("{}/api", clone_base),
+            )
+        };
+        GitHubUrls {
+            clone_base,
+            api_base,
+            graphql_base,
+            proxy: None,
+        }
+    }
+
+    pub fn with_proxy(mut self, proxy: Option<ProxyConfig>) -> Self {
+        self.proxy = proxy;
+        self
+    }
+}
+
+#[derive(Clone)]
 pub struct GitHubClient {
     octocrab: Octocrab,
+    graphql: Octocrab,
     owner: String,
     repo: String,
+    max_retries: u32,
+    retry_base_delay: Duration,
+    labels: Vec<String>,
+    create_missing_labels: bool,
 }
 
 impl GitHubClient {
-    pub fn new(owner: String, repo: String, token: String) -> Self {
-        let octocrab = Octocrab::builder().personal_token(token).build().unwrap();
+    pub fn new(owner: String, repo: String, token: String, urls: &GitHubUrls) -> Self {
+        if let Some(proxy) = &urls.proxy {
+            let client = |base: &str| {
+                proxied_octocrab(
+                    proxy,
+                    base,
+                    Some(format!("Bearer {}", token)),
+                    AuthState::None,
+                )
+                .unwrap()
+            };
+            return GitHubClient::with_octocrab(
+                client(&urls.api_base),
+                client(&urls.graphql_base),
+                owner,
+                repo,
+            );
+        }
+        let mut builder = Octocrab::builder();
+        // Retries are handled by with_retry, which only retries transient errors
+        builder.add_retry_config(RetryConfig::None);
+        let octocrab = builder
+            .base_uri(urls.api_base.as_str())
+            .unwrap()
+            .personal_token(token.clone())
+            .build()
+            .unwrap();
+        // octocrab prefixes every request with the path of its base URI, so GraphQL
+        // needs its own client on GitHub Enterprise Server where it is not under /api/v3
+        let graphql = Octocrab::builder()
+            .base_uri(urls.graphql_base.as_str())
+            .unwrap()
+            .personal_token(token)
+            .build()
+            .unwrap();
+        GitHubClient::with_octocrab(octocrab, graphql, owner, repo)
+    }
+
+    fn with_octocrab(octocrab: Octocrab, graphql: Octocrab, owner: String, repo: String) -> Self {
         GitHubClient {
             octocrab,
+            graphql,
             owner,
             repo,
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay: RETRY_BASE_DELAY,
+            labels: Vec::new(),
+            create_missing_labels: false,
         }
     }
 
-    // Make a request to the GitHub API to create a pull request
-    // with the given branch, default branch, and pull request body
-    // Return the created pull request
-    pub async fn create_pull_request(
+    // Content of <path> on the default branch, None when the file does not exist
+    pub async fn raw_file(&self, path: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let response = self
+            .octocrab
+            .repos(&self.owner, &self.repo)
+            .raw_file(String::from("HEAD"), path)
+            .await?;
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                Ok(Some(self.octocrab.body_to_string(response).await?))
+            }
+            status => Err(Box::from(format!(
+                "Failed to read {} from {}/{}: {}",
+                path, self.owner, self.repo, status
+            ))),
+        }
+    }
+
+    // <owner>:<branch> as the head filter of the pulls API. A branch of a fork is
+    // passed as <fork owner>:<branch> already, a git branch name has no colon.
+    fn head_filter(&self, branch: &str) -> String {
+        if branch.contains(':') {
+            branch.to_string()
+        } else {
+            format!("{}:{}", self.owner, branch)
+        }
+    }
+
+    pub fn with_max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    // Labels applied to the pull requests, labels that do not exist in the repository
+    // are created when <create_missing> is set and skipped with a warning otherwise
+    pub fn with_labels(mut self, labels: Vec<String>, create_missing: bool) -> Self {
+        self.labels = labels;
+        self.create_missing_labels = create_missing;
+        self
+    }
+
+    // Run the GraphQL <mutation> on pull request <number>. The node ID of the pull
+    // request is looked up and passed as $id, next to <variables>.
+    async fn pull_request_mutation(
         &self,
-        branch: &str,
-        default_branch: String,
-        pr_body: String,
-    ) -> Result<PullRequest, Box<dyn std::error::Error>> {
+        number: u64,
+        mutation: &str,
+        mut variables: serde_json::Value,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         let pr = self
             .octocrab
             .pulls(&self.owner, &self.repo)
-            .create("ci: pin versions of actions", branch, default_branch)
-            .body(pr_body)
-            .maintainer_can_modify(true)
-            .send()
+            .get(number)
+            .await?;
+        let node_id = pr
+            .node_id
+            .ok_or_else(|| format!("PR #{} has no node ID", number))?;
+        variables["id"] = serde_json::Value::String(node_id);
+        let response: serde_json::Value = self
+            .graphql
+            .graphql(&serde_json::json!({
+                "query": mutation,
+                "variables": variables,
+            }))
+            .await?;
+        match response.get("errors").and_then(|errors| errors.as_array()) {
+            Some(errors) if !errors.is_empty() => {
+                let messages: Vec<&str> = errors
+                    .iter()
+                    .filter_map(|error| error.get("message").and_then(|m| m.as_str()))
+                    .collect();
+                Err(Box::from(messages.join("; ")))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Run <call> and retry it with exponential backoff while it fails with a
+    // transient error, other errors are returned immediately
+    async fn with_retry<T, F, Fut>(&self, operation: &str, call: F) -> Result<T, GitHubError>
+    where
+        F: Fn() -> Fut,
+        Fut: Future<Output = Result<T, octocrab::Error>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match call().await {
+                Ok(value) => return Ok(value),
+                Err(e) if attempt < self.max_retries && is_retryable(&e) => {
+                    attempt += 1;
+                    let delay = backoff_delay(self.retry_base_delay, attempt);
+                    warn!(
+                        "{} for {}/{} failed: {}, retry {}/{} in {:?}",
+                        operation, self.owner, self.repo, e, attempt, self.max_retries, delay
+                    );
+                    tokio::time::sleep(delay).await;
+                }
+                Err(e) => return Err(self.classify(e).await),
+            }
+        }
+    }
+
+    // <error> as a GitHubError, with the reset time of the rate limit when it was hit
+    async fn classify(&self, error: octocrab::Error) -> GitHubError {
+        match GitHubError::from(error) {
+            GitHubError::RateLimited {
+                message,
+                reset_at: None,
+            } => {
+                let reset_at = match self.octocrab.ratelimit().get().await {
+                    Ok(limit) => DateTime::from_timestamp(limit.resources.core.reset as i64, 0),
+                    Err(_) => None,
+                };
+                GitHubError::RateLimited { message, reset_at }
+            }
+            error => error,
+        }
+    }
+
+    // Client that is not bound to a single repository, used for organization wide calls
+    pub fn for_org(org: String, token: String, urls: &GitHubUrls) -> Self {
+        GitHubClient::new(org, String::new(), token, urls)
+    }
+
+    // Make a request to the GitHub API to get the user of the token, which fails when
+    // the token is invalid. Returns the scopes of a classic personal access token, None
+    // for fine-grained tokens, which have no scope header.
+    pub async fn token_scopes(&self) -> Result<Option<Vec<String>>, GitHubError> {
+        let response = self
+            .with_retry("Validate token", || async {
+                octocrab::map_github_error(self.octocrab._get("/user").await?).await
+            })
+            .await?;
+        Ok(response
+            .headers()
+            .get("x-oauth-scopes")
+            .and_then(|scopes| scopes.to_str().ok())
+            .map(|scopes| {
+                scopes
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|scope| !scope.is_empty())
+                    .map(String::from)
+                    .collect()
+            }))
+    }
+}
+
+// Color and description of the labels created by --create-missing-labels
+const NEW_LABEL_COLOR: &str = "ededed";
+const NEW_LABEL_DESCRIPTION: &str = "Created by ratchet-dispatcher";
+
+// GraphQL mutation that turns a draft pull request into a regular one
+const MARK_READY_FOR_REVIEW: &str = "mutation($id: ID!) { markPullRequestReadyForReview(input: {pullRequestId: $id}) { pullRequest { isDraft } } }";
+
+// GraphQL mutation that merges a pull request with $method once its checks pass
+const ENABLE_AUTO_MERGE: &str = "mutation($id: ID!, $method: PullRequestMergeMethod!) { enablePullRequestAutoMerge(input: {pullRequestId: $id, mergeMethod: $method}) { pullRequest { number } } }";
+
+// --auto-merge
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum MergeMethod {
+    Merge,
+    Squash,
+    Rebase,
+}
+
+impl MergeMethod {
+    // Value of the PullRequestMergeMethod GraphQL enum
+    fn graphql_name(self) -> &'static str {
+        match self {
+            MergeMethod::Merge => "MERGE",
+            MergeMethod::Squash => "SQUASH",
+            MergeMethod::Rebase => "REBASE",
+        }
+    }
+}
+
+impl std::fmt::Display for MergeMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            MergeMethod::Merge => "merge",
+            MergeMethod::Squash => "squash",
+            MergeMethod::Rebase => "rebase",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Minimal view of a pull request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequestRef {
+    pub number: u64,
+    pub html_url: String,
+    pub draft: bool,
+}
+
+impl From<PullRequest> for PullRequestRef {
+    fn from(pr: PullRequest) -> Self {
+        PullRequestRef {
+            number: pr.number,
+            html_url: pr.html_url.map(|url| url.to_string()).unwrap_or_default(),
+            draft: pr.draft.unwrap_or(false),
+        }
+    }
+}
+
+// Most recent closed pull request of a branch
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClosedPullRequest {
+    pub pr: PullRequestRef,
+    // Closed by merging it, as opposed to closing it without merging
+    pub merged: bool,
+}
+
+// What the dispatcher needs to know about a repository before cloning it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RepoInfo {
+    pub default_branch: String,
+    pub archived: bool,
+    pub fork: bool,
+}
+
+// Open pull request that changes files under .github/workflows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WorkflowPullRequest {
+    pub number: u64,
+    pub head: String,
+    pub created_at: chrono::DateTime<chrono::Utc>,
+}
+
+// Pull request operations on the repository the client is bound to
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait PullRequests {
+    async fn create_pull_request(
+        &self,
+        content: &PullRequestContent,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>>;
+
+    async fn find_existing_pr(
+        &self,
+        branch: &str,
+    ) -> Result<Option<PullRequestRef>, Box<dyn std::error::Error>>;
+
+    // Most recent closed pull request with <branch> as its head
+    async fn find_closed_pr(
+        &self,
+        branch: &str,
+    ) -> Result<Option<ClosedPullRequest>, Box<dyn std::error::Error>>;
+
+    // Reopen the closed pull request <number>
+    async fn reopen_pull_request(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>>;
+
+    async fn get_repo_info(&self) -> Result<RepoInfo, Box<dyn std::error::Error>>;
+
+    // Open pull requests against <base> that change workflow files
+    async fn list_open_workflow_prs(
+        &self,
+        base: &str,
+    ) -> Result<Vec<WorkflowPullRequest>, Box<dyn std::error::Error>>;
+
+    // Add the configured labels that pull request <number> does not have yet
+    // Return the labels that were added
+    async fn add_missing_labels(
+        &self,
+        number: u64,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>>;
+
+    // Set the title and body of pull request <number> when they differ from the current ones
+    // Return whether the pull request was changed
+    async fn update_pull_request(
+        &self,
+        number: u64,
+        title: &str,
+        body: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>>;
+
+    // Mark the draft pull request <number> ready for review
+    async fn mark_ready_for_review(&self, number: u64) -> Result<(), Box<dyn std::error::Error>>;
+
+    // Merge pull request <number> with <method> once its required checks pass
+    async fn enable_auto_merge(
+        &self,
+        number: u64,
+        method: MergeMethod,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+// Everything the dispatcher needs from the repository it is processing
+pub trait RepositoryClient: PullRequests + BranchRefs + IssueTracker + Forks + Sync {}
+
+impl<T: PullRequests + BranchRefs + IssueTracker + Forks + Sync> RepositoryClient for T {}
+
+#[async_trait]
+impl PullRequests for GitHubClient {
+    // Make a request to the GitHub API to create the pull request of <content>
+    // Return the created pull request
+    async fn create_pull_request(
+        &self,
+        content: &PullRequestContent,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>> {
+        let pr = self
+            .with_retry("create_pull_request", || async {
+                self.octocrab
+                    .pulls(&self.owner, &self.repo)
+                    .create(&content.title, &content.branch, &content.base)
+                    .body(&content.body)
+                    .draft(content.draft)
+                    .maintainer_can_modify(true)
+                    .send()
+                    .await
+            })
             .await?;
-        Ok(pr)
+        if let Err(e) = self.add_missing_labels(pr.number).await {
+            warn!(
+                "Failed to label PR #{} of {}/{}: {}",
+                pr.number, self.owner, self.repo, e
+            );
+        }
+        Ok(pr.into())
     }
 
     // Make a request to the GitHub API to find an existing pull request
     // with the given branch
     // Return the pull request if it exists, otherwise return None
-    pub async fn find_existing_pr(
+    async fn find_existing_pr(
+        &self,
+        branch: &str,
+    ) -> Result<Option<PullRequestRef>, Box<dyn std::error::Error>> {
+        let pulls = self
+            .with_retry("find_existing_pr", || async {
+                self.octocrab
+                    .pulls(&self.owner, &self.repo)
+                    .list()
+                    .head(self.head_filter(branch))
+                    .state(State::Open)
+                    .send()
+                    .await
+            })
+            .await?;
+
+        Ok(pulls.items.into_iter().next().map(PullRequestRef::from))
+    }
+
+    // Make a request to the GitHub API to find the closed pull requests of <branch>,
+    // GitHub lists the most recently created one first
+    async fn find_closed_pr(
         &self,
         branch: &str,
-    ) -> Result<Option<PullRequest>, Box<dyn std::error::Error>> {
+    ) -> Result<Option<ClosedPullRequest>, Box<dyn std::error::Error>> {
         let pulls = self
+            .with_retry("find_closed_pr", || async {
+                self.octocrab
+                    .pulls(&self.owner, &self.repo)
+                    .list()
+                    .head(self.head_filter(branch))
+                    .state(State::Closed)
+                    .send()
+                    .await
+            })
+            .await?;
+
+        Ok(pulls.items.into_iter().next().map(|pr| ClosedPullRequest {
+            merged: pr.merged_at.is_some(),
+            pr: PullRequestRef::from(pr),
+        }))
+    }
+
+    // Make a request to the GitHub API to set the state of pull request <number> to open
+    async fn reopen_pull_request(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>> {
+        let pr = self
+            .octocrab
+            .pulls(&self.owner, &self.repo)
+            .update(number)
+            .state(octocrab::params::pulls::State::Open)
+            .send()
+            .await?;
+        Ok(pr.into())
+    }
+
+    // Make a request to the GitHub API to get the repository
+    // Return its default branch and whether it is archived or a fork
+    async fn get_repo_info(&self) -> Result<RepoInfo, Box<dyn std::error::Error>> {
+        let repo = self
+            .with_retry("get_repo_info", || async {
+                self.octocrab.repos(&self.owner, &self.repo).get().await
+            })
+            .await?;
+        Ok(RepoInfo {
+            default_branch: repo.default_branch.unwrap_or_else(|| "main".to_string()),
+            archived: repo.archived.unwrap_or(false),
+            fork: repo.fork.unwrap_or(false),
+        })
+    }
+
+    // Make a request to the GitHub API to list the open pull requests against <base>,
+    // then list the changed files of every pull request to find the ones touching workflows
+    async fn list_open_workflow_prs(
+        &self,
+        base: &str,
+    ) -> Result<Vec<WorkflowPullRequest>, Box<dyn std::error::Error>> {
+        let page = self
             .octocrab
             .pulls(&self.owner, &self.repo)
             .list()
-            .head(format!("{}:{}", &self.owner, branch))
-            .state(octocrab::params::State::Open)
+            .base(base)
+            .state(State::Open)
+            .per_page(100)
+            .send()
+            .await?;
+        let pulls = self.octocrab.all_pages(page).await?;
+
+        let mut workflow_prs = Vec::new();
+        for pr in pulls {
+            let page = self
+                .octocrab
+                .pulls(&self.owner, &self.repo)
+                .list_files(pr.number)
+                .await?;
+            let files = self.octocrab.all_pages(page).await?;
+            if files
+                .iter()
+                .any(|file| file.filename.starts_with(".github/workflows/"))
+            {
+                workflow_prs.push(WorkflowPullRequest {
+                    number: pr.number,
+                    head: pr.head.ref_field.clone(),
+                    created_at: pr.created_at.unwrap_or_else(chrono::Utc::now),
+                });
+            }
+        }
+        Ok(workflow_prs)
+    }
+
+    // Make requests to the GitHub API to list the labels of the pull request and of
+    // the repository, create the missing ones if allowed and add them to the pull request
+    async fn add_missing_labels(
+        &self,
+        number: u64,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        if self.labels.is_empty() {
+            return Ok(Vec::new());
+        }
+        let issues = self.octocrab.issues(&self.owner, &self.repo);
+        let page = issues
+            .list_labels_for_issue(number)
+            .per_page(100)
+            .send()
+            .await?;
+        let current = self.octocrab.all_pages(page).await?;
+        let missing: Vec<&String> = self
+            .labels
+            .iter()
+            .filter(|label| {
+                !current
+                    .iter()
+                    .any(|existing| existing.name.eq_ignore_ascii_case(label))
+            })
+            .collect();
+        if missing.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let page = issues.list_labels_for_repo().per_page(100).send().await?;
+        let available = self.octocrab.all_pages(page).await?;
+        let mut added = Vec::new();
+        for label in missing {
+            if available
+                .iter()
+                .any(|existing| existing.name.eq_ignore_ascii_case(label))
+            {
+                added.push(label.clone());
+            } else if self.create_missing_labels {
+                issues
+                    .create_label(label, NEW_LABEL_COLOR, NEW_LABEL_DESCRIPTION)
+                    .await?;
+                info!("Created label {} in {}/{}", label, self.owner, self.repo);
+                added.push(label.clone());
+            } else {
+                warn!(
+                    "Label {} does not exist in {}/{}, skipping it",
+                    label, self.owner, self.repo
+                );
+            }
+        }
+        if !added.is_empty() {
+            issues.add_labels(number, &added).await?;
+        }
+        Ok(added)
+    }
+
+    // Make a request to the GitHub API to get the pull request, and only update it
+    // when needed to avoid edit events on the timeline of every rerun
+    async fn update_pull_request(
+        &self,
+        number: u64,
+        title: &str,
+        body: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let pr = self
+            .octocrab
+            .pulls(&self.owner, &self.repo)
+            .get(number)
+            .await?;
+        if pr.title.as_deref() == Some(title) && pr.body.as_deref() == Some(body) {
+            return Ok(false);
+        }
+        self.octocrab
+            .pulls(&self.owner, &self.repo)
+            .update(number)
+            .title(title)
+            .body(body)
+            .send()
+            .await?;
+        Ok(true)
+    }
+
+    // The REST API cannot change the draft state, use the GraphQL mutation instead
+    async fn mark_ready_for_review(&self, number: u64) -> Result<(), Box<dyn std::error::Error>> {
+        self.pull_request_mutation(number, MARK_READY_FOR_REVIEW, serde_json::json!({}))
+            .await
+    }
+
+    async fn enable_auto_merge(
+        &self,
+        number: u64,
+        method: MergeMethod,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.pull_request_mutation(
+            number,
+            ENABLE_AUTO_MERGE,
+            serde_json::json!({ "method": method.graphql_name() }),
+        )
+        .await
+    }
+}
+
+// Minimal view of an issue, enough to update or close it later on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueRef {
+    pub number: u64,
+    pub html_url: String,
+}
+
+// Issue operations used when reporting unpinned actions instead of opening PRs
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait IssueTracker {
+    // Find an open issue whose body contains <marker>
+    async fn find_open_issue(
+        &self,
+        marker: &str,
+    ) -> Result<Option<IssueRef>, Box<dyn std::error::Error>>;
+
+    async fn create_issue(
+        &self,
+        title: &str,
+        body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>>;
+
+    async fn update_issue(
+        &self,
+        number: u64,
+        body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>>;
+
+    async fn close_issue(&self, number: u64) -> Result<IssueRef, Box<dyn std::error::Error>>;
+}
+
+impl From<octocrab::models::issues::Issue> for IssueRef {
+    fn from(issue: octocrab::models::issues::Issue) -> Self {
+        IssueRef {
+            number: issue.number,
+            html_url: issue.html_url.to_string(),
+        }
+    }
+}
+
+#[async_trait]
+impl IssueTracker for GitHubClient {
+    // Make a request to the GitHub API to list all open issues
+    // Return the first one (that is not a pull request) containing the marker
+    async fn find_open_issue(
+        &self,
+        marker: &str,
+    ) -> Result<Option<IssueRef>, Box<dyn std::error::Error>> {
+        let page = self
+            .octocrab
+            .issues(&self.owner, &self.repo)
+            .list()
+            .state(State::Open)
+            .per_page(100)
             .send()
             .await?;
+        let issues = self.octocrab.all_pages(page).await?;
 
-        Ok(pulls.items.into_iter().next())
+        Ok(issues
+            .into_iter()
+            .filter(|issue| issue.pull_request.is_none())
+            .find(|issue| {
+                issue
+                    .body
+                    .as_deref()
+                    .is_some_and(|body| body.contains(marker))
+            })
+            .map(IssueRef::from))
     }
 
-    // Make a request to the GitHub API to get the default branch of the repository
-    // Return the default branch
-    pub async fn get_default_branch(&self) -> Result<String, Box<dyn std::error::Error>> {
-        let repo = self.octocrab.repos(&self.owner, &self.repo).get().await?;
-        Ok(repo.default_branch.unwrap_or_else(|| "main".to_string()))
+    async fn create_issue(
+        &self,
+        title: &str,
+        body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        let issue = self
+            .octocrab
+            .issues(&self.owner, &self.repo)
+            .create(title)
+            .body(body)
+            .send()
+            .await?;
+        Ok(issue.into())
+    }
+
+    async fn update_issue(
+        &self,
+        number: u64,
+        body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        let issue = self
+            .octocrab
+            .issues(&self.owner, &self.repo)
+            .update(number)
+            .body(body)
+            .send()
+            .await?;
+        Ok(issue.into())
+    }
+
+    async fn close_issue(&self, number: u64) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        let issue = self
+            .octocrab
+            .issues(&self.owner, &self.repo)
+            .update(number)
+            .state(octocrab::models::IssueState::Closed)
+            .send()
+            .await?;
+        Ok(issue.into())
+    }
+}
+
+// Repository listing used by organization discovery
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait RepositoryLister {
+    async fn list_org_repositories(
+        &self,
+        org: &str,
+        visibility: Visibility,
+    ) -> Result<Vec<RepoListing>, Box<dyn std::error::Error>>;
+}
+
+#[async_trait]
+impl RepositoryLister for GitHubClient {
+    // Make a request to the GitHub API to list the repositories of the organization
+    // Follows pagination so every repository is returned
+    async fn list_org_repositories(
+        &self,
+        org: &str,
+        visibility: Visibility,
+    ) -> Result<Vec<RepoListing>, Box<dyn std::error::Error>> {
+        let repo_type = match visibility {
+            Visibility::Public => Type::Public,
+            Visibility::Private => Type::Private,
+            Visibility::Internal => Type::Internal,
+            Visibility::All => Type::All,
+        };
+        let page = self
+            .octocrab
+            .orgs(org)
+            .list_repos()
+            .repo_type(repo_type)
+            .per_page(100)
+            .send()
+            .await?;
+        let repos = self.octocrab.all_pages(page).await?;
+
+        Ok(repos
+            .into_iter()
+            .map(|repo| RepoListing {
+                full_name: repo
+                    .full_name
+                    .unwrap_or_else(|| format!("{}/{}", org, repo.name)),
+                fork: repo.fork.unwrap_or(false),
+                is_template: repo.is_template.unwrap_or(false),
+                archived: repo.archived.unwrap_or(false),
+                disabled: repo.disabled.unwrap_or(false),
+            })
+            .collect())
+    }
+}
+
+// A tag of an action repository and the commit it points to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TagRef {
+    pub name: String,
+    pub sha: String,
+}
+
+// State of the repository an action is loaded from
+#[derive(Debug, Clone, PartialEq, Eq, serde::Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum SourceStatus {
+    Active,
+    Archived,
+    // Deleted, or not visible with the current credentials
+    Missing,
+    // The owner or repository was renamed, GitHub redirects to <moved_to>
+    Moved { moved_to: String },
+}
+
+// Lookups on the repositories of pinned actions, used to verify pins
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait TagLookup {
+    // List the tags of <owner>/<repo>, newest first
+    // Returns None when the repository is not accessible (deleted, private or no permission)
+    async fn list_tags(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<Option<Vec<TagRef>>, Box<dyn std::error::Error>>;
+
+    // Whether <sha> is an ancestor of (or identical to) <tag>
+    async fn is_reachable_from(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        tag: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>>;
+
+    // Commit SHA <reference> (a tag, branch or SHA) points to, None when the
+    // reference or repository is not accessible
+    async fn resolve_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        reference: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>>;
+
+    // Committer date of <sha>, None when the commit or repository is not accessible
+    async fn commit_date(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, Box<dyn std::error::Error>>;
+
+    // Whether <owner>/<repo> still exists, is archived or was moved
+    async fn repository_status(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<SourceStatus, Box<dyn std::error::Error>>;
+}
+
+#[async_trait]
+impl TagLookup for GitHubClient {
+    // Make a request to the GitHub API to list the tags of an action repository
+    async fn list_tags(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<Option<Vec<TagRef>>, Box<dyn std::error::Error>> {
+        let page = match self
+            .octocrab
+            .repos(owner, repo)
+            .list_tags()
+            .per_page(100)
+            .send()
+            .await
+        {
+            Ok(page) => page,
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(StatusCode::NOT_FOUND | StatusCode::FORBIDDEN)
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+
+        Ok(Some(
+            page.items
+                .into_iter()
+                .map(|tag| TagRef {
+                    name: tag.name,
+                    sha: tag.commit.sha,
+                })
+                .collect(),
+        ))
+    }
+
+    // Make a request to the GitHub API to compare <sha> with <tag>
+    // The sha is reachable when the tag is ahead of or identical to it, a sha that is
+    // not part of the repository is not reachable
+    async fn is_reachable_from(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+        tag: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let comparison = match self
+            .octocrab
+            .commits(owner, repo)
+            .compare(sha, tag)
+            .send()
+            .await
+        {
+            Ok(comparison) => comparison,
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(StatusCode::NOT_FOUND | StatusCode::UNPROCESSABLE_ENTITY)
+                ) =>
+            {
+                return Ok(false)
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(matches!(
+            comparison.status,
+            GithubCommitStatus::Ahead | GithubCommitStatus::Identical
+        ))
+    }
+
+    // Make a request to the GitHub API to get the commit <reference> points to,
+    // annotated tags are peeled to their commit
+    async fn resolve_ref(
+        &self,
+        owner: &str,
+        repo: &str,
+        reference: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let route = format!("/repos/{}/{}/commits/{}", owner, repo, reference);
+        let commit: serde_json::Value = match self.octocrab.get(route, None::<&()>).await {
+            Ok(commit) => commit,
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(
+                        StatusCode::NOT_FOUND
+                            | StatusCode::FORBIDDEN
+                            | StatusCode::UNPROCESSABLE_ENTITY
+                    )
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(commit
+            .get("sha")
+            .and_then(|sha| sha.as_str())
+            .map(String::from))
+    }
+
+    // Make a request to the GitHub API to get the commit <sha> of an action repository
+    async fn commit_date(
+        &self,
+        owner: &str,
+        repo: &str,
+        sha: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, Box<dyn std::error::Error>> {
+        let commit = match self.octocrab.commits(owner, repo).get(sha).await {
+            Ok(commit) => commit,
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(
+                        StatusCode::NOT_FOUND
+                            | StatusCode::FORBIDDEN
+                            | StatusCode::UNPROCESSABLE_ENTITY
+                    )
+                ) =>
+            {
+                return Ok(None)
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        Ok(commit
+            .commit
+            .committer
+            .or(commit.commit.author)
+            .and_then(|user| user.date.or(user.user.date)))
+    }
+
+    // Make a request to the GitHub API to get an action repository. Redirects of
+    // renamed repositories are followed, the name of the response tells where it moved.
+    async fn repository_status(
+        &self,
+        owner: &str,
+        repo: &str,
+    ) -> Result<SourceStatus, Box<dyn std::error::Error>> {
+        let repository = match self.octocrab.repos(owner, repo).get().await {
+            Ok(repository) => repository,
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(StatusCode::NOT_FOUND | StatusCode::GONE)
+                ) =>
+            {
+                return Ok(SourceStatus::Missing)
+            }
+            Err(e) => return Err(Box::new(e)),
+        };
+        if repository.archived.unwrap_or(false) {
+            return Ok(SourceStatus::Archived);
+        }
+        let requested = format!("{}/{}", owner, repo);
+        match repository.full_name {
+            Some(full_name) if !full_name.eq_ignore_ascii_case(&requested) => {
+                Ok(SourceStatus::Moved {
+                    moved_to: full_name,
+                })
+            }
+            _ => Ok(SourceStatus::Active),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateRefOutcome {
+    Created,
+    // The token is not allowed to create refs in the repository
+    PermissionDenied,
+}
+
+// Branch operations on the remote repository through the git refs API
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait BranchRefs {
+    // Return the commit SHA of <branch>, or None when the branch does not exist
+    async fn branch_sha(&self, branch: &str) -> Result<Option<String>, Box<dyn std::error::Error>>;
+
+    async fn create_branch_ref(
+        &self,
+        name: &str,
+        sha: &str,
+    ) -> Result<CreateRefOutcome, Box<dyn std::error::Error>>;
+
+    async fn delete_branch_ref(&self, name: &str) -> Result<(), Box<dyn std::error::Error>>;
+}
+
+#[async_trait]
+impl BranchRefs for GitHubClient {
+    // Make a request to the GitHub API to get the ref of a branch
+    async fn branch_sha(&self, branch: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        match self
+            .octocrab
+            .repos(&self.owner, &self.repo)
+            .get_ref(&Reference::Branch(branch.to_string()))
+            .await
+        {
+            Ok(reference) => match reference.object {
+                Object::Commit { sha, .. } | Object::Tag { sha, .. } => Ok(Some(sha)),
+                _ => Err(Box::from(format!("Unexpected ref object for {}", branch))),
+            },
+            Err(e) if status_code(&e) == Some(StatusCode::NOT_FOUND) => Ok(None),
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Make a request to the GitHub API to create a branch pointing at <sha>
+    async fn create_branch_ref(
+        &self,
+        name: &str,
+        sha: &str,
+    ) -> Result<CreateRefOutcome, Box<dyn std::error::Error>> {
+        match self
+            .octocrab
+            .repos(&self.owner, &self.repo)
+            .create_ref(&Reference::Branch(name.to_string()), sha)
+            .await
+        {
+            Ok(_) => Ok(CreateRefOutcome::Created),
+            // GitHub answers 404 instead of 403 when the token can read but not write
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(StatusCode::FORBIDDEN | StatusCode::NOT_FOUND)
+                ) =>
+            {
+                Ok(CreateRefOutcome::PermissionDenied)
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+
+    // Make a request to the GitHub API to delete a branch
+    async fn delete_branch_ref(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        self.octocrab
+            .repos(&self.owner, &self.repo)
+            .delete_ref(&Reference::Branch(name.to_string()))
+            .await?;
+        Ok(())
+    }
+}
+
+// Fork of a repository, --fork
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ForkRef {
+    pub owner: String,
+    pub repo: String,
+}
+
+// Forks of the repository the client is bound to
+#[cfg_attr(test, automock)]
+#[async_trait]
+pub trait Forks {
+    // Fork the repository into <organization>, or into the account of the token.
+    // An existing fork is returned as is.
+    async fn create_fork<'a>(
+        &self,
+        organization: Option<&'a str>,
+    ) -> Result<ForkRef, Box<dyn std::error::Error>>;
+
+    // Whether <branch> of <fork> exists yet, GitHub copies the git data of a new fork
+    // in the background
+    async fn fork_has_branch(
+        &self,
+        fork: &ForkRef,
+        branch: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>>;
+}
+
+#[async_trait]
+impl Forks for GitHubClient {
+    // Make a request to the GitHub API to fork the repository
+    async fn create_fork<'a>(
+        &self,
+        organization: Option<&'a str>,
+    ) -> Result<ForkRef, Box<dyn std::error::Error>> {
+        let fork = self
+            .with_retry("create_fork", || async {
+                let repos = self.octocrab.repos(&self.owner, &self.repo);
+                let builder = repos.create_fork();
+                match organization {
+                    Some(organization) => builder.organization(organization).send().await,
+                    None => builder.send().await,
+                }
+            })
+            .await?;
+        let owner = fork
+            .owner
+            .map(|owner| owner.login)
+            .ok_or_else(|| format!("Fork {} has no owner", fork.name))?;
+        Ok(ForkRef {
+            owner,
+            repo: fork.name,
+        })
+    }
+
+    // Make a request to the GitHub API to get the ref of <branch> in the fork
+    async fn fork_has_branch(
+        &self,
+        fork: &ForkRef,
+        branch: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        match self
+            .octocrab
+            .repos(&fork.owner, &fork.repo)
+            .get_ref(&Reference::Branch(branch.to_string()))
+            .await
+        {
+            Ok(_) => Ok(true),
+            // 409 while the git repository of the fork is still empty
+            Err(e)
+                if matches!(
+                    status_code(&e),
+                    Some(StatusCode::NOT_FOUND | StatusCode::CONFLICT)
+                ) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(Box::new(e)),
+        }
+    }
+}
+
+// What the user has to fix after a failed GitHub API call, carried by the run summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorCategory {
+    NotFound,
+    Unauthorized,
+    Forbidden,
+    // Forbidden until the token is authorized for the SAML single sign-on of the organization
+    SsoRequired,
+    RateLimited,
+    Other,
+}
+
+// A failed GitHub API call, telling a wrong token apart from a wrong repository
+#[derive(Debug, thiserror::Error)]
+pub enum GitHubError {
+    #[error("{0}")]
+    NotFound(String),
+    // The token is invalid or expired
+    #[error("{0}")]
+    Unauthorized(String),
+    // The token lacks a permission or, with <sso>, the SAML authorization of the organization
+    #[error("{message}")]
+    Forbidden { message: String, sso: bool },
+    #[error("{message}{}", reset_at.map_or_else(String::new, |reset_at| format!(", the rate limit resets at {}", reset_at)))]
+    RateLimited {
+        message: String,
+        reset_at: Option<DateTime<Utc>>,
+    },
+    #[error("{0}")]
+    Other(String),
+}
+
+impl GitHubError {
+    pub fn category(&self) -> ErrorCategory {
+        match self {
+            GitHubError::NotFound(_) => ErrorCategory::NotFound,
+            GitHubError::Unauthorized(_) => ErrorCategory::Unauthorized,
+            GitHubError::Forbidden { sso: true, .. } => ErrorCategory::SsoRequired,
+            GitHubError::Forbidden { sso: false, .. } => ErrorCategory::Forbidden,
+            GitHubError::RateLimited { .. } => ErrorCategory::RateLimited,
+            GitHubError::Other(_) => ErrorCategory::Other,
+        }
+    }
+
+    fn with_context(self, context: &str) -> Self {
+        let prefix = |message: String| format!("{}: {}", context, message);
+        match self {
+            GitHubError::NotFound(message) => GitHubError::NotFound(prefix(message)),
+            GitHubError::Unauthorized(message) => GitHubError::Unauthorized(prefix(message)),
+            GitHubError::Forbidden { message, sso } => GitHubError::Forbidden {
+                message: prefix(message),
+                sso,
+            },
+            GitHubError::RateLimited { message, reset_at } => GitHubError::RateLimited {
+                message: prefix(message),
+                reset_at,
+            },
+            GitHubError::Other(message) => GitHubError::Other(prefix(message)),
+        }
+    }
+}
+
+impl From<&octocrab::Error> for GitHubError {
+    fn from(error: &octocrab::Error) -> Self {
+        let source = match error {
+            octocrab::Error::GitHub { source, .. } => source,
+            error => return GitHubError::Other(error.to_string()),
+        };
+        let message = format!("{} ({})", source.message, source.status_code);
+        let lowercase = source.message.to_lowercase();
+        match source.status_code {
+            StatusCode::NOT_FOUND => GitHubError::NotFound(message),
+            StatusCode::UNAUTHORIZED => GitHubError::Unauthorized(message),
+            StatusCode::TOO_MANY_REQUESTS => GitHubError::RateLimited {
+                message,
+                reset_at: None,
+            },
+            StatusCode::FORBIDDEN if lowercase.contains("rate limit") => GitHubError::RateLimited {
+                message,
+                reset_at: None,
+            },
+            // "Resource protected by organization SAML enforcement. You must grant your
+            // Personal Access token access to this organization."
+            StatusCode::FORBIDDEN => GitHubError::Forbidden {
+                sso: lowercase.contains("saml"),
+                message,
+            },
+            _ => GitHubError::Other(message),
+        }
+    }
+}
+
+impl From<octocrab::Error> for GitHubError {
+    fn from(error: octocrab::Error) -> Self {
+        GitHubError::from(&error)
+    }
+}
+
+// <context>: <error>, kept a GitHubError when <error> came from the GitHub API so the run
+// summary still knows what went wrong
+pub fn api_context(context: &str, error: Box<dyn std::error::Error>) -> Box<dyn std::error::Error> {
+    let error = match error.downcast::<octocrab::Error>() {
+        Ok(octocrab) => return Box::new(GitHubError::from(*octocrab).with_context(context)),
+        Err(error) => error,
+    };
+    match error.downcast::<GitHubError>() {
+        Ok(github) => Box::new(github.with_context(context)),
+        Err(error) => Box::from(format!("{}: {}", context, error)),
+    }
+}
+
+// Category of the first GitHub API failure in the source chain of <error>
+pub fn error_category(error: &(dyn std::error::Error + 'static)) -> Option<ErrorCategory> {
+    std::iter::successors(Some(error), |error| error.source()).find_map(|error| {
+        match error.downcast_ref::<GitHubError>() {
+            Some(github) => Some(github.category()),
+            None => error
+                .downcast_ref::<octocrab::Error>()
+                .map(|octocrab| GitHubError::from(octocrab).category()),
+        }
+    })
+}
+
+// HTTP status code of an error returned by the GitHub API, if any
+fn status_code(error: &octocrab::Error) -> Option<StatusCode> {
+    match error {
+        octocrab::Error::GitHub { source, .. } => Some(source.status_code),
+        _ => None,
+    }
+}
+
+// Server errors, secondary rate limits and connection failures are transient.
+// Everything else (404, 422 validation, ...) will fail again the same way.
+fn is_retryable(error: &octocrab::Error) -> bool {
+    match error {
+        octocrab::Error::GitHub { source, .. } => {
+            source.status_code.is_server_error()
+                || (matches!(
+                    source.status_code,
+                    StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS
+                ) && source.message.to_lowercase().contains("rate limit"))
+        }
+        octocrab::Error::Hyper { .. } | octocrab::Error::Service { .. } => true,
+        _ => false,
+    }
+}
+
+// <base> * 2^(attempt - 1) plus up to <base> of jitter, so parallel runs spread out
+pub fn backoff_delay(base: Duration, attempt: u32) -> Duration {
+    let exponential = base.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+    let nanos = SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos())
+        .unwrap_or(0);
+    let jitter = base.mul_f64(f64::from(nanos) / 1_000_000_000.0);
+    exponential + jitter
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{body_json, body_partial_json, method, path, query_param},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[test]
+    fn test_github_com_urls() {
+        assert_eq!(
+            GitHubUrls::new(DEFAULT_GITHUB_URL),
+            GitHubUrls {
+                clone_base: String::from("https://github.com"),
+                api_base: String::from("https://api.github.com"),
+                graphql_base: String::from("https://api.github.com"),
+                proxy: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_enterprise_server_urls() {
+        let urls = GitHubUrls::new("https://ghe.example.com/");
+        assert_eq!(urls.clone_base, "https://ghe.example.com");
+        assert_eq!(urls.api_base, "https://ghe.example.com/api/v3");
+        assert_eq!(urls.graphql_base, "https://ghe.example.com/api");
+    }
+
+    #[tokio::test]
+    async fn test_client_uses_api_base() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(repository_json(&server)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let urls = GitHubUrls::new(&server.uri());
+        assert_eq!(urls.clone_base, server.uri());
+        let client = GitHubClient::new(
+            String::from("org"),
+            String::from("repo"),
+            String::from("token"),
+            &urls,
+        );
+        assert_eq!(
+            client.get_repo_info().await.unwrap(),
+            RepoInfo {
+                default_branch: String::from("trunk"),
+                archived: false,
+                fork: false,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_repo_info_of_an_archived_fork() {
+        let server = MockServer::start().await;
+        let mut json = repository_json(&server);
+        json["archived"] = serde_json::json!(true);
+        json["fork"] = serde_json::json!(true);
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json))
+            .mount(&server)
+            .await;
+
+        let info = retrying_client(&server, 0).get_repo_info().await.unwrap();
+        assert!(info.archived);
+        assert!(info.fork);
+        assert_eq!(info.default_branch, "trunk");
+    }
+
+    fn repository_json(server: &MockServer) -> serde_json::Value {
+        serde_json::json!({
+            "id": 1,
+            "name": "repo",
+            "url": format!("{}/api/v3/repos/org/repo", server.uri()),
+            "default_branch": "trunk"
+        })
+    }
+
+    fn author_json(login: &str) -> serde_json::Value {
+        let url = format!("https://github.com/{}", login);
+        serde_json::json!({
+            "login": login,
+            "id": 2,
+            "node_id": "U_kgDO2",
+            "avatar_url": url,
+            "gravatar_id": "",
+            "url": url,
+            "html_url": url,
+            "followers_url": url,
+            "following_url": url,
+            "gists_url": url,
+            "starred_url": url,
+            "subscriptions_url": url,
+            "organizations_url": url,
+            "repos_url": url,
+            "events_url": url,
+            "received_events_url": url,
+            "type": "User",
+            "site_admin": false
+        })
+    }
+
+    #[tokio::test]
+    async fn test_fork_and_find_its_pull_request() {
+        let server = MockServer::start().await;
+        let mut fork = repository_json(&server);
+        fork["owner"] = author_json("bot");
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/forks"))
+            .and(body_json(serde_json::json!({"organization": "bots"})))
+            .respond_with(ResponseTemplate::new(202).set_body_json(fork))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // A new fork has no git data yet
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/bot/repo/git/ref/heads/main"))
+            .respond_with(
+                ResponseTemplate::new(409)
+                    .set_body_json(serde_json::json!({"message": "Git Repository is empty."})),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls"))
+            .and(query_param("head", "bot:ratchet"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([])))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        let fork = client.create_fork(Some("bots")).await.unwrap();
+        assert_eq!(
+            fork,
+            ForkRef {
+                owner: String::from("bot"),
+                repo: String::from("repo"),
+            }
+        );
+        assert!(!client.fork_has_branch(&fork, "main").await.unwrap());
+        assert!(client
+            .find_existing_pr("bot:ratchet")
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    fn retrying_client(server: &MockServer, max_retries: u32) -> GitHubClient {
+        let mut client = GitHubClient::new(
+            String::from("org"),
+            String::from("repo"),
+            String::from("token"),
+            &GitHubUrls::new(&server.uri()),
+        )
+        .with_max_retries(max_retries);
+        client.retry_base_delay = Duration::from_millis(1);
+        client
+    }
+
+    #[tokio::test]
+    async fn test_retry_transient_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(
+                ResponseTemplate::new(502)
+                    .set_body_json(serde_json::json!({"message": "Bad Gateway"})),
+            )
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(serde_json::json!({
+                "message": "You have exceeded a secondary rate limit"
+            })))
+            .up_to_n_times(1)
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(repository_json(&server)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 3);
+        assert_eq!(
+            client.get_repo_info().await.unwrap().default_branch,
+            "trunk"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_retries_are_exhausted() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(
+                ResponseTemplate::new(503)
+                    .set_body_json(serde_json::json!({"message": "Unavailable"})),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 2);
+        assert!(client.get_repo_info().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_no_retry_on_client_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .set_body_json(serde_json::json!({"message": "Not Found"})),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 3);
+        let err = client.get_repo_info().await.unwrap_err();
+        assert!(matches!(
+            err.downcast_ref::<GitHubError>(),
+            Some(GitHubError::NotFound(_))
+        ));
+    }
+
+    async fn mock_repo_error(server: &MockServer, status: u16, message: &str) {
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo"))
+            .respond_with(
+                ResponseTemplate::new(status)
+                    .set_body_json(serde_json::json!({"message": message})),
+            )
+            .mount(server)
+            .await;
+    }
+
+    #[tokio::test]
+    async fn test_errors_are_classified() {
+        for (status, message, category) in [
+            (401, "Bad credentials", ErrorCategory::Unauthorized),
+            (404, "Not Found", ErrorCategory::NotFound),
+            (
+                403,
+                "Resource protected by organization SAML enforcement. You must grant your Personal Access token access to this organization.",
+                ErrorCategory::SsoRequired,
+            ),
+            (403, "Resource not accessible by integration", ErrorCategory::Forbidden),
+            (422, "Validation Failed", ErrorCategory::Other),
+        ] {
+            let server = MockServer::start().await;
+            mock_repo_error(&server, status, message).await;
+
+            let err = retrying_client(&server, 0).get_repo_info().await.unwrap_err();
+            assert_eq!(error_category(err.as_ref()), Some(category), "{}", message);
+            // The dispatcher adds what it was doing without losing the category
+            let err = api_context("Failed to get repository info", err);
+            assert_eq!(error_category(err.as_ref()), Some(category));
+            assert!(err
+                .to_string()
+                .starts_with("Failed to get repository info: "));
+        }
+        let err = api_context("Failed to fork org/repo", Box::from("Fork is not ready"));
+        assert_eq!(
+            err.to_string(),
+            "Failed to fork org/repo: Fork is not ready"
+        );
+        assert_eq!(error_category(err.as_ref()), None);
+    }
+
+    #[tokio::test]
+    async fn test_token_scopes() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/user"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("X-OAuth-Scopes", "repo, read:org")
+                    .set_body_json(author_json("bot")),
+            )
+            .mount(&server)
+            .await;
+        assert_eq!(
+            retrying_client(&server, 0).token_scopes().await.unwrap(),
+            Some(vec![String::from("repo"), String::from("read:org")])
+        );
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/user"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(author_json("bot")))
+            .mount(&server)
+            .await;
+        assert_eq!(
+            retrying_client(&server, 0).token_scopes().await.unwrap(),
+            None
+        );
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/user"))
+            .respond_with(
+                ResponseTemplate::new(401)
+                    .set_body_json(serde_json::json!({"message": "Bad credentials"})),
+            )
+            .mount(&server)
+            .await;
+        let err = retrying_client(&server, 0)
+            .token_scopes()
+            .await
+            .unwrap_err();
+        assert_eq!(err.category(), ErrorCategory::Unauthorized);
+    }
+
+    #[tokio::test]
+    async fn test_rate_limit_error_tells_when_it_resets() {
+        let server = MockServer::start().await;
+        mock_repo_error(&server, 403, "API rate limit exceeded for installation").await;
+        let rate =
+            serde_json::json!({"limit": 5000, "used": 5000, "remaining": 0, "reset": 1767225600});
+        Mock::given(method("GET"))
+            .and(path("/api/v3/rate_limit"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "resources": {"core": rate, "search": rate},
+                "rate": rate
+            })))
+            .mount(&server)
+            .await;
+
+        let err = retrying_client(&server, 0)
+            .get_repo_info()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error_category(err.as_ref()),
+            Some(ErrorCategory::RateLimited)
+        );
+        assert_eq!(
+            err.to_string(),
+            "API rate limit exceeded for installation (403 Forbidden), the rate limit resets at 2026-01-01 00:00:00 UTC"
+        );
+    }
+
+    fn labels_json(server: &MockServer, names: &[&str]) -> serde_json::Value {
+        names
+            .iter()
+            .enumerate()
+            .map(|(id, name)| {
+                serde_json::json!({
+                    "id": id,
+                    "node_id": name,
+                    "url": format!("{}/api/v3/repos/org/repo/labels/{}", server.uri(), name),
+                    "name": name,
+                    "color": NEW_LABEL_COLOR,
+                    "default": false
+                })
+            })
+            .collect()
+    }
+
+    async fn mock_labels(server: &MockServer, route: &str, names: &[&str]) {
+        Mock::given(method("GET"))
+            .and(path(route))
+            .respond_with(ResponseTemplate::new(200).set_body_json(labels_json(server, names)))
+            .mount(server)
+            .await;
+    }
+
+    fn pr_content(draft: bool) -> PullRequestContent {
+        PullRequestContent {
+            branch: String::from("ratchet"),
+            base: String::from("main"),
+            title: String::from("ci: pin versions of actions"),
+            body: String::from("body"),
+            labels: Vec::new(),
+            draft,
+            auto_merge: None,
+        }
+    }
+
+    fn pr_json(server: &MockServer, draft: bool) -> serde_json::Value {
+        serde_json::json!({
+            "url": format!("{}/api/v3/repos/org/repo/pulls/7", server.uri()),
+            "id": 7,
+            "node_id": "PR_kwDO7",
+            "number": 7,
+            "locked": false,
+            "draft": draft,
+            "maintainer_can_modify": true,
+            "head": {"ref": "ratchet", "sha": "abc"},
+            "base": {"ref": "main", "sha": "def"}
+        })
+    }
+
+    #[tokio::test]
+    async fn test_created_pr_is_labeled() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/pulls"))
+            .respond_with(ResponseTemplate::new(201).set_body_json(pr_json(&server, false)))
+            .expect(1)
+            .mount(&server)
+            .await;
+        mock_labels(&server, "/api/v3/repos/org/repo/issues/7/labels", &[]).await;
+        mock_labels(&server, "/api/v3/repos/org/repo/labels", &["dependencies"]).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/labels"))
+            .respond_with(ResponseTemplate::new(201))
+            .expect(0)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/issues/7/labels"))
+            .and(body_json(serde_json::json!({"labels": ["dependencies"]})))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(labels_json(&server, &["dependencies"])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0).with_labels(
+            vec![String::from("dependencies"), String::from("security")],
+            false,
+        );
+        let pr = client
+            .create_pull_request(&pr_content(false))
+            .await
+            .unwrap();
+        assert_eq!(pr.number, 7);
+    }
+
+    #[tokio::test]
+    async fn test_draft_pr_is_created() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/pulls"))
+            .and(body_partial_json(serde_json::json!({"draft": true})))
+            .respond_with(ResponseTemplate::new(201).set_body_json(pr_json(&server, true)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        let pr = client.create_pull_request(&pr_content(true)).await.unwrap();
+        assert!(pr.draft);
+    }
+
+    fn pr_with_body(server: &MockServer, title: &str, body: &str) -> serde_json::Value {
+        let mut pr = pr_json(server, false);
+        pr["title"] = serde_json::json!(title);
+        pr["body"] = serde_json::json!(body);
+        pr
+    }
+
+    #[tokio::test]
+    async fn test_update_pull_request_with_new_body() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(pr_with_body(&server, "title", "stale")),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .and(body_partial_json(
+                serde_json::json!({"title": "title", "body": "fresh"}),
+            ))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(pr_with_body(&server, "title", "fresh")),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        assert!(client
+            .update_pull_request(7, "title", "fresh")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_pull_request_is_not_updated() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(pr_with_body(&server, "title", "same")),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(0)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        assert!(!client
+            .update_pull_request(7, "title", "same")
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_find_closed_pr_and_reopen() {
+        let server = MockServer::start().await;
+        let mut merged = pr_json(&server, false);
+        merged["state"] = serde_json::json!("closed");
+        merged["merged_at"] = serde_json::json!("2024-05-01T00:00:00Z");
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls"))
+            .and(query_param("state", "closed"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([merged])))
+            .mount(&server)
+            .await;
+        Mock::given(method("PATCH"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .and(body_partial_json(serde_json::json!({"state": "open"})))
+            .respond_with(ResponseTemplate::new(200).set_body_json(pr_json(&server, false)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        let closed = client.find_closed_pr("ratchet").await.unwrap().unwrap();
+        assert_eq!(closed.pr.number, 7);
+        assert!(closed.merged);
+        assert_eq!(client.reopen_pull_request(7).await.unwrap().number, 7);
+    }
+
+    #[tokio::test]
+    async fn test_mark_ready_for_review() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(pr_json(&server, true)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/graphql"))
+            .and(body_partial_json(
+                serde_json::json!({"variables": {"id": "PR_kwDO7"}}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {"markPullRequestReadyForReview": {"pullRequest": {"isDraft": false}}}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        client.mark_ready_for_review(7).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_enable_auto_merge() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(pr_json(&server, false)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/graphql"))
+            .and(body_partial_json(
+                serde_json::json!({"variables": {"id": "PR_kwDO7", "method": "SQUASH"}}),
+            ))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {"enablePullRequestAutoMerge": {"pullRequest": {"number": 7}}}
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        client
+            .enable_auto_merge(7, MergeMethod::Squash)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_auto_merge_not_allowed() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/org/repo/pulls/7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(pr_json(&server, false)))
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/graphql"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "data": {"enablePullRequestAutoMerge": null},
+                "errors": [{
+                    "type": "UNPROCESSABLE",
+                    "message": "Pull request Auto merge is not allowed for this repository"
+                }]
+            })))
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        let err = client
+            .enable_auto_merge(7, MergeMethod::Merge)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Pull request Auto merge is not allowed for this repository"
+        );
+    }
+
+    async fn mock_repository(server: &MockServer, repository: &str, response: ResponseTemplate) {
+        Mock::given(method("GET"))
+            .and(path(format!("/api/v3/repos/{}", repository)))
+            .respond_with(response)
+            .mount(server)
+            .await;
+    }
+
+    fn action_repository_json(
+        server: &MockServer,
+        full_name: &str,
+        archived: bool,
+    ) -> serde_json::Value {
+        serde_json::json!({
+            "id": 1,
+            "name": full_name.rsplit('/').next().unwrap(),
+            "full_name": full_name,
+            "url": format!("{}/api/v3/repos/{}", server.uri(), full_name),
+            "archived": archived
+        })
+    }
+
+    #[tokio::test]
+    async fn test_repository_status() {
+        let server = MockServer::start().await;
+        mock_repository(
+            &server,
+            "actions/active",
+            ResponseTemplate::new(200).set_body_json(action_repository_json(
+                &server,
+                "actions/Active",
+                false,
+            )),
+        )
+        .await;
+        mock_repository(
+            &server,
+            "actions/archived",
+            ResponseTemplate::new(200).set_body_json(action_repository_json(
+                &server,
+                "actions/archived",
+                true,
+            )),
+        )
+        .await;
+        mock_repository(
+            &server,
+            "actions/renamed",
+            ResponseTemplate::new(200).set_body_json(action_repository_json(
+                &server,
+                "new-owner/renamed",
+                false,
+            )),
+        )
+        .await;
+        mock_repository(
+            &server,
+            "actions/deleted",
+            ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "message": "Not Found",
+                "documentation_url": "https://docs.github.com"
+            })),
+        )
+        .await;
+
+        let client = retrying_client(&server, 0);
+        let status = |repo: &'static str| {
+            let client = client.clone();
+            async move { client.repository_status("actions", repo).await.unwrap() }
+        };
+        assert_eq!(status("active").await, SourceStatus::Active);
+        assert_eq!(status("archived").await, SourceStatus::Archived);
+        assert_eq!(
+            status("renamed").await,
+            SourceStatus::Moved {
+                moved_to: String::from("new-owner/renamed")
+            }
+        );
+        assert_eq!(status("deleted").await, SourceStatus::Missing);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ref() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/actions/checkout/commits/v4"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "sha": "b4ffde65f46336ab88eb53be808477a3936bae11"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/api/v3/repos/actions/checkout/commits/v99"))
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "message": "No commit found for SHA: v99",
+                "documentation_url": "https://docs.github.com"
+            })))
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        assert_eq!(
+            client
+                .resolve_ref("actions", "checkout", "v4")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("b4ffde65f46336ab88eb53be808477a3936bae11")
+        );
+        assert_eq!(
+            client
+                .resolve_ref("actions", "checkout", "v99")
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unknown_sha_is_not_reachable() {
+        let server = MockServer::start().await;
+        let unknown = "0".repeat(40);
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/api/v3/repos/actions/checkout/compare/{}...v4",
+                unknown
+            )))
+            .respond_with(ResponseTemplate::new(404).set_body_json(serde_json::json!({
+                "message": "Not Found",
+                "documentation_url": "https://docs.github.com"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/api/v3/repos/actions/checkout/compare/{}...v99",
+                unknown
+            )))
+            .respond_with(ResponseTemplate::new(422).set_body_json(serde_json::json!({
+                "message": "No common ancestor between v99 and 0000000",
+                "documentation_url": "https://docs.github.com"
+            })))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/api/v3/repos/actions/checkout/compare/{}...v5",
+                unknown
+            )))
+            .respond_with(ResponseTemplate::new(500))
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0);
+        for tag in ["v4", "v99"] {
+            assert!(!client
+                .is_reachable_from("actions", "checkout", &unknown, tag)
+                .await
+                .unwrap());
+        }
+        // Anything else is still an error
+        assert!(client
+            .is_reachable_from("actions", "checkout", &unknown, "v5")
+            .await
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_missing_labels_are_created() {
+        let server = MockServer::start().await;
+        mock_labels(
+            &server,
+            "/api/v3/repos/org/repo/issues/7/labels",
+            &["dependencies"],
+        )
+        .await;
+        mock_labels(&server, "/api/v3/repos/org/repo/labels", &["dependencies"]).await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/labels"))
+            .respond_with(
+                ResponseTemplate::new(201)
+                    .set_body_json(labels_json(&server, &["security"])[0].clone()),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/api/v3/repos/org/repo/issues/7/labels"))
+            .and(body_json(serde_json::json!({"labels": ["security"]})))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(labels_json(&server, &["dependencies", "security"])),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = retrying_client(&server, 0).with_labels(
+            vec![String::from("dependencies"), String::from("security")],
+            true,
+        );
+        assert_eq!(
+            client.add_missing_labels(7).await.unwrap(),
+            [String::from("security")]
+        );
+    }
+
+    #[test]
+    fn test_backoff_delay_grows_exponentially() {
+        let base = Duration::from_millis(100);
+        for attempt in 1..5 {
+            let delay = backoff_delay(base, attempt);
+            let exponential = base * 2u32.pow(attempt - 1);
+            assert!(delay >= exponential && delay < exponential + base);
+        }
     }
 }
@@ -0,0 +1,2278 @@
+// Local end-to-end test harness.
+// Spins up bare "origin" repositories on disk, runs the full Dispatcher against them
+// with a fake ratchet and a recording GitHub client, and exposes helpers to assert
+// on the remote branches and the pull requests that would have been created.
+use std::{
+    collections::BTreeMap,
+    fs,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use clap::Parser;
+use git2::{Index, IndexEntry, IndexTime, Oid, Repository, Signature};
+use secrecy::Secret;
+use tempfile::TempDir;
+
+use crate::{
+    auth::Credentials,
+    dispatcher::Dispatcher,
+    github::{
+        BranchRefs, ClosedPullRequest, CreateRefOutcome, ForkRef, Forks, IssueRef, IssueTracker,
+        MergeMethod, PullRequestRef, PullRequests, RepoInfo, SourceStatus, TagLookup, TagRef,
+        WorkflowPullRequest,
+    },
+    io::DEFAULT_PR_BODY,
+    pr_content::PullRequestContent,
+    Args,
+};
+
+// Replaces every unpinned fixture action and image with its pinned form, like ratchet pin would.
+// The comment of an existing checkout pin is rewritten too, like a newer ratchet does.
+// A file with a `# fail-ratchet` line fails the run before any file is written, like a
+// file ratchet can not parse.
+const FAKE_RATCHET: &str = r#"#!/bin/sh
+[ "$1" = "--version" ] && echo "ratchet local-e2e" && exit 0
+[ "$1" = "pin" ] || exit 1
+shift
+for file in "$@"; do
+  grep -q '^# fail-ratchet$' "$file" && echo "failed to parse $file" >&2 && exit 1
+done
+for file in "$@"; do
+sed -i \
+  -e 's|\(uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744\) # ratchet:actions/checkout@v3$|\1 # ratchet:actions/checkout@v3.6.0|' \
+  -e 's|uses: actions/checkout@v3$|uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3|' \
+  -e 's|uses: actions/setup-node@v3$|uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # ratchet:actions/setup-node@v3|' \
+  -e 's|^image: node:20$|image: node@sha256:6f5ba5c1bb8a1b8fd3a48f3a80ab6a1cb3ba7c4f7d4f7da5c8b7b5a1ab1b6e5d # ratchet:node:20|' \
+  "$file"
+done
+"#;
+
+// Account the forks of --fork are created in without --fork-org
+pub const FORK_OWNER: &str = "bot";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecordedPr {
+    pub repository: String,
+    pub branch: String,
+    pub base: String,
+    pub body: String,
+    pub draft: bool,
+    // Merge method of --auto-merge, once enabled
+    pub auto_merge: Option<MergeMethod>,
+    pub state: RecordedState,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordedState {
+    Open,
+    Closed,
+    Merged,
+}
+
+pub struct LocalE2e {
+    dir: TempDir,
+    prs: Arc<Mutex<Vec<RecordedPr>>>,
+    // Token handed to the client of every processed repository
+    api_tokens: Arc<Mutex<Vec<String>>>,
+    // Repositories marked archived or fork by set_repo_flags
+    flags: Arc<Mutex<BTreeMap<String, (bool, bool)>>>,
+    // Set by refuse_reopen, closed pull requests can not be reopened
+    reopen_refused: Arc<Mutex<bool>>,
+}
+
+impl LocalE2e {
+    pub fn new() -> Self {
+        let dir = TempDir::new().unwrap();
+        let ratchet = dir.path().join("ratchet");
+        fs::write(&ratchet, FAKE_RATCHET).unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+        LocalE2e {
+            dir,
+            prs: Arc::new(Mutex::new(Vec::new())),
+            api_tokens: Arc::new(Mutex::new(Vec::new())),
+            flags: Arc::new(Mutex::new(BTreeMap::new())),
+            reopen_refused: Arc::new(Mutex::new(false)),
+        }
+    }
+
+    fn origins(&self) -> PathBuf {
+        self.dir.path().join("origins")
+    }
+
+    fn origin_path(&self, repository: &str) -> PathBuf {
+        self.origins().join(format!("{}.git", repository))
+    }
+
+    // Create a bare origin for <repository> (owner/repo) with one commit on main
+    // containing the given workflow files (name, content)
+    pub fn add_repository(&self, repository: &str, workflows: &[(&str, &str)]) {
+        let files: Vec<(String, &str)> = workflows
+            .iter()
+            .map(|(name, content)| (format!(".github/workflows/{}", name), *content))
+            .collect();
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, content)| (path.as_str(), *content))
+            .collect();
+        self.add_repository_files(repository, &files);
+    }
+
+    // Create a bare origin for <repository> (owner/repo) with one commit on main
+    // containing the given files (path relative to the repository root, content)
+    pub fn add_repository_files(&self, repository: &str, files: &[(&str, &str)]) {
+        let repo = Repository::init_bare(self.origin_path(repository)).unwrap();
+        commit_files(&repo, "refs/heads/main", files);
+        repo.set_head("refs/heads/main").unwrap();
+    }
+
+    // Add <branch> to the origin of <repository>, with a commit on top of main whose
+    // tree is only the given files
+    pub fn add_branch(&self, repository: &str, branch: &str, files: &[(&str, &str)]) {
+        let repo = Repository::open_bare(self.origin_path(repository)).unwrap();
+        repo.reference(
+            &format!("refs/heads/{}", branch),
+            repo.refname_to_id("refs/heads/main").unwrap(),
+            false,
+            "branch from main",
+        )
+        .unwrap();
+        commit_files(&repo, &format!("refs/heads/{}", branch), files);
+    }
+
+    // Dispatcher wired to the local origins, the fake ratchet and the recording client.
+    // <args> are the command line arguments, without the program name.
+    pub fn dispatcher(&self, args: &[&str]) -> Dispatcher {
+        self.dispatcher_with_credentials(
+            args,
+            Credentials::Token(Secret::new(String::from("local-e2e"))),
+        )
+    }
+
+    // Like dispatcher, authenticating with <credentials>
+    pub fn dispatcher_with_credentials(
+        &self,
+        args: &[&str],
+        credentials: Credentials,
+    ) -> Dispatcher {
+        let clone_dir = self.dir.path().join("clones");
+        let mut argv = vec![
+            "ratchet-dispatcher",
+            "--clone-dir",
+            clone_dir.to_str().unwrap(),
+            "--reproducible-commits",
+        ];
+        argv.extend_from_slice(args);
+        let args = Args::parse_from(argv);
+        let pr_body = match &args.pr_body_path {
+            Some(path) => fs::read_to_string(path).unwrap(),
+            None => String::from(DEFAULT_PR_BODY),
+        };
+
+        let origins = self.origins();
+        let prs = self.prs.clone();
+        let api_tokens = self.api_tokens.clone();
+        let flags = self.flags.clone();
+        let reopen_refused = self.reopen_refused.clone();
+        Dispatcher::new(args, Arc::new(credentials))
+            .with_pr_body(pr_body)
+            .with_clone_url_base(self.origins().to_str().unwrap())
+            .with_ratchet_path(self.dir.path().join("ratchet").to_str().unwrap())
+            .with_clients(Box::new(move |owner, repo, token| {
+                api_tokens.lock().unwrap().push(token.to_string());
+                let repository = format!("{}/{}", owner, repo);
+                let (archived, fork) = flags
+                    .lock()
+                    .unwrap()
+                    .get(&repository)
+                    .copied()
+                    .unwrap_or_default();
+                Box::new(RecordingClient {
+                    origin: origins.join(format!("{}.git", repository)),
+                    repository,
+                    prs: prs.clone(),
+                    archived,
+                    fork,
+                    reopen_refused: *reopen_refused.lock().unwrap(),
+                })
+            }))
+            .with_tag_lookup(Box::new(NoTags))
+    }
+
+    pub fn api_tokens(&self) -> Vec<String> {
+        self.api_tokens.lock().unwrap().clone()
+    }
+
+    pub fn pull_requests(&self) -> Vec<RecordedPr> {
+        self.prs.lock().unwrap().clone()
+    }
+
+    // Report <repository> as archived or as a fork from now on
+    pub fn set_repo_flags(&self, repository: &str, archived: bool, fork: bool) {
+        self.flags
+            .lock()
+            .unwrap()
+            .insert(repository.to_string(), (archived, fork));
+    }
+
+    // Fail every reopen of a closed pull request from now on, like GitHub does once
+    // the branch of the pull request was force-pushed
+    pub fn refuse_reopen(&self) {
+        *self.reopen_refused.lock().unwrap() = true;
+    }
+
+    // Close or merge the recorded pull request <number>
+    pub fn set_pr_state(&self, number: u64, state: RecordedState) {
+        self.prs.lock().unwrap()[number as usize - 1].state = state;
+    }
+
+    pub fn remote_branches(&self, repository: &str) -> Vec<String> {
+        let repo = Repository::open_bare(self.origin_path(repository)).unwrap();
+        let mut branches: Vec<String> = repo
+            .branches(Some(git2::BranchType::Local))
+            .unwrap()
+            .map(|branch| branch.unwrap().0.name().unwrap().unwrap().to_string())
+            .collect();
+        branches.sort();
+        branches
+    }
+
+    pub fn remote_head(&self, repository: &str, branch: &str) -> Oid {
+        let repo = Repository::open_bare(self.origin_path(repository)).unwrap();
+        let oid = repo
+            .find_reference(&format!("refs/heads/{}", branch))
+            .unwrap()
+            .peel_to_commit()
+            .unwrap()
+            .id();
+        oid
+    }
+
+    // Content of <path> on <branch> of the origin, None when the file does not exist
+    pub fn remote_file(&self, repository: &str, branch: &str, path: &str) -> Option<String> {
+        let repo = Repository::open_bare(self.origin_path(repository)).unwrap();
+        let reference = repo
+            .find_reference(&format!("refs/heads/{}", branch))
+            .ok()?;
+        let tree = reference.peel_to_tree().unwrap();
+        let entry = tree.get_path(Path::new(path)).ok()?;
+        let blob = repo.find_blob(entry.id()).unwrap();
+        Some(String::from_utf8_lossy(blob.content()).to_string())
+    }
+}
+
+// Commit <files> (path relative to the repository root, content) as the whole tree
+// of <reference>, on top of its current commit when it has one
+fn commit_files(repo: &Repository, reference: &str, files: &[(&str, &str)]) {
+    let mut index = Index::new().unwrap();
+    for (path, content) in files {
+        index
+            .add(&IndexEntry {
+                ctime: IndexTime::new(0, 0),
+                mtime: IndexTime::new(0, 0),
+                dev: 0,
+                ino: 0,
+                mode: 0o100644,
+                uid: 0,
+                gid: 0,
+                file_size: content.len() as u32,
+                id: repo.blob(content.as_bytes()).unwrap(),
+                flags: 0,
+                flags_extended: 0,
+                path: path.as_bytes().to_vec(),
+            })
+            .unwrap();
+    }
+    let tree = repo.find_tree(index.write_tree_to(repo).unwrap()).unwrap();
+
+    let signature = Signature::now("fixture", "fixture@example.com").unwrap();
+    let parent = repo
+        .find_reference(reference)
+        .and_then(|parent| parent.peel_to_commit())
+        .ok();
+    repo.commit(
+        Some(reference),
+        &signature,
+        &signature,
+        "add workflows",
+        &tree,
+        &parent.iter().collect::<Vec<_>>(),
+    )
+    .unwrap();
+}
+
+impl Default for LocalE2e {
+    fn default() -> Self {
+        LocalE2e::new()
+    }
+}
+
+// GitHub client that records pull requests instead of creating them
+struct RecordingClient {
+    repository: String,
+    origin: PathBuf,
+    prs: Arc<Mutex<Vec<RecordedPr>>>,
+    archived: bool,
+    fork: bool,
+    reopen_refused: bool,
+}
+
+impl RecordingClient {
+    fn pr_ref(&self, prs: &[RecordedPr], index: usize) -> PullRequestRef {
+        PullRequestRef {
+            number: index as u64 + 1,
+            html_url: format!("local://{}/pull/{}", self.repository, index + 1),
+            draft: prs[index].draft,
+        }
+    }
+}
+
+#[async_trait]
+impl PullRequests for RecordingClient {
+    async fn create_pull_request(
+        &self,
+        content: &PullRequestContent,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>> {
+        let mut prs = self.prs.lock().unwrap();
+        prs.push(RecordedPr {
+            repository: self.repository.clone(),
+            branch: content.branch.clone(),
+            base: content.base.clone(),
+            body: content.body.clone(),
+            draft: content.draft,
+            auto_merge: None,
+            state: RecordedState::Open,
+        });
+        Ok(PullRequestRef {
+            number: prs.len() as u64,
+            html_url: format!("local://{}/pull/{}", self.repository, prs.len()),
+            draft: content.draft,
+        })
+    }
+
+    async fn find_existing_pr(
+        &self,
+        branch: &str,
+    ) -> Result<Option<PullRequestRef>, Box<dyn std::error::Error>> {
+        let prs = self.prs.lock().unwrap();
+        Ok(prs
+            .iter()
+            .position(|pr| {
+                pr.repository == self.repository
+                    && pr.branch == branch
+                    && pr.state == RecordedState::Open
+            })
+            .map(|index| self.pr_ref(&prs, index)))
+    }
+
+    async fn find_closed_pr(
+        &self,
+        branch: &str,
+    ) -> Result<Option<ClosedPullRequest>, Box<dyn std::error::Error>> {
+        let prs = self.prs.lock().unwrap();
+        Ok(prs
+            .iter()
+            .rposition(|pr| {
+                pr.repository == self.repository
+                    && pr.branch == branch
+                    && pr.state != RecordedState::Open
+            })
+            .map(|index| ClosedPullRequest {
+                pr: self.pr_ref(&prs, index),
+                merged: prs[index].state == RecordedState::Merged,
+            }))
+    }
+
+    async fn reopen_pull_request(
+        &self,
+        number: u64,
+    ) -> Result<PullRequestRef, Box<dyn std::error::Error>> {
+        let mut prs = self.prs.lock().unwrap();
+        let index = number as usize - 1;
+        let pr = prs
+            .get_mut(index)
+            .ok_or_else(|| format!("PR #{} not found", number))?;
+        if pr.state != RecordedState::Closed {
+            return Err(Box::from(format!("PR #{} is not closed", number)));
+        }
+        if self.reopen_refused {
+            return Err(Box::from(format!("PR #{} can not be reopened", number)));
+        }
+        pr.state = RecordedState::Open;
+        Ok(self.pr_ref(&prs, index))
+    }
+
+    async fn get_repo_info(&self) -> Result<RepoInfo, Box<dyn std::error::Error>> {
+        Ok(RepoInfo {
+            default_branch: String::from("main"),
+            archived: self.archived,
+            fork: self.fork,
+        })
+    }
+
+    async fn list_open_workflow_prs(
+        &self,
+        _base: &str,
+    ) -> Result<Vec<WorkflowPullRequest>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+
+    async fn add_missing_labels(
+        &self,
+        _number: u64,
+    ) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        Ok(Vec::new())
+    }
+
+    async fn update_pull_request(
+        &self,
+        number: u64,
+        _title: &str,
+        body: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut prs = self.prs.lock().unwrap();
+        let pr = prs
+            .get_mut(number as usize - 1)
+            .ok_or_else(|| format!("PR #{} not found", number))?;
+        if pr.body == body {
+            return Ok(false);
+        }
+        pr.body = body.to_string();
+        Ok(true)
+    }
+
+    async fn mark_ready_for_review(&self, number: u64) -> Result<(), Box<dyn std::error::Error>> {
+        let mut prs = self.prs.lock().unwrap();
+        let pr = prs
+            .get_mut(number as usize - 1)
+            .ok_or_else(|| format!("PR #{} not found", number))?;
+        pr.draft = false;
+        Ok(())
+    }
+
+    async fn enable_auto_merge(
+        &self,
+        number: u64,
+        method: MergeMethod,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut prs = self.prs.lock().unwrap();
+        let pr = prs
+            .get_mut(number as usize - 1)
+            .ok_or_else(|| format!("PR #{} not found", number))?;
+        pr.auto_merge = Some(method);
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl BranchRefs for RecordingClient {
+    async fn branch_sha(&self, branch: &str) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        let repo = Repository::open_bare(&self.origin)?;
+        let sha = repo
+            .find_reference(&format!("refs/heads/{}", branch))
+            .ok()
+            .and_then(|reference| reference.target())
+            .map(|oid| oid.to_string());
+        Ok(sha)
+    }
+
+    async fn create_branch_ref(
+        &self,
+        name: &str,
+        sha: &str,
+    ) -> Result<CreateRefOutcome, Box<dyn std::error::Error>> {
+        let repo = Repository::open_bare(&self.origin)?;
+        repo.reference(
+            &format!("refs/heads/{}", name),
+            Oid::from_str(sha)?,
+            false,
+            "create branch",
+        )?;
+        Ok(CreateRefOutcome::Created)
+    }
+
+    async fn delete_branch_ref(&self, name: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let repo = Repository::open_bare(&self.origin)?;
+        repo.find_reference(&format!("refs/heads/{}", name))?
+            .delete()?;
+        Ok(())
+    }
+}
+
+// Forks are bare clones of the origin under FORK_OWNER, next to the other origins
+#[async_trait]
+impl Forks for RecordingClient {
+    async fn create_fork<'a>(
+        &self,
+        organization: Option<&'a str>,
+    ) -> Result<ForkRef, Box<dyn std::error::Error>> {
+        let (_, name) = self.repository.split_once('/').unwrap();
+        let fork = ForkRef {
+            owner: organization.unwrap_or(FORK_OWNER).to_string(),
+            repo: name.to_string(),
+        };
+        let path = fork_path(&self.origin, &fork);
+        if !path.exists() {
+            git2::build::RepoBuilder::new()
+                .bare(true)
+                .clone(self.origin.to_str().unwrap(), &path)?;
+        }
+        Ok(fork)
+    }
+
+    async fn fork_has_branch(
+        &self,
+        fork: &ForkRef,
+        branch: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let repo = Repository::open_bare(fork_path(&self.origin, fork))?;
+        let found = repo
+            .find_reference(&format!("refs/heads/{}", branch))
+            .is_ok();
+        Ok(found)
+    }
+}
+
+// <origins>/<fork owner>/<repo>.git, for the origin at <origins>/<owner>/<repo>.git
+fn fork_path(origin: &Path, fork: &ForkRef) -> PathBuf {
+    origin
+        .parent()
+        .and_then(Path::parent)
+        .unwrap()
+        .join(&fork.owner)
+        .join(format!("{}.git", fork.repo))
+}
+
+#[async_trait]
+impl IssueTracker for RecordingClient {
+    async fn find_open_issue(
+        &self,
+        _marker: &str,
+    ) -> Result<Option<IssueRef>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn create_issue(
+        &self,
+        _title: &str,
+        _body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        Err(Box::from("Issues are not supported by the local harness"))
+    }
+
+    async fn update_issue(
+        &self,
+        _number: u64,
+        _body: &str,
+    ) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        Err(Box::from("Issues are not supported by the local harness"))
+    }
+
+    async fn close_issue(&self, _number: u64) -> Result<IssueRef, Box<dyn std::error::Error>> {
+        Err(Box::from("Issues are not supported by the local harness"))
+    }
+}
+
+// Action repositories are never reachable from the harness
+struct NoTags;
+
+#[async_trait]
+impl TagLookup for NoTags {
+    async fn list_tags(
+        &self,
+        _owner: &str,
+        _repo: &str,
+    ) -> Result<Option<Vec<TagRef>>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn is_reachable_from(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _sha: &str,
+        _tag: &str,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        Ok(false)
+    }
+
+    async fn resolve_ref(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _reference: &str,
+    ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn commit_date(
+        &self,
+        _owner: &str,
+        _repo: &str,
+        _sha: &str,
+    ) -> Result<Option<chrono::DateTime<chrono::Utc>>, Box<dyn std::error::Error>> {
+        Ok(None)
+    }
+
+    async fn repository_status(
+        &self,
+        _owner: &str,
+        _repo: &str,
+    ) -> Result<SourceStatus, Box<dyn std::error::Error>> {
+        Ok(SourceStatus::Active)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        approval::{Approval, Approver, SKIPPED_BY_USER},
+        auth::{Clock, InstallationToken, MockClock, MockTokenSource, TokenManager},
+        budget::DEFERRED_BUDGET_EXHAUSTED,
+        discovery::{SKIPPED_ARCHIVED, SKIPPED_FORK},
+        dispatcher::DEFERRED_FAIL_FAST,
+        dry_run::write_index,
+        git::FORK_REMOTE,
+        interrupt::DEFERRED_INTERRUPTED,
+        ledger::{Ledger, SKIPPED_RESUMED},
+        output::{logger_builder, LogFormat},
+        progress::{describe_outcome, Phase, Progress},
+        ratchet::{ALREADY_PINNED, NOTHING_TO_COMMIT, NO_WORKFLOWS},
+        repo_config::SKIPPED_BY_REPO_CONFIG,
+        state::{StateFile, SKIPPED_UNCHANGED},
+        summary::{
+            KeptClone, RepoStatus, RepoSummary, EXIT_CHANGES_PENDING, EXIT_FAILED,
+            EXIT_SOME_FAILED, KEPT_ALWAYS, KEPT_ON_ERROR,
+        },
+    };
+    use chrono::{DateTime, Duration, Utc};
+    use env_logger::Target;
+    use log::LevelFilter;
+
+    const UNPINNED_WORKFLOW: &str = include_str!("../resources/ci_unpinned.yml");
+    const PINNED_WORKFLOW: &str = include_str!("../resources/ci_pinned.yml");
+    const UNPINNED_ACTION: &str = include_str!("../resources/action_unpinned.yml");
+    const PINNED_ACTION: &str = include_str!("../resources/action_pinned.yml");
+    const UNPINNED_GITLAB: &str = include_str!("../resources/gitlab_unpinned.yml");
+    const PINNED_GITLAB: &str = include_str!("../resources/gitlab_pinned.yml");
+    const BRANCH: &str = "automated-ratchet-dispatcher-pin";
+
+    #[tokio::test]
+    async fn test_e2e_pins_workflows_and_opens_pr() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(harness.remote_branches("org/app"), vec![BRANCH, "main"]);
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+        assert_eq!(
+            harness
+                .remote_file("org/app", "main", ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(UNPINNED_WORKFLOW)
+        );
+
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].branch, BRANCH);
+        assert_eq!(prs[0].base, "main");
+        assert!(prs[0]
+            .body
+            .contains("from **2** unpinned action(s) to **0**"));
+        assert!(prs[0]
+            .body
+            .contains("- actions/checkout v3 -> f43a0e5ff2bd294095638e18286ca9a3d1956744"));
+        assert!(prs[0].body.contains("| `.github/workflows/ci.yml` | `actions/checkout` | `v3` | [`f43a0e5ff2bd294095638e18286ca9a3d1956744`](https://github.com/actions/checkout/commit/f43a0e5ff2bd294095638e18286ca9a3d1956744) | [v3](https://github.com/actions/checkout/releases/tag/v3) |"));
+        assert!(prs[0].body.contains("| ratchet local-e2e |"));
+        assert!(prs[0].body.contains("Ratchet fingerprint: `"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_rerun_force_pushes_without_new_pr() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dispatcher = harness.dispatcher(&["--repos", "org/app"]);
+
+        dispatcher.run(&[String::from("org/app")]).await;
+        let first = harness.remote_head("org/app", BRANCH);
+        dispatcher.run(&[String::from("org/app")]).await;
+
+        // Reproducible commits over identical input give the same commit
+        assert_eq!(harness.remote_head("org/app", BRANCH), first);
+        assert_eq!(harness.pull_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_rerun_refreshes_pr_body() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        let template = harness.dir.path().join("body.md");
+        fs::write(&template, "Pins for {{repo}}").unwrap();
+        harness
+            .dispatcher(&[
+                "--repos",
+                "org/app",
+                "--pr-body-path",
+                template.to_str().unwrap(),
+            ])
+            .run(&[String::from("org/app")])
+            .await;
+
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert!(prs[0].body.contains("Pins for org/app"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_reopen_closed_pr() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dispatcher = harness.dispatcher(&["--repos", "org/app", "--reopen-closed"]);
+        dispatcher.run(&[String::from("org/app")]).await;
+        harness.set_pr_state(1, RecordedState::Closed);
+
+        let summary = dispatcher.run(&[String::from("org/app")]).await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Updated);
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].state, RecordedState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_merged_pr_gets_suffixed_branch() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dispatcher = harness.dispatcher(&["--repos", "org/app", "--reopen-closed"]);
+        dispatcher.run(&[String::from("org/app")]).await;
+        harness.set_pr_state(1, RecordedState::Merged);
+
+        let summary = dispatcher.run(&[String::from("org/app")]).await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 2);
+        assert_eq!(prs[0].state, RecordedState::Merged);
+        assert_eq!(prs[1].branch, format!("{}-2", BRANCH));
+        assert_eq!(prs[1].state, RecordedState::Open);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_prune_only_deletes_merged_branch() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+        let prune = harness.dispatcher(&["--repos", "org/app", "--prune-branches", "--prune-only"]);
+
+        // An open PR keeps its branch
+        let summary = prune.run(&[String::from("org/app")]).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Skipped);
+        assert_eq!(harness.remote_branches("org/app"), vec![BRANCH, "main"]);
+
+        harness.set_pr_state(1, RecordedState::Merged);
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/app",
+                "--prune-branches",
+                "--prune-only",
+                "--dry-run",
+            ])
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::DryRun);
+        assert_eq!(harness.remote_branches("org/app"), vec![BRANCH, "main"]);
+
+        let summary = prune.run(&[String::from("org/app")]).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Pruned);
+        assert_eq!(
+            summary.repositories[0].reason.as_deref(),
+            Some("deleted branch automated-ratchet-dispatcher-pin of merged PR #1")
+        );
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_ensure_base_branch() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--ensure-base-branch", "automation"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(
+            harness.remote_branches("org/app"),
+            vec![BRANCH, "automation", "main"]
+        );
+        assert_eq!(harness.pull_requests()[0].base, "automation");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_base_branch() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", PINNED_WORKFLOW)]);
+        harness.add_branch(
+            "org/app",
+            "develop",
+            &[(".github/workflows/ci.yml", UNPINNED_WORKFLOW)],
+        );
+
+        // Ratchet runs on develop, main has nothing to pin
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--base-branch", "develop"])
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(harness.pull_requests()[0].base, "develop");
+        let pinned = harness.remote_head("org/app", BRANCH);
+        let origin = Repository::open_bare(harness.origin_path("org/app")).unwrap();
+        assert_eq!(
+            origin.find_commit(pinned).unwrap().parent_id(0).unwrap(),
+            harness.remote_head("org/app", "develop")
+        );
+
+        // A missing base branch falls back to the default branch, or fails when strict
+        let args = [
+            "--repos",
+            "org/app",
+            "--base-branch",
+            "missing",
+            "--branch",
+            "other",
+        ];
+        let summary = harness
+            .dispatcher(&args)
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Skipped);
+        let summary = harness
+            .dispatcher(&[&args[..], &["--strict-base"]].concat())
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(
+            summary.repositories[0].error.as_deref(),
+            Some("Failed to check base branch missing: Base branch missing does not exist")
+        );
+        assert_eq!(harness.pull_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_state_file_skips_unchanged_repositories() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join("state.json");
+        let args = [
+            "--repos",
+            "org/app",
+            "--state-file",
+            state.to_str().unwrap(),
+        ];
+        let repositories = [String::from("org/app")];
+
+        let summary = harness.dispatcher(&args).run(&repositories).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        let recorded = StateFile::load(state.to_str().unwrap()).unwrap();
+        assert!(recorded.is_unchanged(
+            "org/app",
+            "main",
+            &harness.remote_head("org/app", "main").to_string()
+        ));
+
+        // The open PR is still looked after
+        let summary = harness.dispatcher(&args).run(&repositories).await;
+        assert_ne!(
+            summary.repositories[0].reason.as_deref(),
+            Some(SKIPPED_UNCHANGED)
+        );
+
+        // Once it is merged nothing changed on main, until --ignore-state
+        harness.set_pr_state(1, RecordedState::Merged);
+        let summary = harness.dispatcher(&args).run(&repositories).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Skipped);
+        assert_eq!(
+            summary.repositories[0].reason.as_deref(),
+            Some(SKIPPED_UNCHANGED)
+        );
+        assert_eq!(harness.pull_requests().len(), 1);
+
+        let summary = harness
+            .dispatcher(&[&args[..], &["--ignore-state"]].concat())
+            .run(&repositories)
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(harness.pull_requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_resume_skips_finished_repositories() {
+        let harness = LocalE2e::new();
+        for repository in ["org/a", "org/b", "org/c"] {
+            harness.add_repository(repository, &[("ci.yml", UNPINNED_WORKFLOW)]);
+        }
+        let repositories = [
+            String::from("org/a"),
+            String::from("org/b"),
+            String::from("org/c"),
+        ];
+        // The earlier run created the PR of org/a, failed org/b and died before org/c
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let path = path.to_str().unwrap();
+        std::fs::write(
+            path,
+            "{\"version\":1}\n\
+            {\"repository\":\"org/a\",\"status\":\"created\",\"pr_number\":7}\n\
+            {\"repository\":\"org/b\",\"status\":\"failed\",\"error\":\"Failed to clone repository\"}\n",
+        )
+        .unwrap();
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/a,org/b,org/c", "--resume", path])
+            .with_ledger(Ledger::resume(path).unwrap())
+            .run(&repositories)
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Skipped);
+        assert_eq!(
+            summary.repositories[0].reason.as_deref(),
+            Some(SKIPPED_RESUMED)
+        );
+        assert_eq!(summary.repositories[1].status, RepoStatus::Created);
+        assert_eq!(summary.repositories[2].status, RepoStatus::Created);
+        assert_eq!(harness.remote_branches("org/a"), vec!["main"]);
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 2);
+        assert_eq!(prs[0].repository, "org/b");
+        assert_eq!(prs[1].repository, "org/c");
+
+        // Everything is done now, a second resume touches nothing
+        let ledger = Ledger::resume(path).unwrap();
+        assert_eq!(ledger.finished(), 3);
+        let summary = harness
+            .dispatcher(&["--repos", "org/a,org/b,org/c", "--resume", path])
+            .with_ledger(ledger)
+            .run(&repositories)
+            .await;
+        assert!(summary
+            .repositories
+            .iter()
+            .all(|entry| entry.reason.as_deref() == Some(SKIPPED_RESUMED)));
+        assert_eq!(harness.pull_requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_skips_archived_repositories_and_forks() {
+        let harness = LocalE2e::new();
+        for repository in ["org/archived", "org/fork", "org/app"] {
+            harness.add_repository(repository, &[("ci.yml", UNPINNED_WORKFLOW)]);
+        }
+        harness.set_repo_flags("org/archived", true, false);
+        harness.set_repo_flags("org/fork", false, true);
+        let repositories = [
+            String::from("org/archived"),
+            String::from("org/fork"),
+            String::from("org/app"),
+        ];
+
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/archived,org/fork,org/app",
+                "--skip-forks",
+                "true",
+            ])
+            .run(&repositories)
+            .await;
+        let reasons: Vec<_> = summary
+            .repositories
+            .iter()
+            .map(|entry| (entry.status, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                (RepoStatus::Skipped, Some(SKIPPED_ARCHIVED)),
+                (RepoStatus::Skipped, Some(SKIPPED_FORK)),
+                (RepoStatus::Created, None),
+            ]
+        );
+        assert_eq!(harness.remote_branches("org/archived"), vec!["main"]);
+        assert_eq!(harness.remote_branches("org/fork"), vec!["main"]);
+
+        // A listed fork is processed unless --skip-forks is set
+        let summary = harness
+            .dispatcher(&["--repos", "org/fork"])
+            .run(&repositories[1..2])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_fork_opens_the_pr_from_the_fork() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let args = ["--repos", "org/app", "--fork", "--keep-clones"];
+
+        let summary = harness
+            .dispatcher(&args)
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].repository, "org/app");
+        assert_eq!(prs[0].branch, format!("{}:{}", FORK_OWNER, BRANCH));
+        assert_eq!(prs[0].base, "main");
+        // The upstream is never pushed to
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+        assert_eq!(harness.remote_branches("bot/app"), vec![BRANCH, "main"]);
+        let clone_path = &summary.repositories[0].kept_clone.as_ref().unwrap().path;
+        let clone = Repository::open(clone_path).unwrap();
+        assert_eq!(
+            clone.find_remote("origin").unwrap().url(),
+            harness.origin_path("org/app").to_str()
+        );
+        assert_eq!(
+            clone.find_remote(FORK_REMOTE).unwrap().url(),
+            harness.origin_path("bot/app").to_str()
+        );
+
+        // The second run finds the PR through the fork owner and updates it
+        fs::remove_dir_all(clone_path).unwrap();
+        let summary = harness
+            .dispatcher(&args)
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Updated);
+        assert_eq!(harness.pull_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_max_prs_defers_remaining_repositories() {
+        let harness = LocalE2e::new();
+        for repository in ["org/a", "org/b", "org/c"] {
+            harness.add_repository(repository, &[("ci.yml", UNPINNED_WORKFLOW)]);
+        }
+        let repositories = [
+            String::from("org/a"),
+            String::from("org/b"),
+            String::from("org/c"),
+        ];
+
+        harness
+            .dispatcher(&["--repos", "org/a,org/b,org/c", "--max-prs", "1"])
+            .run(&repositories)
+            .await;
+
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(harness.remote_branches("org/a"), vec![BRANCH, "main"]);
+        assert_eq!(harness.remote_branches("org/b"), vec!["main"]);
+        assert_eq!(harness.remote_branches("org/c"), vec!["main"]);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_failed_reopen_counts_against_max_prs() {
+        let harness = LocalE2e::new();
+        for repository in ["org/a", "org/b"] {
+            harness.add_repository(repository, &[("ci.yml", UNPINNED_WORKFLOW)]);
+        }
+        harness
+            .dispatcher(&["--repos", "org/a"])
+            .run(&[String::from("org/a")])
+            .await;
+        harness.set_pr_state(1, RecordedState::Closed);
+        harness.refuse_reopen();
+        let head = harness.remote_head("org/a", BRANCH);
+
+        // org/b takes the only PR, the new PR org/a would need instead of the
+        // reopened one does not fit in the budget anymore
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/b,org/a",
+                "--reopen-closed",
+                "--max-prs",
+                "1",
+            ])
+            .run(&[String::from("org/b"), String::from("org/a")])
+            .await;
+
+        let statuses: Vec<_> = summary
+            .repositories
+            .iter()
+            .map(|entry| (entry.status, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (RepoStatus::Created, None),
+                (RepoStatus::Deferred, Some(DEFERRED_BUDGET_EXHAUSTED)),
+            ]
+        );
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 2);
+        assert_eq!(prs[0].state, RecordedState::Closed);
+        assert_eq!(prs[1].repository, "org/b");
+        assert_eq!(harness.remote_head("org/a", BRANCH), head);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_run_summary() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/pinned", &[("ci.yml", PINNED_WORKFLOW)]);
+        let repositories = [
+            String::from("org/app"),
+            String::from("org/pinned"),
+            String::from("org/missing"),
+        ];
+        let dispatcher = harness.dispatcher(&["--repos", "org/app,org/pinned,org/missing"]);
+
+        let summary = dispatcher.run(&repositories).await;
+        let statuses: Vec<RepoStatus> = summary
+            .repositories
+            .iter()
+            .map(|entry| entry.status)
+            .collect();
+        assert_eq!(
+            statuses,
+            [RepoStatus::Created, RepoStatus::Skipped, RepoStatus::Failed]
+        );
+        let created = &summary.repositories[0];
+        assert_eq!(created.pr_number, Some(1));
+        assert_eq!(created.pr_url.as_deref(), Some("local://org/app/pull/1"));
+        assert_eq!(created.files_changed, 1);
+        assert_eq!(created.actions_pinned, 2);
+        assert_eq!((created.pins.before.tag, created.pins.after.sha), (2, 2));
+        assert_eq!(summary.pins.after.sha, 4);
+        assert!(summary.repositories[2].error.is_some());
+        assert_eq!(harness.remote_branches("org/pinned"), vec!["main"]);
+
+        let summary = dispatcher.run(&repositories[..1]).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Updated);
+        assert_eq!(summary.repositories[0].pr_number, Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_already_pinned_repository_is_skipped() {
+        let harness = LocalE2e::new();
+        // The fake ratchet leaves this comment alone, ratchet changes nothing
+        let pinned = PINNED_WORKFLOW.replace("checkout@v3\n", "checkout@v3.6.0\n");
+        harness.add_repository("org/pinned", &[("ci.yml", &pinned)]);
+        // Here it only rewrites a comment, which is not committed
+        harness.add_repository("org/churn", &[("ci.yml", PINNED_WORKFLOW)]);
+        let repositories = [String::from("org/pinned"), String::from("org/churn")];
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/pinned,org/churn"])
+            .run(&repositories)
+            .await;
+        let reasons: Vec<_> = summary
+            .repositories
+            .iter()
+            .map(|entry| (entry.status, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            reasons,
+            [
+                (RepoStatus::Skipped, Some(ALREADY_PINNED)),
+                (RepoStatus::Skipped, Some(NOTHING_TO_COMMIT)),
+            ]
+        );
+        assert_eq!(harness.remote_branches("org/pinned"), vec!["main"]);
+        assert_eq!(harness.remote_branches("org/churn"), vec!["main"]);
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_native_backend_pins_without_ratchet() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        fs::remove_file(harness.dir.path().join("ratchet")).unwrap();
+        let mut lookup = crate::github::MockTagLookup::new();
+        lookup
+            .expect_resolve_ref()
+            .returning(|_, repo, _| match repo {
+                "checkout" => Ok(Some(String::from(
+                    "f43a0e5ff2bd294095638e18286ca9a3d1956744",
+                ))),
+                _ => Ok(Some(String::from(
+                    "1a4442cacd436585916779262731d5b162bc6ec7",
+                ))),
+            });
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--backend", "native"])
+            .with_tag_lookup(Box::new(lookup))
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+        let prs = harness.pull_requests();
+        assert!(prs[0]
+            .body
+            .contains("- actions/checkout v3 -> f43a0e5ff2bd294095638e18286ca9a3d1956744"));
+        // No ratchet ran, the provenance records the native run instead
+        assert!(prs[0]
+            .body
+            .contains("`native pin .github/workflows/ci.yml` | unknown |"));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_failed_tag_lookup_fails_verify_pins() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let mut lookup = crate::github::MockTagLookup::new();
+        lookup.expect_list_tags().returning(|_, _| Ok(None));
+        lookup
+            .expect_resolve_ref()
+            .returning(|_, _, _| Err(Box::from("API rate limit exceeded")));
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--verify-pins=fail"])
+            .with_tag_lookup(Box::new(lookup))
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Failed);
+        assert!(summary.repositories[0]
+            .error
+            .as_deref()
+            .unwrap()
+            .contains("Failed to verify 2 pin(s) against their tag"));
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_failing_file_does_not_block_its_batch() {
+        let harness = LocalE2e::new();
+        let broken = format!("# fail-ratchet\n{}", UNPINNED_WORKFLOW);
+        harness.add_repository(
+            "org/app",
+            &[
+                ("a.yml", UNPINNED_WORKFLOW),
+                ("b.yml", &broken),
+                ("c.yml", UNPINNED_WORKFLOW),
+            ],
+        );
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        for file in ["a.yml", "c.yml"] {
+            assert_eq!(
+                harness
+                    .remote_file("org/app", BRANCH, &format!(".github/workflows/{}", file))
+                    .as_deref(),
+                Some(PINNED_WORKFLOW),
+                "{}",
+                file
+            );
+        }
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/b.yml")
+                .as_deref(),
+            Some(broken.as_str())
+        );
+    }
+
+    // Progress that keeps every call as a line
+    #[derive(Default)]
+    struct RecordingProgress(std::sync::Mutex<Vec<String>>);
+
+    impl Progress for RecordingProgress {
+        fn start(&self, index: usize, total: usize, repository: &str) {
+            let line = format!("start {}/{} {}", index, total, repository);
+            self.0.lock().unwrap().push(line);
+        }
+
+        fn phase(&self, phase: Phase) {
+            self.0.lock().unwrap().push(phase.to_string());
+        }
+
+        fn finish(&self, entry: &RepoSummary) {
+            let line = format!("finish {}", describe_outcome(entry));
+            self.0.lock().unwrap().push(line);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_progress_follows_the_phases() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/pinned", &[("ci.yml", PINNED_WORKFLOW)]);
+        let progress = Arc::new(RecordingProgress::default());
+
+        harness
+            .dispatcher(&["--repos", "org/app,org/pinned,not-a-repository"])
+            .with_progress(progress.clone())
+            .run(&[
+                String::from("org/app"),
+                String::from("org/pinned"),
+                String::from("not-a-repository"),
+            ])
+            .await;
+        assert_eq!(
+            *progress.0.lock().unwrap(),
+            [
+                "start 1/3 org/app",
+                "clone",
+                "pin",
+                "stage",
+                "push",
+                "PR",
+                "finish created PR #1",
+                "start 2/3 org/pinned",
+                "clone",
+                "pin",
+                "stage",
+                "finish skipped (no changes left to commit)",
+                "start 3/3 not-a-repository",
+                "finish failed: Invalid repository format: not-a-repository",
+            ]
+        );
+    }
+
+    // Approver answering from a script, keeping the repositories it was asked about
+    struct ScriptedApprover {
+        answers: std::sync::Mutex<Vec<Approval>>,
+        asked: Arc<std::sync::Mutex<Vec<String>>>,
+    }
+
+    impl Approver for ScriptedApprover {
+        fn approve(&self, repository: &str, diff: &str) -> Approval {
+            assert!(diff.contains("actions/checkout@"), "{}", diff);
+            self.asked.lock().unwrap().push(repository.to_string());
+            self.answers.lock().unwrap().remove(0)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_interactive_approval() {
+        let harness = LocalE2e::new();
+        let repositories = ["org/a", "org/b", "org/c", "org/d"].map(String::from);
+        for repo in &repositories {
+            harness.add_repository(repo, &[("ci.yml", UNPINNED_WORKFLOW)]);
+        }
+        let asked = Arc::new(std::sync::Mutex::new(Vec::new()));
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/a,org/b,org/c,org/d", "--interactive"])
+            .with_approver(Box::new(ScriptedApprover {
+                answers: std::sync::Mutex::new(vec![
+                    Approval::Skip,
+                    Approval::Push,
+                    Approval::Quit,
+                ]),
+                asked: asked.clone(),
+            }))
+            .run(&repositories)
+            .await;
+        let outcomes: Vec<_> = summary
+            .repositories
+            .iter()
+            .map(|entry| (entry.status, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            outcomes,
+            [
+                (RepoStatus::Skipped, Some(SKIPPED_BY_USER)),
+                (RepoStatus::Created, None),
+                (RepoStatus::Deferred, Some(DEFERRED_INTERRUPTED)),
+                (RepoStatus::Deferred, Some(DEFERRED_INTERRUPTED)),
+            ]
+        );
+        // Quitting stops the run, org/d is never asked about
+        assert_eq!(*asked.lock().unwrap(), ["org/a", "org/b", "org/c"]);
+        assert_eq!(harness.remote_branches("org/a"), vec!["main"]);
+        assert_eq!(harness.remote_branches("org/c"), vec!["main"]);
+        assert_eq!(harness.pull_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_exit_code_and_fail_fast() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let repositories = [String::from("not-a-repository"), String::from("org/app")];
+
+        let summary = harness
+            .dispatcher(&["--repos", "not-a-repository,org/app", "--fail-fast"])
+            .run(&repositories)
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Failed);
+        assert_eq!(summary.repositories[1].status, RepoStatus::Deferred);
+        assert_eq!(
+            summary.repositories[1].reason.as_deref(),
+            Some(DEFERRED_FAIL_FAST)
+        );
+        assert_eq!(summary.exit_code(), EXIT_SOME_FAILED);
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+
+        // Without --fail-fast the valid repository is still pinned
+        let summary = harness
+            .dispatcher(&["--repos", "not-a-repository,org/app"])
+            .run(&repositories)
+            .await;
+        assert_eq!(summary.repositories[1].status, RepoStatus::Created);
+        assert_eq!(summary.exit_code(), EXIT_SOME_FAILED);
+
+        let summary = harness
+            .dispatcher(&["--repos", "not-a-repository,org/missing"])
+            .run(&[
+                String::from("not-a-repository"),
+                String::from("org/missing"),
+            ])
+            .await;
+        assert_eq!(summary.exit_code(), EXIT_FAILED);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_pins_composite_action() {
+        let harness = LocalE2e::new();
+        harness.add_repository_files(
+            "org/app",
+            &[
+                (".github/workflows/ci.yml", PINNED_WORKFLOW),
+                (".github/actions/setup/action.yml", UNPINNED_ACTION),
+            ],
+        );
+
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/actions/setup/action.yml")
+                .as_deref(),
+            Some(PINNED_ACTION)
+        );
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+        assert_eq!(harness.pull_requests().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_pins_gitlab_ci() {
+        let harness = LocalE2e::new();
+        harness.add_repository_files(
+            "org/app",
+            &[
+                (".github/workflows/ci.yml", UNPINNED_WORKFLOW),
+                (".gitlab-ci.yml", UNPINNED_GITLAB),
+            ],
+        );
+
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/app",
+                "--pin-paths",
+                ".gitlab-ci.yml",
+                "--pin-paths",
+                ".github/workflows/**",
+            ])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(summary.repositories[0].files_changed, 2);
+        assert!(summary.repositories[0].corrupted_workflows.is_empty());
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".gitlab-ci.yml")
+                .as_deref(),
+            Some(PINNED_GITLAB)
+        );
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_repository_without_workflows_is_skipped() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/docs", &[]);
+        let repositories = [String::from("org/docs")];
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/docs"])
+            .run(&repositories)
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Skipped);
+        assert_eq!(
+            summary.repositories[0].reason.as_deref(),
+            Some(NO_WORKFLOWS)
+        );
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/docs", "--fail-on-missing-workflows"])
+            .run(&repositories)
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Failed);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_local_path_commits_without_pushing() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let checkout_path = harness.dir.path().join("checkout");
+        let checkout = Repository::clone(
+            harness.origin_path("org/app").to_str().unwrap(),
+            &checkout_path,
+        )
+        .unwrap();
+        fs::write(checkout_path.join("notes.txt"), "work in progress").unwrap();
+        let local_path = checkout_path.to_str().unwrap();
+
+        let summary = harness
+            .dispatcher(&["--local-path", local_path])
+            .run_local(local_path, None)
+            .await;
+
+        assert_eq!(summary.repositories[0].repository, "checkout");
+        assert_eq!(summary.repositories[0].status, RepoStatus::Committed);
+        assert_eq!(summary.repositories[0].actions_pinned, 2);
+        assert_eq!(
+            checkout.head().unwrap().shorthand(),
+            Some(BRANCH),
+            "the pins are committed to a new branch"
+        );
+        let pinned = checkout
+            .head()
+            .unwrap()
+            .peel_to_tree()
+            .unwrap()
+            .get_path(Path::new(".github/workflows/ci.yml"))
+            .unwrap()
+            .to_object(&checkout)
+            .unwrap();
+        assert_eq!(
+            pinned.as_blob().unwrap().content(),
+            PINNED_WORKFLOW.as_bytes()
+        );
+        assert!(checkout_path.join("notes.txt").exists());
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+        assert!(harness.api_tokens().is_empty());
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_local_path_push_opens_pr() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let checkout_path = harness.dir.path().join("checkout");
+        Repository::clone(
+            harness.origin_path("org/app").to_str().unwrap(),
+            &checkout_path,
+        )
+        .unwrap();
+        let local_path = checkout_path.to_str().unwrap();
+
+        let summary = harness
+            .dispatcher(&["--local-path", local_path, "--push", "--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+        assert_eq!(harness.pull_requests().len(), 1);
+        // The checkout is left in place
+        assert!(checkout_path.join(".git").exists());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_keep_clones_on_error() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/docs", &[]);
+        let clones = harness.dir.path().join("clones");
+
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/app,org/docs",
+                "--fail-on-missing-workflows",
+                "--keep-clones-on-error",
+            ])
+            .run(&[String::from("org/app"), String::from("org/docs")])
+            .await;
+
+        assert_eq!(summary.repositories[1].status, RepoStatus::Failed);
+        let docs = clones.join("org_docs").to_str().unwrap().to_string();
+        assert_eq!(
+            summary.kept_clones(),
+            [(
+                "org/docs",
+                &KeptClone {
+                    path: docs,
+                    reason: KEPT_ON_ERROR
+                }
+            )]
+        );
+        assert!(clones.join("org_docs").join(".git").exists());
+        assert!(!clones.join("org_app").exists());
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--keep-clones"])
+            .run(&[String::from("org/app")])
+            .await;
+        assert_eq!(
+            summary.repositories[0]
+                .kept_clone
+                .as_ref()
+                .map(|kept| kept.reason),
+            Some(KEPT_ALWAYS)
+        );
+        assert!(clones.join("org_app").join(".git").exists());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_stale_clone_dir_is_removed() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/lib", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        // Left behind by a run that crashed halfway through cloning
+        let clones = harness.dir.path().join("clones");
+        fs::create_dir_all(clones.join("org_app").join(".git")).unwrap();
+        fs::write(clones.join("org_lib"), "not a clone").unwrap();
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app,org/lib"])
+            .run(&[String::from("org/app"), String::from("org/lib")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(summary.repositories[1].status, RepoStatus::Created);
+        assert_eq!(harness.pull_requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_repo_config_overrides_run() {
+        let harness = LocalE2e::new();
+        harness.add_repository_files(
+            "org/app",
+            &[
+                (".github/workflows/ci.yml", UNPINNED_WORKFLOW),
+                (
+                    ".github/ratchet-dispatcher.yml",
+                    "branch: deps/pin-actions\n",
+                ),
+            ],
+        );
+        harness.add_repository_files(
+            "org/off",
+            &[
+                (".github/workflows/ci.yml", UNPINNED_WORKFLOW),
+                (".github/ratchet-dispatcher.yml", "enabled: false\n"),
+            ],
+        );
+        harness.add_repository_files(
+            "org/bad",
+            &[
+                (".github/workflows/ci.yml", UNPINNED_WORKFLOW),
+                (".github/ratchet-dispatcher.yml", "enabled: [\n"),
+            ],
+        );
+
+        let repos = ["org/app", "org/off", "org/bad"].map(String::from);
+        let summary = harness
+            .dispatcher(&["--repos", "org/app,org/off,org/bad"])
+            .run(&repos)
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(
+            harness
+                .remote_file("org/app", "deps/pin-actions", ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+        assert_eq!(summary.repositories[1].status, RepoStatus::Skipped);
+        assert_eq!(
+            summary.repositories[1].reason.as_deref(),
+            Some(SKIPPED_BY_REPO_CONFIG)
+        );
+        assert_eq!(summary.repositories[2].status, RepoStatus::Skipped);
+        assert!(summary.repositories[2].reason.as_deref().is_some_and(
+            |reason| reason.starts_with("skipped: invalid .github/ratchet-dispatcher.yml")
+        ));
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].branch, "deps/pin-actions");
+    }
+
+    #[tokio::test]
+    async fn test_e2e_only_new_pins_are_committed_next_to_old_ratchet_comments() {
+        let harness = LocalE2e::new();
+        let base = PINNED_WORKFLOW.replace(
+            "actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # ratchet:actions/setup-node@v3",
+            "actions/setup-node@v3",
+        );
+        harness.add_repository("org/app", &[("ci.yml", &base)]);
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(summary.repositories[0].comment_only_changes, 1);
+        let pinned = harness
+            .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+            .unwrap();
+        assert_eq!(pinned, PINNED_WORKFLOW);
+        let changed: Vec<&str> = base
+            .lines()
+            .zip(pinned.lines())
+            .filter(|(old, new)| old != new)
+            .map(|(_, new)| new)
+            .collect();
+        assert_eq!(
+            changed,
+            vec!["        uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # ratchet:actions/setup-node@v3"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_clean_comment_keeps_the_version() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--clean-comment"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(
+                PINNED_WORKFLOW
+                    .replace("# ratchet:actions/checkout@v3", "# v3")
+                    .replace("# ratchet:actions/setup-node@v3", "# v3")
+                    .as_str()
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_unclean_restores_ratchet_comments() {
+        let harness = LocalE2e::new();
+        let cleaned = PINNED_WORKFLOW
+            .replace("# ratchet:actions/checkout@v3", "# v3")
+            .replace("# ratchet:actions/setup-node@v3", "# v3");
+        harness.add_repository("org/app", &[("ci.yml", &cleaned)]);
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--unclean"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(
+            harness
+                .remote_file("org/app", BRANCH, ".github/workflows/ci.yml")
+                .as_deref(),
+            Some(PINNED_WORKFLOW)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_interrupted_run_does_not_push() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/lib", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dispatcher = harness.dispatcher(&["--repos", "org/app,org/lib"]);
+
+        // Ctrl-C while the first repository is being processed
+        let cancellation = dispatcher.cancellation();
+        let origins = harness.origins();
+        let prs = harness.prs.clone();
+        let dispatcher = dispatcher.with_clients(Box::new(move |owner, repo, _| {
+            cancellation.cancel();
+            let repository = format!("{}/{}", owner, repo);
+            Box::new(RecordingClient {
+                origin: origins.join(format!("{}.git", repository)),
+                repository,
+                prs: prs.clone(),
+                archived: false,
+                fork: false,
+                reopen_refused: false,
+            })
+        }));
+        let summary = dispatcher
+            .run(&[String::from("org/app"), String::from("org/lib")])
+            .await;
+
+        let statuses: Vec<_> = summary
+            .repositories
+            .iter()
+            .map(|entry| (entry.status, entry.reason.as_deref()))
+            .collect();
+        assert_eq!(
+            statuses,
+            [
+                (RepoStatus::Deferred, Some(DEFERRED_INTERRUPTED)),
+                (RepoStatus::Deferred, Some(DEFERRED_INTERRUPTED))
+            ]
+        );
+        // The pins of the first repository were made, but never pushed without a PR
+        assert_eq!(summary.repositories[0].actions_pinned, 2);
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+        assert!(harness.pull_requests().is_empty());
+        assert!(!harness.dir.path().join("clones").join("org_app").exists());
+    }
+
+    // Clock that moves <step> forward every time it is read, like a long run would
+    struct SteppingClock {
+        clock: MockClock,
+        step: Duration,
+    }
+
+    impl Clock for SteppingClock {
+        fn now(&self) -> DateTime<Utc> {
+            let now = self.clock.now();
+            self.clock.advance(self.step);
+            now
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_installation_token_refreshed_before_push() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let start = Utc::now();
+        let mut source = MockTokenSource::new();
+        let mut sequence = mockall::Sequence::new();
+        for token in ["ghs_first", "ghs_second"] {
+            source
+                .expect_create_token()
+                .times(1)
+                .in_sequence(&mut sequence)
+                .returning(move || {
+                    Ok(InstallationToken {
+                        token: token.to_string(),
+                        expires_at: start + Duration::minutes(60),
+                    })
+                });
+        }
+        // The token expires between the clone and the push
+        let clock = SteppingClock {
+            clock: MockClock::new(start),
+            step: Duration::minutes(58),
+        };
+        let credentials = Credentials::App(TokenManager::new(Box::new(source), Box::new(clock)));
+
+        let summary = harness
+            .dispatcher_with_credentials(&["--repos", "org/app"], credentials)
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::Created);
+        assert_eq!(harness.api_tokens(), ["ghs_first"]);
+        assert_eq!(harness.remote_branches("org/app"), vec![BRANCH, "main"]);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_draft_stays_draft_until_ready() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--draft"])
+            .run(&[String::from("org/app")])
+            .await;
+        assert!(harness.pull_requests()[0].draft);
+
+        // A rerun without --draft does not flip the existing draft
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+        assert!(harness.pull_requests()[0].draft);
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--ready"])
+            .run(&[String::from("org/app")])
+            .await;
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert!(!prs[0].draft);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_dry_run_does_not_push() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        let summary = harness
+            .dispatcher(&["--repos", "org/app", "--dry-run", "--draft"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_eq!(summary.repositories[0].status, RepoStatus::DryRun);
+        assert_eq!(summary.repositories[0].actions_pinned, 2);
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    // Shared buffer the JSON log lines of a test end up in
+    #[derive(Clone, Default)]
+    struct LogLines(Arc<Mutex<Vec<u8>>>);
+
+    impl std::io::Write for LogLines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_e2e_json_logs_of_dry_run() {
+        // The logger is global: lines of tests running at the same time end up here
+        // too, only the ones about this repository are checked
+        let lines = LogLines::default();
+        let logger = logger_builder(
+            LevelFilter::Debug,
+            false,
+            LogFormat::Json,
+            Target::Pipe(Box::new(lines.clone())),
+        )
+        .build();
+        log::set_boxed_logger(Box::new(logger)).unwrap();
+        log::set_max_level(LevelFilter::Debug);
+
+        let harness = LocalE2e::new();
+        harness.add_repository("org/json-logs", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let summary = harness
+            .dispatcher(&["--repos", "org/json-logs", "--dry-run"])
+            .run(&[String::from("org/json-logs")])
+            .await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::DryRun);
+
+        let output = String::from_utf8(lines.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .filter(|line: &serde_json::Value| line["repo"] == "org/json-logs")
+            .collect();
+        for line in &lines {
+            for field in ["timestamp", "level", "message"] {
+                assert!(line[field].is_string(), "{} without {}", line, field);
+            }
+        }
+        let phases: Vec<&str> = lines
+            .iter()
+            .filter_map(|line| line["phase"].as_str())
+            .collect();
+        assert!(phases.contains(&"clone"), "{:?}", phases);
+        assert!(lines.iter().any(|line| line["phase"] == "ratchet"
+            && line["file"]
+                .as_str()
+                .is_some_and(|file| file.ends_with(".github/workflows/ci.yml"))));
+        assert!(lines
+            .iter()
+            .any(|line| line["message"].as_str().is_some_and(
+                |message| message.starts_with("Dry run: would create a PR for org/json-logs")
+            )));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_auto_merge_enabled_on_created_pr() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--auto-merge", "squash"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        let prs = harness.pull_requests();
+        assert_eq!(prs.len(), 1);
+        assert_eq!(prs[0].auto_merge, Some(MergeMethod::Squash));
+    }
+
+    #[tokio::test]
+    async fn test_e2e_dry_run_writes_pr_preview() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let previews = harness.dir.path().join("previews");
+
+        harness
+            .dispatcher(&[
+                "--repos",
+                "org/app",
+                "--dry-run",
+                "--pr-preview-dir",
+                previews.to_str().unwrap(),
+            ])
+            .run(&[String::from("org/app")])
+            .await;
+
+        let preview = fs::read_to_string(previews.join("org__app.md")).unwrap();
+        assert!(preview.starts_with(&format!(
+            "---\nrepository: org/app\nbranch: {}\nbase: main\naction: create\n",
+            BRANCH
+        )));
+        assert!(
+            preview.contains("- actions/checkout v3 -> f43a0e5ff2bd294095638e18286ca9a3d1956744")
+        );
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_dry_run_writes_patches() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        harness.add_repository("org/pinned", &[("ci.yml", PINNED_WORKFLOW)]);
+        let out = harness.dir.path().join("patches");
+
+        let summary = harness
+            .dispatcher(&[
+                "--repos",
+                "org/app,org/pinned",
+                "--dry-run",
+                "--dry-run-output",
+                out.to_str().unwrap(),
+            ])
+            .run(&[String::from("org/app"), String::from("org/pinned")])
+            .await;
+        write_index(out.to_str().unwrap(), &summary).unwrap();
+
+        assert_eq!(
+            summary.repositories[0].patch.as_deref(),
+            Some("org_app.patch")
+        );
+        assert_eq!(summary.repositories[1].patch, None);
+        let index: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(out.join("index.json")).unwrap()).unwrap();
+        assert_eq!(
+            index,
+            serde_json::json!({
+                "org/app": { "patch": "org_app.patch", "files_changed": 1, "actions_pinned": 2 }
+            })
+        );
+        assert_eq!(harness.remote_branches("org/app"), vec!["main"]);
+
+        // The patch applies to a checkout of the default branch with git am
+        let checkout = harness.dir.path().join("checkout");
+        Repository::clone(harness.origin_path("org/app").to_str().unwrap(), &checkout).unwrap();
+        let output = std::process::Command::new("git")
+            .args([
+                "-c",
+                "user.name=test",
+                "-c",
+                "user.email=test@example.com",
+                "am",
+            ])
+            .arg(out.join("org_app.patch"))
+            .current_dir(&checkout)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{:?}", output);
+        assert_eq!(
+            fs::read_to_string(checkout.join(".github/workflows/ci.yml")).unwrap(),
+            PINNED_WORKFLOW
+        );
+    }
+
+    #[tokio::test]
+    async fn test_e2e_exit_code_on_changes() {
+        let harness = LocalE2e::new();
+        let pinned = PINNED_WORKFLOW.replace("checkout@v3\n", "checkout@v3.6.0\n");
+        harness.add_repository("org/clean", &[("ci.yml", &pinned)]);
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let args = ["--dry-run", "--exit-code-on-changes", "--repos"];
+
+        let clean = harness
+            .dispatcher(&[&args[..], &["org/clean"]].concat())
+            .run(&[String::from("org/clean")])
+            .await;
+        assert!(clean.pending_changes().is_empty());
+        assert_eq!(clean.changes_exit_code(), 0);
+
+        let pending = harness
+            .dispatcher(&[&args[..], &["org/clean,org/app"]].concat())
+            .run(&[String::from("org/clean"), String::from("org/app")])
+            .await;
+        assert_eq!(pending.changes_exit_code(), EXIT_CHANGES_PENDING);
+        let changes: Vec<_> = pending
+            .pending_changes()
+            .iter()
+            .map(|entry| (entry.repository.as_str(), entry.changed_files.clone()))
+            .collect();
+        assert_eq!(
+            changes,
+            [("org/app", vec![String::from(".github/workflows/ci.yml")])]
+        );
+        assert!(harness.pull_requests().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_e2e_reuse_clones() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+        let dispatcher = harness.dispatcher(&["--repos", "org/app", "--reuse-clones"]);
+        let clone_path = harness.dir.path().join("clones").join("org_app");
+
+        dispatcher.run(&[String::from("org/app")]).await;
+        assert!(clone_path.join(".git").exists());
+        let first = harness.remote_head("org/app", BRANCH);
+
+        let summary = dispatcher.run(&[String::from("org/app")]).await;
+        assert_eq!(summary.repositories[0].status, RepoStatus::Updated);
+        assert_eq!(harness.remote_head("org/app", BRANCH), first);
+        assert_eq!(harness.pull_requests().len(), 1);
+        assert!(clone_path.join(".git").exists());
+    }
+
+    // Whether the automation branch of org/app is main plus the pins, and nothing else
+    fn assert_branch_is_base_and_pins(harness: &LocalE2e) {
+        let origin = Repository::open_bare(harness.origin_path("org/app")).unwrap();
+        let main = origin
+            .find_commit(harness.remote_head("org/app", "main"))
+            .unwrap();
+        let pinned = origin
+            .find_commit(harness.remote_head("org/app", BRANCH))
+            .unwrap();
+        assert_eq!(pinned.parent_id(0).unwrap(), main.id());
+        let diff = origin
+            .diff_tree_to_tree(
+                Some(&main.tree().unwrap()),
+                Some(&pinned.tree().unwrap()),
+                None,
+            )
+            .unwrap();
+        let changed: Vec<_> = diff
+            .deltas()
+            .map(|delta| delta.new_file().path().unwrap().to_path_buf())
+            .collect();
+        assert_eq!(changed, vec![PathBuf::from(".github/workflows/ci.yml")]);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_kept_clone_is_cloned_again() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        // A clone kept by an earlier run, with a manual commit on the branch
+        let clone_path = harness.dir.path().join("clones").join("org_app");
+        let clone = Repository::clone(
+            harness.origin_path("org/app").to_str().unwrap(),
+            &clone_path,
+        )
+        .unwrap();
+        let head = clone.head().unwrap().peel_to_commit().unwrap();
+        clone.branch(BRANCH, &head, false).unwrap();
+        clone.set_head(&format!("refs/heads/{}", BRANCH)).unwrap();
+        fs::write(clone_path.join("manual.txt"), "manual").unwrap();
+        let mut index = clone.index().unwrap();
+        index.add_path(Path::new("manual.txt")).unwrap();
+        index.write().unwrap();
+        let tree = clone.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("someone", "someone@example.com").unwrap();
+        clone
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                "manual",
+                &tree,
+                &[&head],
+            )
+            .unwrap();
+
+        // Without --reuse-clones the clone and its branch are not picked up
+        harness
+            .dispatcher(&["--repos", "org/app"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        assert_branch_is_base_and_pins(&harness);
+    }
+
+    #[tokio::test]
+    async fn test_e2e_overwrite_existing_branch() {
+        let harness = LocalE2e::new();
+        harness.add_repository("org/app", &[("ci.yml", UNPINNED_WORKFLOW)]);
+
+        // Someone pushed a manual commit to the automation branch
+        let origin = Repository::open_bare(harness.origin_path("org/app")).unwrap();
+        let main = harness.remote_head("org/app", "main");
+        origin
+            .reference(&format!("refs/heads/{}", BRANCH), main, false, "branch")
+            .unwrap();
+        commit_files(
+            &origin,
+            &format!("refs/heads/{}", BRANCH),
+            &[
+                (".github/workflows/ci.yml", UNPINNED_WORKFLOW),
+                ("manual.txt", "manual"),
+            ],
+        );
+
+        harness
+            .dispatcher(&["--repos", "org/app", "--overwrite-existing-branch"])
+            .run(&[String::from("org/app")])
+            .await;
+
+        // The branch is the base plus the pins, the manual commit is gone
+        assert_branch_is_base_and_pins(&harness);
+    }
+}
@@ -0,0 +1,318 @@
+use std::{
+    env,
+    error::Error,
+    fs,
+    io::Read,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use flate2::read::GzDecoder;
+use http::StatusCode;
+use http_body_util::BodyExt;
+use log::{info, warn};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    proxy::{url_client, ProxyConfig},
+    ratchet::RatchetVersion,
+};
+
+// Release of ratchet fetched by --install-ratchet unless --ratchet-version says otherwise
+pub const DEFAULT_RATCHET_VERSION: &str = "0.10.2";
+// Where the ratchet release assets are downloaded from
+pub const RATCHET_RELEASES_URL: &str = "https://github.com/sethvargo/ratchet/releases/download";
+
+// Why the configured ratchet can not be used
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum RatchetUnavailable {
+    #[error("ratchet could not be run from {0}. Install it from https://github.com/sethvargo/ratchet, point --ratchet-bin at it, or pass --install-ratchet to download it")]
+    NotFound(String),
+    #[error("ratchet {found} at {path} is older than the minimum version {minimum}, upgrade it or lower --min-ratchet-version")]
+    TooOld {
+        path: String,
+        found: RatchetVersion,
+        minimum: RatchetVersion,
+    },
+}
+
+// Fail early when <ratchet_path> can not be run or is older than <minimum>, instead of
+// once per workflow file. Returns the version of ratchet, None when its output has no
+// version number (like a development build), which is let through.
+pub fn check_ratchet_availability(
+    ratchet_path: &str,
+    minimum: RatchetVersion,
+) -> Result<Option<RatchetVersion>, RatchetUnavailable> {
+    let output = match Command::new(ratchet_path).arg("--version").output() {
+        Ok(output) if output.status.success() => output,
+        _ => return Err(RatchetUnavailable::NotFound(ratchet_path.to_string())),
+    };
+    let output = String::from_utf8_lossy(&output.stdout);
+    match RatchetVersion::parse(&output) {
+        Some(found) if found < minimum => Err(RatchetUnavailable::TooOld {
+            path: ratchet_path.to_string(),
+            found,
+            minimum,
+        }),
+        Some(found) => Ok(Some(found)),
+        None => {
+            warn!(
+                "Could not find a version in {:?}, skipping the minimum ratchet version check",
+                output.trim()
+            );
+            Ok(None)
+        }
+    }
+}
+
+// Directory the downloaded ratchet binaries are kept in: $XDG_CACHE_HOME, ~/.cache,
+// or <clone_dir> when neither is set
+pub fn ratchet_cache_dir(clone_dir: &str) -> PathBuf {
+    let cache = match (env::var_os("XDG_CACHE_HOME"), env::var_os("HOME")) {
+        (Some(cache), _) if !cache.is_empty() => PathBuf::from(cache),
+        (_, Some(home)) if !home.is_empty() => Path::new(&home).join(".cache"),
+        _ => PathBuf::from(clone_dir),
+    };
+    cache.join("ratchet-dispatcher")
+}
+
+// Release asset of <version> for the current OS and architecture, like
+// ratchet_0.10.2_linux_amd64.tar.gz
+fn asset_name(version: &str) -> Result<String, String> {
+    let os = match env::consts::OS {
+        "macos" => "darwin",
+        os @ ("linux" | "windows") => os,
+        os => return Err(format!("ratchet has no release for {}", os)),
+    };
+    let arch = match env::consts::ARCH {
+        "x86_64" => "amd64",
+        "aarch64" => "arm64",
+        arch => return Err(format!("ratchet has no release for {}", arch)),
+    };
+    Ok(format!("ratchet_{}_{}_{}.tar.gz", version, os, arch))
+}
+
+// --install-ratchet: download ratchet <version> from <releases_url> into <cache_dir>,
+// verify it against the SHA256SUMS of the release and return the path of the binary.
+// A binary of the same version downloaded by an earlier run is used as it is.
+pub async fn install_ratchet(
+    releases_url: &str,
+    version: &str,
+    cache_dir: &Path,
+    proxy: Option<&ProxyConfig>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    let version = version.trim_start_matches('v');
+    let binary = cache_dir
+        .join(format!("ratchet-{}", version))
+        .join("ratchet");
+    if binary.is_file() {
+        info!("Using cached ratchet {} at {}", version, binary.display());
+        return Ok(binary);
+    }
+
+    let asset = asset_name(version)?;
+    let release_url = format!("{}/v{}", releases_url.trim_end_matches('/'), version);
+    info!("Downloading ratchet {} from {}", version, release_url);
+    let sums = download(
+        &format!("{}/ratchet_{}_SHA256SUMS", release_url, version),
+        proxy,
+    )
+    .await?;
+    let expected = String::from_utf8_lossy(&sums)
+        .lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, name)| name.trim() == asset)
+        .map(|(sum, _)| sum.to_lowercase())
+        .ok_or_else(|| format!("no checksum for {} in release v{}", asset, version))?;
+    let archive = download(&format!("{}/{}", release_url, asset), proxy).await?;
+    let actual: String = Sha256::digest(&archive)
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    if actual != expected {
+        return Err(format!(
+            "checksum mismatch for {}: expected {}, got {}",
+            asset, expected, actual
+        )
+        .into());
+    }
+
+    let content = extract_binary(&archive)?;
+    let dir = binary.parent().unwrap_or(cache_dir);
+    fs::create_dir_all(dir)?;
+    // Write next to the final path and rename, a failed run never leaves half a binary
+    let partial = dir.join("ratchet.partial");
+    fs::write(&partial, content)?;
+    fs::set_permissions(&partial, fs::Permissions::from_mode(0o755))?;
+    fs::rename(&partial, &binary)?;
+    info!("Installed ratchet {} to {}", version, binary.display());
+    Ok(binary)
+}
+
+// GET <url> without credentials, GitHub redirects release assets to its storage host
+async fn download(url: &str, proxy: Option<&ProxyConfig>) -> Result<Vec<u8>, Box<dyn Error>> {
+    let octocrab = url_client(proxy)?;
+    let response = octocrab._get(url).await?;
+    match response.status() {
+        status if status.is_success() => {
+            Ok(response.into_body().collect().await?.to_bytes().to_vec())
+        }
+        StatusCode::NOT_FOUND => Err(format!("{} does not exist", url).into()),
+        status => Err(format!("unexpected status {} for {}", status, url).into()),
+    }
+}
+
+// The ratchet binary out of a release tarball
+fn extract_binary(archive: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut tarball = tar::Archive::new(GzDecoder::new(archive));
+    for entry in tarball.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.file_name().map(|name| name.to_owned());
+        if name.is_some_and(|name| name == "ratchet" || name == "ratchet.exe") {
+            let mut content = Vec::new();
+            entry.read_to_end(&mut content)?;
+            return Ok(content);
+        }
+    }
+    Err(Box::from("the release archive does not contain ratchet"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratchet::DEFAULT_MIN_RATCHET_VERSION;
+    use flate2::{write::GzEncoder, Compression};
+    use tempfile::tempdir;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    const VERSION: &str = "0.10.2";
+
+    fn minimum() -> RatchetVersion {
+        DEFAULT_MIN_RATCHET_VERSION.parse().unwrap()
+    }
+    const BINARY: &[u8] = b"#!/bin/sh\necho ratchet 0.10.2\n";
+
+    fn tarball() -> Vec<u8> {
+        let mut builder = tar::Builder::new(GzEncoder::new(Vec::new(), Compression::default()));
+        let mut header = tar::Header::new_gnu();
+        header.set_size(BINARY.len() as u64);
+        header.set_mode(0o644);
+        header.set_cksum();
+        builder.append_data(&mut header, "ratchet", BINARY).unwrap();
+        builder.into_inner().unwrap().finish().unwrap()
+    }
+
+    fn sha256(content: &[u8]) -> String {
+        Sha256::digest(content)
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+
+    // A release of VERSION whose SHA256SUMS lists <sum> for the archive, each file
+    // is expected to be downloaded once
+    async fn release(sum: &str) -> MockServer {
+        let server = MockServer::start().await;
+        let asset = asset_name(VERSION).unwrap();
+        Mock::given(method("GET"))
+            .and(path(format!(
+                "/v{}/ratchet_{}_SHA256SUMS",
+                VERSION, VERSION
+            )))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!(
+                "{}  ratchet_{}_other_arch.tar.gz\n{}  {}\n",
+                "0".repeat(64),
+                VERSION,
+                sum,
+                asset
+            )))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/v{}/{}", VERSION, asset)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(tarball()))
+            .expect(1)
+            .mount(&server)
+            .await;
+        server
+    }
+
+    #[tokio::test]
+    async fn test_install_ratchet_downloads_and_caches() {
+        let server = release(&sha256(&tarball())).await;
+        let cache = tempdir().unwrap();
+
+        let binary = install_ratchet(&server.uri(), VERSION, cache.path(), None)
+            .await
+            .unwrap();
+        assert_eq!(binary, cache.path().join("ratchet-0.10.2/ratchet"));
+        assert_eq!(fs::read(&binary).unwrap(), BINARY);
+        let mode = fs::metadata(&binary).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o755);
+        assert_eq!(
+            check_ratchet_availability(binary.to_str().unwrap(), minimum()),
+            Ok(Some("0.10.2".parse().unwrap()))
+        );
+
+        // The cached binary is used, the release is not downloaded again
+        let cached = install_ratchet(&server.uri(), "v0.10.2", cache.path(), None)
+            .await
+            .unwrap();
+        assert_eq!(cached, binary);
+    }
+
+    #[tokio::test]
+    async fn test_install_ratchet_checksum_mismatch() {
+        let server = release(&"1".repeat(64)).await;
+        let cache = tempdir().unwrap();
+
+        let err = install_ratchet(&server.uri(), VERSION, cache.path(), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("checksum mismatch"));
+        assert!(!cache.path().join("ratchet-0.10.2/ratchet").exists());
+    }
+
+    #[tokio::test]
+    async fn test_install_ratchet_missing_release() {
+        let server = MockServer::start().await;
+        let cache = tempdir().unwrap();
+
+        let err = install_ratchet(&server.uri(), "0.0.1", cache.path(), None)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("does not exist"));
+    }
+
+    #[test]
+    fn test_missing_ratchet_is_reported() {
+        let err = check_ratchet_availability("/nonexistent/ratchet", minimum()).unwrap_err();
+        assert!(err.to_string().contains("--install-ratchet"));
+    }
+
+    #[test]
+    fn test_old_ratchet_is_rejected() {
+        let dir = tempdir().unwrap();
+        let ratchet = dir.path().join("ratchet");
+        fs::write(&ratchet, "#!/bin/sh\necho 'ratchet v0.4.0 (dd6f0a6)'\n").unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let err = check_ratchet_availability(ratchet.to_str().unwrap(), minimum()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "ratchet 0.4.0 at {} is older than the minimum version 0.5.0, upgrade it or lower --min-ratchet-version",
+                ratchet.display()
+            )
+        );
+        assert_eq!(
+            check_ratchet_availability(ratchet.to_str().unwrap(), "0.4".parse().unwrap()),
+            Ok(Some("0.4.0".parse().unwrap()))
+        );
+    }
+}
@@ -0,0 +1,47 @@
+use std::{
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use log::warn;
+
+// Exit code of a run that was interrupted with Ctrl-C, like a shell reports SIGINT
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+// Summary reason of the repositories left alone after Ctrl-C
+pub const DEFERRED_INTERRUPTED: &str = "deferred: run interrupted";
+
+// Set once the run is asked to stop. Repositories are only checked between steps, so
+// the one in flight is finished or left without pushing, never half pushed.
+#[derive(Debug, Clone, Default)]
+pub struct Cancellation(Arc<AtomicBool>);
+
+impl Cancellation {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+// Cancel <cancellation> on the first Ctrl-C and exit right away on the second
+pub fn cancel_on_ctrl_c(cancellation: Cancellation) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!(
+            "Interrupted, finishing the current repository. Press Ctrl-C again to exit immediately"
+        );
+        cancellation.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("Interrupted again, exiting without cleaning up");
+            process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    });
+}
@@ -1,6 +1,26 @@
-use std::fs;
+use std::{
+    error::Error,
+    fs,
+    io::{ErrorKind, Read},
+    path::Path,
+    time::Duration,
+};
 
-use log::{debug, error};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use log::{debug, error, warn};
+use octocrab::Octocrab;
+
+use crate::{git::PinChange, pr_content::MAX_PR_BODY_LENGTH};
+
+// PR body used without --pr-body-path
+// --pr-body-path that reads the PR body from stdin
+const PR_BODY_STDIN: &str = "-";
+// Longest a --pr-body-path URL may take to respond
+const PR_BODY_FETCH_TIMEOUT: Duration = Duration::from_secs(30);
+// Largest --pr-body-path download, MAX_PR_BODY_LENGTH characters of up to 4 bytes
+const MAX_PR_BODY_BYTES: usize = 4 * MAX_PR_BODY_LENGTH;
+
+pub const DEFAULT_PR_BODY: &str = "This automatically generated pull request upgrades the workflows using ratchet. It pins the versions of the actions used in the workflows to prevent bad actors from overwriting tags/versions. Please review the changes and merge if everything looks good.\n\nChanged pins in {{files_changed}} workflow file(s):\n\n{{actions_pinned}}";
 
 // Function that will remove the temporary directory
 pub fn cleanup_clone_dir(local_path: &str) {
@@ -11,17 +31,449 @@ pub fn cleanup_clone_dir(local_path: &str) {
     }
 }
 
+// Write <content> next to <path> and rename it into place, a reader never sees half a
+// file and an earlier file is only replaced once the new one is complete
+pub fn write_atomically(path: &Path, content: &str) -> Result<(), Box<dyn Error>> {
+    let partial = path.with_extension("partial");
+    fs::write(&partial, content)
+        .and_then(|_| fs::rename(&partial, path))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e).into())
+}
+
+// Why a clone directory could not be prepared
+#[derive(Debug, thiserror::Error)]
+pub enum CloneDirError {
+    #[error("Permission denied removing {0}, remove it by hand or pick another --clone-dir")]
+    PermissionDenied(String),
+    #[error("Failed to remove {path}: {source}")]
+    Remove {
+        path: String,
+        source: std::io::Error,
+    },
+}
+
+// Remove whatever a crashed earlier run left at <local_path>, a directory or a file, so
+// it can be cloned into again. Nothing to do when <local_path> does not exist.
+pub fn prepare_clone_dir(local_path: &str) -> Result<(), CloneDirError> {
+    let removed = match fs::symlink_metadata(local_path) {
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(e) => Err(e),
+        Ok(metadata) if metadata.is_dir() => fs::remove_dir_all(local_path),
+        Ok(_) => fs::remove_file(local_path),
+    };
+    match removed {
+        Ok(()) => {
+            warn!("Removed {} left behind by an earlier run", local_path);
+            Ok(())
+        }
+        Err(e) if e.kind() == ErrorKind::PermissionDenied => {
+            Err(CloneDirError::PermissionDenied(local_path.to_string()))
+        }
+        Err(source) => Err(CloneDirError::Remove {
+            path: local_path.to_string(),
+            source,
+        }),
+    }
+}
+
+// Why --pr-body-path can not be used as the PR body
+#[derive(Debug, thiserror::Error)]
+pub enum PrBodyError {
+    #[error("Failed to read the PR body from {path}: {source}")]
+    Read {
+        path: String,
+        source: std::io::Error,
+    },
+    #[error("Failed to fetch the PR body from {url}: {message}")]
+    Fetch { url: String, message: String },
+    #[error("The PR body in {0} is empty")]
+    Empty(String),
+    #[error("The PR body in {path} is {length} characters long, GitHub allows at most {max}")]
+    TooLong {
+        path: String,
+        length: usize,
+        max: usize,
+    },
+}
+
 // If the user has a custom PR body, we should read the file and use that as the PR body
-// Otherwise, we should use a default PR body
-pub fn get_pr_body_from_file(pr_body_path: &Option<String>) -> String {
-    match pr_body_path {
+// Otherwise, we should use a default PR body. A path of `-` reads the body from stdin,
+// an https:// URL downloads it.
+pub async fn get_pr_body_from_file(pr_body_path: &Option<String>) -> Result<String, PrBodyError> {
+    match pr_body_path.as_deref() {
+        None => Ok(String::from(DEFAULT_PR_BODY)),
+        Some(PR_BODY_STDIN) => read_pr_body("standard input", std::io::stdin()),
+        Some(url) if url.starts_with("https://") => fetch_pr_body(url, PR_BODY_FETCH_TIMEOUT).await,
         Some(path) => {
-            fs::read_to_string(path).unwrap()
+            let body = fs::read_to_string(path).map_err(|source| PrBodyError::Read {
+                path: path.to_string(),
+                source,
+            })?;
+            validate_pr_body(path, body)
         }
-        None => {
-            String::from(
-                "This automatically generated pull request upgrades the workflows using ratchet. It pins the versions of the actions used in the workflows to prevent bad actors from overwriting tags/versions. Please review the changes and merge if everything looks good.",
+    }
+}
+
+// Read the PR body from <reader>, <source> names it in errors
+fn read_pr_body(source: &str, mut reader: impl Read) -> Result<String, PrBodyError> {
+    let mut body = String::new();
+    reader
+        .read_to_string(&mut body)
+        .map_err(|e| PrBodyError::Read {
+            path: source.to_string(),
+            source: e,
+        })?;
+    validate_pr_body(source, body)
+}
+
+// Download the PR body from <url>, giving up after <timeout>
+async fn fetch_pr_body(url: &str, timeout: Duration) -> Result<String, PrBodyError> {
+    let fetch_error = |message: String| PrBodyError::Fetch {
+        url: url.to_string(),
+        message,
+    };
+    let body = match tokio::time::timeout(timeout, download_pr_body(url)).await {
+        Ok(Ok(body)) => body,
+        Ok(Err(e)) => return Err(fetch_error(e.to_string())),
+        Err(_) => {
+            return Err(fetch_error(format!(
+                "no response within {} seconds",
+                timeout.as_secs_f32()
+            )))
+        }
+    };
+    validate_pr_body(url, body)
+}
+
+// GET <url> without credentials, reading at most MAX_PR_BODY_BYTES of the response
+async fn download_pr_body(url: &str) -> Result<String, Box<dyn Error + Send + Sync>> {
+    let octocrab = Octocrab::builder().build()?;
+    let response = octocrab._get(url).await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(format!("unexpected status {}", status).into());
+    }
+    let body = Limited::new(response.into_body(), MAX_PR_BODY_BYTES)
+        .collect()
+        .await
+        .map_err(|e| {
+            if e.is::<LengthLimitError>() {
+                format!("the response is larger than {} bytes", MAX_PR_BODY_BYTES).into()
+            } else {
+                e
+            }
+        })?;
+    Ok(String::from_utf8(body.to_bytes().to_vec())?)
+}
+
+// Reject a PR body GitHub would not accept, <source> names where it came from
+fn validate_pr_body(source: &str, body: String) -> Result<String, PrBodyError> {
+    let length = body.chars().count();
+    if body.trim().is_empty() {
+        Err(PrBodyError::Empty(source.to_string()))
+    } else if length > MAX_PR_BODY_LENGTH {
+        Err(PrBodyError::TooLong {
+            path: source.to_string(),
+            length,
+            max: MAX_PR_BODY_LENGTH,
+        })
+    } else {
+        Ok(body)
+    }
+}
+
+// Check the size of a file using its metadata, without reading its content
+pub fn is_file_too_large(path: &Path, max_size: u64) -> bool {
+    fs::metadata(path).is_ok_and(|metadata| metadata.len() > max_size)
+}
+
+// Values of the placeholders of a PR body
+pub struct PrBodyValues<'a> {
+    pub repo: &'a str,
+    pub files_changed: usize,
+    pub pin_changes: &'a [PinChange],
+    pub run_date: &'a str,
+}
+
+// Fill in the placeholders of a PR body template:
+// {{repo}}, {{files_changed}}, {{actions_pinned}} and {{run_date}}
+pub fn render_pr_body(template: &str, values: &PrBodyValues) -> String {
+    let actions_pinned = if values.pin_changes.is_empty() {
+        String::from("_No pins changed_")
+    } else {
+        values
+            .pin_changes
+            .iter()
+            .map(|change| format!("- {} {} -> {}", change.action, change.old, change.new))
+            .collect::<Vec<_>>()
+            .join("\n")
+    };
+    template
+        .replace("{{repo}}", values.repo)
+        .replace("{{files_changed}}", &values.files_changed.to_string())
+        .replace("{{actions_pinned}}", &actions_pinned)
+        .replace("{{run_date}}", values.run_date)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    #[tokio::test]
+    async fn test_pr_body_from_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("body.md");
+        fs::write(&path, "Pins for {{repo}}").unwrap();
+
+        let body = get_pr_body_from_file(&Some(path.to_str().unwrap().to_string()))
+            .await
+            .unwrap();
+        assert_eq!(body, "Pins for {{repo}}");
+        assert_eq!(get_pr_body_from_file(&None).await.unwrap(), DEFAULT_PR_BODY);
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_missing_file() {
+        let err = get_pr_body_from_file(&Some(String::from("/nonexistent/body.md")))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PrBodyError::Read { .. }));
+        assert!(err
+            .to_string()
+            .starts_with("Failed to read the PR body from /nonexistent/body.md: "));
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_empty_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("body.md");
+        fs::write(&path, " \n\n").unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let err = get_pr_body_from_file(&Some(path.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), format!("The PR body in {} is empty", path));
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_too_long() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("body.md");
+        fs::write(&path, "é".repeat(MAX_PR_BODY_LENGTH)).unwrap();
+        let path = path.to_str().unwrap().to_string();
+        // The limit is in characters, not bytes
+        assert!(get_pr_body_from_file(&Some(path.clone())).await.is_ok());
+
+        fs::write(&path, "a".repeat(MAX_PR_BODY_LENGTH + 1)).unwrap();
+        let err = get_pr_body_from_file(&Some(path.clone()))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "The PR body in {} is 65537 characters long, GitHub allows at most 65536",
+                path
+            )
+        );
+    }
+
+    #[test]
+    fn test_pr_body_from_reader() {
+        let body = read_pr_body("standard input", "Pins for {{repo}}\n".as_bytes()).unwrap();
+        assert_eq!(body, "Pins for {{repo}}\n");
+
+        let err = read_pr_body("standard input", "".as_bytes()).unwrap_err();
+        assert_eq!(err.to_string(), "The PR body in standard input is empty");
+    }
+
+    async fn serve_pr_body(response: ResponseTemplate) -> (MockServer, String) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/body.md"))
+            .respond_with(response)
+            .mount(&server)
+            .await;
+        let url = format!("{}/body.md", server.uri());
+        (server, url)
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_from_url() {
+        let (_server, url) =
+            serve_pr_body(ResponseTemplate::new(200).set_body_string("Pins for {{repo}}")).await;
+        let body = fetch_pr_body(&url, PR_BODY_FETCH_TIMEOUT).await.unwrap();
+        assert_eq!(body, "Pins for {{repo}}");
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_url_errors() {
+        let (_server, url) = serve_pr_body(ResponseTemplate::new(404)).await;
+        let err = fetch_pr_body(&url, PR_BODY_FETCH_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Failed to fetch the PR body from {}: unexpected status 404 Not Found",
+                url
             )
+        );
+
+        let (_server, url) = serve_pr_body(ResponseTemplate::new(200)).await;
+        let err = fetch_pr_body(&url, PR_BODY_FETCH_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PrBodyError::Empty(_)));
+
+        let (_server, url) = serve_pr_body(
+            ResponseTemplate::new(200).set_body_string("a".repeat(MAX_PR_BODY_LENGTH + 1)),
+        )
+        .await;
+        let err = fetch_pr_body(&url, PR_BODY_FETCH_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(matches!(err, PrBodyError::TooLong { length: 65537, .. }));
+
+        let (_server, url) = serve_pr_body(
+            ResponseTemplate::new(200).set_body_string("a".repeat(MAX_PR_BODY_BYTES + 1)),
+        )
+        .await;
+        let err = fetch_pr_body(&url, PR_BODY_FETCH_TIMEOUT)
+            .await
+            .unwrap_err();
+        assert!(err
+            .to_string()
+            .ends_with("the response is larger than 262144 bytes"));
+    }
+
+    #[tokio::test]
+    async fn test_pr_body_url_timeout() {
+        let (_server, url) = serve_pr_body(
+            ResponseTemplate::new(200)
+                .set_body_string("Pins")
+                .set_delay(Duration::from_secs(10)),
+        )
+        .await;
+        let err = fetch_pr_body(&url, Duration::from_millis(100))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().ends_with("no response within 0.1 seconds"));
+    }
+
+    #[test]
+    fn test_prepare_clone_dir_removes_directory() {
+        let dir = tempdir().unwrap();
+        let clone = dir.path().join("org_app");
+        fs::create_dir_all(clone.join(".git/objects")).unwrap();
+        fs::write(clone.join("README.md"), "half cloned").unwrap();
+
+        prepare_clone_dir(clone.to_str().unwrap()).unwrap();
+        assert!(!clone.exists());
+        // Nothing left to remove
+        prepare_clone_dir(clone.to_str().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_prepare_clone_dir_removes_file() {
+        let dir = tempdir().unwrap();
+        let clone = dir.path().join("org_app");
+        fs::write(&clone, "not a directory").unwrap();
+
+        prepare_clone_dir(clone.to_str().unwrap()).unwrap();
+        assert!(!clone.exists());
+    }
+
+    #[test]
+    fn test_prepare_clone_dir_permission_denied() {
+        let dir = tempdir().unwrap();
+        let clone_dir = dir.path().join("clones");
+        let clone = clone_dir.join("org_app");
+        fs::create_dir_all(&clone).unwrap();
+        fs::set_permissions(&clone_dir, fs::Permissions::from_mode(0o555)).unwrap();
+        // Permissions do not apply to root
+        if fs::write(clone_dir.join("probe"), "").is_ok() {
+            return;
+        }
+
+        let result = prepare_clone_dir(clone.to_str().unwrap());
+        fs::set_permissions(&clone_dir, fs::Permissions::from_mode(0o755)).unwrap();
+        assert!(matches!(
+            result,
+            Err(CloneDirError::PermissionDenied(path)) if path == clone.to_str().unwrap()
+        ));
+    }
+
+    fn values(pin_changes: &[PinChange]) -> PrBodyValues<'_> {
+        PrBodyValues {
+            repo: "org/app",
+            files_changed: 2,
+            pin_changes,
+            run_date: "2024-05-01",
+        }
+    }
+
+    fn checkout_change() -> PinChange {
+        PinChange {
+            action: String::from("actions/checkout"),
+            old: String::from("v3"),
+            new: String::from("f43a0e5ff2bd294095638e18286ca9a3d1956744"),
         }
     }
+
+    #[test]
+    fn test_render_repo() {
+        assert_eq!(
+            render_pr_body("Pins of {{repo}}", &values(&[])),
+            "Pins of org/app"
+        );
+    }
+
+    #[test]
+    fn test_render_files_changed() {
+        assert_eq!(
+            render_pr_body("{{files_changed}} file(s)", &values(&[])),
+            "2 file(s)"
+        );
+    }
+
+    #[test]
+    fn test_render_actions_pinned() {
+        let changes = [
+            checkout_change(),
+            PinChange {
+                action: String::from("actions/setup-node"),
+                old: String::from("v3"),
+                new: String::from("1a4442cacd436585916779262731d5b162bc6ec7"),
+            },
+        ];
+        assert_eq!(
+            render_pr_body("{{actions_pinned}}", &values(&changes)),
+            "- actions/checkout v3 -> f43a0e5ff2bd294095638e18286ca9a3d1956744\n- actions/setup-node v3 -> 1a4442cacd436585916779262731d5b162bc6ec7"
+        );
+        assert_eq!(
+            render_pr_body("{{actions_pinned}}", &values(&[])),
+            "_No pins changed_"
+        );
+    }
+
+    #[test]
+    fn test_render_run_date() {
+        assert_eq!(
+            render_pr_body("Run of {{run_date}}", &values(&[])),
+            "Run of 2024-05-01"
+        );
+    }
+
+    #[test]
+    fn test_render_without_placeholders() {
+        let body = "Pins actions, see {repo} and {{unknown}}";
+        assert_eq!(render_pr_body(body, &values(&[checkout_change()])), body);
+    }
 }
@@ -0,0 +1,238 @@
+use std::{error::Error, str::FromStr};
+
+use http::StatusCode;
+use log::warn;
+use serde::Deserialize;
+
+use crate::{
+    github::{GitHubClient, GitHubUrls},
+    proxy::{url_client, ProxyConfig},
+};
+
+// Exit code of a run that was stopped by the kill switch
+pub const KILL_SWITCH_EXIT_CODE: i32 = 3;
+
+// Where the kill switch file lives: a plain URL or github://owner/repo/path
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KillSwitchLocation {
+    Url(String),
+    GitHub {
+        owner: String,
+        repo: String,
+        path: String,
+    },
+}
+
+impl FromStr for KillSwitchLocation {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if let Some(rest) = spec.strip_prefix("github://") {
+            let parts: Vec<&str> = rest.splitn(3, '/').collect();
+            match parts[..] {
+                [owner, repo, path]
+                    if !owner.is_empty() && !repo.is_empty() && !path.is_empty() =>
+                {
+                    Ok(KillSwitchLocation::GitHub {
+                        owner: owner.to_string(),
+                        repo: repo.to_string(),
+                        path: path.to_string(),
+                    })
+                }
+                _ => Err(format!("expected github://owner/repo/path, got {}", spec)),
+            }
+        } else if spec.starts_with("https://") || spec.starts_with("http://") {
+            Ok(KillSwitchLocation::Url(spec.to_string()))
+        } else {
+            Err(format!(
+                "expected an http(s) URL or github://owner/repo/path, got {}",
+                spec
+            ))
+        }
+    }
+}
+
+// Content of the kill switch file, for example:
+//   disabled: true
+//   message: ratchet 0.9.3 produces corrupt pins
+//   allow: [org/canary]
+#[derive(Debug, Default, Deserialize)]
+struct KillSwitchFile {
+    #[serde(default)]
+    disabled: bool,
+    message: Option<String>,
+    #[serde(default)]
+    allow: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KillSwitch {
+    NotEngaged,
+    // Only the allowed repositories may still be processed
+    Engaged { message: String, allow: Vec<String> },
+}
+
+impl KillSwitch {
+    fn parse(content: &str) -> Result<Self, serde_yaml::Error> {
+        let file: Option<KillSwitchFile> = serde_yaml::from_str(content)?;
+        let file = file.unwrap_or_default();
+        if !file.disabled {
+            return Ok(KillSwitch::NotEngaged);
+        }
+        Ok(KillSwitch::Engaged {
+            message: file.message.unwrap_or_else(|| {
+                String::from("ratchet-dispatcher is disabled by the kill switch")
+            }),
+            allow: file.allow,
+        })
+    }
+
+    // Repositories of <repos> that may be processed, Err with the message when none
+    pub fn apply(&self, repos: Vec<String>) -> Result<Vec<String>, String> {
+        match self {
+            KillSwitch::NotEngaged => Ok(repos),
+            KillSwitch::Engaged { message, allow } => {
+                let allowed: Vec<String> = repos
+                    .into_iter()
+                    .filter(|repo| allow.contains(repo))
+                    .collect();
+                if allowed.is_empty() {
+                    Err(message.clone())
+                } else {
+                    warn!(
+                        "Kill switch engaged ({}), only processing {:?}",
+                        message, allowed
+                    );
+                    Ok(allowed)
+                }
+            }
+        }
+    }
+}
+
+// Fetch and evaluate the kill switch. A missing file means not engaged, and so does
+// a failing fetch: an outage of the switch host must not halt automation.
+pub async fn check_kill_switch(
+    location: &KillSwitchLocation,
+    urls: &GitHubUrls,
+    token: &str,
+) -> KillSwitch {
+    let content = match location {
+        KillSwitchLocation::Url(url) => fetch_url(url, urls.proxy.as_ref()).await,
+        KillSwitchLocation::GitHub { owner, repo, path } => {
+            GitHubClient::new(owner.clone(), repo.clone(), token.to_string(), urls)
+                .raw_file(path)
+                .await
+        }
+    };
+    match content {
+        Ok(Some(content)) => KillSwitch::parse(&content).unwrap_or_else(|e| {
+            warn!("Ignoring invalid kill switch {:?}: {}", location, e);
+            KillSwitch::NotEngaged
+        }),
+        Ok(None) => KillSwitch::NotEngaged,
+        Err(e) => {
+            warn!(
+                "Failed to fetch kill switch {:?}, assuming not engaged: {}",
+                location, e
+            );
+            KillSwitch::NotEngaged
+        }
+    }
+}
+
+// GET <url> without credentials, the token is only ever sent to GitHub.
+// Returns None when the file does not exist.
+async fn fetch_url(
+    url: &str,
+    proxy: Option<&ProxyConfig>,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let octocrab = url_client(proxy)?;
+    let response = octocrab._get(url).await?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Ok(None),
+        status if status.is_success() => Ok(Some(octocrab.body_to_string(response).await?)),
+        status => Err(Box::from(format!("unexpected status {}", status))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    fn repos(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    async fn serve(status: u16, body: &str) -> (MockServer, KillSwitchLocation) {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/kill-switch.yml"))
+            .respond_with(ResponseTemplate::new(status).set_body_string(body))
+            .mount(&server)
+            .await;
+        let location = format!("{}/kill-switch.yml", server.uri()).parse().unwrap();
+        (server, location)
+    }
+
+    async fn check(location: &KillSwitchLocation) -> KillSwitch {
+        check_kill_switch(location, &GitHubUrls::new("https://github.com"), "token").await
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(
+            "github://org/ops/ratchet/kill-switch.yml".parse(),
+            Ok(KillSwitchLocation::GitHub {
+                owner: String::from("org"),
+                repo: String::from("ops"),
+                path: String::from("ratchet/kill-switch.yml"),
+            })
+        );
+        assert!("github://org/ops".parse::<KillSwitchLocation>().is_err());
+        assert!("kill-switch.yml".parse::<KillSwitchLocation>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_engaged() {
+        let (_server, location) = serve(200, "disabled: true\nmessage: corrupt pins\n").await;
+        let kill_switch = check(&location).await;
+        assert_eq!(
+            kill_switch.apply(repos(&["org/a", "org/b"])),
+            Err(String::from("corrupt pins"))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_not_engaged() {
+        let (_server, location) = serve(200, "disabled: false\n").await;
+        assert_eq!(check(&location).await, KillSwitch::NotEngaged);
+
+        let (_server, location) = serve(404, "").await;
+        assert_eq!(check(&location).await, KillSwitch::NotEngaged);
+    }
+
+    #[tokio::test]
+    async fn test_allowlisted_repositories() {
+        let (_server, location) = serve(200, "disabled: true\nallow:\n  - org/canary\n").await;
+        let kill_switch = check(&location).await;
+        assert_eq!(
+            kill_switch.apply(repos(&["org/a", "org/canary"])),
+            Ok(repos(&["org/canary"]))
+        );
+        assert!(kill_switch.apply(repos(&["org/a"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_failure_is_not_engaged() {
+        let (_server, location) = serve(500, "disabled: true\n").await;
+        assert_eq!(check(&location).await, KillSwitch::NotEngaged);
+
+        let location = KillSwitchLocation::Url(String::from("http://127.0.0.1:1/kill-switch.yml"));
+        assert_eq!(check(&location).await, KillSwitch::NotEngaged);
+    }
+}
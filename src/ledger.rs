@@ -0,0 +1,239 @@
+use std::{
+    collections::BTreeSet,
+    fs::{self, File, OpenOptions},
+    io::{self, Write},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::summary::{RepoStatus, RepoSummary};
+
+// Summary reason of a repository that the resumed run already finished, --resume
+pub const SKIPPED_RESUMED: &str = "skipped: already done by the resumed run";
+
+// Version of the ledger format, a ledger of another version is not resumed
+const LEDGER_VERSION: u32 = 1;
+
+// First line of a ledger
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
+struct Header {
+    version: u32,
+}
+
+// What a resumed run needs of a ledger line, the rest of the RepoSummary is ignored
+#[derive(Debug, Deserialize)]
+struct Line {
+    repository: String,
+    status: RepoStatus,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum LedgerError {
+    #[error("failed to open ledger {0}: {1}")]
+    Open(String, io::Error),
+    #[error("invalid ledger {0} at line {1}: {2}")]
+    Parse(String, usize, serde_json::Error),
+    #[error("ledger {0} has version {1}, expected {LEDGER_VERSION}")]
+    Version(String, u32),
+}
+
+// --ledger and --resume: one JSON line per finished repository, the same entry as in
+// --summary-json, appended and flushed as soon as the repository is done so a run
+// that dies keeps everything before it
+pub struct Ledger {
+    path: String,
+    file: File,
+    // Repositories whose latest line is created, updated or skipped
+    done: BTreeSet<String>,
+}
+
+impl Ledger {
+    // A new ledger at <path>, replacing an existing one
+    pub fn create(path: &str) -> Result<Self, LedgerError> {
+        let mut file = File::create(path).map_err(|e| LedgerError::Open(path.to_string(), e))?;
+        write_line(
+            &mut file,
+            &Header {
+                version: LEDGER_VERSION,
+            },
+        )
+        .map_err(|e| LedgerError::Open(path.to_string(), e))?;
+        Ok(Ledger {
+            path: path.to_string(),
+            file,
+            done: BTreeSet::new(),
+        })
+    }
+
+    // The ledger at <path> of an earlier run, appended to from now on. A missing
+    // ledger starts a new one.
+    pub fn resume(path: &str) -> Result<Self, LedgerError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) if !content.is_empty() => content,
+            Ok(_) => return Ledger::create(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ledger::create(path),
+            Err(e) => return Err(LedgerError::Open(path.to_string(), e)),
+        };
+        let done = parse(path, &content)?;
+        let file = OpenOptions::new()
+            .append(true)
+            .open(path)
+            .map_err(|e| LedgerError::Open(path.to_string(), e))?;
+        // The run that wrote it died halfway through a line, which is dropped
+        let complete = content.rfind('\n').map_or(0, |end| end + 1);
+        if complete < content.len() {
+            file.set_len(complete as u64)
+                .map_err(|e| LedgerError::Open(path.to_string(), e))?;
+        }
+        Ok(Ledger {
+            path: path.to_string(),
+            file,
+            done,
+        })
+    }
+
+    // Whether the resumed run already finished <repository>
+    pub fn is_done(&self, repository: &str) -> bool {
+        self.done.contains(repository)
+    }
+
+    // Number of repositories the resumed run already finished
+    pub fn finished(&self) -> usize {
+        self.done.len()
+    }
+
+    // Append the outcome of a repository. A deferred repository is not finished and
+    // is not recorded.
+    pub fn append(&mut self, entry: &RepoSummary) {
+        if entry.status == RepoStatus::Deferred {
+            return;
+        }
+        if let Err(e) = write_line(&mut self.file, entry) {
+            warn!(
+                "Failed to write {} to the ledger {}: {}",
+                entry.repository, self.path, e
+            );
+        }
+    }
+}
+
+fn write_line(file: &mut File, value: &impl Serialize) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    file.write_all(line.as_bytes())?;
+    file.flush()
+}
+
+// The finished repositories of a ledger. Later lines win, a repository that failed
+// after it was skipped is not finished. An incomplete last line is ignored.
+fn parse(path: &str, content: &str) -> Result<BTreeSet<String>, LedgerError> {
+    let mut lines = content.lines().enumerate().peekable();
+    let header: Header = match lines.next() {
+        Some((_, line)) => {
+            serde_json::from_str(line).map_err(|e| LedgerError::Parse(path.to_string(), 1, e))?
+        }
+        None => Header {
+            version: LEDGER_VERSION,
+        },
+    };
+    if header.version != LEDGER_VERSION {
+        return Err(LedgerError::Version(path.to_string(), header.version));
+    }
+    let mut done = BTreeSet::new();
+    while let Some((index, line)) = lines.next() {
+        let line: Line = match serde_json::from_str(line) {
+            Ok(line) => line,
+            Err(_) if lines.peek().is_none() && !content.ends_with('\n') => break,
+            Err(e) => return Err(LedgerError::Parse(path.to_string(), index + 1, e)),
+        };
+        match line.status {
+            RepoStatus::Created | RepoStatus::Updated | RepoStatus::Skipped => {
+                done.insert(line.repository)
+            }
+            _ => done.remove(&line.repository),
+        };
+    }
+    Ok(done)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::error::Error;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_ledger_round_trips() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let path = path.to_str().unwrap();
+
+        let mut ledger = Ledger::create(path).unwrap();
+        ledger.append(&RepoSummary::new("org/a", RepoStatus::Created));
+        ledger.append(&RepoSummary::new("org/b", RepoStatus::Skipped));
+        ledger.append(&RepoSummary::failed(
+            "org/c",
+            &*Box::<dyn Error>::from("Failed to clone repository"),
+        ));
+        ledger.append(&RepoSummary::new("org/d", RepoStatus::Deferred));
+        drop(ledger);
+        assert_eq!(
+            fs::read_to_string(path).unwrap().lines().next(),
+            Some("{\"version\":1}")
+        );
+
+        let mut ledger = Ledger::resume(path).unwrap();
+        assert!(ledger.is_done("org/a"));
+        assert!(ledger.is_done("org/b"));
+        assert!(!ledger.is_done("org/c"));
+        assert!(!ledger.is_done("org/d"));
+        ledger.append(&RepoSummary::new("org/c", RepoStatus::Updated));
+        ledger.append(&RepoSummary::failed(
+            "org/b",
+            &*Box::<dyn Error>::from("Failed to clone repository"),
+        ));
+        drop(ledger);
+
+        let ledger = Ledger::resume(path).unwrap();
+        assert!(ledger.is_done("org/a"));
+        assert!(!ledger.is_done("org/b"));
+        assert!(ledger.is_done("org/c"));
+        assert_eq!(ledger.finished(), 2);
+    }
+
+    #[test]
+    fn test_resume_an_interrupted_ledger() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("ledger.jsonl");
+        let path = path.to_str().unwrap();
+
+        // A missing ledger is a new one
+        assert_eq!(Ledger::resume(path).unwrap().finished(), 0);
+
+        fs::write(
+            path,
+            "{\"version\":1}\n{\"repository\":\"org/a\",\"status\":\"created\"}\n{\"repos",
+        )
+        .unwrap();
+        let mut ledger = Ledger::resume(path).unwrap();
+        assert!(ledger.is_done("org/a"));
+        ledger.append(&RepoSummary::new("org/b", RepoStatus::Created));
+        drop(ledger);
+        let ledger = Ledger::resume(path).unwrap();
+        assert!(ledger.is_done("org/b"));
+
+        for (content, message) in [
+            ("{\"version\":2}\n", "has version 2, expected 1"),
+            ("[]\n", "invalid ledger"),
+            (
+                "{\"version\":1}\n{\"repos\n{\"repository\":\"org/a\",\"status\":\"created\"}\n",
+                "at line 2",
+            ),
+        ] {
+            fs::write(path, content).unwrap();
+            let err = Ledger::resume(path).err().unwrap();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
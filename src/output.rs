@@ -0,0 +1,281 @@
+use std::{
+    future::Future,
+    io::{IsTerminal, Write},
+    sync::Arc,
+};
+
+use chrono::{SecondsFormat, Utc};
+use clap::ValueEnum;
+use env_logger::{fmt::WriteStyle, Builder, Logger, Target};
+use log::{
+    kv::{self, Key, Value, VisitSource},
+    LevelFilter, Log, Metadata, Record,
+};
+use serde_json::Map;
+
+use crate::progress::TerminalProgress;
+
+tokio::task_local! {
+    // Repository the log lines of the current task are about
+    static LOG_REPOSITORY: String;
+}
+
+// --color, decides once for the whole run whether escape sequences are written
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ColorChoice {
+    Always,
+    Auto,
+    Never,
+}
+
+impl ColorChoice {
+    // Auto only colors output that goes to a terminal, never to a pipe or file
+    pub fn use_color(self, is_terminal: bool) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Auto => is_terminal,
+            ColorChoice::Never => false,
+        }
+    }
+}
+
+// --log-format
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum LogFormat {
+    // Human readable lines, the fields of a log call are left out
+    Text,
+    // One JSON object per line with timestamp, level, message, the repository being
+    // processed and the fields of the log call, like `phase` and `file`
+    Json,
+}
+
+// All output is written as log lines to stderr, stdout is left for data. With
+// <progress> every line is written above its status line instead of through it.
+pub fn init_logger(
+    level: LevelFilter,
+    color: ColorChoice,
+    format: LogFormat,
+    progress: Option<Arc<TerminalProgress>>,
+) {
+    let colors = color.use_color(std::io::stderr().is_terminal());
+    let logger = logger_builder(level, colors, format, Target::Stderr).build();
+    log::set_max_level(logger.filter());
+    let logger: Box<dyn Log> = match progress {
+        Some(progress) => Box::new(ProgressLogger { logger, progress }),
+        None => Box::new(logger),
+    };
+    // Only fails when a logger was already set
+    let _ = log::set_boxed_logger(logger);
+}
+
+// Clears the status line of --progress around every log line
+struct ProgressLogger {
+    logger: Logger,
+    progress: Arc<TerminalProgress>,
+}
+
+impl Log for ProgressLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.logger.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if self.logger.matches(record) {
+            self.progress.suspend(|| self.logger.log(record));
+        }
+    }
+
+    fn flush(&self) {
+        self.logger.flush();
+    }
+}
+
+// Run <future> with <repository> attached to its JSON log lines
+pub async fn with_log_repository<F: Future>(repository: &str, future: F) -> F::Output {
+    LOG_REPOSITORY.scope(repository.to_string(), future).await
+}
+
+pub fn logger_builder(
+    level: LevelFilter,
+    colors: bool,
+    format: LogFormat,
+    target: Target,
+) -> Builder {
+    let mut builder = Builder::new();
+    builder
+        .filter_level(level)
+        .format_module_path(false)
+        .format_target(false)
+        .write_style(if colors {
+            WriteStyle::Always
+        } else {
+            WriteStyle::Never
+        })
+        .target(target);
+    if format == LogFormat::Json {
+        builder.format(|buf, record| writeln!(buf, "{}", json_line(record)));
+    }
+    builder
+}
+
+fn json_line(record: &Record) -> serde_json::Value {
+    let mut line = Map::new();
+    line.insert(
+        String::from("timestamp"),
+        Utc::now()
+            .to_rfc3339_opts(SecondsFormat::Millis, true)
+            .into(),
+    );
+    line.insert(String::from("level"), record.level().as_str().into());
+    line.insert(String::from("message"), record.args().to_string().into());
+    if let Ok(repository) = LOG_REPOSITORY.try_with(String::clone) {
+        line.insert(String::from("repo"), repository.into());
+    }
+    // A field that can not be read is left out, the line is still written
+    let _ = record.key_values().visit(&mut Fields(&mut line));
+    serde_json::Value::Object(line)
+}
+
+// Copies the fields of a log call into a JSON object, as strings
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        self.0.insert(key.to_string(), value.to_string().into());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use log::Log;
+    use std::{
+        io::Write,
+        sync::{Arc, Mutex},
+    };
+
+    // Writer that is not a terminal, like a pipe into tee or jq
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_to_pipe(color: ColorChoice) -> String {
+        let captured = Captured::default();
+        let logger = logger_builder(
+            LevelFilter::Info,
+            color.use_color(false),
+            LogFormat::Text,
+            Target::Pipe(Box::new(captured.clone())),
+        )
+        .build();
+        for level in [log::Level::Error, log::Level::Warn, log::Level::Info] {
+            logger.log(
+                &log::Record::builder()
+                    .level(level)
+                    .args(format_args!("Created PR for org/repo"))
+                    .build(),
+            );
+        }
+        logger.flush();
+        let output = captured.0.lock().unwrap().clone();
+        String::from_utf8(output).unwrap()
+    }
+
+    #[test]
+    fn test_use_color() {
+        assert!(ColorChoice::Always.use_color(false));
+        assert!(ColorChoice::Auto.use_color(true));
+        assert!(!ColorChoice::Auto.use_color(false));
+        assert!(!ColorChoice::Never.use_color(true));
+    }
+
+    #[test]
+    fn test_no_escape_sequences_when_piped() {
+        for color in [ColorChoice::Auto, ColorChoice::Never] {
+            let output = log_to_pipe(color);
+            assert!(output.contains("Created PR for org/repo"));
+            assert!(!output.contains('\x1b'), "{:?}: {:?}", color, output);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_carry_repository_and_fields() {
+        let captured = Captured::default();
+        let logger = logger_builder(
+            LevelFilter::Info,
+            false,
+            LogFormat::Json,
+            Target::Pipe(Box::new(captured.clone())),
+        )
+        .build();
+        let fields = [("phase", "ratchet"), ("file", ".github/workflows/ci.yml")];
+        let record = |logger: &env_logger::Logger| {
+            logger.log(
+                &Record::builder()
+                    .level(log::Level::Warn)
+                    .args(format_args!("ratchet \"failed\""))
+                    .key_values(&fields)
+                    .build(),
+            )
+        };
+        with_log_repository("org/repo", async { record(&logger) }).await;
+        record(&logger);
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0]["level"], "WARN");
+        assert_eq!(lines[0]["message"], "ratchet \"failed\"");
+        assert_eq!(lines[0]["repo"], "org/repo");
+        assert_eq!(lines[0]["phase"], "ratchet");
+        assert_eq!(lines[0]["file"], ".github/workflows/ci.yml");
+        assert!(lines[0]["timestamp"]
+            .as_str()
+            .is_some_and(|timestamp| timestamp.ends_with('Z')));
+        // Outside of a repository there is no repo
+        assert!(lines[1].get("repo").is_none());
+    }
+
+    #[test]
+    fn test_text_lines_leave_fields_out() {
+        let captured = Captured::default();
+        let logger = logger_builder(
+            LevelFilter::Info,
+            false,
+            LogFormat::Text,
+            Target::Pipe(Box::new(captured.clone())),
+        )
+        .build();
+        logger.log(
+            &Record::builder()
+                .level(log::Level::Info)
+                .args(format_args!("Upgrading workflows"))
+                .key_values(&[("phase", "ratchet")])
+                .build(),
+        );
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            output.trim_end().ends_with("] Upgrading workflows"),
+            "{}",
+            output
+        );
+    }
+
+    #[test]
+    fn test_color_always_is_honored_when_piped() {
+        assert!(log_to_pipe(ColorChoice::Always).contains('\x1b'));
+    }
+}
@@ -0,0 +1,271 @@
+use std::{collections::BTreeMap, collections::HashSet, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    io::write_atomically,
+    workflow::{RefKind, UsesLine},
+};
+
+// Default of --pin-cache-ttl, in hours
+pub const DEFAULT_PIN_CACHE_TTL: u64 = 24;
+
+// Version of the --pin-cache format, a cache of another version is not used
+const PIN_CACHE_VERSION: u32 = 1;
+
+// SHA a reference resolved to, and when (seconds since the epoch)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct CacheEntry {
+    sha: String,
+    resolved_at: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheFile {
+    version: u32,
+    entries: BTreeMap<String, CacheEntry>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum PinCacheError {
+    #[error("failed to read pin cache {0}: {1}")]
+    Read(String, io::Error),
+    #[error("invalid pin cache {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("pin cache {0} has version {1}, expected {PIN_CACHE_VERSION}")]
+    Version(String, u32),
+}
+
+// How often the cache had the SHA of a tag, reported in the run summary
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PinCacheStats {
+    pub hits: usize,
+    pub misses: usize,
+}
+
+// --pin-cache: the SHAs tags of actions resolved to, like actions/checkout@v4, shared
+// by the files and repositories of a run and by later runs until they are --pin-cache-ttl
+// old. Only tags are cached, branches move too often.
+#[derive(Debug)]
+pub struct PinCache {
+    path: String,
+    ttl_secs: i64,
+    entries: BTreeMap<String, CacheEntry>,
+    stats: PinCacheStats,
+}
+
+impl PinCache {
+    // An empty cache written to <path>
+    pub fn new(path: &str, ttl_hours: u64) -> Self {
+        PinCache {
+            path: path.to_string(),
+            ttl_secs: (ttl_hours * 3600) as i64,
+            entries: BTreeMap::new(),
+            stats: PinCacheStats::default(),
+        }
+    }
+
+    // The cache at <path> without the entries that expired by <now>, empty when the
+    // file does not exist yet
+    pub fn load(path: &str, ttl_hours: u64, now: i64) -> Result<Self, PinCacheError> {
+        let mut cache = PinCache::new(path, ttl_hours);
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(PinCacheError::Read(path.to_string(), e)),
+        };
+        let file: CacheFile = serde_json::from_str(&content)
+            .map_err(|e| PinCacheError::Parse(path.to_string(), e))?;
+        if file.version != PIN_CACHE_VERSION {
+            return Err(PinCacheError::Version(path.to_string(), file.version));
+        }
+        cache.entries = file
+            .entries
+            .into_iter()
+            .filter(|(_, entry)| now - entry.resolved_at < cache.ttl_secs)
+            .collect();
+        Ok(cache)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let file = CacheFile {
+            version: PIN_CACHE_VERSION,
+            entries: self.entries.clone(),
+        };
+        let json = serde_json::to_string_pretty(&file)? + "\n";
+        write_atomically(Path::new(&self.path), &json)
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    pub fn stats(&self) -> PinCacheStats {
+        self.stats
+    }
+
+    // SHA of <uses>, like actions/checkout@v4, counted as a hit or a miss
+    pub fn get(&mut self, uses: &str) -> Option<String> {
+        let sha = self.entries.get(uses).map(|entry| entry.sha.clone());
+        match sha {
+            Some(_) => self.stats.hits += 1,
+            None => self.stats.misses += 1,
+        }
+        sha
+    }
+
+    pub fn insert(&mut self, uses: &str, sha: &str) {
+        self.entries.insert(
+            uses.to_string(),
+            CacheEntry {
+                sha: sha.to_string(),
+                resolved_at: chrono::Utc::now().timestamp(),
+            },
+        );
+    }
+}
+
+// <content> with the tags the cache knows pinned the way ratchet pins them, like
+// `actions/checkout@<sha> # ratchet:actions/checkout@v4`, and the number of references
+// left for ratchet. Lines with a comment of their own are left to ratchet as well.
+pub fn apply_cached_pins(content: &str, cache: &mut PinCache) -> (String, usize) {
+    let mut pending = 0;
+    let pinned = content
+        .split_inclusive('\n')
+        .map(|raw| {
+            let Some(line) = UsesLine::parse(raw, 0) else {
+                return raw.to_string();
+            };
+            if line.is_immutable() || line.is_excluded() {
+                return raw.to_string();
+            }
+            match line.kind() {
+                RefKind::Local | RefKind::Expression => return raw.to_string(),
+                RefKind::Tag if line.comment().is_none() => {
+                    if let Some(sha) = cache.get(line.uses()) {
+                        let comment = format!("ratchet:{}", line.uses());
+                        let with_sha = line.with_ref(&sha);
+                        if let Some(pinned) = UsesLine::parse(&with_sha, 0) {
+                            return pinned.with_comment(Some(&comment));
+                        }
+                    }
+                }
+                _ => {}
+            }
+            pending += 1;
+            raw.to_string()
+        })
+        .collect();
+    (pinned, pending)
+}
+
+// Add the tags ratchet pinned between <before> and <after> to the cache: the lines of
+// <after> that are not in <before>, pinned to a SHA with the tag in their ratchet comment
+pub fn learn_pins(before: &str, after: &str, cache: &mut PinCache) {
+    let unchanged: HashSet<&str> = before.split_inclusive('\n').collect();
+    for raw in after.split_inclusive('\n') {
+        if unchanged.contains(raw) {
+            continue;
+        }
+        let Some(line) = UsesLine::parse(raw, 0) else {
+            continue;
+        };
+        let (Some(original), Some(action)) = (line.ratchet_original(), line.action_ref()) else {
+            continue;
+        };
+        if let (RefKind::Sha, RefKind::Tag) = (line.kind(), RefKind::of(original)) {
+            cache.insert(original, action.reference.unwrap_or_default());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const CHECKOUT_SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+    const SETUP_NODE_SHA: &str = "1a4442cacd436585916779262731d5b162bc6ec7";
+
+    #[test]
+    fn test_pin_cache_round_trips_and_expires() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("pins.json");
+        let path = path.to_str().unwrap();
+
+        // A missing file is an empty cache
+        let mut cache = PinCache::load(path, 24, 0).unwrap();
+        assert_eq!(cache.get("actions/checkout@v4"), None);
+        cache.insert("actions/checkout@v4", CHECKOUT_SHA);
+        cache.save().unwrap();
+        assert!(!dir.path().join("pins.partial").exists());
+
+        let now = chrono::Utc::now().timestamp();
+        let mut cache = PinCache::load(path, 24, now).unwrap();
+        assert_eq!(
+            cache.get("actions/checkout@v4").as_deref(),
+            Some(CHECKOUT_SHA)
+        );
+        assert_eq!(cache.get("actions/checkout@v3"), None);
+        assert_eq!(cache.stats(), PinCacheStats { hits: 1, misses: 1 });
+
+        // A day later the entry expired
+        let mut cache = PinCache::load(path, 24, now + 24 * 3600).unwrap();
+        assert_eq!(cache.get("actions/checkout@v4"), None);
+
+        for (content, message) in [
+            (
+                "{\"version\":2,\"entries\":{}}",
+                "has version 2, expected 1",
+            ),
+            ("[]", "invalid pin cache"),
+        ] {
+            fs::write(path, content).unwrap();
+            let err = PinCache::load(path, 24, now).unwrap_err();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_apply_and_learn_cached_pins() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("pins.json");
+        let mut cache = PinCache::new(path.to_str().unwrap(), 24);
+        cache.insert("actions/checkout@v4", CHECKOUT_SHA);
+
+        let content = "steps:\n  - uses: actions/checkout@v4\n  - uses: \"actions/setup-node@v4\"\n  - uses: ./local\n  - uses: org/tool@main\n";
+        let (pinned, pending) = apply_cached_pins(content, &mut cache);
+        assert_eq!(
+            pinned,
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # ratchet:actions/checkout@v4\n  - uses: \"actions/setup-node@v4\"\n  - uses: ./local\n  - uses: org/tool@main\n",
+                CHECKOUT_SHA
+            )
+        );
+        // setup-node and the branch are left to ratchet
+        assert_eq!(pending, 2);
+        assert_eq!(cache.stats(), PinCacheStats { hits: 1, misses: 1 });
+
+        // What ratchet pinned is cached, the branch is not
+        let ratcheted = pinned
+            .replace(
+                "\"actions/setup-node@v4\"",
+                &format!(
+                    "\"actions/setup-node@{}\" # ratchet:actions/setup-node@v4",
+                    SETUP_NODE_SHA
+                ),
+            )
+            .replace(
+                "org/tool@main",
+                &format!("org/tool@{} # ratchet:org/tool@main", CHECKOUT_SHA),
+            );
+        learn_pins(&pinned, &ratcheted, &mut cache);
+        let (repinned, pending) = apply_cached_pins(content, &mut cache);
+        assert_eq!(pending, 1);
+        assert!(repinned.contains(&format!(
+            "\"actions/setup-node@{}\" # ratchet:actions/setup-node@v4",
+            SETUP_NODE_SHA
+        )));
+        assert_eq!(cache.get("org/tool@main"), None);
+    }
+}
@@ -0,0 +1,285 @@
+use std::{error::Error, fs, path::PathBuf};
+
+use crate::{
+    freshness::{render_age_distribution, AgedPin},
+    git::PinChange,
+    github::MergeMethod,
+    io::{render_pr_body, PrBodyValues},
+    ratchet::UpgradeResult,
+    report::{
+        render_branch_pins, render_comment_only_changes, render_pin_counts, render_pinned_actions,
+        render_provenance, render_remaining_unpinned, PinnedAction,
+    },
+    scan::ActionUse,
+    verify::{render_source_issues, render_tag_mismatches, SourceIssue, TagMismatch},
+    Args,
+};
+
+pub const PR_TITLE: &str = "ci: pin versions of actions";
+// Title with --update, versions moved before they were pinned
+pub const UPDATE_PR_TITLE: &str = "ci: update and pin versions of actions";
+
+// GitHub rejects pull request bodies longer than this many characters
+pub const MAX_PR_BODY_LENGTH: usize = 65536;
+
+const TRUNCATED_NOTICE: &str =
+    "\n\n_This description was truncated, see the changed files for all changes._\n";
+
+// Everything staged for a repository that ends up in its pull request
+pub struct StagedChanges<'a> {
+    pub repository: &'a str,
+    pub run_date: &'a str,
+    pub files_changed: usize,
+    pub pin_changes: &'a [PinChange],
+    // Changed references per file, listed in a table unless --no-change-table
+    pub pinned_actions: &'a [PinnedAction],
+    pub unpinned_before: usize,
+    pub remaining: &'a [ActionUse],
+    // Rendered --verify-pins section, empty when pins were not verified
+    pub floating_pins: &'a str,
+    // --verify-pins=warn, pins that do not match their tag
+    pub tag_mismatches: &'a [TagMismatch],
+    // --verify-sources, empty when sources were not verified
+    pub source_issues: &'a [SourceIssue],
+    pub comment_only_changes: usize,
+    pub aged: &'a [AgedPin],
+    pub held_back: usize,
+    pub upgrade: &'a UpgradeResult,
+}
+
+// Pull request as it is created, and as it is previewed by --pr-preview-dir
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PullRequestContent {
+    // Head of the pull request, <fork owner>:<branch> with --fork
+    pub branch: String,
+    pub base: String,
+    pub title: String,
+    pub body: String,
+    pub labels: Vec<String>,
+    pub draft: bool,
+    pub auto_merge: Option<MergeMethod>,
+}
+
+// Build the pull request of <changes> with the body template <pr_body>, pushed to
+// <branch> and targeting <base>
+pub fn build_pr_content(
+    args: &Args,
+    pr_body: &str,
+    branch: &str,
+    base: &str,
+    changes: &StagedChanges,
+) -> PullRequestContent {
+    let body = render_pr_body(
+        pr_body,
+        &PrBodyValues {
+            repo: changes.repository,
+            files_changed: changes.files_changed,
+            pin_changes: changes.pin_changes,
+            run_date: changes.run_date,
+        },
+    );
+    let pinned_actions = if args.no_change_table {
+        String::new()
+    } else {
+        render_pinned_actions(&args.github_url, changes.pinned_actions)
+    };
+    let body = format!(
+        "{}{}{}{}{}{}{}{}{}{}{}",
+        render_pin_counts(changes.unpinned_before, changes.remaining),
+        body,
+        pinned_actions,
+        changes.floating_pins,
+        render_tag_mismatches(changes.tag_mismatches),
+        render_branch_pins(&changes.upgrade.branch_pins),
+        render_source_issues(changes.source_issues),
+        render_remaining_unpinned(changes.remaining),
+        render_comment_only_changes(changes.comment_only_changes),
+        render_age_distribution(changes.aged, changes.held_back),
+        render_provenance(changes.upgrade)
+    );
+    PullRequestContent {
+        branch: branch.to_string(),
+        base: base.to_string(),
+        title: String::from(if args.update {
+            UPDATE_PR_TITLE
+        } else {
+            PR_TITLE
+        }),
+        body: truncate_body(body, MAX_PR_BODY_LENGTH),
+        labels: args.label.clone(),
+        draft: args.draft,
+        auto_merge: args.auto_merge,
+    }
+}
+
+// Cut <body> to at most <max_length> characters, ending with a notice when it was cut
+fn truncate_body(body: String, max_length: usize) -> String {
+    if body.chars().count() <= max_length {
+        return body;
+    }
+    let keep = max_length.saturating_sub(TRUNCATED_NOTICE.chars().count());
+    let mut truncated: String = body.chars().take(keep).collect();
+    truncated.push_str(TRUNCATED_NOTICE);
+    truncated
+}
+
+// Markdown preview of <content>: a metadata header followed by the title and body.
+// <existing_pr> is the number of the pull request that would be updated.
+pub fn render_preview(
+    repository: &str,
+    content: &PullRequestContent,
+    existing_pr: Option<u64>,
+) -> String {
+    let mut header = format!(
+        "---\nrepository: {}\nbranch: {}\nbase: {}\n",
+        repository, content.branch, content.base
+    );
+    match existing_pr {
+        Some(number) => header.push_str(&format!("action: update #{}\n", number)),
+        None => header.push_str("action: create\n"),
+    }
+    header.push_str(&format!(
+        "labels: [{}]\ndraft: {}\n",
+        content.labels.join(", "),
+        content.draft
+    ));
+    if let Some(method) = content.auto_merge {
+        header.push_str(&format!("auto_merge: {}\n", method));
+    }
+    format!("{}---\n\n# {}\n\n{}\n", header, content.title, content.body)
+}
+
+// Write the preview of <repository> to <dir>/<owner>__<repo>.md, return the path
+pub fn write_preview(
+    dir: &str,
+    repository: &str,
+    content: &PullRequestContent,
+    existing_pr: Option<u64>,
+) -> Result<PathBuf, Box<dyn Error>> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir, e))?;
+    let path = PathBuf::from(dir).join(format!("{}.md", repository.replace('/', "__")));
+    fs::write(&path, render_preview(repository, content, existing_pr))
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::io::DEFAULT_PR_BODY;
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    const PREVIEW_SNAPSHOT: &str = include_str!("../resources/pr_preview_snapshot.md");
+
+    fn pin_changes() -> Vec<PinChange> {
+        vec![
+            PinChange {
+                action: String::from("actions/checkout"),
+                old: String::from("v3"),
+                new: String::from("f43a0e5ff2bd294095638e18286ca9a3d1956744"),
+            },
+            PinChange {
+                action: String::from("actions/setup-node"),
+                old: String::from("v3"),
+                new: String::from("1a4442cacd436585916779262731d5b162bc6ec7"),
+            },
+        ]
+    }
+
+    fn content(args: &[&str]) -> PullRequestContent {
+        let mut argv = vec!["ratchet-dispatcher", "--repos", "org/app"];
+        argv.extend_from_slice(args);
+        let args = Args::parse_from(argv);
+        let pin_changes = pin_changes();
+        let pinned_actions: Vec<PinnedAction> = pin_changes
+            .iter()
+            .map(|change| PinnedAction {
+                file: String::from(".github/workflows/ci.yml"),
+                action: change.action.clone(),
+                old: change.old.clone(),
+                new: change.new.clone(),
+                tag: Some(change.old.clone()),
+            })
+            .collect();
+        build_pr_content(
+            &args,
+            DEFAULT_PR_BODY,
+            "automated-ratchet-dispatcher-pin",
+            "main",
+            &StagedChanges {
+                repository: "org/app",
+                run_date: "2024-05-01",
+                files_changed: 1,
+                pin_changes: &pin_changes,
+                pinned_actions: &pinned_actions,
+                unpinned_before: 2,
+                remaining: &[],
+                floating_pins: "",
+                tag_mismatches: &[],
+                source_issues: &[],
+                comment_only_changes: 1,
+                aged: &[],
+                held_back: 0,
+                upgrade: &UpgradeResult::default(),
+            },
+        )
+    }
+
+    #[test]
+    fn test_preview_snapshot() {
+        let content = content(&[
+            "--dry-run",
+            "--draft",
+            "--label",
+            "dependencies",
+            "--label",
+            "security",
+            "--auto-merge",
+            "squash",
+        ]);
+        assert_eq!(render_preview("org/app", &content, None), PREVIEW_SNAPSHOT);
+    }
+
+    #[test]
+    fn test_preview_of_existing_pr() {
+        let preview = render_preview("org/app", &content(&[]), Some(7));
+        assert!(preview.contains("action: update #7\nlabels: []\ndraft: false\n---\n"));
+    }
+
+    #[test]
+    fn test_update_title() {
+        assert_eq!(content(&[]).title, PR_TITLE);
+        assert_eq!(content(&["--update"]).title, UPDATE_PR_TITLE);
+    }
+
+    #[test]
+    fn test_no_change_table() {
+        assert!(content(&[]).body.contains("### Pinned actions"));
+        assert!(!content(&["--no-change-table"])
+            .body
+            .contains("### Pinned actions"));
+    }
+
+    #[test]
+    fn test_long_body_is_truncated() {
+        let body = "é".repeat(100);
+        let truncated = truncate_body(body.clone(), 80);
+        assert_eq!(truncated.chars().count(), 80);
+        assert!(truncated.ends_with(TRUNCATED_NOTICE));
+        assert_eq!(truncate_body(body.clone(), 100), body);
+    }
+
+    #[test]
+    fn test_write_preview() {
+        let dir = tempdir().unwrap();
+        let dir = dir.path().join("previews");
+        let content = content(&[]);
+        let path = write_preview(dir.to_str().unwrap(), "org/app", &content, None).unwrap();
+        assert_eq!(path, dir.join("org__app.md"));
+        assert_eq!(
+            fs::read_to_string(path).unwrap(),
+            render_preview("org/app", &content, None)
+        );
+    }
+}
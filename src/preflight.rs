@@ -0,0 +1,140 @@
+use chrono::{Duration, Utc};
+use log::info;
+
+use crate::github::PullRequests;
+
+// Checks for open pull requests that change workflow files, the pin PR would
+// most likely conflict with them. Our own pull request (head <branch>) is ignored,
+// as are pull requests older than <max_age> so a stale PR does not block forever.
+// Returns the deferral reason when the repository should be skipped for now.
+pub async fn find_open_workflow_prs<C: PullRequests + Sync + ?Sized>(
+    client: &C,
+    base: &str,
+    branch: &str,
+    max_age: Option<Duration>,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let now = Utc::now();
+    let mut blocking = Vec::new();
+    for pr in client.list_open_workflow_prs(base).await? {
+        if pr.head == branch {
+            continue;
+        }
+        if max_age.is_some_and(|max_age| now - pr.created_at > max_age) {
+            info!(
+                "Ignoring workflow PR #{} opened at {}, older than --defer-max-age",
+                pr.number, pr.created_at
+            );
+            continue;
+        }
+        blocking.push(format!("#{}", pr.number));
+    }
+
+    if blocking.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(format!(
+            "deferred: workflow PRs open: {}",
+            blocking.join(", ")
+        )))
+    }
+}
+
+// What a classic personal access token with <scopes> will fail to do. Pushing changes
+// to .github/workflows needs the workflow scope on top of repo, runs that push nothing
+// (<pushes> false) only need to read.
+pub fn scope_warnings(scopes: &[String], pushes: bool) -> Vec<String> {
+    let has = |scope: &str| scopes.iter().any(|s| s == scope);
+    let mut warnings = Vec::new();
+    if !has("repo") {
+        warnings.push(String::from(
+            "The GitHub token has no repo scope, private repositories can not be cloned and no pull requests can be opened",
+        ));
+    }
+    if pushes && !has("workflow") {
+        warnings.push(String::from(
+            "The GitHub token has no workflow scope, GitHub rejects every push that changes .github/workflows",
+        ));
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{MockPullRequests, WorkflowPullRequest};
+    use mockall::predicate::*;
+
+    fn workflow_pr(number: u64, head: &str, age_days: i64) -> WorkflowPullRequest {
+        WorkflowPullRequest {
+            number,
+            head: head.to_string(),
+            created_at: Utc::now() - Duration::days(age_days),
+        }
+    }
+
+    fn client(prs: Vec<WorkflowPullRequest>) -> MockPullRequests {
+        let mut client = MockPullRequests::new();
+        client
+            .expect_list_open_workflow_prs()
+            .with(eq("main"))
+            .times(1)
+            .returning(move |_| Ok(prs.clone()));
+        client
+    }
+
+    #[tokio::test]
+    async fn test_defer_when_workflow_prs_open() {
+        let client = client(vec![
+            workflow_pr(12, "feature", 1),
+            workflow_pr(34, "fix-ci", 3),
+            workflow_pr(56, "automated-ratchet-dispatcher-pin", 1),
+        ]);
+        let reason =
+            find_open_workflow_prs(&client, "main", "automated-ratchet-dispatcher-pin", None)
+                .await
+                .unwrap();
+        assert_eq!(
+            reason.as_deref(),
+            Some("deferred: workflow PRs open: #12, #34")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_proceed_when_workflow_prs_are_old() {
+        let client = client(vec![workflow_pr(12, "feature", 30)]);
+        let reason = find_open_workflow_prs(
+            &client,
+            "main",
+            "automated-ratchet-dispatcher-pin",
+            Some(Duration::days(14)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reason, None);
+    }
+
+    #[test]
+    fn test_scope_warnings() {
+        let scopes = |scopes: &[&str]| -> Vec<String> {
+            scopes.iter().map(|scope| scope.to_string()).collect()
+        };
+        assert!(scope_warnings(&scopes(&["repo", "workflow"]), true).is_empty());
+        assert!(scope_warnings(&scopes(&["repo"]), false).is_empty());
+        let warnings = scope_warnings(&scopes(&["repo", "read:org"]), true);
+        assert_eq!(warnings.len(), 1);
+        assert!(warnings[0].contains("no workflow scope"));
+        let warnings = scope_warnings(&scopes(&[]), true);
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("no repo scope"));
+    }
+
+    #[tokio::test]
+    async fn test_proceed_without_open_workflow_prs() {
+        let client = client(Vec::new());
+        let reason =
+            find_open_workflow_prs(&client, "main", "automated-ratchet-dispatcher-pin", None)
+                .await
+                .unwrap();
+        assert_eq!(reason, None);
+    }
+}
@@ -0,0 +1,266 @@
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use log::info;
+
+use crate::summary::{RepoStatus, RepoSummary};
+
+// Step of a repository shown in the progress line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Phase {
+    Clone,
+    Pin,
+    Stage,
+    Push,
+    PullRequest,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Clone => "clone",
+            Phase::Pin => "pin",
+            Phase::Stage => "stage",
+            Phase::Push => "push",
+            Phase::PullRequest => "PR",
+        })
+    }
+}
+
+// Told where a run is: which repository of how many, and which step of it
+pub trait Progress: Send + Sync {
+    // <index> counts from 1
+    fn start(&self, index: usize, total: usize, repository: &str);
+    fn phase(&self, phase: Phase);
+    fn finish(&self, entry: &RepoSummary);
+}
+
+// No progress at all, the default of a Dispatcher
+pub struct NoProgress;
+
+impl Progress for NoProgress {
+    fn start(&self, _: usize, _: usize, _: &str) {}
+    fn phase(&self, _: Phase) {}
+    fn finish(&self, _: &RepoSummary) {}
+}
+
+// One log line per finished repository, like `[12/300] org/repo: created PR #45`.
+// Used when stderr is not a terminal.
+#[derive(Default)]
+pub struct LineProgress {
+    position: Mutex<(usize, usize)>,
+}
+
+impl Progress for LineProgress {
+    fn start(&self, index: usize, total: usize, _: &str) {
+        if let Ok(mut position) = self.position.lock() {
+            *position = (index, total);
+        }
+    }
+
+    fn phase(&self, _: Phase) {}
+
+    fn finish(&self, entry: &RepoSummary) {
+        let (index, total) = self.position.lock().map_or((0, 0), |position| *position);
+        info!(
+            "[{}/{}] {}: {}",
+            index,
+            total,
+            entry.repository,
+            describe_outcome(entry)
+        );
+    }
+}
+
+// The repository being processed
+struct Status {
+    index: usize,
+    total: usize,
+    repository: String,
+    phase: Option<Phase>,
+    started: Instant,
+}
+
+// Status line at the bottom of the terminal, redrawn on every change. Log lines are
+// written above it through suspend, see output::init_logger.
+#[derive(Default)]
+pub struct TerminalProgress {
+    status: Mutex<Option<Status>>,
+}
+
+impl TerminalProgress {
+    // Clear the status line, run <write> and draw the line again below its output
+    pub fn suspend(&self, write: impl FnOnce()) {
+        let status = self.status.lock();
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r\x1b[2K");
+        let _ = stderr.flush();
+        write();
+        if let Ok(status) = &status {
+            draw(&mut stderr, status.as_ref());
+        }
+    }
+
+    fn update(&self, change: impl FnOnce(&mut Option<Status>)) {
+        if let Ok(mut status) = self.status.lock() {
+            change(&mut status);
+            draw(&mut io::stderr().lock(), status.as_ref());
+        }
+    }
+}
+
+impl Progress for TerminalProgress {
+    fn start(&self, index: usize, total: usize, repository: &str) {
+        self.update(|status| {
+            *status = Some(Status {
+                index,
+                total,
+                repository: repository.to_string(),
+                phase: None,
+                started: Instant::now(),
+            })
+        });
+    }
+
+    fn phase(&self, phase: Phase) {
+        self.update(|status| {
+            if let Some(status) = status {
+                status.phase = Some(phase);
+            }
+        });
+    }
+
+    fn finish(&self, entry: &RepoSummary) {
+        self.update(|status| {
+            if let Some(finished) = status.take() {
+                eprintln!(
+                    "\r\x1b[2K[{}/{}] {}: {}",
+                    finished.index,
+                    finished.total,
+                    entry.repository,
+                    describe_outcome(entry)
+                );
+            }
+        });
+    }
+}
+
+fn draw(stderr: &mut impl Write, status: Option<&Status>) {
+    if let Some(status) = status {
+        let _ = write!(
+            stderr,
+            "\r\x1b[2K{}",
+            render_status(
+                status.index,
+                status.total,
+                &status.repository,
+                status.phase,
+                status.started.elapsed()
+            )
+        );
+        let _ = stderr.flush();
+    }
+}
+
+// The status line, like `[12/300] org/repo: stage (1m 05s)`
+fn render_status(
+    index: usize,
+    total: usize,
+    repository: &str,
+    phase: Option<Phase>,
+    elapsed: Duration,
+) -> String {
+    let seconds = elapsed.as_secs();
+    let elapsed = if seconds >= 60 {
+        format!("{}m {:02}s", seconds / 60, seconds % 60)
+    } else {
+        format!("{}s", seconds)
+    };
+    match phase {
+        Some(phase) => format!(
+            "[{}/{}] {}: {} ({})",
+            index, total, repository, phase, elapsed
+        ),
+        None => format!("[{}/{}] {} ({})", index, total, repository, elapsed),
+    }
+}
+
+// What happened to a repository in a few words, like `created PR #45`
+pub fn describe_outcome(entry: &RepoSummary) -> String {
+    let pr = entry
+        .pr_number
+        .map_or_else(String::new, |number| format!(" #{}", number));
+    let reason = entry
+        .reason
+        .as_deref()
+        .map_or_else(String::new, |reason| format!(" ({})", reason));
+    match entry.status {
+        RepoStatus::Created => format!("created PR{}", pr),
+        RepoStatus::Updated => format!("updated PR{}", pr),
+        RepoStatus::Skipped => format!("skipped{}", reason),
+        RepoStatus::Deferred => format!("deferred{}", reason),
+        RepoStatus::Reported => String::from("reported unpinned actions"),
+        RepoStatus::DryRun => String::from("dry run"),
+        RepoStatus::Pruned => String::from("pruned"),
+        RepoStatus::Committed => String::from("committed locally"),
+        RepoStatus::Failed => format!(
+            "failed: {}",
+            entry.error.as_deref().unwrap_or("unknown error")
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::PullRequestRef;
+
+    #[test]
+    fn test_render_status() {
+        assert_eq!(
+            render_status(
+                12,
+                300,
+                "org/repo",
+                Some(Phase::Stage),
+                Duration::from_secs(65)
+            ),
+            "[12/300] org/repo: stage (1m 05s)"
+        );
+        assert_eq!(
+            render_status(1, 3, "org/repo", None, Duration::from_millis(2500)),
+            "[1/3] org/repo (2s)"
+        );
+        assert_eq!(
+            render_status(2, 3, "org/repo", Some(Phase::PullRequest), Duration::ZERO),
+            "[2/3] org/repo: PR (0s)"
+        );
+    }
+
+    #[test]
+    fn test_describe_outcome() {
+        let created = RepoSummary::new("org/repo", RepoStatus::Created).with_pr(&PullRequestRef {
+            number: 45,
+            html_url: String::from("https://github.com/org/repo/pull/45"),
+            draft: false,
+        });
+        assert_eq!(describe_outcome(&created), "created PR #45");
+        assert_eq!(
+            describe_outcome(
+                &RepoSummary::new("org/repo", RepoStatus::Skipped).with_reason("already pinned")
+            ),
+            "skipped (already pinned)"
+        );
+        assert_eq!(
+            describe_outcome(&RepoSummary::failed(
+                "org/repo",
+                &*Box::<dyn std::error::Error>::from("Failed to clone repository")
+            )),
+            "failed: Failed to clone repository"
+        );
+    }
+}
@@ -0,0 +1,194 @@
+use crate::github::{BranchRefs, PullRequests};
+
+// What --prune-branches did with the automation branch of a repository
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PruneOutcome {
+    // The branch of the merged or closed PR <number> was deleted
+    Deleted { number: u64, merged: bool },
+    // --dry-run, the branch would have been deleted
+    WouldDelete { number: u64, merged: bool },
+    // The branch has an open PR and is never deleted
+    OpenPr(u64),
+    // The PR was closed without merging and is kept for --reopen-closed
+    KeptForReopen(u64),
+    // The branch never had a PR
+    NoPr,
+    // The branch does not exist (anymore)
+    NoBranch,
+}
+
+impl PruneOutcome {
+    pub fn describe(&self, branch: &str) -> String {
+        let state = |merged: bool| if merged { "merged" } else { "closed" };
+        match self {
+            PruneOutcome::Deleted { number, merged } => {
+                format!(
+                    "deleted branch {} of {} PR #{}",
+                    branch,
+                    state(*merged),
+                    number
+                )
+            }
+            PruneOutcome::WouldDelete { number, merged } => format!(
+                "would delete branch {} of {} PR #{}",
+                branch,
+                state(*merged),
+                number
+            ),
+            PruneOutcome::OpenPr(number) => {
+                format!("kept branch {}, PR #{} is open", branch, number)
+            }
+            PruneOutcome::KeptForReopen(number) => {
+                format!("kept branch {} to reopen closed PR #{}", branch, number)
+            }
+            PruneOutcome::NoPr => format!("kept branch {}, it has no PR", branch),
+            PruneOutcome::NoBranch => format!("branch {} does not exist", branch),
+        }
+    }
+}
+
+// Delete <branch> once its PR is merged or closed. A branch with an open PR is never
+// deleted, and with <keep_closed> (--reopen-closed) neither is the branch of a PR that
+// was closed without merging. With <dry_run> nothing is deleted.
+pub async fn prune_branch<P, R>(
+    prs: &P,
+    refs: &R,
+    branch: &str,
+    keep_closed: bool,
+    dry_run: bool,
+) -> Result<PruneOutcome, Box<dyn std::error::Error>>
+where
+    P: PullRequests + Sync + ?Sized,
+    R: BranchRefs + Sync + ?Sized,
+{
+    if refs.branch_sha(branch).await?.is_none() {
+        return Ok(PruneOutcome::NoBranch);
+    }
+    if let Some(pr) = prs.find_existing_pr(branch).await? {
+        return Ok(PruneOutcome::OpenPr(pr.number));
+    }
+    Ok(match prs.find_closed_pr(branch).await? {
+        None => PruneOutcome::NoPr,
+        Some(closed) if keep_closed && !closed.merged => {
+            PruneOutcome::KeptForReopen(closed.pr.number)
+        }
+        Some(closed) if dry_run => PruneOutcome::WouldDelete {
+            number: closed.pr.number,
+            merged: closed.merged,
+        },
+        Some(closed) => {
+            refs.delete_branch_ref(branch).await?;
+            PruneOutcome::Deleted {
+                number: closed.pr.number,
+                merged: closed.merged,
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{ClosedPullRequest, MockBranchRefs, MockPullRequests, PullRequestRef};
+    use mockall::predicate::*;
+
+    const BRANCH: &str = "automated-ratchet-dispatcher-pin";
+
+    fn pr(number: u64) -> PullRequestRef {
+        PullRequestRef {
+            number,
+            html_url: format!("https://github.com/org/app/pull/{}", number),
+            draft: false,
+        }
+    }
+
+    fn prs(open: Option<u64>, closed: Option<(u64, bool)>) -> MockPullRequests {
+        let mut prs = MockPullRequests::new();
+        prs.expect_find_existing_pr()
+            .with(eq(BRANCH))
+            .returning(move |_| Ok(open.map(pr)));
+        prs.expect_find_closed_pr()
+            .with(eq(BRANCH))
+            .returning(move |_| {
+                Ok(closed.map(|(number, merged)| ClosedPullRequest {
+                    pr: pr(number),
+                    merged,
+                }))
+            });
+        prs
+    }
+
+    fn refs(deletes: usize) -> MockBranchRefs {
+        let mut refs = MockBranchRefs::new();
+        refs.expect_branch_sha()
+            .with(eq(BRANCH))
+            .returning(|_| Ok(Some(String::from("abc"))));
+        refs.expect_delete_branch_ref()
+            .with(eq(BRANCH))
+            .times(deletes)
+            .returning(|_| Ok(()));
+        refs
+    }
+
+    #[tokio::test]
+    async fn test_prune_merged_branch() {
+        let outcome = prune_branch(&prs(None, Some((7, true))), &refs(1), BRANCH, false, false)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome,
+            PruneOutcome::Deleted {
+                number: 7,
+                merged: true
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_never_deletes_branch_with_open_pr() {
+        let outcome = prune_branch(
+            &prs(Some(8), Some((7, true))),
+            &refs(0),
+            BRANCH,
+            false,
+            false,
+        )
+        .await
+        .unwrap();
+        assert_eq!(outcome, PruneOutcome::OpenPr(8));
+    }
+
+    #[tokio::test]
+    async fn test_prune_dry_run() {
+        let outcome = prune_branch(&prs(None, Some((7, false))), &refs(0), BRANCH, false, true)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome.describe(BRANCH),
+            "would delete branch automated-ratchet-dispatcher-pin of closed PR #7"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prune_keeps_closed_branch_for_reopen() {
+        let outcome = prune_branch(&prs(None, Some((7, false))), &refs(0), BRANCH, true, false)
+            .await
+            .unwrap();
+        assert_eq!(outcome, PruneOutcome::KeptForReopen(7));
+    }
+
+    #[tokio::test]
+    async fn test_prune_without_pr_or_branch() {
+        let outcome = prune_branch(&prs(None, None), &refs(0), BRANCH, false, false)
+            .await
+            .unwrap();
+        assert_eq!(outcome, PruneOutcome::NoPr);
+
+        let mut refs = MockBranchRefs::new();
+        refs.expect_branch_sha().returning(|_| Ok(None));
+        let outcome = prune_branch(&MockPullRequests::new(), &refs, BRANCH, false, false)
+            .await
+            .unwrap();
+        assert_eq!(outcome, PruneOutcome::NoBranch);
+    }
+}
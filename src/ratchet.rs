@@ -1,77 +1,1229 @@
-use std::{fs, path::Path, process::Command};
+use std::{
+    collections::{HashMap, HashSet},
+    env, fmt, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
-use log::{debug, error, info};
+use clap::ValueEnum;
+use log::{debug, error, info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-pub async fn upgrade_workflows(local_path: &str) -> Result<(), Box<dyn std::error::Error>> {
-    info!("Upgrading workflows in {}", local_path);
-    let workflows_path = format!("{}/.github/workflows", local_path);
-    if !Path::new(&workflows_path).exists() {
-        error!("No workflows directory found at {}", workflows_path);
-        return Err(Box::from("Workflows directory not found"));
+use crate::{
+    github::TagLookup,
+    io::is_file_too_large,
+    pin_cache::{apply_cached_pins, learn_pins, PinCache},
+    redact::redact,
+    workflow::{is_sha, is_version, parse_workflow, ActionRef, RefKind, UsesLine},
+};
+
+// Ratchet binary looked up on the PATH, unless --ratchet-bin says otherwise
+pub const DEFAULT_RATCHET_PATH: &str = "ratchet";
+
+// Environment variables ratchet reads its GitHub token from
+const GITHUB_TOKEN: &str = "GITHUB_TOKEN";
+const ACTIONS_TOKEN: &str = "ACTIONS_TOKEN";
+
+// Command of the invocations --backend native records
+const NATIVE_BACKEND: &str = "native";
+
+// Default of --ratchet-batch-size
+pub const DEFAULT_RATCHET_BATCH_SIZE: u64 = 20;
+
+// Default of --subprocess-timeout, in seconds
+pub const DEFAULT_SUBPROCESS_TIMEOUT: u64 = 120;
+
+// How ratchet is run: --ratchet-bin, --ratchet-arg and --subprocess-timeout
+#[derive(Clone, Copy)]
+pub struct RatchetOptions<'a> {
+    pub path: &'a str,
+    // Passed to every run between the subcommand and the file
+    pub args: &'a [String],
+    // A run taking longer is killed
+    pub timeout: Duration,
+    // --comment-style, applied to the lines a run changed
+    pub comment_style: CommentStyle,
+    // Set for every run, the proxy of --https-proxy
+    pub env: &'a [(String, String)],
+    // Token ratchet resolves tags with, its own environment is used without one
+    // (--no-ratchet-token)
+    pub token: Option<&'a str>,
+    // --ratchet-batch-size: files passed to a single ratchet run
+    pub batch_size: u64,
+    // --backend native: references are resolved through this lookup and pinned
+    // without running ratchet at all
+    pub native: Option<&'a (dyn TagLookup + Sync)>,
+}
+
+// --backend: what pins the workflows
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PinBackend {
+    // The ratchet binary of --ratchet-bin
+    #[default]
+    Ratchet,
+    // Built in, resolves references through the GitHub API
+    Native,
+}
+
+// --comment-style: what is kept of the comment ratchet writes next to a new pin
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommentStyle {
+    // The comment as ratchet wrote it
+    #[default]
+    None,
+    // Only `# ratchet:owner/action@v4`, text after it is dropped
+    Ratchet,
+    // Only the version, like `# v4`. Pins of a branch keep their ratchet comment.
+    Semver,
+    // No comment at all
+    Strip,
+}
+
+impl CommentStyle {
+    // Warning for a run with <self> that does not update pins. Without the ratchet
+    // comment `ratchet update` can not find the reference a pin came from.
+    pub fn update_warning(self, update: bool) -> Option<String> {
+        let name = match self {
+            CommentStyle::Semver => "semver",
+            CommentStyle::Strip => "strip",
+            CommentStyle::None | CommentStyle::Ratchet => return None,
+        };
+        (!update).then(|| {
+            format!(
+                "--comment-style {} drops the ratchet comments of new pins, so ratchet update can no longer find their original reference and they stay on their commit. Run with --unclean to restore the comments",
+                name
+            )
+        })
     }
+}
 
-    debug!("Found workflows directory at {}", workflows_path);
-    for entry in fs::read_dir(&workflows_path)? {
-        let entry = entry?;
-        let path = entry.path();
-        if path.is_file() {
-            // Instead of returning an error, we continue
-            let _ = upgrade_single_workflow(&path);
+// Rewrite the ratchet comments of <content> to <style>. Lines that are also in
+// <original>, the file before ratchet ran, were not changed by this run and are kept.
+pub fn rewrite_ratchet_comments(content: &str, original: &str, style: CommentStyle) -> String {
+    if style == CommentStyle::None {
+        return content.to_string();
+    }
+    let unchanged: HashSet<&str> = original.split_inclusive('\n').collect();
+    content
+        .split_inclusive('\n')
+        .map(|raw| match UsesLine::parse(raw, 0) {
+            Some(line) if !unchanged.contains(raw) => {
+                restyle_line(&line, style).unwrap_or_else(|| raw.to_string())
+            }
+            _ => raw.to_string(),
+        })
+        .collect()
+}
+
+// <line> with its ratchet comment in <style>, None when it has no ratchet comment or
+// keeps it as it is
+fn restyle_line(line: &UsesLine, style: CommentStyle) -> Option<String> {
+    let original = line.ratchet_original()?;
+    match style {
+        CommentStyle::None => None,
+        CommentStyle::Ratchet => Some(line.with_comment(Some(&format!("ratchet:{}", original)))),
+        CommentStyle::Semver => {
+            let version = original_version(original)?;
+            is_version(version).then(|| line.with_comment(Some(version)))
+        }
+        CommentStyle::Strip => Some(line.with_comment(None)),
+    }
+}
+
+// The reference of a ratchet original: v4 of owner/action@v4, 3.19 of
+// docker://alpine:3.19. A docker image without a tag has none.
+fn original_version(original: &str) -> Option<&str> {
+    match original.strip_prefix("docker://") {
+        Some(image) => {
+            let name_end = image.rfind('/').map_or(0, |slash| slash + 1);
+            let (_, tag) = image[name_end..].split('@').next()?.rsplit_once(':')?;
+            Some(tag)
+        }
+        None => original.rsplit_once('@').map(|(_, version)| version),
+    }
+}
+
+// --unclean: turn the version comments of pins, like `# v4` written by
+// --comment-style semver, back into `# ratchet:owner/action@v4` so ratchet update can
+// read the original reference again. The # stays in its column.
+pub fn restore_ratchet_comments(content: &str) -> String {
+    content
+        .split_inclusive('\n')
+        .map(|raw| {
+            UsesLine::parse(raw, 0)
+                .filter(|line| line.is_immutable())
+                .and_then(|line| {
+                    let version = line
+                        .comment()
+                        .filter(|comment| is_version(comment) && !comment.contains(' '))?;
+                    let original = match line.kind() {
+                        // Docker images keep their tag after a :, replacing the one
+                        // left next to the digest
+                        RefKind::Docker => {
+                            let image = line.action_key();
+                            let name_end = image.rfind('/').map_or(0, |slash| slash + 1);
+                            let name = match image[name_end..].rsplit_once(':') {
+                                Some((_, tag)) => &image[..image.len() - tag.len() - 1],
+                                None => image,
+                            };
+                            format!("{}:{}", name, version)
+                        }
+                        _ => format!("{}@{}", line.action_key(), version),
+                    };
+                    Some(line.with_comment(Some(&format!("ratchet:{}", original))))
+                })
+                .unwrap_or_else(|| raw.to_string())
+        })
+        .collect()
+}
+
+// restore_ratchet_comments on every file of <local_path> ratchet pins, files above
+// <max_size> bytes are left alone. Returns the number of restored comments.
+pub fn restore_ratchet_comments_in(
+    local_path: &str,
+    pin_paths: &[String],
+    extra_extensions: &[String],
+    max_size: u64,
+) -> usize {
+    let mut restored = 0;
+    for path in pin_files(local_path, pin_paths, extra_extensions) {
+        if is_file_too_large(&path, max_size) {
+            debug!(phase = "restore-comments", file:% = path.display(); "{}: {}", path.display(), SKIP_TOO_LARGE);
+            continue;
+        }
+        let Ok(content) = fs::read_to_string(&path) else {
+            continue;
+        };
+        let rewritten = restore_ratchet_comments(&content);
+        if rewritten == content {
+            continue;
+        }
+        if let Err(e) = fs::write(&path, &rewritten) {
+            warn!(
+                phase = "restore-comments", file:% = path.display();
+                "Failed to restore the ratchet comments of {}: {}",
+                path.display(),
+                e
+            );
+            continue;
+        }
+        restored += content
+            .lines()
+            .zip(rewritten.lines())
+            .filter(|(old, new)| old != new)
+            .count();
+    }
+    restored
+}
+
+// Apply <style> to the lines ratchet changed in <path>, <original> is its content
+// before ratchet ran
+fn restyle_comments(path: &Path, original: &str, style: CommentStyle) {
+    if style == CommentStyle::None {
+        return;
+    }
+    let result = fs::read_to_string(path).and_then(|content| {
+        let rewritten = rewrite_ratchet_comments(&content, original, style);
+        if rewritten == content {
+            Ok(())
+        } else {
+            fs::write(path, rewritten)
+        }
+    });
+    if let Err(e) = result {
+        warn!(
+            phase = "comment-style", file:% = path.display();
+            "Failed to rewrite the ratchet comments of {}: {}",
+            path.display(),
+            e
+        );
+    }
+}
+
+// Errors of a single ratchet run, a failing ratchet is not one of them
+#[derive(Debug, thiserror::Error)]
+pub enum RatchetError {
+    #[error("Failed to run {command}: {source}")]
+    Spawn {
+        command: String,
+        source: std::io::Error,
+    },
+    #[error("{command} on {file} did not finish within {timeout:?} and was killed")]
+    TimedOut {
+        command: String,
+        file: String,
+        timeout: Duration,
+    },
+    #[error("Failed to pin {file}: {source}")]
+    Native {
+        file: String,
+        source: std::io::Error,
+    },
+}
+
+// Oldest ratchet whose pin comments the staging heuristics understand
+pub const DEFAULT_MIN_RATCHET_VERSION: &str = "0.5.0";
+
+// Ratchet subcommands: pin unpinned references, or move existing pins to the
+// latest commit of their ref (--update-pins)
+const RATCHET_PIN: &str = "pin";
+const RATCHET_UPDATE: &str = "update";
+
+// How ratchet is run on every file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatchetMode {
+    // Pin unpinned references
+    Pin,
+    // --update-pins: move existing pins to the latest commit of their ref
+    UpdatePins,
+    // --update: move references to their latest version, then pin them
+    UpdateAndPin,
+}
+
+impl RatchetMode {
+    pub fn new(update: bool, update_pins: bool) -> Self {
+        if update {
+            RatchetMode::UpdateAndPin
+        } else if update_pins {
+            RatchetMode::UpdatePins
+        } else {
+            RatchetMode::Pin
+        }
+    }
+
+    // Ratchet subcommands run on every file, in order
+    pub fn subcommands(self) -> &'static [&'static str] {
+        match self {
+            RatchetMode::Pin => &[RATCHET_PIN],
+            RatchetMode::UpdatePins => &[RATCHET_UPDATE],
+            RatchetMode::UpdateAndPin => &[RATCHET_UPDATE, RATCHET_PIN],
+        }
+    }
+
+    pub fn commit_message(self) -> &'static str {
+        match self {
+            RatchetMode::Pin => "ci: pin versions of workflow actions",
+            RatchetMode::UpdatePins => "ci: update pinned workflow actions",
+            RatchetMode::UpdateAndPin => "ci: update and pin versions of workflow actions",
+        }
+    }
+}
+
+// Default for --max-workflow-size, 2 MiB
+pub const DEFAULT_MAX_WORKFLOW_SIZE: u64 = 2 * 1024 * 1024;
+
+// A workflow file that was not passed to ratchet
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SkippedWorkflow {
+    pub path: PathBuf,
+    pub reason: &'static str,
+}
+
+pub const SKIP_TOO_LARGE: &str = "skipped: file too large";
+pub const SKIP_INVALID_YAML: &str = "skipped: not valid YAML";
+pub const SKIP_TIMED_OUT: &str = "skipped: ratchet timed out";
+
+// Extensions of the workflow files passed to ratchet, --include-extension adds more
+const WORKFLOW_EXTENSIONS: [&str; 2] = ["yml", "yaml"];
+
+// Summary reason of a repository without any file matching --pin-paths
+pub const NO_WORKFLOWS: &str = "no workflows to pin";
+
+// Summary reason of a repository ratchet did not change anything in
+pub const ALREADY_PINNED: &str = "already pinned";
+
+// Summary reason of a repository ratchet changed, but where every change was left out,
+// like comment-only changes, excluded actions or corrupted workflows
+pub const NOTHING_TO_COMMIT: &str = "no changes left to commit";
+
+// Default of --pin-paths
+pub const DEFAULT_PIN_PATH: &str = ".github/workflows/**";
+
+// Environment variables ratchet may read, only their names are recorded
+const RECORDED_ENV_PREFIXES: &[&str] = &["ACTIONS_", "GITHUB_", "RATCHET_"];
+const RECORDED_ENV_NAMES: &[&str] = &[
+    "HOME",
+    "HTTPS_PROXY",
+    "HTTP_PROXY",
+    "NO_PROXY",
+    "PATH",
+    "SSL_CERT_DIR",
+    "SSL_CERT_FILE",
+];
+// Never recorded, even though they match the names above
+const DENIED_ENV_PARTS: &[&str] = &["TOKEN", "SECRET", "PASSWORD", "KEY", "CREDENTIAL", "AUTH"];
+
+// How workflow files were processed by a ratchet run, so a pin can be reconstructed later
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RatchetInvocation {
+    // Workflow files of the run relative to the repository root, the last arguments
+    pub files: Vec<String>,
+    pub args: Vec<String>,
+    pub ratchet_version: Option<String>,
+    pub env_names: Vec<String>,
+    pub duration: Duration,
+    // None when ratchet was killed by a signal
+    pub exit_code: Option<i32>,
+}
+
+// How a `uses:` reference is pinned, for the pin statistics of the run summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinState {
+    // Full commit SHA, or the digest of a docker image
+    Sha,
+    // Version tag, or a docker image tag
+    Tag,
+    // Branch, an action without a ref, or a docker image without a tag
+    Branch,
+}
+
+// Classify <uses>, the value of a `uses:` key. Local actions and expressions are None,
+// they are not counted at all.
+pub fn classify_pin(uses: &str) -> Option<PinState> {
+    match RefKind::of(uses) {
+        RefKind::Sha => Some(PinState::Sha),
+        RefKind::Tag => Some(PinState::Tag),
+        RefKind::Branch | RefKind::Missing => Some(PinState::Branch),
+        RefKind::Docker => {
+            let image = uses.trim_start_matches("docker://");
+            if image.contains("@sha256:") {
+                return Some(PinState::Sha);
+            }
+            let name_end = image.rfind('/').map_or(0, |slash| slash + 1);
+            match image[name_end..].rsplit_once(':') {
+                Some((_, "latest")) | None => Some(PinState::Branch),
+                Some(_) => Some(PinState::Tag),
+            }
+        }
+        RefKind::Local | RefKind::Expression => None,
+    }
+}
+
+// Number of references per PinState
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PinCounts {
+    pub sha: usize,
+    pub tag: usize,
+    pub branch: usize,
+}
+
+impl PinCounts {
+    // Count the `uses:` values <uses>
+    pub fn of<'a>(uses: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut counts = PinCounts::default();
+        for state in uses.into_iter().filter_map(classify_pin) {
+            match state {
+                PinState::Sha => counts.sha += 1,
+                PinState::Tag => counts.tag += 1,
+                PinState::Branch => counts.branch += 1,
+            }
+        }
+        counts
+    }
+
+    pub fn add(&mut self, other: &PinCounts) {
+        self.sha += other.sha;
+        self.tag += other.tag;
+        self.branch += other.branch;
+    }
+
+    pub fn total(&self) -> usize {
+        self.sha + self.tag + self.branch
+    }
+
+    // Share of the counted references pinned to a SHA, None when there are none
+    pub fn pinned_percent(&self) -> Option<f64> {
+        match self.total() {
+            0 => None,
+            total => Some(self.sha as f64 * 100.0 / total as f64),
+        }
+    }
+}
+
+// Pin counts of the workflows before ratchet ran and of what the run leaves behind
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PinStats {
+    pub before: PinCounts,
+    pub after: PinCounts,
+}
+
+impl PinStats {
+    // Stats of a repository the run did not change
+    pub fn unchanged(counts: PinCounts) -> Self {
+        PinStats {
+            before: counts,
+            after: counts,
+        }
+    }
+
+    pub fn add(&mut self, other: &PinStats) {
+        self.before.add(&other.before);
+        self.after.add(&other.after);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.before.total() == 0 && self.after.total() == 0
+    }
+}
+
+// A pin whose ratchet comment follows a branch, like actions/checkout@main. The next
+// ratchet update moves it to whatever the branch points to then.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct BranchPin {
+    pub file: String,
+    pub line: usize,
+    pub action: String,
+    pub branch: String,
+}
+
+// Whether <reference> is a branch name rather than a full commit SHA, a sha256 digest
+// or a version like v4, v4.1.2 or 1.0
+pub fn is_branch_reference(reference: &str) -> bool {
+    !is_sha(reference) && !is_version(reference) && !reference.starts_with("sha256:")
+}
+
+// Pins of <content> whose ratchet comment keeps a branch as the original reference
+pub fn find_branch_pins(file: &str, content: &str) -> Vec<BranchPin> {
+    parse_workflow(content)
+        .iter()
+        .filter_map(|line| {
+            let action = ActionRef::parse(line.ratchet_original()?)?;
+            let branch = action
+                .reference
+                .filter(|reference| is_branch_reference(reference))?;
+            Some(BranchPin {
+                file: file.to_string(),
+                line: line.line,
+                action: action.action(),
+                branch: branch.to_string(),
+            })
+        })
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UpgradeResult {
+    pub skipped: Vec<SkippedWorkflow>,
+    pub invocations: Vec<RatchetInvocation>,
+    // Pins that follow a branch after ratchet ran
+    pub branch_pins: Vec<BranchPin>,
+}
+
+impl UpgradeResult {
+    // SHA-256 over everything that influences the pins: ratchet version, arguments,
+    // environment variable names and exit codes. Durations and the absolute workflow
+    // path (the last argument, it depends on --clone-dir) are left out so identical
+    // runs give identical fingerprints.
+    pub fn fingerprint(&self) -> String {
+        let mut hasher = Sha256::new();
+        for invocation in &self.invocations {
+            let args =
+                &invocation.args[..invocation.args.len().saturating_sub(invocation.files.len())];
+            hasher.update(invocation.files.join(", ").as_bytes());
+            hasher.update([0]);
+            hasher.update(args.join(" ").as_bytes());
+            hasher.update([0]);
+            hasher.update(
+                invocation
+                    .ratchet_version
+                    .as_deref()
+                    .unwrap_or("")
+                    .as_bytes(),
+            );
+            hasher.update([0]);
+            hasher.update(invocation.env_names.join(",").as_bytes());
+            hasher.update([0]);
+            hasher.update(format!("{:?}", invocation.exit_code).as_bytes());
+            hasher.update([0]);
+        }
+        hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect()
+    }
+}
+
+// Whether <local_path> has any file to pin, see pin_files
+pub fn has_pin_files(local_path: &str, pin_paths: &[String], extra_extensions: &[String]) -> bool {
+    !pin_files(local_path, pin_paths, extra_extensions).is_empty()
+}
+
+// Files under <dir> and all of its subdirectories accepted by <keep>, sorted
+fn walk_files(dir: &Path, keep: impl Fn(&Path) -> bool) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            match entry.file_type() {
+                Ok(file_type) if file_type.is_dir() => dirs.push(path),
+                Ok(file_type) if file_type.is_file() && keep(&path) => files.push(path),
+                _ => {}
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// Files of <local_path> matching one of the <pin_paths> globs (relative to
+// <local_path>, like .github/workflows/**) with a .yml or .yaml extension or one of
+// <extra_extensions>, followed by the composite actions. A matching directory stands
+// for all files below it.
+fn pin_files(local_path: &str, pin_paths: &[String], extra_extensions: &[String]) -> Vec<PathBuf> {
+    let has_extension = |path: &Path| {
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                WORKFLOW_EXTENSIONS.contains(&extension)
+                    || extra_extensions.iter().any(|extra| extra == extension)
+            })
+    };
+    let mut files = Vec::new();
+    for pin_path in pin_paths {
+        // A trailing ** only matches subdirectories, the directory itself covers them all
+        let directory = pin_path.strip_suffix("/**").unwrap_or(pin_path);
+        let pattern = format!("{}/{}", glob::Pattern::escape(local_path), directory);
+        let paths = match glob::glob(&pattern) {
+            Ok(paths) => paths,
+            Err(e) => {
+                warn!("Ignoring invalid pin path {}: {}", pin_path, e);
+                continue;
+            }
+        };
+        for path in paths.flatten() {
+            if path.is_dir() {
+                files.extend(walk_files(&path, has_extension));
+            } else if path.is_file() && has_extension(&path) {
+                files.push(path);
+            }
+        }
+    }
+    files.sort();
+    files.dedup();
+    files.extend(composite_action_files(local_path));
+    files.dedup();
+    files
+}
+
+// action.yml and action.yaml files of the composite actions under .github/actions
+// of <local_path>, in any subdirectory
+fn composite_action_files(local_path: &str) -> Vec<PathBuf> {
+    walk_files(&Path::new(local_path).join(".github/actions"), |path| {
+        matches!(
+            path.file_name().and_then(|name| name.to_str()),
+            Some("action.yml" | "action.yaml")
+        )
+    })
+}
+
+// Whether <path> holds YAML, files ratchet can not parse are not passed to it
+fn is_valid_yaml(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .map(|content| serde_yaml::from_str::<serde_yaml::Value>(&content).is_ok())
+        .unwrap_or(false)
+}
+
+// Run the ratchet subcommands of <mode> on every file of <local_path> matching
+// <pin_paths>, and on the composite actions under .github/actions
+// Files larger than <max_size> bytes or that are not valid YAML are skipped, every
+// ratchet run is recorded. <extra_extensions> are workflow extensions besides .yml and .yaml.
+// A file on which ratchet times out is skipped, the other files are still pinned.
+// With <cache> (--pin-cache) the tags it knows are pinned before ratchet runs, which is
+// only run on files that have references left, and what ratchet pinned is added to it.
+pub async fn upgrade_workflows(
+    local_path: &str,
+    ratchet: &RatchetOptions<'_>,
+    mode: RatchetMode,
+    max_size: u64,
+    pin_paths: &[String],
+    extra_extensions: &[String],
+    cache: Option<&Mutex<PinCache>>,
+) -> Result<UpgradeResult, Box<dyn std::error::Error>> {
+    info!(phase = "ratchet"; "Upgrading workflows in {}", local_path);
+    let paths = pin_files(local_path, pin_paths, extra_extensions);
+    if paths.is_empty() {
+        info!(phase = "ratchet"; "No files matching {:?} in {}", pin_paths, local_path);
+        return Ok(UpgradeResult::default());
+    }
+
+    let ratchet_version = match ratchet.native {
+        Some(_) => None,
+        None => ratchet_version(ratchet).await,
+    };
+    let mut result = UpgradeResult::default();
+    // --backend native resolves every reference once per repository
+    let mut resolved = HashMap::new();
+
+    let mut pending = Vec::new();
+    for path in paths {
+        if is_file_too_large(&path, max_size) {
+            debug!(phase = "ratchet", file:% = path.display(); "{}: {}", path.display(), SKIP_TOO_LARGE);
+            result.skipped.push(SkippedWorkflow {
+                path,
+                reason: SKIP_TOO_LARGE,
+            });
+            continue;
+        }
+        if !is_valid_yaml(&path) {
+            debug!(phase = "ratchet", file:% = path.display(); "{}: {}", path.display(), SKIP_INVALID_YAML);
+            result.skipped.push(SkippedWorkflow {
+                path,
+                reason: SKIP_INVALID_YAML,
+            });
+            continue;
+        }
+        let file = path
+            .strip_prefix(local_path)
+            .unwrap_or(&path)
+            .display()
+            .to_string();
+        let original = fs::read_to_string(&path).unwrap_or_default();
+        pending.push(PendingFile {
+            path,
+            file,
+            before: original.clone(),
+            original,
+        });
+    }
+
+    let mut timed_out = HashSet::new();
+    for subcommand in mode.subcommands() {
+        if let Some(lookup) = ratchet.native {
+            for pending in &mut pending {
+                if let (Some(cache), RatchetMode::Pin) = (cache, mode) {
+                    if !apply_pin_cache(pending, cache) {
+                        continue;
+                    }
+                }
+                let started = Instant::now();
+                let exit_code = match upgrade_natively(
+                    &pending.path,
+                    lookup,
+                    subcommand,
+                    &mut resolved,
+                )
+                .await
+                {
+                    Ok(0) => 0,
+                    Ok(_) => 1,
+                    Err(e) => {
+                        error!(phase = "ratchet", file = pending.file.as_str(); "{}", e);
+                        1
+                    }
+                };
+                result.invocations.push(RatchetInvocation {
+                    files: vec![pending.file.clone()],
+                    args: vec![
+                        String::from(NATIVE_BACKEND),
+                        subcommand.to_string(),
+                        pending.file.clone(),
+                    ],
+                    ratchet_version: None,
+                    env_names: Vec::new(),
+                    duration: started.elapsed(),
+                    exit_code: Some(exit_code),
+                });
+                learn_from(pending, cache);
+            }
+            continue;
+        }
+        let files: Vec<&mut PendingFile> = pending
+            .iter_mut()
+            .filter(|pending| !timed_out.contains(&pending.path))
+            .collect();
+        let mut chunks = files.into_iter().peekable();
+        while chunks.peek().is_some() {
+            let mut chunk: Vec<&mut PendingFile> = chunks
+                .by_ref()
+                .take(ratchet.batch_size.max(1) as usize)
+                .collect();
+            // The pin cache learns from every batch, so later batches of the repository
+            // need ratchet for less
+            if let (Some(cache), RatchetMode::Pin) = (cache, mode) {
+                chunk.retain_mut(|pending| apply_pin_cache(pending, cache));
+            }
+            run_batches(
+                &chunk,
+                ratchet,
+                subcommand,
+                &ratchet_version,
+                &mut result,
+                &mut timed_out,
+            )
+            .await;
+            for pending in &chunk {
+                learn_from(pending, cache);
+            }
+        }
+    }
+
+    for PendingFile {
+        path,
+        file,
+        original,
+        ..
+    } in &pending
+    {
+        restyle_comments(path, original, ratchet.comment_style);
+        if let Ok(content) = fs::read_to_string(path) {
+            for pin in find_branch_pins(file, &content) {
+                warn!(
+                    phase = "ratchet", file = pin.file.as_str(), line = pin.line;
+                    "{}:{}: {} is pinned from branch {}, the next update moves it",
+                    pin.file, pin.line, pin.action, pin.branch
+                );
+                result.branch_pins.push(pin);
+            }
+        }
+    }
+
+    result
+        .invocations
+        .sort_by(|left, right| left.files.cmp(&right.files));
+    Ok(result)
+}
+
+// A workflow file upgrade_workflows passes to ratchet
+struct PendingFile {
+    path: PathBuf,
+    // Relative to the repository root
+    file: String,
+    // Content before this run, and before ratchet with the pins of the cache
+    original: String,
+    before: String,
+}
+
+// Pin the tags of <pending> the cache knows, false when that left nothing for ratchet
+fn apply_pin_cache(pending: &mut PendingFile, cache: &Mutex<PinCache>) -> bool {
+    let Ok(mut cache) = cache.lock() else {
+        return true;
+    };
+    let (pinned, references) = apply_cached_pins(&pending.original, &mut cache);
+    if pinned != pending.original {
+        match fs::write(&pending.path, &pinned) {
+            Ok(()) => pending.before = pinned,
+            Err(e) => {
+                warn!(phase = "ratchet", file = pending.file.as_str(); "Failed to apply the pin cache to {}: {}", pending.file, e)
+            }
         }
     }
+    if references == 0 {
+        debug!(phase = "ratchet", file = pending.file.as_str(); "{}: every reference came from the pin cache", pending.file);
+    }
+    references > 0
+}
 
-    Ok(())
+// Cache what ratchet pinned in <pending>
+fn learn_from(pending: &PendingFile, cache: Option<&Mutex<PinCache>>) {
+    if let Some(Ok(mut cache)) = cache.map(Mutex::lock) {
+        if let Ok(after) = fs::read_to_string(&pending.path) {
+            learn_pins(&pending.before, &after, &mut cache);
+        }
+    }
 }
 
-pub fn upgrade_single_workflow(path: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    debug!("Upgrading workflow: {}", path.display());
+// Run ratchet on <files> in one go. A run that failed is repeated for the files named in
+// the errors of ratchet one at a time and for the other files as a batch of their own,
+// or one file at a time when ratchet named none. A run that timed out is repeated one
+// file at a time, and a file that times out on its own is skipped.
+async fn run_batches(
+    files: &[&mut PendingFile],
+    ratchet: &RatchetOptions<'_>,
+    subcommand: &str,
+    ratchet_version: &Option<String>,
+    result: &mut UpgradeResult,
+    timed_out: &mut HashSet<PathBuf>,
+) {
+    let mut batches: Vec<Vec<&PendingFile>> =
+        vec![files.iter().map(|pending| &**pending).collect()];
+    while let Some(batch) = batches.pop() {
+        if batch.is_empty() {
+            continue;
+        }
+        let paths: Vec<&Path> = batch.iter().map(|pending| pending.path.as_path()).collect();
+        let names = batch
+            .iter()
+            .map(|pending| pending.file.as_str())
+            .collect::<Vec<_>>()
+            .join(", ");
+        match upgrade_batch(&paths, ratchet, subcommand).await {
+            Ok((mut invocation, stderr)) => {
+                let failed = invocation.exit_code != Some(0);
+                invocation.files = batch.iter().map(|pending| pending.file.clone()).collect();
+                invocation.ratchet_version = ratchet_version.clone();
+                result.invocations.push(invocation);
+                if failed && batch.len() > 1 {
+                    // Ratchet writes nothing when one of the files fails
+                    let (named, rest): (Vec<&PendingFile>, Vec<&PendingFile>) =
+                        batch.into_iter().partition(|pending| {
+                            stderr.contains(&pending.file)
+                                || stderr.contains(&*pending.path.to_string_lossy())
+                        });
+                    if named.is_empty() {
+                        batches.extend(rest.into_iter().rev().map(|pending| vec![pending]));
+                    } else {
+                        batches.push(rest);
+                        batches.extend(named.into_iter().rev().map(|pending| vec![pending]));
+                    }
+                }
+            }
+            Err(e @ RatchetError::TimedOut { .. }) if batch.len() > 1 => {
+                warn!(phase = "ratchet", file = names.as_str(); "{}, running its files one at a time", e);
+                batches.extend(batch.into_iter().rev().map(|pending| vec![pending]));
+            }
+            Err(e @ RatchetError::TimedOut { .. }) => {
+                warn!(phase = "ratchet", file = names.as_str(); "{}", e);
+                result.skipped.push(SkippedWorkflow {
+                    path: batch[0].path.clone(),
+                    reason: SKIP_TIMED_OUT,
+                });
+                timed_out.insert(batch[0].path.clone());
+            }
+            // Instead of returning an error, we continue
+            Err(e) => error!(phase = "ratchet", file = names.as_str(); "{}", e),
+        }
+    }
+}
 
-    let output = run_ratchet_command(path)?;
+// Run ratchet on the workflow files of <paths> at once and record the invocation, with
+// the stderr of ratchet. Fails only when ratchet could not be started or timed out, a
+// failing ratchet is recorded with its exit code. The whole batch gets the timeout of a
+// single file, a batch that times out is split to find the file that hangs.
+async fn upgrade_batch(
+    paths: &[&Path],
+    ratchet: &RatchetOptions<'_>,
+    subcommand: &str,
+) -> Result<(RatchetInvocation, String), RatchetError> {
+    let names = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ");
+    debug!(phase = "ratchet", file = names.as_str(); "Upgrading workflows: {}", names);
 
-    debug!("Ratchet output: {:?}", output);
+    let (output, invocation) = run_ratchet_command(paths, ratchet, subcommand).await?;
+
+    debug!(phase = "ratchet", file = names.as_str(); "Ratchet output: {}", redact(&format!("{:?}", output)));
+    let stderr = redact(&String::from_utf8_lossy(&output.stderr));
     if !output.status.success() {
         error!(
+            phase = "ratchet", file = names.as_str();
             "ratchet upgrade failed for {}: {}",
-            path.display(),
-            String::from_utf8_lossy(&output.stderr)
+            names,
+            stderr
+        );
+    } else {
+        info!(
+            phase = "ratchet", file = names.as_str();
+            "Successfully upgraded workflows: {}",
+            names
         );
-        return Err(Box::from(format!(
-            "ratchet upgrade command for path {} failed",
-            path.display()
-        )));
     }
 
-    info!(
-        "Successfully upgraded workflow: {:?}",
-        path.file_name().unwrap().to_str()
-    );
+    Ok((invocation, stderr))
+}
+
+// --backend native: what `ratchet <subcommand>` does to <path>, with <lookup> resolving
+// the references. pin pins the tags and branches of unpinned lines, update moves pins to
+// the commit of the reference in their ratchet comment. Only the `uses:` value and the
+// comment of such lines change, every other byte of the file is kept. Local actions,
+// expressions, docker images and excluded lines are left alone, and so is a reference
+// that could not be resolved. <resolved> remembers the lookups across files.
+// Returns the number of references of <path> that could not be resolved.
+async fn upgrade_natively(
+    path: &Path,
+    lookup: &(dyn TagLookup + Sync),
+    subcommand: &str,
+    resolved: &mut HashMap<String, Option<String>>,
+) -> Result<usize, RatchetError> {
+    let native_error = |source| RatchetError::Native {
+        file: path.display().to_string(),
+        source,
+    };
+    let content = fs::read_to_string(path).map_err(native_error)?;
+    let mut upgraded = String::with_capacity(content.len());
+    let mut unresolved = 0;
+    for raw in content.split_inclusive('\n') {
+        let line = UsesLine::parse(raw, 0).filter(|line| !line.is_excluded());
+        let reference = line.as_ref().and_then(|line| match subcommand {
+            RATCHET_UPDATE if line.kind() == RefKind::Sha => line.ratchet_original(),
+            RATCHET_PIN if !line.is_immutable() => Some(line.uses()),
+            _ => None,
+        });
+        let (Some(line), Some(reference)) = (line, reference) else {
+            upgraded.push_str(raw);
+            continue;
+        };
+        let action = match (RefKind::of(reference), ActionRef::parse(reference)) {
+            (RefKind::Tag | RefKind::Branch, Some(action)) => action,
+            _ => {
+                upgraded.push_str(raw);
+                continue;
+            }
+        };
+        if !resolved.contains_key(reference) {
+            let sha = match lookup
+                .resolve_ref(
+                    action.owner,
+                    action.name,
+                    action.reference.unwrap_or_default(),
+                )
+                .await
+            {
+                Ok(Some(sha)) => Some(sha),
+                Ok(None) => {
+                    warn!(phase = "ratchet", file:% = path.display(); "{} could not be resolved, it is left as it is", reference);
+                    None
+                }
+                Err(e) => {
+                    warn!(phase = "ratchet", file:% = path.display(); "Failed to resolve {}, it is left as it is: {}", reference, e);
+                    None
+                }
+            };
+            resolved.insert(reference.to_string(), sha);
+        }
+        if resolved[reference].is_none() {
+            unresolved += 1;
+        }
+        match &resolved[reference] {
+            Some(sha) if line.uses().rsplit_once('@').map(|(_, old)| old) != Some(sha) => {
+                let comment = format!("ratchet:{}", reference);
+                let with_sha = line.with_ref(sha);
+                match UsesLine::parse(&with_sha, 0) {
+                    Some(pinned) => upgraded.push_str(&pinned.with_comment(Some(&comment))),
+                    None => upgraded.push_str(raw),
+                }
+            }
+            _ => upgraded.push_str(raw),
+        }
+    }
+    if upgraded != content {
+        fs::write(path, upgraded).map_err(native_error)?;
+    }
+    Ok(unresolved)
+}
+
+// `ratchet <subcommand> <ratchet_args> <paths>`, ratchet only parses flags before the
+// paths
+fn ratchet_command(paths: &[&Path], ratchet: &RatchetOptions, subcommand: &str) -> Command {
+    let mut cmd = Command::new(ratchet.path);
+    cmd.arg(subcommand)
+        .args(ratchet.args)
+        .args(paths)
+        .envs(ratchet.env.iter().map(|(name, value)| (name, value)));
+    if let Some(token) = ratchet.token {
+        let actions_token = env::var_os(ACTIONS_TOKEN).is_some();
+        cmd.envs(token_env(token, actions_token));
+    }
+    cmd
+}
+
+// GITHUB_TOKEN for <token>, and ACTIONS_TOKEN when the environment has one since
+// ratchet prefers it over GITHUB_TOKEN
+fn token_env(token: &str, actions_token: bool) -> Vec<(&'static str, &str)> {
+    let mut vars = vec![(GITHUB_TOKEN, token)];
+    if actions_token {
+        vars.push((ACTIONS_TOKEN, token));
+    }
+    vars
+}
+
+// Run <cmd>, killing it once <timeout> has passed. <command> and <file> name it in errors.
+async fn output_within(
+    cmd: Command,
+    timeout: Duration,
+    command: String,
+    file: String,
+) -> Result<std::process::Output, RatchetError> {
+    let mut cmd = tokio::process::Command::from(cmd);
+    cmd.kill_on_drop(true);
+    match tokio::time::timeout(timeout, cmd.output()).await {
+        Ok(output) => output.map_err(|source| RatchetError::Spawn { command, source }),
+        Err(_) => Err(RatchetError::TimedOut {
+            command,
+            file,
+            timeout,
+        }),
+    }
+}
+
+async fn run_ratchet_command(
+    paths: &[&Path],
+    ratchet: &RatchetOptions<'_>,
+    subcommand: &str,
+) -> Result<(std::process::Output, RatchetInvocation), RatchetError> {
+    let cmd = ratchet_command(paths, ratchet, subcommand);
+    let files: Vec<String> = paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect();
+    let args: Vec<String> = std::iter::once(ratchet.path.to_string())
+        .chain(cmd.get_args().map(|arg| arg.to_string_lossy().to_string()))
+        .collect();
+    // Without the environment, which holds the token and the proxy
+    debug!(phase = "ratchet", file = files.join(", ").as_str(); "Running command: {}", redact(&args.join(" ")));
+    let env_names = child_env_names(&cmd);
+
+    let started = Instant::now();
+    let output = output_within(
+        cmd,
+        ratchet.timeout,
+        format!("{} {}", ratchet.path, subcommand),
+        files.join(", "),
+    )
+    .await?;
+    let invocation = RatchetInvocation {
+        files,
+        args,
+        ratchet_version: None,
+        env_names,
+        duration: started.elapsed(),
+        exit_code: output.status.code(),
+    };
+    Ok((output, invocation))
+}
+
+// Release of ratchet, compared against --min-ratchet-version
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct RatchetVersion {
+    major: u64,
+    minor: u64,
+    patch: u64,
+}
+
+impl RatchetVersion {
+    // First version number in the output of `ratchet --version`, which has been
+    // `ratchet 0.10.2 (<commit>, <os>/<arch>)`, `ratchet v0.4.0 (<commit>)` and a bare
+    // `0.3.1`. Pre-release suffixes like -dev are ignored.
+    pub fn parse(output: &str) -> Option<Self> {
+        output.split_whitespace().find_map(|word| word.parse().ok())
+    }
+}
+
+impl FromStr for RatchetVersion {
+    type Err = String;
+
+    // 0.10.2, v0.10.2 or 0.10
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let core = version
+            .trim_start_matches('v')
+            .split(['-', '+'])
+            .next()
+            .unwrap_or_default();
+        let numbers = core
+            .split('.')
+            .map(|part| part.parse::<u64>())
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("invalid ratchet version {:?}", version))?;
+        match numbers[..] {
+            [major, minor] => Ok(RatchetVersion {
+                major,
+                minor,
+                patch: 0,
+            }),
+            [major, minor, patch] => Ok(RatchetVersion {
+                major,
+                minor,
+                patch,
+            }),
+            _ => Err(format!("invalid ratchet version {:?}", version)),
+        }
+    }
+}
+
+impl fmt::Display for RatchetVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
 
-    Ok(())
+// First line of `ratchet --version`, None when it can not be determined
+async fn ratchet_version(ratchet: &RatchetOptions<'_>) -> Option<String> {
+    let mut cmd = Command::new(ratchet.path);
+    cmd.arg("--version");
+    let command = format!("{} --version", ratchet.path);
+    let output = output_within(cmd, ratchet.timeout, command, String::new())
+        .await
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .next()
+        .map(|line| line.trim().to_string())
+        .filter(|line| !line.is_empty())
 }
 
-fn run_ratchet_command(path: &Path) -> Result<std::process::Output, Box<dyn std::error::Error>> {
-    let mut cmd = Command::new("ratchet");
-    cmd.arg("pin").arg(path.to_str().unwrap());
-    debug!("Running command: {:?}", cmd);
+// Recorded names of the environment <cmd> runs with, the environment of the
+// dispatcher and the variables <cmd> sets or removes
+fn child_env_names(cmd: &Command) -> Vec<String> {
+    let mut names: HashSet<_> = env::vars_os().map(|(name, _)| name).collect();
+    for (name, value) in cmd.get_envs() {
+        match value {
+            Some(_) => names.insert(name.to_os_string()),
+            None => names.remove(name),
+        };
+    }
+    recorded_env_names(
+        names
+            .into_iter()
+            .map(|name| name.to_string_lossy().to_string()),
+    )
+}
 
-    let output = cmd.output()?;
-    Ok(output)
+// Sorted names of the relevant environment variables, secrets are never included
+fn recorded_env_names(names: impl Iterator<Item = String>) -> Vec<String> {
+    let mut recorded: Vec<String> = names
+        .filter(|name| {
+            RECORDED_ENV_NAMES.contains(&name.as_str())
+                || RECORDED_ENV_PREFIXES
+                    .iter()
+                    .any(|prefix| name.starts_with(prefix))
+        })
+        .filter(|name| {
+            let upper = name.to_uppercase();
+            !DENIED_ENV_PARTS.iter().any(|part| upper.contains(part))
+        })
+        .collect();
+    recorded.sort();
+    recorded.dedup();
+    recorded
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::{
-        fs::{self, File},
-        io::Write,
-    };
+    use crate::pin_cache::PinCacheStats;
+    use mockall::predicate::eq;
 
-    use assert_cmd::Command;
-    use mockall::predicate::*;
-    use tempfile::{tempdir, TempDir};
+    use std::os::unix::fs::PermissionsExt;
+    use tempfile::tempdir;
 
-    const UNPINNED_WORKFLOW: &str = include_str!("../resources/ci_unpinned.yml");
-    const PINNED_WORKFLOW: &str = include_str!("../resources/ci_pinned.yml");
+    fn options(path: &str) -> RatchetOptions<'_> {
+        RatchetOptions {
+            path,
+            args: &[],
+            timeout: Duration::from_secs(DEFAULT_SUBPROCESS_TIMEOUT),
+            comment_style: CommentStyle::None,
+            env: &[],
+            token: None,
+            native: None,
+            batch_size: DEFAULT_RATCHET_BATCH_SIZE,
+        }
+    }
 
     // #[test]
     // fn test_upgrade_workflows_success() {
@@ -95,8 +1247,1059 @@ mod tests {
     async fn test_upgrade_workflows_missing_directory() {
         let dir = tempdir().unwrap();
 
-        let result = upgrade_workflows(dir.path().to_str().unwrap()).await;
-        assert!(result.is_err());
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &options(DEFAULT_RATCHET_PATH),
+            RatchetMode::Pin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+        assert_eq!(result, UpgradeResult::default());
+        assert!(!has_pin_files(
+            dir.path().to_str().unwrap(),
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[]
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_with_pin_cache() {
+        const SETUP_NODE_SHA: &str = "1a4442cacd436585916779262731d5b162bc6ec7";
+        let tools = tempdir().unwrap();
+        let ratchet = tools.path().join("ratchet");
+        fs::write(
+            &ratchet,
+            format!(
+                "#!/bin/sh\nsed -i 's|actions/setup-node@v4$|actions/setup-node@{} # ratchet:actions/setup-node@v4|' \"$2\"\n",
+                SETUP_NODE_SHA
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        fs::write(
+            workflows.join("a.yml"),
+            "steps:\n  - uses: actions/checkout@v4\n  - uses: actions/setup-node@v4\n",
+        )
+        .unwrap();
+        fs::write(
+            workflows.join("b.yml"),
+            "steps:\n  - uses: actions/setup-node@v4\n",
+        )
+        .unwrap();
+        let cache_path = tools.path().join("pins.json");
+        let mut cache = PinCache::new(cache_path.to_str().unwrap(), 24);
+        cache.insert("actions/checkout@v4", CHECKOUT_SHA);
+        let cache = Mutex::new(cache);
+
+        // One file at a time, so b.yml sees what ratchet resolved for a.yml
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &RatchetOptions {
+                batch_size: 1,
+                ..options(ratchet.to_str().unwrap())
+            },
+            RatchetMode::Pin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            Some(&cache),
+        )
+        .await
+        .unwrap();
+        // b.yml only needed what ratchet resolved for a.yml
+        let files: Vec<&str> = result
+            .invocations
+            .iter()
+            .flat_map(|invocation| invocation.files.iter().map(String::as_str))
+            .collect();
+        assert_eq!(files, [".github/workflows/a.yml"]);
+        assert_eq!(
+            fs::read_to_string(workflows.join("a.yml")).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # ratchet:actions/checkout@v4\n  - uses: actions/setup-node@{} # ratchet:actions/setup-node@v4\n",
+                CHECKOUT_SHA, SETUP_NODE_SHA
+            )
+        );
+        assert_eq!(
+            fs::read_to_string(workflows.join("b.yml")).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/setup-node@{} # ratchet:actions/setup-node@v4\n",
+                SETUP_NODE_SHA
+            )
+        );
+        assert_eq!(
+            cache.lock().unwrap().stats(),
+            PinCacheStats { hits: 2, misses: 1 }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_native_backend() {
+        const CODEQL_SHA: &str = "1a4442cacd436585916779262731d5b162bc6ec7";
+        let mut lookup = crate::github::MockTagLookup::new();
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("checkout"), eq("v4"))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(CHECKOUT_SHA.to_string())));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("github"), eq("codeql-action"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(CODEQL_SHA.to_string())));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("org"), eq("gone"), eq("v1"))
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        let unpinned = "jobs:\r\n  build:\r\n    steps:\r\n      - uses: actions/checkout@v4   # keep me\r\n      - uses: \"github/codeql-action/init@v3\"\r\n      - uses: docker://alpine:3.19\r\n      - uses: ./local\r\n      - uses: org/gone@v1\r\n      - uses: org/pinned@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:org/pinned@v2\r\n      - uses: actions/checkout@v4 # ratchet:exclude\r\n";
+        fs::write(workflows.join("ci.yml"), unpinned).unwrap();
+        fs::write(
+            workflows.join("release.yml"),
+            "steps:\n  - uses: actions/checkout@v4\n",
+        )
+        .unwrap();
+
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &RatchetOptions {
+                native: Some(&lookup),
+                ..options("/nonexistent/ratchet")
+            },
+            RatchetMode::Pin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+        // org/gone could not be resolved, which fails ci.yml the way a failing ratchet would
+        let runs: Vec<(String, Option<i32>)> = result
+            .invocations
+            .iter()
+            .map(|invocation| (invocation.args.join(" "), invocation.exit_code))
+            .collect();
+        assert_eq!(
+            runs,
+            [
+                (String::from("native pin .github/workflows/ci.yml"), Some(1)),
+                (
+                    String::from("native pin .github/workflows/release.yml"),
+                    Some(0)
+                ),
+            ]
+        );
+        assert_eq!(
+            fs::read_to_string(workflows.join("ci.yml")).unwrap(),
+            format!("jobs:\r\n  build:\r\n    steps:\r\n      - uses: actions/checkout@{}   # ratchet:actions/checkout@v4\r\n      - uses: \"github/codeql-action/init@{}\" # ratchet:github/codeql-action/init@v3\r\n      - uses: docker://alpine:3.19\r\n      - uses: ./local\r\n      - uses: org/gone@v1\r\n      - uses: org/pinned@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:org/pinned@v2\r\n      - uses: actions/checkout@v4 # ratchet:exclude\r\n", CHECKOUT_SHA, CODEQL_SHA)
+        );
+        assert_eq!(
+            fs::read_to_string(workflows.join("release.yml")).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # ratchet:actions/checkout@v4\n",
+                CHECKOUT_SHA
+            )
+        );
+
+        // update moves the pin to the commit its ratchet comment points to now
+        let mut lookup = crate::github::MockTagLookup::new();
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("checkout"), eq("v4"))
+            .returning(|_, _, _| Ok(Some(CODEQL_SHA.to_string())));
+        let mut resolved = HashMap::new();
+        let unresolved = upgrade_natively(
+            &workflows.join("release.yml"),
+            &lookup,
+            RATCHET_UPDATE,
+            &mut resolved,
+        )
+        .await
+        .unwrap();
+        assert_eq!(unresolved, 0);
+        assert_eq!(
+            fs::read_to_string(workflows.join("release.yml")).unwrap(),
+            format!(
+                "steps:\n  - uses: actions/checkout@{} # ratchet:actions/checkout@v4\n",
+                CODEQL_SHA
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_recurses_and_skips_other_files() {
+        let tools = tempdir().unwrap();
+        let ratchet = tools.path().join("ratchet");
+        fs::write(&ratchet, "#!/bin/sh\nexit 0\n").unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(workflows.join("nested")).unwrap();
+        fs::write(workflows.join("ci.yml"), "on: push\n").unwrap();
+        fs::write(workflows.join("nested/release.yaml"), "on: push\n").unwrap();
+        fs::write(workflows.join("README.md"), "# Workflows\n").unwrap();
+        fs::write(workflows.join("matrix.json"), "{\"on\": \"push\"}\n").unwrap();
+        fs::write(workflows.join("broken.yml"), "on: [push\n").unwrap();
+
+        let upgrade = |extensions: Vec<String>| {
+            let local_path = dir.path().to_str().unwrap().to_string();
+            let ratchet = ratchet.to_str().unwrap().to_string();
+            async move {
+                upgrade_workflows(
+                    &local_path,
+                    &options(&ratchet),
+                    RatchetMode::Pin,
+                    DEFAULT_MAX_WORKFLOW_SIZE,
+                    &[String::from(DEFAULT_PIN_PATH)],
+                    &extensions,
+                    None,
+                )
+                .await
+                .unwrap()
+            }
+        };
+        let files = |result: &UpgradeResult| -> Vec<String> {
+            result
+                .invocations
+                .iter()
+                .flat_map(|invocation| invocation.files.clone())
+                .collect()
+        };
+
+        let result = upgrade(Vec::new()).await;
+        assert_eq!(
+            files(&result),
+            vec![
+                ".github/workflows/ci.yml",
+                ".github/workflows/nested/release.yaml"
+            ]
+        );
+        assert_eq!(
+            result.skipped,
+            vec![SkippedWorkflow {
+                path: workflows.join("broken.yml"),
+                reason: SKIP_INVALID_YAML
+            }]
+        );
+
+        let result = upgrade(vec![String::from("json")]).await;
+        assert_eq!(
+            files(&result),
+            vec![
+                ".github/workflows/ci.yml",
+                ".github/workflows/matrix.json",
+                ".github/workflows/nested/release.yaml"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_pin_files_globs() {
+        let dir = tempdir().unwrap();
+        let local_path = dir.path().to_str().unwrap();
+        fs::create_dir_all(dir.path().join(".github/workflows")).unwrap();
+        fs::create_dir_all(dir.path().join(".circleci")).unwrap();
+        fs::write(dir.path().join(".github/workflows/ci.yml"), "on: push\n").unwrap();
+        fs::write(dir.path().join(".gitlab-ci.yml"), "image: node:20\n").unwrap();
+        fs::write(dir.path().join(".circleci/config.yml"), "version: 2.1\n").unwrap();
+
+        let pin_paths = [String::from(".gitlab-ci.yml"), String::from(".circleci/*")];
+        assert_eq!(
+            pin_files(local_path, &pin_paths, &[]),
+            vec![
+                dir.path().join(".circleci/config.yml"),
+                dir.path().join(".gitlab-ci.yml")
+            ]
+        );
+        assert_eq!(
+            pin_files(local_path, &[String::from(DEFAULT_PIN_PATH)], &[]),
+            vec![dir.path().join(".github/workflows/ci.yml")]
+        );
+        assert!(!has_pin_files(local_path, &[String::from("*.json")], &[]));
+    }
+
+    #[test]
+    fn test_ratchet_commands_of_each_mode() {
+        let commands = |mode: RatchetMode| -> Vec<Vec<String>> {
+            mode.subcommands()
+                .iter()
+                .map(|subcommand| {
+                    ratchet_command(
+                        &[Path::new("ci.yml")],
+                        &options(DEFAULT_RATCHET_PATH),
+                        subcommand,
+                    )
+                    .get_args()
+                    .map(|arg| arg.to_string_lossy().to_string())
+                    .collect()
+                })
+                .collect()
+        };
+        assert_eq!(
+            commands(RatchetMode::new(false, false)),
+            vec![vec!["pin", "ci.yml"]]
+        );
+        assert_eq!(
+            commands(RatchetMode::new(false, true)),
+            vec![vec!["update", "ci.yml"]]
+        );
+        assert_eq!(
+            commands(RatchetMode::new(true, false)),
+            vec![vec!["update", "ci.yml"], vec!["pin", "ci.yml"]]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_batches_files() {
+        let cmd = ratchet_command(
+            &[Path::new("a.yml"), Path::new("b.yml")],
+            &options("ratchet"),
+            RATCHET_PIN,
+        );
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, ["pin", "a.yml", "b.yml"]);
+        // File names of a cloned repository do not have to be UTF-8
+        let name = std::os::unix::ffi::OsStrExt::from_bytes(b"\xff.yml");
+        let cmd = ratchet_command(&[Path::new(name)], &options("ratchet"), RATCHET_PIN);
+        assert_eq!(cmd.get_args().last(), Some(name));
+
+        // Every run of ratchet is logged, a run with bad.yml fails and names it
+        let tools = tempdir().unwrap();
+        let ratchet = tools.path().join("ratchet");
+        let log = tools.path().join("runs.log");
+        fs::write(
+            &ratchet,
+            format!(
+                "#!/bin/sh
+[ \"$1\" = \"--version\" ] && echo \"ratchet 0.10.2\" && exit 0
+echo \"$*\" >> {}
+for f in \"$@\"; do case \"$f\" in *bad.yml) echo \"failed to parse $f\" >&2; exit 1;; esac; done
+",
+                log.display()
+            ),
+        )
+        .unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        for name in ["a.yml", "b.yml", "bad.yml"] {
+            fs::write(workflows.join(name), "on: push\n").unwrap();
+        }
+
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &options(ratchet.to_str().unwrap()),
+            RatchetMode::Pin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+
+        // One run for the three files, then the others together and bad.yml on its own
+        assert_eq!(fs::read_to_string(&log).unwrap().lines().count(), 3);
+        let files: Vec<(Vec<String>, Option<i32>)> = result
+            .invocations
+            .iter()
+            .map(|invocation| (invocation.files.clone(), invocation.exit_code))
+            .collect();
+        assert_eq!(
+            files,
+            [
+                (
+                    vec![
+                        String::from(".github/workflows/a.yml"),
+                        String::from(".github/workflows/b.yml"),
+                    ],
+                    Some(0)
+                ),
+                (
+                    vec![
+                        String::from(".github/workflows/a.yml"),
+                        String::from(".github/workflows/b.yml"),
+                        String::from(".github/workflows/bad.yml"),
+                    ],
+                    Some(1)
+                ),
+                (vec![String::from(".github/workflows/bad.yml")], Some(1)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_kills_hanging_ratchet() {
+        let tools = tempdir().unwrap();
+        let ratchet = tools.path().join("ratchet");
+        fs::write(
+            &ratchet,
+            "#!/bin/sh\n[ \"$1\" = \"--version\" ] && echo \"ratchet 0.10.2\" && exit 0\ncase \"$*\" in *slow.yml*) exec sleep 30;; esac\nexit 0\n",
+        )
+        .unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        fs::write(workflows.join("ci.yml"), "on: push\n").unwrap();
+        fs::write(workflows.join("slow.yml"), "on: push\n").unwrap();
+
+        let started = Instant::now();
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &RatchetOptions {
+                timeout: Duration::from_millis(500),
+                ..options(ratchet.to_str().unwrap())
+            },
+            RatchetMode::UpdateAndPin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(
+            result.skipped,
+            vec![SkippedWorkflow {
+                path: workflows.join("slow.yml"),
+                reason: SKIP_TIMED_OUT
+            }]
+        );
+        let files: Vec<&str> = result
+            .invocations
+            .iter()
+            .flat_map(|invocation| invocation.files.iter().map(String::as_str))
+            .collect();
+        assert_eq!(
+            files,
+            [".github/workflows/ci.yml", ".github/workflows/ci.yml"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_timeout_error_names_command_and_file() {
+        let mut cmd = Command::new("sleep");
+        cmd.arg("30");
+        let err = output_within(
+            cmd,
+            Duration::from_millis(100),
+            String::from("ratchet pin"),
+            String::from("ci.yml"),
+        )
+        .await
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "ratchet pin on ci.yml did not finish within 100ms and was killed"
+        );
+    }
+
+    #[test]
+    fn test_parse_ratchet_version() {
+        let parse = |output: &str| RatchetVersion::parse(output).map(|v| v.to_string());
+        assert_eq!(
+            parse("ratchet 0.10.2 (4a1ab87, linux/amd64)\n").as_deref(),
+            Some("0.10.2")
+        );
+        assert_eq!(parse("ratchet v0.4.0 (dd6f0a6)").as_deref(), Some("0.4.0"));
+        assert_eq!(parse("0.3.1\n").as_deref(), Some("0.3.1"));
+        assert_eq!(
+            parse("ratchet 0.9.2-dev (HEAD, darwin/arm64)").as_deref(),
+            Some("0.9.2")
+        );
+        assert_eq!(parse("ratchet local-e2e"), None);
+        assert_eq!(parse(""), None);
+    }
+
+    #[test]
+    fn test_compare_ratchet_versions() {
+        let version = |version: &str| version.parse::<RatchetVersion>().unwrap();
+        assert!(version("0.4.0") < version(DEFAULT_MIN_RATCHET_VERSION));
+        assert!(version("0.10.0") > version("0.9.2"));
+        assert_eq!(version("v0.10"), version("0.10.0"));
+        assert!("0.x".parse::<RatchetVersion>().is_err());
+        assert!("1".parse::<RatchetVersion>().is_err());
+    }
+
+    #[test]
+    fn test_ratchet_command_with_bin_and_args() {
+        let command = |ratchet_path: &str, ratchet_args: &[&str], subcommand: &str| {
+            let ratchet_args: Vec<String> =
+                ratchet_args.iter().map(|arg| arg.to_string()).collect();
+            let ratchet = RatchetOptions {
+                args: &ratchet_args,
+                ..options(ratchet_path)
+            };
+            let cmd = ratchet_command(&[Path::new("ci.yml")], &ratchet, subcommand);
+            std::iter::once(cmd.get_program())
+                .chain(cmd.get_args())
+                .map(|arg| arg.to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            command("/opt/bin/ratchet", &[], RATCHET_PIN),
+            ["/opt/bin/ratchet", "pin", "ci.yml"]
+        );
+        assert_eq!(
+            command(
+                DEFAULT_RATCHET_PATH,
+                &["-experimental-keep-newlines"],
+                RATCHET_PIN
+            ),
+            ["ratchet", "pin", "-experimental-keep-newlines", "ci.yml"]
+        );
+        assert_eq!(
+            command(
+                "/opt/bin/ratchet",
+                &["-experimental-keep-newlines", "-out=pinned.yml"],
+                RATCHET_UPDATE
+            ),
+            [
+                "/opt/bin/ratchet",
+                "update",
+                "-experimental-keep-newlines",
+                "-out=pinned.yml",
+                "ci.yml"
+            ]
+        );
+
+        let env = [(
+            String::from("HTTPS_PROXY"),
+            String::from("http://proxy:3128"),
+        )];
+        let ratchet = RatchetOptions {
+            env: &env,
+            ..options("ratchet")
+        };
+        let cmd = ratchet_command(&[Path::new("ci.yml")], &ratchet, RATCHET_PIN);
+        assert_eq!(
+            cmd.get_envs().collect::<Vec<_>>(),
+            [(
+                std::ffi::OsStr::new("HTTPS_PROXY"),
+                Some(std::ffi::OsStr::new("http://proxy:3128"))
+            )]
+        );
+    }
+
+    #[test]
+    fn test_composite_action_files() {
+        let dir = tempdir().unwrap();
+        let actions = dir.path().join(".github/actions");
+        fs::create_dir_all(actions.join("setup/nested")).unwrap();
+        fs::create_dir_all(actions.join("release")).unwrap();
+        fs::write(actions.join("setup/action.yml"), "runs:\n").unwrap();
+        fs::write(actions.join("setup/nested/action.yaml"), "runs:\n").unwrap();
+        fs::write(actions.join("setup/README.md"), "# setup\n").unwrap();
+        fs::write(actions.join("release/release.yml"), "runs:\n").unwrap();
+
+        assert_eq!(
+            composite_action_files(dir.path().to_str().unwrap()),
+            vec![
+                actions.join("setup/action.yml"),
+                actions.join("setup/nested/action.yaml")
+            ]
+        );
+        assert!(composite_action_files("/nonexistent").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_skips_large_files() {
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+
+        // Generated at test time, a matrix explosion of 3 MiB
+        let large = workflows.join("large.yml");
+        let file = fs::File::create(&large).unwrap();
+        file.set_len(3 * 1024 * 1024).unwrap();
+
+        let result = upgrade_workflows(
+            dir.path().to_str().unwrap(),
+            &options(DEFAULT_RATCHET_PATH),
+            RatchetMode::Pin,
+            DEFAULT_MAX_WORKFLOW_SIZE,
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(result.invocations.is_empty());
+        assert_eq!(
+            result.skipped,
+            vec![SkippedWorkflow {
+                path: large,
+                reason: SKIP_TOO_LARGE
+            }]
+        );
+    }
+
+    #[test]
+    fn test_token_is_passed_to_ratchet() {
+        let value = |cmd: &Command, name: &str| {
+            cmd.get_envs()
+                .find(|(var, _)| *var == name)
+                .and_then(|(_, value)| value)
+                .map(|value| value.to_string_lossy().to_string())
+        };
+        let ratchet = RatchetOptions {
+            token: Some("ghs_abc"),
+            ..options(DEFAULT_RATCHET_PATH)
+        };
+        let cmd = ratchet_command(&[Path::new("ci.yml")], &ratchet, RATCHET_PIN);
+        assert_eq!(value(&cmd, GITHUB_TOKEN).as_deref(), Some("ghs_abc"));
+
+        // --no-ratchet-token leaves the environment alone
+        let cmd = ratchet_command(
+            &[Path::new("ci.yml")],
+            &options(DEFAULT_RATCHET_PATH),
+            RATCHET_PIN,
+        );
+        assert_eq!(value(&cmd, GITHUB_TOKEN), None);
+
+        assert_eq!(token_env("ghs_abc", false), [(GITHUB_TOKEN, "ghs_abc")]);
+        assert_eq!(
+            token_env("ghs_abc", true),
+            [(GITHUB_TOKEN, "ghs_abc"), (ACTIONS_TOKEN, "ghs_abc")]
+        );
+    }
+
+    #[test]
+    fn test_recorded_env_names_exclude_secrets() {
+        let names = [
+            "GITHUB_TOKEN",
+            "GH_TOKEN",
+            "GITHUB_API_URL",
+            "RATCHET_EXP_KEEP_NEWLINES",
+            "ACTIONS_RUNTIME_TOKEN",
+            "ACTIONS_ID_TOKEN_REQUEST_URL",
+            "HTTPS_PROXY",
+            "PATH",
+            "AWS_SECRET_ACCESS_KEY",
+            "USER",
+        ];
+        assert_eq!(
+            recorded_env_names(names.iter().map(|name| name.to_string())),
+            vec![
+                "GITHUB_API_URL",
+                "HTTPS_PROXY",
+                "PATH",
+                "RATCHET_EXP_KEEP_NEWLINES"
+            ]
+        );
+
+        // Names set for ratchet only are recorded too, its token is not
+        let env = [(
+            String::from("RATCHET_DISPATCHER_TEST_ONLY"),
+            String::from("1"),
+        )];
+        let cmd = ratchet_command(
+            &[Path::new("ci.yml")],
+            &RatchetOptions {
+                env: &env,
+                token: Some("secret"),
+                ..options("ratchet")
+            },
+            RATCHET_PIN,
+        );
+        let names = child_env_names(&cmd);
+        assert!(names.contains(&String::from("RATCHET_DISPATCHER_TEST_ONLY")));
+        assert!(!names.contains(&String::from(GITHUB_TOKEN)));
+    }
+
+    const CHECKOUT_SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+
+    // Steps ratchet pinned in this run, with their comments aligned in a column
+    fn pinned_steps(checkout_comment: &str, node_comment: &str) -> String {
+        format!(
+            "      - uses: actions/checkout@{}                   {}\n      - uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 {}\n",
+            CHECKOUT_SHA, checkout_comment, node_comment
+        )
+    }
+
+    fn restyle(style: CommentStyle) -> String {
+        rewrite_ratchet_comments(
+            &pinned_steps(
+                "# ratchet:actions/checkout@v3",
+                "# ratchet:actions/setup-node@v4.0.2   ",
+            ),
+            "",
+            style,
+        )
+    }
+
+    #[test]
+    fn test_comment_style_none_and_ratchet() {
+        let pinned = pinned_steps(
+            "# ratchet:actions/checkout@v3",
+            "# ratchet:actions/setup-node@v4.0.2   ",
+        );
+        assert_eq!(restyle(CommentStyle::None), pinned);
+        // Only the trailing whitespace goes
+        assert_eq!(
+            restyle(CommentStyle::Ratchet),
+            pinned_steps(
+                "# ratchet:actions/checkout@v3",
+                "# ratchet:actions/setup-node@v4.0.2"
+            )
+        );
+        assert_eq!(
+            rewrite_ratchet_comments(
+                "  uses: org/tool@b4ffde65f46336ab88eb53be808477a3936bae11 #ratchet:org/tool@v1 see #12\r\n",
+                "",
+                CommentStyle::Ratchet
+            ),
+            "  uses: org/tool@b4ffde65f46336ab88eb53be808477a3936bae11 # ratchet:org/tool@v1\r\n"
+        );
+    }
+
+    #[test]
+    fn test_comment_style_semver() {
+        // The # stays in its column
+        assert_eq!(
+            restyle(CommentStyle::Semver),
+            pinned_steps("# v3", "# v4.0.2")
+        );
+        // A branch has no version to keep
+        let branch = "      - uses: org/tool@b4ffde65f46336ab88eb53be808477a3936bae11 # ratchet:org/tool@main\n";
+        assert_eq!(
+            rewrite_ratchet_comments(branch, "", CommentStyle::Semver),
+            branch
+        );
+    }
+
+    #[test]
+    fn test_comment_style_strip() {
+        assert_eq!(
+            restyle(CommentStyle::Strip),
+            format!(
+                "      - uses: actions/checkout@{}\n      - uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7\n",
+                CHECKOUT_SHA
+            )
+        );
+        // Other comments and lines without a ratchet comment are kept
+        let other = "      - uses: actions/cache@v4 # keep floating\n      - run: make # ratchet:org/tool@v1\n      - uses: org/tool@v1 # ratchet:exclude\n";
+        assert_eq!(
+            rewrite_ratchet_comments(other, "", CommentStyle::Strip),
+            other
+        );
+    }
+
+    #[test]
+    fn test_comment_style_only_changes_lines_of_this_run() {
+        let old_pin = format!(
+            "      - uses: actions/checkout@{} # ratchet:actions/checkout@v3\n",
+            CHECKOUT_SHA
+        );
+        let original = format!("{}      - uses: actions/setup-node@v4\n", old_pin);
+        let pinned = format!(
+            "{}      - uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # ratchet:actions/setup-node@v4\n",
+            old_pin
+        );
+        assert_eq!(
+            rewrite_ratchet_comments(&pinned, &original, CommentStyle::Semver),
+            format!(
+                "{}      - uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # v4\n",
+                old_pin
+            )
+        );
+    }
+
+    #[test]
+    fn test_clean_and_restore_round_trip() {
+        let pinned = pinned_steps(
+            "# ratchet:actions/checkout@v3",
+            "# ratchet:actions/setup-node@v4.0.2",
+        );
+        let cleaned = pinned_steps("# v3", "# v4.0.2");
+        assert_eq!(
+            rewrite_ratchet_comments(&pinned, "", CommentStyle::Semver),
+            cleaned
+        );
+        assert_eq!(restore_ratchet_comments(&cleaned), pinned);
+        assert_eq!(
+            rewrite_ratchet_comments(
+                &restore_ratchet_comments(&cleaned),
+                "",
+                CommentStyle::Semver
+            ),
+            cleaned
+        );
+        // Nothing to restore on ratchet comments, unpinned actions and other comments
+        let other = format!(
+            "{}      - uses: actions/cache@v4 # v4\n      - uses: org/tool@{} # v4 until 5.0\n",
+            pinned, CHECKOUT_SHA
+        );
+        assert_eq!(restore_ratchet_comments(&other), other);
+    }
+
+    #[test]
+    fn test_restore_skips_large_files() {
+        let cleaned = pinned_steps("# v3", "# v4.0.2");
+        let dir = tempdir().unwrap();
+        let workflows = dir.path().join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        fs::write(workflows.join("ci.yml"), &cleaned).unwrap();
+        let large = format!("{}# {}\n", cleaned, "x".repeat(100));
+        fs::write(workflows.join("large.yml"), &large).unwrap();
+
+        let restored = restore_ratchet_comments_in(
+            dir.path().to_str().unwrap(),
+            &[String::from(DEFAULT_PIN_PATH)],
+            &[],
+            cleaned.len() as u64,
+        );
+
+        assert_eq!(restored, 2);
+        assert_eq!(
+            fs::read_to_string(workflows.join("large.yml")).unwrap(),
+            large
+        );
+    }
+
+    #[test]
+    fn test_clean_and_restore_docker_digest() {
+        let digest = "sha256:4bcff63911fcb4448bd4fdacec207030997caf25e9bea4045fa6c8c44de311d1";
+        let pinned = format!(
+            "      - uses: docker://alpine@{} # ratchet:docker://alpine:3.19\n      - uses: docker://ghcr.io/org/tool:1.2@{} # ratchet:docker://ghcr.io/org/tool:1.2\n",
+            digest, digest
+        );
+        let cleaned = format!(
+            "      - uses: docker://alpine@{} # 3.19\n      - uses: docker://ghcr.io/org/tool:1.2@{} # 1.2\n",
+            digest, digest
+        );
+        assert_eq!(
+            rewrite_ratchet_comments(&pinned, "", CommentStyle::Semver),
+            cleaned
+        );
+        // The tag of the image is restored after a :, not an @
+        assert_eq!(restore_ratchet_comments(&cleaned), pinned);
+        // An image without a tag has no version to keep
+        let latest = format!(
+            "      - uses: docker://alpine@{} # ratchet:docker://alpine\n",
+            digest
+        );
+        assert_eq!(
+            rewrite_ratchet_comments(&latest, "", CommentStyle::Semver),
+            latest
+        );
+    }
+
+    #[test]
+    fn test_comment_style_update_warning() {
+        assert!(CommentStyle::Semver
+            .update_warning(false)
+            .is_some_and(|warning| warning.contains("--unclean")));
+        assert!(CommentStyle::Strip.update_warning(false).is_some());
+        assert_eq!(CommentStyle::Semver.update_warning(true), None);
+        assert_eq!(CommentStyle::None.update_warning(false), None);
+        assert_eq!(CommentStyle::Ratchet.update_warning(false), None);
+    }
+
+    #[test]
+    fn test_classify_pin() {
+        let cases = [
+            (
+                format!("actions/checkout@{}", CHECKOUT_SHA),
+                Some(PinState::Sha),
+            ),
+            (String::from("actions/checkout@v4"), Some(PinState::Tag)),
+            (
+                String::from("github/codeql-action/init@v3"),
+                Some(PinState::Tag),
+            ),
+            (
+                format!("github/codeql-action/init@{}", CHECKOUT_SHA),
+                Some(PinState::Sha),
+            ),
+            (String::from("org/tool@main"), Some(PinState::Branch)),
+            (String::from("org/tool"), Some(PinState::Branch)),
+            (
+                String::from("docker://alpine@sha256:abc"),
+                Some(PinState::Sha),
+            ),
+            (String::from("docker://alpine:3.19"), Some(PinState::Tag)),
+            (
+                String::from("docker://localhost:5000/tool"),
+                Some(PinState::Branch),
+            ),
+            (
+                String::from("docker://alpine:latest"),
+                Some(PinState::Branch),
+            ),
+            (String::from("./.github/actions/setup"), None),
+            (String::from("${{ matrix.action }}"), None),
+        ];
+        for (uses, expected) in cases {
+            assert_eq!(classify_pin(&uses), expected, "{}", uses);
+        }
+    }
+
+    #[test]
+    fn test_pin_counts() {
+        let pinned = format!("actions/checkout@{}", CHECKOUT_SHA);
+        let counts = PinCounts::of([
+            pinned.as_str(),
+            "actions/setup-node@v4",
+            "org/tool@main",
+            "./local",
+        ]);
+        assert_eq!(
+            counts,
+            PinCounts {
+                sha: 1,
+                tag: 1,
+                branch: 1
+            }
+        );
+        assert_eq!(counts.total(), 3);
+        let percent = counts.pinned_percent().unwrap();
+        assert!((percent - 100.0 / 3.0).abs() < 1e-9);
+        assert_eq!(PinCounts::of(["./local"]).pinned_percent(), None);
+
+        let mut stats = PinStats::default();
+        assert!(stats.is_empty());
+        stats.add(&PinStats {
+            before: counts,
+            after: PinCounts::of([pinned.as_str(), pinned.as_str(), "org/tool@main"]),
+        });
+        stats.add(&PinStats::unchanged(counts));
+        assert_eq!(stats.before.total(), 6);
+        assert_eq!(stats.after.sha, 3);
+    }
+
+    #[test]
+    fn test_is_branch_reference() {
+        for branch in ["main", "master", "develop", "release/v2", "vnext"] {
+            assert!(is_branch_reference(branch), "{}", branch);
+        }
+        for reference in [
+            "v4",
+            "v4.1.2",
+            "1.0",
+            "a81bbbf8298c0fa03ea29cdc473d45769f953675",
+            "sha256:5d0da3dc976460b72c77d94c8a1ad043720b0416bfc16c52c45d4847e53fadb6",
+        ] {
+            assert!(!is_branch_reference(reference), "{}", reference);
+        }
+    }
+
+    #[test]
+    fn test_find_branch_pins() {
+        let content = "jobs:\n  build:\n    steps:\n      - uses: actions/checkout@a81bbbf8298c0fa03ea29cdc473d45769f953675 # ratchet:actions/checkout@v4\n      - uses: org/tool/setup@b4ffde65f46336ab88eb53be808477a3936bae11 # ratchet:org/tool/setup@main\n      - uses: org/other@main\n";
+        assert_eq!(
+            find_branch_pins(".github/workflows/ci.yml", content),
+            vec![BranchPin {
+                file: String::from(".github/workflows/ci.yml"),
+                line: 5,
+                action: String::from("org/tool/setup"),
+                branch: String::from("main"),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_find_branch_pins_in_ratchet_comments() {
+        let step = |comment: &str| {
+            format!(
+                "      - uses: org/tool@a81bbbf8298c0fa03ea29cdc473d45769f953675 # {}\n",
+                comment
+            )
+        };
+        let not_branches = [
+            "ratchet:github/codeql-action/init@v3",
+            "ratchet:github/codeql-action/init@v3.25.1 bump with care",
+            "ratchet:org/tool@a81bbbf8298c0fa03ea29cdc473d45769f953675",
+            "ratchet:org/tool@a81bbbf8298c0fa03ea29cdc473d45769f953675 reviewed",
+            "ratchet:docker://ghcr.io/foo/bar@sha256:5d0da3dc976460b72c77d94c8a1ad043720b0416bfc16c52c45d4847e53fadb6",
+            "ratchet:exclude",
+            "v4",
+        ];
+        let content: String = not_branches.iter().map(|comment| step(comment)).collect();
+        assert_eq!(find_branch_pins("ci.yml", &content), vec![]);
+
+        let content = format!(
+            "{}{}",
+            step("ratchet:github/codeql-action/init@main"),
+            step("ratchet:org/tool@release/v2 until 2.0 ships")
+        );
+        let branches: Vec<(String, String)> = find_branch_pins("ci.yml", &content)
+            .into_iter()
+            .map(|pin| (pin.action, pin.branch))
+            .collect();
+        assert_eq!(
+            branches,
+            vec![
+                (
+                    String::from("github/codeql-action/init"),
+                    String::from("main")
+                ),
+                (String::from("org/tool"), String::from("release/v2")),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upgrade_workflows_records_invocations() {
+        let tools = tempdir().unwrap();
+        let ratchet = tools.path().join("ratchet");
+        fs::write(
+            &ratchet,
+            "#!/bin/sh\n[ \"$1\" = \"--version\" ] && echo \"ratchet 0.9.2\" && exit 0\nexit 3\n",
+        )
+        .unwrap();
+        fs::set_permissions(&ratchet, fs::Permissions::from_mode(0o755)).unwrap();
+
+        let mut results = Vec::new();
+        for _ in 0..2 {
+            let dir = tempdir().unwrap();
+            let workflows = dir.path().join(".github/workflows");
+            fs::create_dir_all(&workflows).unwrap();
+            fs::write(workflows.join("ci.yml"), "on: push\n").unwrap();
+            let result = upgrade_workflows(
+                dir.path().to_str().unwrap(),
+                &options(ratchet.to_str().unwrap()),
+                RatchetMode::Pin,
+                DEFAULT_MAX_WORKFLOW_SIZE,
+                &[String::from(DEFAULT_PIN_PATH)],
+                &[],
+                None,
+            )
+            .await
+            .unwrap();
+            results.push(result);
+        }
+
+        let invocation = &results[0].invocations[0];
+        assert_eq!(invocation.files, [".github/workflows/ci.yml"]);
+        assert_eq!(invocation.args[..2], [ratchet.to_str().unwrap(), "pin"]);
+        assert_eq!(invocation.ratchet_version.as_deref(), Some("ratchet 0.9.2"));
+        assert_eq!(invocation.exit_code, Some(3));
+        assert!(!invocation
+            .env_names
+            .iter()
+            .any(|name| name.contains("TOKEN")));
+        // Independent of the clone directory
+        assert_eq!(results[0].fingerprint(), results[1].fingerprint());
+        assert_eq!(results[0].fingerprint().len(), 64);
     }
 
     // #[test]
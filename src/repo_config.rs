@@ -0,0 +1,169 @@
+use std::{fs, io, path::Path};
+
+use glob::Pattern;
+use serde::{de, Deserialize, Deserializer};
+
+use crate::{ratchet::CommentStyle, Args};
+
+// Config file repository owners keep in their own repository to opt out of or tune
+// the run, without changing the central list of repositories
+pub const REPO_CONFIG_PATH: &str = ".github/ratchet-dispatcher.yml";
+
+// Summary reason of a repository that opted out with `enabled: false`
+pub const SKIPPED_BY_REPO_CONFIG: &str = "skipped: disabled by .github/ratchet-dispatcher.yml";
+
+// .github/ratchet-dispatcher.yml, every key is optional and overrides the command line
+// for that repository only
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RepoConfig {
+    // false skips the repository
+    pub enabled: Option<bool>,
+    // Branch the changes are pushed to instead of --branch
+    pub branch: Option<String>,
+    // Globs of actions whose pins are left out, on top of --exclude-actions
+    #[serde(default, deserialize_with = "patterns")]
+    pub exclude_actions: Vec<Pattern>,
+    // Like --clean-comment, false keeps the comments as ratchet wrote them
+    pub clean_comment: Option<bool>,
+    // Instead of --comment-style, wins over clean_comment
+    pub comment_style: Option<CommentStyle>,
+    // Labels of the PR instead of --label
+    pub labels: Option<Vec<String>>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum RepoConfigError {
+    #[error("failed to read {REPO_CONFIG_PATH}: {0}")]
+    Read(#[from] io::Error),
+    #[error("invalid {REPO_CONFIG_PATH}: {0}")]
+    Parse(#[from] serde_yaml::Error),
+}
+
+impl RepoConfig {
+    // The config of the checkout at <local_path>, the default when it has none
+    pub fn load(local_path: &str) -> Result<Self, RepoConfigError> {
+        let content = match fs::read_to_string(Path::new(local_path).join(REPO_CONFIG_PATH)) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(RepoConfig::default()),
+            Err(e) => return Err(e.into()),
+        };
+        // A file with only comments is the same as no file
+        if serde_yaml::from_str::<serde_yaml::Value>(&content)?.is_null() {
+            return Ok(RepoConfig::default());
+        }
+        Ok(serde_yaml::from_str(&content)?)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    // <args> with the overrides of this config applied
+    pub fn apply(&self, args: &Args) -> Args {
+        let mut args = args.clone();
+        args.exclude_actions
+            .extend(self.exclude_actions.iter().cloned());
+        match (self.comment_style, self.clean_comment) {
+            (Some(style), _) => args.comment_style = style,
+            (None, Some(true)) => args.comment_style = CommentStyle::Semver,
+            (None, Some(false)) => args.comment_style = CommentStyle::None,
+            (None, None) => {}
+        }
+        if let Some(labels) = &self.labels {
+            args.label = labels.clone();
+        }
+        args
+    }
+}
+
+// exclude_actions, every glob is checked while parsing so a typo fails the file
+fn patterns<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Pattern>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|glob| {
+            Pattern::new(glob)
+                .map_err(|e| de::Error::custom(format!("invalid glob {:?}: {}", glob, e)))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+    use tempfile::tempdir;
+
+    fn load(content: Option<&str>) -> Result<RepoConfig, RepoConfigError> {
+        let dir = tempdir().unwrap();
+        if let Some(content) = content {
+            fs::create_dir_all(dir.path().join(".github")).unwrap();
+            fs::write(dir.path().join(REPO_CONFIG_PATH), content).unwrap();
+        }
+        RepoConfig::load(dir.path().to_str().unwrap())
+    }
+
+    #[test]
+    fn test_missing_or_empty_config() {
+        for content in [None, Some(""), Some("# nothing here\n")] {
+            let config = load(content).unwrap();
+            assert!(config.is_enabled());
+            assert!(config.branch.is_none());
+        }
+    }
+
+    #[test]
+    fn test_config_overrides_args() {
+        let config = load(Some(
+            "enabled: true\nbranch: deps/pin-actions\nexclude_actions: [\"my-org/*\"]\nclean_comment: false\nlabels: [security]\n",
+        ))
+        .unwrap();
+        assert!(config.is_enabled());
+        assert_eq!(config.branch.as_deref(), Some("deps/pin-actions"));
+
+        let args = Args::parse_from([
+            "ratchet-dispatcher",
+            "--repos",
+            "org/a",
+            "--exclude-actions",
+            "actions/cache",
+            "--label",
+            "dependencies",
+            "--clean-comment",
+        ]);
+        assert_eq!(args.comment_style, CommentStyle::Semver);
+        let applied = config.apply(&args);
+        assert_eq!(
+            applied.exclude_actions,
+            vec![
+                Pattern::new("actions/cache").unwrap(),
+                Pattern::new("my-org/*").unwrap()
+            ]
+        );
+        assert_eq!(applied.comment_style, CommentStyle::None);
+        assert_eq!(applied.label, vec![String::from("security")]);
+    }
+
+    #[test]
+    fn test_disabled_config() {
+        assert!(!load(Some("enabled: false\n")).unwrap().is_enabled());
+    }
+
+    #[test]
+    fn test_malformed_config() {
+        for content in [
+            "enabled: maybe\n",
+            "exclude_actions: [\"my-org/[\"]\n",
+            "branches: main\n",
+            "- not a mapping\n",
+        ] {
+            let err = load(Some(content)).unwrap_err();
+            assert!(
+                err.to_string()
+                    .starts_with("invalid .github/ratchet-dispatcher.yml: "),
+                "{}",
+                err
+            );
+        }
+    }
+}
@@ -0,0 +1,593 @@
+use crate::{
+    github::IssueTracker,
+    ratchet::{BranchPin, UpgradeResult},
+    scan::{ActionUse, UnpinnedReason},
+    workflow::{ActionRef, UsesLine},
+};
+
+pub const UNPINNED_ISSUE_TITLE: &str = "Unpinned GitHub Actions detected";
+// Hidden marker used to find the issue created by a previous run
+pub const UNPINNED_ISSUE_MARKER: &str = "<!-- ratchet-dispatcher:unpinned-actions -->";
+
+// An action or docker image whose reference changed in a file of the staged diff
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PinnedAction {
+    pub file: String,
+    // owner/name[/path], or docker://image
+    pub action: String,
+    pub old: String,
+    pub new: String,
+    // Tag preserved in the ratchet comment of the new line, like v3
+    pub tag: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueTransition {
+    Opened,
+    Updated,
+    Closed,
+    // Everything is pinned and there was no open issue to close
+    None,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IssueReport {
+    pub url: Option<String>,
+    pub transition: IssueTransition,
+}
+
+// Open, update or close the unpinned actions issue of a repository
+// depending on the scan results and on whether an issue is already open
+pub async fn report_unpinned_actions<T: IssueTracker + Sync + ?Sized>(
+    tracker: &T,
+    unpinned: &[ActionUse],
+) -> Result<IssueReport, Box<dyn std::error::Error>> {
+    let existing = tracker.find_open_issue(UNPINNED_ISSUE_MARKER).await?;
+
+    let (issue, transition) = match (existing, unpinned.is_empty()) {
+        (None, true) => {
+            return Ok(IssueReport {
+                url: None,
+                transition: IssueTransition::None,
+            })
+        }
+        (None, false) => (
+            tracker
+                .create_issue(UNPINNED_ISSUE_TITLE, &render_issue_body(unpinned))
+                .await?,
+            IssueTransition::Opened,
+        ),
+        (Some(issue), false) => (
+            tracker
+                .update_issue(issue.number, &render_issue_body(unpinned))
+                .await?,
+            IssueTransition::Updated,
+        ),
+        (Some(issue), true) => (
+            tracker.close_issue(issue.number).await?,
+            IssueTransition::Closed,
+        ),
+    };
+
+    Ok(IssueReport {
+        url: Some(issue.html_url),
+        transition,
+    })
+}
+
+pub fn render_issue_body(unpinned: &[ActionUse]) -> String {
+    let mut body = format!(
+        "{}\nThe following actions are not pinned to a commit SHA. Pinning them prevents bad actors from overwriting tags/versions. This issue is updated automatically and closed once every action is pinned.\n\n| File | Line | Action |\n| --- | --- | --- |\n",
+        UNPINNED_ISSUE_MARKER
+    );
+    for action in unpinned {
+        body.push_str(&format!(
+            "| `{}` | {} | `{}` |\n",
+            action.file, action.line, action.uses
+        ));
+    }
+    body
+}
+
+// Every `uses:` whose reference changed in <diff>, a unified diff like
+// GitRepository::staged_diff produces. Removed and added lines are paired per hunk by
+// action, the same change is listed once per file.
+pub fn parse_pinned_actions(diff: &str) -> Vec<PinnedAction> {
+    let mut pinned = Vec::new();
+    let mut file = String::new();
+    let mut removed: Vec<Option<UsesRef>> = Vec::new();
+    let mut added: Vec<UsesRef> = Vec::new();
+    let mut in_hunk = false;
+    for line in diff.lines() {
+        if line.starts_with("diff --git ") || line.starts_with("@@") {
+            pair_hunk(&file, &mut removed, &mut added, &mut pinned);
+            in_hunk = line.starts_with("@@");
+        } else if !in_hunk {
+            if let Some(path) = line.strip_prefix("+++ b/") {
+                file = path.to_string();
+            }
+        } else if let Some(uses) = line.strip_prefix('-').and_then(UsesRef::parse) {
+            removed.push(Some(uses));
+        } else if let Some(uses) = line.strip_prefix('+').and_then(UsesRef::parse) {
+            added.push(uses);
+        }
+    }
+    pair_hunk(&file, &mut removed, &mut added, &mut pinned);
+    pinned
+}
+
+// Pair the added lines of a hunk with the first removed line of the same action
+fn pair_hunk(
+    file: &str,
+    removed: &mut Vec<Option<UsesRef>>,
+    added: &mut Vec<UsesRef>,
+    pinned: &mut Vec<PinnedAction>,
+) {
+    for new in added.drain(..) {
+        let old = removed
+            .iter_mut()
+            .find(|old| matches!(old, Some(old) if old.action == new.action))
+            .and_then(Option::take);
+        let change = match old {
+            Some(old) if old.reference != new.reference => PinnedAction {
+                file: file.to_string(),
+                action: new.action,
+                old: old.reference,
+                new: new.reference,
+                tag: new.tag,
+            },
+            _ => continue,
+        };
+        if !pinned.contains(&change) {
+            pinned.push(change);
+        }
+    }
+    removed.clear();
+}
+
+// Action and reference of a `uses:` line, with the tag its ratchet comment preserves
+struct UsesRef {
+    action: String,
+    reference: String,
+    tag: Option<String>,
+}
+
+impl UsesRef {
+    // Docker images are split at their digest or tag: docker://alpine:3.19 and
+    // docker://alpine@sha256:... are both docker://alpine
+    fn parse(text: &str) -> Option<Self> {
+        let line = UsesLine::parse(text, 0)?;
+        let uses = line.uses();
+        if let Some(image) = uses.strip_prefix("docker://") {
+            let name_end = image.rfind('/').map_or(0, |slash| slash + 1);
+            let (name, reference) = match image.split_once('@') {
+                Some((name, digest)) => (name, digest),
+                None => image[name_end..]
+                    .rsplit_once(':')
+                    .map(|(_, tag)| (&image[..image.len() - tag.len() - 1], tag))?,
+            };
+            return Some(UsesRef {
+                action: format!("docker://{}", name),
+                reference: reference.to_string(),
+                tag: None,
+            });
+        }
+        let action = ActionRef::parse(uses)?;
+        let tag = line
+            .ratchet_original()
+            .and_then(|original| original.rsplit_once('@'))
+            .filter(|(original, _)| *original == action.action())
+            .map(|(_, tag)| tag.to_string());
+        Some(UsesRef {
+            action: action.action(),
+            reference: action.reference?.to_string(),
+            tag,
+        })
+    }
+}
+
+// Table of the changed references, empty when nothing was pinned. New SHAs link to
+// their commit and preserved tags to their release on <github_url>, docker images
+// are listed without links.
+pub fn render_pinned_actions(github_url: &str, pinned: &[PinnedAction]) -> String {
+    if pinned.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n### Pinned actions\n\n| File | Action | Old | New | Release |\n| --- | --- | --- | --- | --- |\n",
+    );
+    for action in pinned {
+        let repository = ActionRef::parse(&action.action).map(|parsed| {
+            format!(
+                "{}/{}/{}",
+                github_url.trim_end_matches('/'),
+                parsed.owner,
+                parsed.name
+            )
+        });
+        let (new, release) = match repository {
+            Some(repository) => (
+                format!("[`{}`]({}/commit/{})", action.new, repository, action.new),
+                action
+                    .tag
+                    .as_ref()
+                    .map(|tag| format!("[{}]({}/releases/tag/{})", tag, repository, tag))
+                    .unwrap_or_default(),
+            ),
+            None => (format!("`{}`", action.new), String::new()),
+        };
+        section.push_str(&format!(
+            "| `{}` | `{}` | `{}` | {} | {} |\n",
+            action.file, action.action, action.old, new, release
+        ));
+    }
+    section
+}
+
+// Headline of the PR body: the number of unpinned actions before and after this PR
+pub fn render_pin_counts(before: usize, remaining: &[ActionUse]) -> String {
+    format!(
+        "This PR takes this repository from **{}** unpinned action(s) to **{}**.\n\n",
+        before,
+        remaining.len()
+    )
+}
+
+// Per-file record of the ratchet runs, followed by the fingerprint footer
+pub fn render_provenance(upgrade: &UpgradeResult) -> String {
+    if upgrade.invocations.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n<details>\n<summary>Ratchet provenance</summary>\n\n| File | Command | Ratchet | Environment | Duration | Exit code |\n| --- | --- | --- | --- | --- | --- |\n",
+    );
+    for invocation in &upgrade.invocations {
+        section.push_str(&format!(
+            "| `{}` | `{}` | {} | {} | {:.2}s | {} |\n",
+            invocation.files.join(", "),
+            invocation.args.join(" "),
+            invocation.ratchet_version.as_deref().unwrap_or("unknown"),
+            invocation.env_names.join(", "),
+            invocation.duration.as_secs_f64(),
+            invocation
+                .exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_else(|| String::from("signal")),
+        ));
+    }
+    section.push_str(&format!(
+        "\n</details>\n\n<sub>Ratchet fingerprint: `{}`</sub>\n",
+        upgrade.fingerprint()
+    ));
+    section
+}
+
+// Section appended to the PR body listing the pins that follow a branch, empty when
+// there are none
+pub fn render_branch_pins(branch_pins: &[BranchPin]) -> String {
+    if branch_pins.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n### ⚠️ Branch-pinned actions\n\nThe following actions are pinned from a branch, the next update moves them to whatever the branch points to. Consider pinning them from a release tag instead.\n\n",
+    );
+    for pin in branch_pins {
+        section.push_str(&format!(
+            "- `{}@{}` in `{}` line {}\n",
+            pin.action, pin.branch, pin.file, pin.line
+        ));
+    }
+    section
+}
+
+// Note on the lines left out because only their ratchet comment changed
+pub fn render_comment_only_changes(count: usize) -> String {
+    if count == 0 {
+        return String::new();
+    }
+    format!(
+        "\n\n{} line(s) where only the ratchet comment changed were left out of this PR.\n",
+        count
+    )
+}
+
+// Details on why actions are still unpinned after this PR, empty when everything is pinned
+pub fn render_remaining_unpinned(remaining: &[ActionUse]) -> String {
+    if remaining.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n### Remaining unpinned actions\n\n| File | Line | Action | Reason |\n| --- | --- | --- | --- |\n",
+    );
+    for action in remaining {
+        section.push_str(&format!(
+            "| `{}` | {} | `{}` | {} |\n",
+            action.file,
+            action.line,
+            action.uses,
+            UnpinnedReason::of(action).description()
+        ));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{IssueRef, MockIssueTracker};
+    use mockall::predicate::*;
+
+    fn unpinned() -> Vec<ActionUse> {
+        vec![ActionUse {
+            file: ".github/workflows/ci.yml".to_string(),
+            line: 17,
+            uses: "actions/checkout@v3".to_string(),
+            excluded: false,
+        }]
+    }
+
+    fn issue() -> IssueRef {
+        IssueRef {
+            number: 7,
+            html_url: "https://github.com/org/repo/issues/7".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_report_creates_issue() {
+        let mut tracker = MockIssueTracker::new();
+        tracker.expect_find_open_issue().returning(|_| Ok(None));
+        tracker
+            .expect_create_issue()
+            .with(eq(UNPINNED_ISSUE_TITLE), always())
+            .times(1)
+            .returning(|_, body| {
+                assert!(body.contains("actions/checkout@v3"));
+                assert!(body.contains(UNPINNED_ISSUE_MARKER));
+                Ok(issue())
+            });
+
+        let report = report_unpinned_actions(&tracker, &unpinned())
+            .await
+            .unwrap();
+        assert_eq!(report.transition, IssueTransition::Opened);
+        assert_eq!(report.url, Some(issue().html_url));
+    }
+
+    #[tokio::test]
+    async fn test_report_updates_existing_issue() {
+        let mut tracker = MockIssueTracker::new();
+        tracker
+            .expect_find_open_issue()
+            .returning(|_| Ok(Some(issue())));
+        tracker
+            .expect_update_issue()
+            .with(eq(7), always())
+            .times(1)
+            .returning(|_, _| Ok(issue()));
+
+        let report = report_unpinned_actions(&tracker, &unpinned())
+            .await
+            .unwrap();
+        assert_eq!(report.transition, IssueTransition::Updated);
+    }
+
+    #[tokio::test]
+    async fn test_report_closes_issue_when_everything_is_pinned() {
+        let mut tracker = MockIssueTracker::new();
+        tracker
+            .expect_find_open_issue()
+            .returning(|_| Ok(Some(issue())));
+        tracker
+            .expect_close_issue()
+            .with(eq(7))
+            .times(1)
+            .returning(|_| Ok(issue()));
+
+        let report = report_unpinned_actions(&tracker, &[]).await.unwrap();
+        assert_eq!(report.transition, IssueTransition::Closed);
+    }
+
+    #[tokio::test]
+    async fn test_report_nothing_to_do() {
+        let mut tracker = MockIssueTracker::new();
+        tracker.expect_find_open_issue().returning(|_| Ok(None));
+
+        let report = report_unpinned_actions(&tracker, &[]).await.unwrap();
+        assert_eq!(report.transition, IssueTransition::None);
+        assert_eq!(report.url, None);
+    }
+
+    const STAGED_DIFF: &str = "diff --git a/.github/workflows/ci.yml b/.github/workflows/ci.yml
+index 1b2c3d4..5e6f7a8 100644
+--- a/.github/workflows/ci.yml
++++ b/.github/workflows/ci.yml
+@@ -17,3 +17,3 @@ jobs:
+-      - uses: actions/checkout@v3
+-      - uses: actions/setup-node@v3
+-      - uses: github/codeql-action/init@v3
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3
++      - uses: actions/setup-node@1a4442cacd436585916779262731d5b162bc6ec7 # ratchet:actions/setup-node@v3
++      - uses: github/codeql-action/init@4fa2a7953630fd2f3fb380f21be14ede0169dd4f # ratchet:github/codeql-action/init@v3
+@@ -30 +30 @@ jobs:
+-      - uses: actions/checkout@v3
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3
+diff --git a/.github/workflows/release.yml b/.github/workflows/release.yml
+index 2b3c4d5..6f7a8b9 100644
+--- a/.github/workflows/release.yml
++++ b/.github/workflows/release.yml
+@@ -9,2 +9,2 @@ jobs:
+-      - uses: actions/checkout@v3
+-      - uses: docker://alpine:3.19
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3
++      - uses: docker://alpine@sha256:c5b1261d6d3e43071626931fc004f70149baeba2c8ec672bd4f27761f8e1ad6b # ratchet:docker://alpine:3.19
+";
+
+    const CHECKOUT_SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+    const ALPINE_DIGEST: &str =
+        "sha256:c5b1261d6d3e43071626931fc004f70149baeba2c8ec672bd4f27761f8e1ad6b";
+
+    fn pinned(file: &str, action: &str, new: &str, tag: Option<&str>) -> PinnedAction {
+        PinnedAction {
+            file: file.to_string(),
+            action: action.to_string(),
+            old: tag.unwrap_or("3.19").to_string(),
+            new: new.to_string(),
+            tag: tag.map(String::from),
+        }
+    }
+
+    #[test]
+    fn test_parse_pinned_actions() {
+        let ci = ".github/workflows/ci.yml";
+        let release = ".github/workflows/release.yml";
+        assert_eq!(
+            parse_pinned_actions(STAGED_DIFF),
+            [
+                pinned(ci, "actions/checkout", CHECKOUT_SHA, Some("v3")),
+                pinned(
+                    ci,
+                    "actions/setup-node",
+                    "1a4442cacd436585916779262731d5b162bc6ec7",
+                    Some("v3")
+                ),
+                pinned(
+                    ci,
+                    "github/codeql-action/init",
+                    "4fa2a7953630fd2f3fb380f21be14ede0169dd4f",
+                    Some("v3")
+                ),
+                // The same change in another file gets its own entry
+                pinned(release, "actions/checkout", CHECKOUT_SHA, Some("v3")),
+                pinned(release, "docker://alpine", ALPINE_DIGEST, None),
+            ]
+        );
+        assert!(parse_pinned_actions("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_pinned_actions_without_ratchet_comment() {
+        let diff = "diff --git a/.github/workflows/ci.yml b/.github/workflows/ci.yml
+--- a/.github/workflows/ci.yml
++++ b/.github/workflows/ci.yml
+@@ -17 +17 @@ jobs:
+-      - uses: actions/checkout@v3
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # v3
+";
+        assert_eq!(parse_pinned_actions(diff)[0].tag, None);
+    }
+
+    #[test]
+    fn test_parse_pinned_actions_with_note_after_ratchet_comment() {
+        let diff = "diff --git a/.github/workflows/ci.yml b/.github/workflows/ci.yml
+--- a/.github/workflows/ci.yml
++++ b/.github/workflows/ci.yml
+@@ -17 +17 @@ jobs:
+-      - uses: actions/checkout@v3 # keep on v3
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3 keep on v3
+";
+        assert_eq!(parse_pinned_actions(diff)[0].tag.as_deref(), Some("v3"));
+    }
+
+    #[test]
+    fn test_parse_pinned_actions_ignores_comment_only_changes() {
+        let diff = "diff --git a/.github/workflows/ci.yml b/.github/workflows/ci.yml
+--- a/.github/workflows/ci.yml
++++ b/.github/workflows/ci.yml
+@@ -17 +17 @@ jobs:
+-      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744
++      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3
+";
+        assert!(parse_pinned_actions(diff).is_empty());
+    }
+
+    #[test]
+    fn test_render_pinned_actions() {
+        assert!(render_pinned_actions("https://github.com", &[]).is_empty());
+        let section = render_pinned_actions(
+            "https://github.com/",
+            &[
+                pinned(
+                    ".github/workflows/ci.yml",
+                    "actions/checkout",
+                    CHECKOUT_SHA,
+                    Some("v3"),
+                ),
+                pinned(
+                    ".github/workflows/ci.yml",
+                    "github/codeql-action/init",
+                    "4fa2a7953630fd2f3fb380f21be14ede0169dd4f",
+                    Some("v3"),
+                ),
+                pinned(
+                    ".github/workflows/release.yml",
+                    "docker://alpine",
+                    ALPINE_DIGEST,
+                    None,
+                ),
+            ],
+        );
+        assert_eq!(
+            section,
+            "\n\n### Pinned actions\n\n| File | Action | Old | New | Release |\n| --- | --- | --- | --- | --- |\n\
+            | `.github/workflows/ci.yml` | `actions/checkout` | `v3` | [`f43a0e5ff2bd294095638e18286ca9a3d1956744`](https://github.com/actions/checkout/commit/f43a0e5ff2bd294095638e18286ca9a3d1956744) | [v3](https://github.com/actions/checkout/releases/tag/v3) |\n\
+            | `.github/workflows/ci.yml` | `github/codeql-action/init` | `v3` | [`4fa2a7953630fd2f3fb380f21be14ede0169dd4f`](https://github.com/github/codeql-action/commit/4fa2a7953630fd2f3fb380f21be14ede0169dd4f) | [v3](https://github.com/github/codeql-action/releases/tag/v3) |\n\
+            | `.github/workflows/release.yml` | `docker://alpine` | `3.19` | `sha256:c5b1261d6d3e43071626931fc004f70149baeba2c8ec672bd4f27761f8e1ad6b` |  |\n"
+        );
+    }
+
+    #[test]
+    fn test_render_pin_counts() {
+        assert_eq!(
+            render_pin_counts(14, &[]),
+            "This PR takes this repository from **14** unpinned action(s) to **0**.\n\n"
+        );
+        assert!(render_remaining_unpinned(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_render_remaining_unpinned() {
+        let mut remaining = unpinned();
+        remaining[0].excluded = true;
+        let section = render_remaining_unpinned(&remaining);
+        assert!(section.contains("| `.github/workflows/ci.yml` | 17 | `actions/checkout@v3` | excluded with ratchet:exclude |"));
+    }
+
+    #[test]
+    fn test_render_branch_pins() {
+        assert!(render_branch_pins(&[]).is_empty());
+        let section = render_branch_pins(&[BranchPin {
+            file: String::from(".github/workflows/ci.yml"),
+            line: 12,
+            action: String::from("org/tool"),
+            branch: String::from("main"),
+        }]);
+        assert!(section.starts_with("\n\n### ⚠️ Branch-pinned actions\n"));
+        assert!(section.ends_with("- `org/tool@main` in `.github/workflows/ci.yml` line 12\n"));
+    }
+
+    #[test]
+    fn test_render_provenance() {
+        assert!(render_provenance(&UpgradeResult::default()).is_empty());
+
+        let upgrade = UpgradeResult {
+            skipped: Vec::new(),
+            invocations: vec![crate::ratchet::RatchetInvocation {
+                files: vec![".github/workflows/ci.yml".to_string()],
+                args: vec![
+                    "ratchet".to_string(),
+                    "pin".to_string(),
+                    "/tmp/clone/.github/workflows/ci.yml".to_string(),
+                ],
+                ratchet_version: Some("ratchet 0.9.2".to_string()),
+                env_names: vec!["HOME".to_string(), "PATH".to_string()],
+                duration: std::time::Duration::from_millis(1250),
+                exit_code: Some(0),
+            }],
+            branch_pins: Vec::new(),
+        };
+        let section = render_provenance(&upgrade);
+        assert!(section.contains("| `.github/workflows/ci.yml` | `ratchet pin /tmp/clone/.github/workflows/ci.yml` | ratchet 0.9.2 | HOME, PATH | 1.25s | 0 |"));
+        assert!(section.contains(&format!("Ratchet fingerprint: `{}`", upgrade.fingerprint())));
+    }
+}
@@ -0,0 +1,243 @@
+use std::{fs, path::Path};
+
+use log::debug;
+
+use crate::{
+    io::is_file_too_large,
+    workflow::{parse_workflow, ActionRef, RefKind},
+};
+
+// A single `uses:` reference in a workflow file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActionUse {
+    pub file: String,
+    pub line: usize,
+    pub uses: String,
+    // The line carries a `ratchet:exclude` comment
+    pub excluded: bool,
+}
+
+impl ActionUse {
+    // Split the reference into owner, repository and ref.
+    // Returns None for local actions, docker references and references without a ref.
+    pub fn parts(&self) -> Option<(&str, &str, &str)> {
+        let action = ActionRef::parse(&self.uses)?;
+        Some((action.owner, action.name, action.reference?))
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        RefKind::of(&self.uses).is_pinned()
+    }
+}
+
+// Scan all workflow files of the repository at <local_path> and return every
+// `uses:` reference that is not pinned to a full commit SHA.
+// Local actions (./path) and docker references are not considered.
+// Files larger than <max_size> bytes are not read.
+pub fn find_unpinned_actions(
+    local_path: &str,
+    max_size: u64,
+) -> Result<Vec<ActionUse>, Box<dyn std::error::Error>> {
+    Ok(find_actions(local_path, max_size)?
+        .into_iter()
+        .filter(|action| !action.is_pinned())
+        .collect())
+}
+
+// Same as find_unpinned_actions but returns the references pinned to a commit SHA
+pub fn find_pinned_actions(
+    local_path: &str,
+    max_size: u64,
+) -> Result<Vec<ActionUse>, Box<dyn std::error::Error>> {
+    Ok(find_actions(local_path, max_size)?
+        .into_iter()
+        .filter(|action| action.is_pinned() && action.parts().is_some())
+        .collect())
+}
+
+// Return every `uses:` reference in the workflow files of <local_path>
+pub fn find_actions(
+    local_path: &str,
+    max_size: u64,
+) -> Result<Vec<ActionUse>, Box<dyn std::error::Error>> {
+    let workflows_path = Path::new(local_path).join(".github/workflows");
+    // Repositories that only pin other --pin-paths have no actions
+    if !workflows_path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let mut paths: Vec<_> = fs::read_dir(&workflows_path)?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file())
+        .collect();
+    paths.sort();
+
+    let mut actions = Vec::new();
+    for path in paths {
+        if is_file_too_large(&path, max_size) {
+            debug!("Not scanning {}: file too large", path.display());
+            continue;
+        }
+        debug!("Scanning workflow: {}", path.display());
+        let content = fs::read_to_string(&path)?;
+        let file = path
+            .strip_prefix(local_path)
+            .unwrap_or(&path)
+            .to_string_lossy()
+            .to_string();
+        for line in parse_workflow(&content) {
+            actions.push(ActionUse {
+                file: file.clone(),
+                line: line.line,
+                uses: line.uses().to_string(),
+                excluded: line.is_excluded(),
+            });
+        }
+    }
+
+    Ok(actions)
+}
+
+// Why an action is still unpinned after ratchet ran
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnpinnedReason {
+    // Excluded with a `ratchet:exclude` comment
+    Excluded,
+    // The reference is an expression like ${{ matrix.action }}
+    DynamicRef,
+    // Ratchet could not resolve the reference to a SHA
+    NotResolved,
+}
+
+impl UnpinnedReason {
+    pub fn of(action: &ActionUse) -> Self {
+        if action.excluded {
+            UnpinnedReason::Excluded
+        } else if RefKind::of(&action.uses) == RefKind::Expression {
+            UnpinnedReason::DynamicRef
+        } else {
+            UnpinnedReason::NotResolved
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            UnpinnedReason::Excluded => "excluded with ratchet:exclude",
+            UnpinnedReason::DynamicRef => "dynamic reference",
+            UnpinnedReason::NotResolved => "could not be resolved",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ratchet::DEFAULT_MAX_WORKFLOW_SIZE;
+    use tempfile::tempdir;
+
+    const UNPINNED_WORKFLOW: &str = include_str!("../resources/ci_unpinned.yml");
+    const PINNED_WORKFLOW: &str = include_str!("../resources/ci_pinned.yml");
+
+    fn write_workflow(dir: &Path, name: &str, content: &str) {
+        let workflows = dir.join(".github/workflows");
+        fs::create_dir_all(&workflows).unwrap();
+        fs::write(workflows.join(name), content).unwrap();
+    }
+
+    #[test]
+    fn test_find_unpinned_actions_reports_file_and_line() {
+        let dir = tempdir().unwrap();
+        write_workflow(dir.path(), "ci.yml", UNPINNED_WORKFLOW);
+
+        let unpinned =
+            find_unpinned_actions(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE).unwrap();
+
+        assert_eq!(
+            unpinned,
+            vec![
+                ActionUse {
+                    file: ".github/workflows/ci.yml".to_string(),
+                    line: 17,
+                    uses: "actions/checkout@v3".to_string(),
+                    excluded: false,
+                },
+                ActionUse {
+                    file: ".github/workflows/ci.yml".to_string(),
+                    line: 19,
+                    uses: "actions/setup-node@v3".to_string(),
+                    excluded: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_find_unpinned_actions_pinned_workflow() {
+        let dir = tempdir().unwrap();
+        write_workflow(dir.path(), "ci.yml", PINNED_WORKFLOW);
+
+        let unpinned =
+            find_unpinned_actions(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE).unwrap();
+        assert!(unpinned.is_empty());
+    }
+
+    #[test]
+    fn test_find_pinned_actions() {
+        let dir = tempdir().unwrap();
+        write_workflow(dir.path(), "ci.yml", PINNED_WORKFLOW);
+
+        let pinned =
+            find_pinned_actions(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE).unwrap();
+        assert_eq!(pinned.len(), 2);
+        assert_eq!(
+            pinned[0].parts(),
+            Some((
+                "actions",
+                "checkout",
+                "f43a0e5ff2bd294095638e18286ca9a3d1956744"
+            ))
+        );
+    }
+
+    #[test]
+    fn test_action_use_parts_with_sub_path() {
+        let action = ActionUse {
+            file: "ci.yml".to_string(),
+            line: 1,
+            uses: "github/codeql-action/init@v3".to_string(),
+            excluded: false,
+        };
+        assert_eq!(action.parts(), Some(("github", "codeql-action", "v3")));
+    }
+
+    #[test]
+    fn test_find_unpinned_actions_skips_large_files() {
+        let dir = tempdir().unwrap();
+        write_workflow(dir.path(), "ci.yml", UNPINNED_WORKFLOW);
+
+        let unpinned = find_unpinned_actions(dir.path().to_str().unwrap(), 16).unwrap();
+        assert!(unpinned.is_empty());
+    }
+
+    #[test]
+    fn test_unpinned_reasons_for_mixed_workflow() {
+        let dir = tempdir().unwrap();
+        write_workflow(
+            dir.path(),
+            "mixed.yml",
+            "jobs:\n  build:\n    steps:\n      - uses: actions/checkout@f43a0e5ff2bd294095638e18286ca9a3d1956744 # ratchet:actions/checkout@v3\n      - uses: actions/setup-node@v3 # ratchet:exclude\n      - uses: ${{ matrix.action }}@v1\n      - uses: example/unknown@v9\n      - uses: ./.github/actions/local\n",
+        );
+
+        let unpinned =
+            find_unpinned_actions(dir.path().to_str().unwrap(), DEFAULT_MAX_WORKFLOW_SIZE).unwrap();
+        let reasons: Vec<_> = unpinned.iter().map(UnpinnedReason::of).collect();
+        assert_eq!(
+            reasons,
+            vec![
+                UnpinnedReason::Excluded,
+                UnpinnedReason::DynamicRef,
+                UnpinnedReason::NotResolved
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+use std::{collections::BTreeMap, fs, io, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::io::write_atomically;
+
+// Summary reason of a repository skipped by --state-file
+pub const SKIPPED_UNCHANGED: &str = "skipped: base branch unchanged since the last run";
+
+// Version of the --state-file format, a file of another version is not used
+const STATE_VERSION: u32 = 1;
+
+// Base branch commit a repository was last processed at
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RecordedHead {
+    pub branch: String,
+    pub sha: String,
+}
+
+// --state-file, the heads of the repositories processed by earlier runs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateFile {
+    version: u32,
+    repositories: BTreeMap<String, RecordedHead>,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum StateError {
+    #[error("failed to read state file {0}: {1}")]
+    Read(String, io::Error),
+    #[error("invalid state file {0}: {1}")]
+    Parse(String, serde_json::Error),
+    #[error("state file {0} has version {1}, expected {STATE_VERSION}")]
+    Version(String, u32),
+}
+
+impl Default for StateFile {
+    fn default() -> Self {
+        StateFile {
+            version: STATE_VERSION,
+            repositories: BTreeMap::new(),
+        }
+    }
+}
+
+impl StateFile {
+    // The state at <path>, empty when the file does not exist yet
+    pub fn load(path: &str) -> Result<Self, StateError> {
+        let content = match fs::read_to_string(path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(StateFile::default()),
+            Err(e) => return Err(StateError::Read(path.to_string(), e)),
+        };
+        let state: StateFile =
+            serde_json::from_str(&content).map_err(|e| StateError::Parse(path.to_string(), e))?;
+        if state.version != STATE_VERSION {
+            return Err(StateError::Version(path.to_string(), state.version));
+        }
+        Ok(state)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+        let json = serde_json::to_string_pretty(self)? + "\n";
+        write_atomically(Path::new(path), &json)
+    }
+
+    // Whether <repository> was processed at commit <sha> of <branch> before
+    pub fn is_unchanged(&self, repository: &str, branch: &str, sha: &str) -> bool {
+        self.repositories
+            .get(repository)
+            .is_some_and(|head| head.branch == branch && head.sha == sha)
+    }
+
+    pub fn record(&mut self, repository: &str, branch: &str, sha: &str) {
+        self.repositories.insert(
+            repository.to_string(),
+            RecordedHead {
+                branch: branch.to_string(),
+                sha: sha.to_string(),
+            },
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    const SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+
+    #[test]
+    fn test_state_round_trips() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        let path = path.to_str().unwrap();
+
+        // A missing file is an empty state
+        let mut state = StateFile::load(path).unwrap();
+        assert!(!state.is_unchanged("org/app", "main", SHA));
+        state.record("org/app", "main", SHA);
+        state.save(path).unwrap();
+
+        let state = StateFile::load(path).unwrap();
+        assert!(state.is_unchanged("org/app", "main", SHA));
+        assert!(!state.is_unchanged("org/app", "develop", SHA));
+        assert!(!state.is_unchanged(
+            "org/app",
+            "main",
+            "1a4442cacd436585916779262731d5b162bc6ec7"
+        ));
+        assert!(!state.is_unchanged("org/other", "main", SHA));
+        assert!(!dir.path().join("state.partial").exists());
+    }
+
+    #[test]
+    fn test_corrupt_state_file() {
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("state.json");
+        for (content, message) in [
+            ("{\"version\": 1", "invalid state file"),
+            ("[]", "invalid state file"),
+            (
+                "{\"version\": 2, \"repositories\": {}}",
+                "has version 2, expected 1",
+            ),
+        ] {
+            fs::write(&path, content).unwrap();
+            let err = StateFile::load(path.to_str().unwrap()).unwrap_err();
+            assert!(err.to_string().contains(message), "{}", err);
+        }
+    }
+}
@@ -0,0 +1,746 @@
+use std::{error::Error, fs};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    git::CorruptedWorkflow,
+    github::{error_category, ErrorCategory, PullRequestRef},
+    pin_cache::PinCacheStats,
+    ratchet::{BranchPin, PinCounts, PinStats},
+    verify::{SourceIssue, TagMismatch},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RepoStatus {
+    Created,
+    Updated,
+    // Ratchet did not change any workflow file
+    Skipped,
+    Deferred,
+    // --only-report-unpinned
+    Reported,
+    // --dry-run, changes were staged but not pushed
+    DryRun,
+    // --prune-only, the automation branch was deleted
+    Pruned,
+    // --local-path without --push, the changes were committed to the checkout
+    Committed,
+    Failed,
+}
+
+// Exit codes of a run: some repositories failed, or all of them (or a check before the
+// first one, like the token or the ratchet binary). --no-fail-on-error exits with 0.
+pub const EXIT_SOME_FAILED: i32 = 1;
+pub const EXIT_FAILED: i32 = 2;
+// --exit-code-on-changes: a dry run found changes to commit. 3 is taken by the kill switch.
+pub const EXIT_CHANGES_PENDING: i32 = 4;
+
+// Reasons a clone stays in --clone-dir after its repository was processed
+pub const KEPT_ON_ERROR: &str = "processing failed (--keep-clones-on-error)";
+pub const KEPT_ALWAYS: &str = "--keep-clones";
+pub const KEPT_FOR_REUSE: &str = "reused by the next run (--reuse-clones)";
+
+// A clone left in --clone-dir
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct KeptClone {
+    pub path: String,
+    pub reason: &'static str,
+}
+
+// Outcome of a single repository, one entry of --summary-json
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct RepoSummary {
+    pub repository: String,
+    pub status: RepoStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_number: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pr_url: Option<String>,
+    pub files_changed: usize,
+    pub actions_pinned: usize,
+    // Lines left out because only their ratchet comment changed
+    pub comment_only_changes: usize,
+    // SHA, tag and branch references in the workflows before and after the run
+    #[serde(skip_serializing_if = "PinStats::is_empty")]
+    pub pins: PinStats,
+    // --verify-sources, action repositories that are archived, missing or moved
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub action_sources: Vec<SourceIssue>,
+    // --verify-pins=warn, new pins that do not match the tag in their ratchet comment
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub tag_mismatches: Vec<TagMismatch>,
+    // Pins whose ratchet comment follows a branch instead of a tag
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub branch_pins: Vec<BranchPin>,
+    // Workflows left unchanged because ratchet broke more than their `uses:` values
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub corrupted_workflows: Vec<CorruptedWorkflow>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub reason: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Whether a failure came from the token, the repository or the rate limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error_category: Option<ErrorCategory>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub kept_clone: Option<KeptClone>,
+    // Files a dry run would have committed
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub changed_files: Vec<String>,
+    // File name of the patch in --dry-run-output
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<String>,
+}
+
+impl RepoSummary {
+    pub fn new(repository: &str, status: RepoStatus) -> Self {
+        RepoSummary {
+            repository: repository.to_string(),
+            status,
+            pr_number: None,
+            pr_url: None,
+            files_changed: 0,
+            actions_pinned: 0,
+            comment_only_changes: 0,
+            pins: PinStats::default(),
+            action_sources: Vec::new(),
+            tag_mismatches: Vec::new(),
+            branch_pins: Vec::new(),
+            corrupted_workflows: Vec::new(),
+            reason: None,
+            error: None,
+            error_category: None,
+            kept_clone: None,
+            changed_files: Vec::new(),
+            patch: None,
+        }
+    }
+
+    pub fn with_pr(mut self, pr: &PullRequestRef) -> Self {
+        self.pr_number = Some(pr.number);
+        self.pr_url = Some(pr.html_url.clone());
+        self
+    }
+
+    pub fn with_files_changed(mut self, files_changed: usize) -> Self {
+        self.files_changed = files_changed;
+        self
+    }
+
+    pub fn with_actions_pinned(mut self, actions_pinned: usize) -> Self {
+        self.actions_pinned = actions_pinned;
+        self
+    }
+
+    pub fn with_comment_only_changes(mut self, comment_only_changes: usize) -> Self {
+        self.comment_only_changes = comment_only_changes;
+        self
+    }
+
+    pub fn with_pins(mut self, pins: PinStats) -> Self {
+        self.pins = pins;
+        self
+    }
+
+    pub fn with_source_issues(mut self, issues: &[SourceIssue]) -> Self {
+        self.action_sources = issues.to_vec();
+        self
+    }
+
+    pub fn with_tag_mismatches(mut self, mismatches: &[TagMismatch]) -> Self {
+        self.tag_mismatches = mismatches.to_vec();
+        self
+    }
+
+    pub fn with_branch_pins(mut self, branch_pins: &[BranchPin]) -> Self {
+        self.branch_pins = branch_pins.to_vec();
+        self
+    }
+
+    pub fn with_corrupted_workflows(mut self, workflows: &[CorruptedWorkflow]) -> Self {
+        self.corrupted_workflows = workflows.to_vec();
+        self
+    }
+
+    pub fn with_reason(mut self, reason: &str) -> Self {
+        self.reason = Some(reason.to_string());
+        self
+    }
+
+    pub fn with_kept_clone(mut self, kept_clone: Option<KeptClone>) -> Self {
+        self.kept_clone = kept_clone;
+        self
+    }
+
+    pub fn with_changed_files(mut self, files: Vec<String>) -> Self {
+        self.changed_files = files;
+        self
+    }
+
+    pub fn with_patch(mut self, patch: Option<String>) -> Self {
+        self.patch = patch;
+        self
+    }
+
+    pub fn failed(repository: &str, error: &(dyn Error + 'static)) -> Self {
+        RepoSummary {
+            error: Some(error.to_string()),
+            error_category: error_category(error),
+            ..RepoSummary::new(repository, RepoStatus::Failed)
+        }
+    }
+}
+
+// Everything a run did, collected while processing instead of scraped from logs
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct RunSummary {
+    pub repositories: Vec<RepoSummary>,
+    // Version of the ratchet binary that was used, when it could be determined
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ratchet_version: Option<String>,
+    // The pins of every repository added up
+    #[serde(skip_serializing_if = "PinStats::is_empty")]
+    pub pins: PinStats,
+    // --pin-cache: how often it had the SHA of a tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pin_cache: Option<PinCacheStats>,
+}
+
+impl RunSummary {
+    pub fn record(&mut self, entry: RepoSummary) {
+        self.pins.add(&entry.pins);
+        self.repositories.push(entry);
+    }
+
+    // Table of the pins across the run, logged at its end
+    pub fn render_pin_table(&self) -> String {
+        let row = |name: &str, count: fn(&PinCounts) -> usize| {
+            format!(
+                "{:<8}{:>8}{:>8}\n",
+                name,
+                count(&self.pins.before),
+                count(&self.pins.after)
+            )
+        };
+        let percent = |counts: &PinCounts| match counts.pinned_percent() {
+            Some(percent) => format!("{:.1}%", percent),
+            None => String::from("-"),
+        };
+        format!(
+            "{:<8}{:>8}{:>8}\n{}{}{}{:<8}{:>8}{:>8}\n",
+            "Pins",
+            "before",
+            "after",
+            row("SHA", |counts| counts.sha),
+            row("tag", |counts| counts.tag),
+            row("branch", |counts| counts.branch),
+            "pinned",
+            percent(&self.pins.before),
+            percent(&self.pins.after)
+        )
+    }
+
+    // Write the summary as JSON to <path>, or to stdout when <path> is "-"
+    pub fn write(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let json = serde_json::to_string_pretty(self)?;
+        if path == "-" {
+            println!("{}", json);
+        } else {
+            fs::write(path, json + "\n")?;
+        }
+        Ok(())
+    }
+
+    // Repositories whose clone was left in --clone-dir
+    pub fn kept_clones(&self) -> Vec<(&str, &KeptClone)> {
+        self.repositories
+            .iter()
+            .filter_map(|entry| Some((entry.repository.as_str(), entry.kept_clone.as_ref()?)))
+            .collect()
+    }
+
+    // 0 when no repository failed, skipped and deferred ones included
+    pub fn exit_code(&self) -> i32 {
+        match self.count(RepoStatus::Failed) {
+            0 => 0,
+            failed if failed == self.repositories.len() => EXIT_FAILED,
+            _ => EXIT_SOME_FAILED,
+        }
+    }
+
+    // Dry-run repositories that would have gotten a commit
+    pub fn pending_changes(&self) -> Vec<&RepoSummary> {
+        self.repositories
+            .iter()
+            .filter(|entry| entry.status == RepoStatus::DryRun && entry.files_changed > 0)
+            .collect()
+    }
+
+    // --exit-code-on-changes: EXIT_CHANGES_PENDING when a dry run found changes
+    pub fn changes_exit_code(&self) -> i32 {
+        if self.pending_changes().is_empty() {
+            0
+        } else {
+            EXIT_CHANGES_PENDING
+        }
+    }
+
+    // What to do about the GitHub API failures of the run, a line per category. Other
+    // failures are already explained by their error.
+    pub fn error_guidance(&self, github_url: &str) -> Vec<String> {
+        let failed = |category| -> Vec<&str> {
+            self.repositories
+                .iter()
+                .filter(|entry| entry.error_category == Some(category))
+                .map(|entry| entry.repository.as_str())
+                .collect()
+        };
+        let mut guidance = Vec::new();
+        let repositories = failed(ErrorCategory::Unauthorized);
+        if !repositories.is_empty() {
+            guidance.push(format!(
+                "{} repositories failed because GitHub rejected the token, check that it is valid and has not expired",
+                repositories.len()
+            ));
+        }
+        let mut organizations: Vec<&str> = failed(ErrorCategory::SsoRequired)
+            .into_iter()
+            .map(|repository| repository.split('/').next().unwrap_or(repository))
+            .collect();
+        organizations.sort();
+        organizations.dedup();
+        for organization in organizations {
+            guidance.push(format!(
+                "The token is not authorized for the SAML single sign-on of {}, authorize the token for SSO at {}/orgs/{}/sso",
+                organization,
+                github_url.trim_end_matches('/'),
+                organization
+            ));
+        }
+        let repositories = failed(ErrorCategory::Forbidden);
+        if !repositories.is_empty() {
+            guidance.push(format!(
+                "The token lacks permissions on {}, it needs read and write access to contents and pull requests",
+                repositories.join(", ")
+            ));
+        }
+        let repositories = failed(ErrorCategory::NotFound);
+        if !repositories.is_empty() {
+            guidance.push(format!(
+                "{} not found, check the repository list and that the token can access them",
+                repositories.join(", ")
+            ));
+        }
+        let repositories = failed(ErrorCategory::RateLimited);
+        if !repositories.is_empty() {
+            guidance.push(format!(
+                "{} repositories hit the GitHub rate limit, rerun them once it resets",
+                repositories.len()
+            ));
+        }
+        guidance
+    }
+
+    fn count(&self, status: RepoStatus) -> usize {
+        self.repositories
+            .iter()
+            .filter(|entry| entry.status == status)
+            .count()
+    }
+
+    // Markdown digest of the run for --changes-output. Meant to be archived and
+    // diffed between runs, so it only depends on the outcomes (no timestamps) and
+    // lists repositories in input order.
+    pub fn render_changes(&self) -> String {
+        // Deferred repositories were not pushed, their pins do not count
+        let actions_pinned: usize = self
+            .repositories
+            .iter()
+            .filter(|entry| matches!(entry.status, RepoStatus::Created | RepoStatus::Updated))
+            .map(|entry| entry.actions_pinned)
+            .sum();
+        let mut changes = format!(
+            "# ratchet-dispatcher changes\n\n- Repositories scanned: {}\n- Pull requests opened: {}\n- Pull requests updated: {}\n- Actions pinned: {}\n- Failures: {}\n",
+            self.repositories.len(),
+            self.count(RepoStatus::Created),
+            self.count(RepoStatus::Updated),
+            actions_pinned,
+            self.count(RepoStatus::Failed),
+        );
+
+        for entry in &self.repositories {
+            changes.push_str(&format!(
+                "\n## {}\n\n{}\n",
+                entry.repository,
+                describe(entry)
+            ));
+        }
+
+        // Action sources are listed once, with every repository that uses them
+        let mut sources: Vec<(&SourceIssue, Vec<&str>)> = Vec::new();
+        for entry in &self.repositories {
+            for issue in &entry.action_sources {
+                match sources.iter_mut().find(|(known, _)| *known == issue) {
+                    Some((_, repositories)) => repositories.push(&entry.repository),
+                    None => sources.push((issue, vec![&entry.repository])),
+                }
+            }
+        }
+        if !sources.is_empty() {
+            changes.push_str("\n## Action sources\n\n");
+            for (issue, repositories) in sources {
+                changes.push_str(&format!(
+                    "- {} (used by {})\n",
+                    issue.describe(),
+                    repositories.join(", ")
+                ));
+            }
+        }
+
+        let problems: Vec<&RepoSummary> = self
+            .repositories
+            .iter()
+            .filter(|entry| matches!(entry.status, RepoStatus::Failed | RepoStatus::Deferred))
+            .collect();
+        if !problems.is_empty() {
+            changes.push_str("\n## Failures and deferred repositories\n\n");
+            for entry in problems {
+                let reason = match entry.status {
+                    RepoStatus::Failed => format!(
+                        "failed: {}",
+                        entry.error.as_deref().unwrap_or("unknown error")
+                    ),
+                    _ => entry
+                        .reason
+                        .clone()
+                        .unwrap_or_else(|| String::from("deferred")),
+                };
+                changes.push_str(&format!("- {}: {}\n", entry.repository, reason));
+            }
+        }
+        changes
+    }
+
+    // Write the markdown digest to <path>, or to stdout when <path> is "-"
+    pub fn write_changes(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let changes = self.render_changes();
+        if path == "-" {
+            print!("{}", changes);
+        } else {
+            fs::write(path, changes)?;
+        }
+        Ok(())
+    }
+}
+
+// One line description of a repository for the changes digest
+fn describe(entry: &RepoSummary) -> String {
+    let pinned = format!(
+        "pinned {} across {}",
+        plural(entry.actions_pinned, "action"),
+        plural(entry.files_changed, "workflow")
+    );
+    let churn = match entry.comment_only_changes {
+        0 => String::new(),
+        count => format!(" ({} skipped)", plural(count, "comment-only change")),
+    };
+    let pr = match (entry.pr_number, &entry.pr_url) {
+        (Some(number), Some(url)) => format!("[#{}]({})", number, url),
+        _ => String::new(),
+    };
+    match entry.status {
+        RepoStatus::Created => format!("Opened {}: {}{}", pr, pinned, churn),
+        RepoStatus::Updated => format!("Updated {}: {}{}", pr, pinned, churn),
+        RepoStatus::Skipped => format!("No workflow changes{}", churn),
+        RepoStatus::Reported => String::from("Reported unpinned actions"),
+        RepoStatus::DryRun => match &entry.reason {
+            Some(reason) => format!("Dry run, {}", reason),
+            None => format!("Dry run, would have {}{}", pinned, churn),
+        },
+        RepoStatus::Pruned => format!(
+            "Pruned, {}",
+            entry.reason.as_deref().unwrap_or("deleted branch")
+        ),
+        RepoStatus::Committed => format!("Committed locally, {}{}", pinned, churn),
+        RepoStatus::Deferred => String::from("Deferred, see below"),
+        RepoStatus::Failed => String::from("Failed, see below"),
+    }
+}
+
+fn plural(count: usize, noun: &str) -> String {
+    if count == 1 {
+        format!("{} {}", count, noun)
+    } else {
+        format!("{} {}s", count, noun)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{GitHubError, SourceStatus};
+    use tempfile::tempdir;
+
+    const CHANGES_SNAPSHOT: &str = include_str!("../resources/changes_snapshot.md");
+
+    #[test]
+    fn test_write_summary_json() {
+        let mut summary = RunSummary::default();
+        summary.record(
+            RepoSummary::new("org/a", RepoStatus::Created)
+                .with_pr(&PullRequestRef {
+                    number: 12,
+                    html_url: String::from("https://github.com/org/a/pull/12"),
+                    draft: false,
+                })
+                .with_files_changed(2),
+        );
+        summary.record(RepoSummary::new("org/b", RepoStatus::Skipped));
+        summary.record(RepoSummary::failed(
+            "org/c",
+            &*Box::<dyn Error>::from("Failed to clone repository"),
+        ));
+        summary.ratchet_version = Some(String::from("0.10.2"));
+
+        let dir = tempdir().unwrap();
+        let path = dir.path().join("summary.json");
+        summary.write(path.to_str().unwrap()).unwrap();
+
+        let json: serde_json::Value =
+            serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "repositories": [
+                    {
+                        "repository": "org/a",
+                        "status": "created",
+                        "pr_number": 12,
+                        "pr_url": "https://github.com/org/a/pull/12",
+                        "files_changed": 2,
+                        "actions_pinned": 0,
+                        "comment_only_changes": 0
+                    },
+                    {
+                        "repository": "org/b",
+                        "status": "skipped",
+                        "files_changed": 0,
+                        "actions_pinned": 0,
+                        "comment_only_changes": 0
+                    },
+                    {
+                        "repository": "org/c",
+                        "status": "failed",
+                        "files_changed": 0,
+                        "actions_pinned": 0,
+                        "comment_only_changes": 0,
+                        "error": "Failed to clone repository"
+                    }
+                ],
+                "ratchet_version": "0.10.2"
+            })
+        );
+    }
+
+    #[test]
+    fn test_exit_code() {
+        let error = Box::<dyn Error>::from("Failed to clone repository");
+        let mut summary = RunSummary::default();
+        assert_eq!(summary.exit_code(), 0);
+        summary.record(RepoSummary::new("org/a", RepoStatus::Created));
+        summary.record(RepoSummary::new("org/b", RepoStatus::Skipped));
+        summary.record(RepoSummary::new("org/c", RepoStatus::Deferred));
+        assert_eq!(summary.exit_code(), 0);
+        summary.record(RepoSummary::failed("org/d", &*error));
+        assert_eq!(summary.exit_code(), EXIT_SOME_FAILED);
+
+        let mut failed = RunSummary::default();
+        failed.record(RepoSummary::failed("org/a", &*error));
+        failed.record(RepoSummary::failed("org/b", &*error));
+        assert_eq!(failed.exit_code(), EXIT_FAILED);
+    }
+
+    #[test]
+    fn test_error_guidance() {
+        let mut summary = RunSummary::default();
+        for (repository, error) in [
+            (
+                "org/a",
+                GitHubError::Unauthorized(String::from("Bad credentials (401)")),
+            ),
+            (
+                "sso/a",
+                GitHubError::Forbidden {
+                    message: String::from("Resource protected by organization SAML enforcement"),
+                    sso: true,
+                },
+            ),
+            (
+                "sso/b",
+                GitHubError::Forbidden {
+                    message: String::from("Resource protected by organization SAML enforcement"),
+                    sso: true,
+                },
+            ),
+            (
+                "org/b",
+                GitHubError::Forbidden {
+                    message: String::from("Resource not accessible by integration (403)"),
+                    sso: false,
+                },
+            ),
+            (
+                "org/c",
+                GitHubError::NotFound(String::from("Not Found (404)")),
+            ),
+        ] {
+            summary.record(RepoSummary::failed(repository, &error));
+        }
+        summary.record(RepoSummary::failed(
+            "org/d",
+            &*Box::<dyn Error>::from("Failed to clone repository"),
+        ));
+        assert_eq!(
+            summary.repositories[0].error_category,
+            Some(ErrorCategory::Unauthorized)
+        );
+        assert_eq!(
+            summary.repositories[1].error_category,
+            Some(ErrorCategory::SsoRequired)
+        );
+        assert_eq!(summary.repositories[5].error_category, None);
+        assert_eq!(
+            summary.error_guidance("https://github.com/"),
+            [
+                "1 repositories failed because GitHub rejected the token, check that it is valid and has not expired",
+                "The token is not authorized for the SAML single sign-on of sso, authorize the token for SSO at https://github.com/orgs/sso/sso",
+                "The token lacks permissions on org/b, it needs read and write access to contents and pull requests",
+                "org/c not found, check the repository list and that the token can access them",
+            ]
+        );
+        assert!(RunSummary::default()
+            .error_guidance("https://github.com")
+            .is_empty());
+    }
+
+    #[test]
+    fn test_changes_exit_code() {
+        let mut summary = RunSummary::default();
+        summary.record(RepoSummary::new("org/a", RepoStatus::Skipped));
+        summary.record(RepoSummary::new("org/b", RepoStatus::DryRun));
+        assert_eq!(summary.changes_exit_code(), 0);
+        summary.record(
+            RepoSummary::new("org/c", RepoStatus::DryRun)
+                .with_files_changed(1)
+                .with_changed_files(vec![String::from(".github/workflows/ci.yml")]),
+        );
+        assert_eq!(summary.changes_exit_code(), EXIT_CHANGES_PENDING);
+        let pending: Vec<_> = summary
+            .pending_changes()
+            .iter()
+            .map(|entry| entry.repository.as_str())
+            .collect();
+        assert_eq!(pending, ["org/c"]);
+    }
+
+    #[test]
+    fn test_pin_totals() {
+        let stats = |sha, tag, branch| PinCounts { sha, tag, branch };
+        let mut summary = RunSummary::default();
+        summary.record(
+            RepoSummary::new("org/a", RepoStatus::Created).with_pins(PinStats {
+                before: stats(1, 2, 1),
+                after: stats(3, 0, 1),
+            }),
+        );
+        summary.record(
+            RepoSummary::new("org/b", RepoStatus::Skipped)
+                .with_pins(PinStats::unchanged(stats(4, 0, 0))),
+        );
+        summary.record(RepoSummary::new("org/c", RepoStatus::Failed));
+
+        assert_eq!(summary.pins.before, stats(5, 2, 1));
+        assert_eq!(summary.pins.after, stats(7, 0, 1));
+        assert_eq!(
+            summary.render_pin_table(),
+            "Pins      before   after\nSHA            5       7\ntag            2       0\nbranch         1       1\npinned     62.5%   87.5%\n"
+        );
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(
+            json["pins"],
+            serde_json::json!({
+                "before": {"sha": 5, "tag": 2, "branch": 1},
+                "after": {"sha": 7, "tag": 0, "branch": 1}
+            })
+        );
+        assert_eq!(json["repositories"][1]["pins"]["after"]["sha"], 4);
+        assert!(json["repositories"][2].get("pins").is_none());
+    }
+
+    #[test]
+    fn test_render_changes_snapshot() {
+        let pr = |repository: &str, number: u64| PullRequestRef {
+            number,
+            html_url: format!("https://github.com/{}/pull/{}", repository, number),
+            draft: false,
+        };
+        let mut summary = RunSummary::default();
+        summary.record(
+            RepoSummary::new("org/a", RepoStatus::Created)
+                .with_pr(&pr("org/a", 12))
+                .with_files_changed(3)
+                .with_actions_pinned(5),
+        );
+        summary.record(
+            RepoSummary::new("org/b", RepoStatus::Updated)
+                .with_pr(&pr("org/b", 7))
+                .with_files_changed(1)
+                .with_actions_pinned(1),
+        );
+        summary.record(RepoSummary::new("org/c", RepoStatus::Skipped));
+        summary.record(
+            RepoSummary::new("org/d", RepoStatus::Deferred)
+                .with_reason("deferred: PR budget exhausted")
+                .with_files_changed(2)
+                .with_actions_pinned(4),
+        );
+        summary.record(RepoSummary::failed(
+            "org/e",
+            &*Box::<dyn Error>::from("Failed to clone repository"),
+        ));
+
+        assert_eq!(summary.render_changes(), CHANGES_SNAPSHOT);
+    }
+
+    #[test]
+    fn test_render_changes_lists_action_sources() {
+        let archived = SourceIssue {
+            action: String::from("actions/foo"),
+            status: SourceStatus::Archived,
+        };
+        let moved = SourceIssue {
+            action: String::from("old/bar"),
+            status: SourceStatus::Moved {
+                moved_to: String::from("new/bar"),
+            },
+        };
+        let mut summary = RunSummary::default();
+        summary.record(
+            RepoSummary::new("org/a", RepoStatus::Skipped)
+                .with_source_issues(&[archived.clone(), moved.clone()]),
+        );
+        summary
+            .record(RepoSummary::new("org/b", RepoStatus::Skipped).with_source_issues(&[archived]));
+
+        let changes = summary.render_changes();
+        assert!(changes.ends_with(
+            "\n## Action sources\n\n- ⚠️ `actions/foo` is archived — consider a maintained fork (used by org/a, org/b)\n- `old/bar` moved to `new/bar` — plan a follow-up to update the reference (used by org/a)\n"
+        ));
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(
+            json["repositories"][0]["action_sources"][1],
+            serde_json::json!({"action": "old/bar", "status": "moved", "moved_to": "new/bar"})
+        );
+    }
+}
@@ -0,0 +1,658 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+use log::{debug, warn};
+use serde::Serialize;
+
+use crate::{
+    github::{SourceStatus, TagLookup},
+    report::PinnedAction,
+    scan::ActionUse,
+    workflow::{ActionRef, RefKind},
+};
+
+// Number of most recent tags compared against a pinned SHA before giving up
+const MAX_COMPARED_TAGS: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinStatus {
+    // The pinned SHA is the commit of a tag
+    Tagged,
+    // The pinned SHA is an ancestor of a tag
+    ReachableFromTag,
+    // The pinned SHA is not part of any (recent) tag: a floating commit pin
+    Floating,
+    // The action repository could not be read, nothing was checked
+    Inaccessible,
+}
+
+// --verify-pins: what a pin that does not match the tag in its ratchet comment does
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum PinVerification {
+    // The repository fails and nothing is pushed
+    Fail,
+    // The mismatch is listed in the PR body and the run summary
+    Warn,
+}
+
+// A new pin whose SHA is neither the commit of the tag in its ratchet comment nor an
+// ancestor of it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TagMismatch {
+    pub file: String,
+    pub action: String,
+    pub tag: String,
+    pub sha: String,
+    // Commit the tag points to
+    pub tag_sha: String,
+}
+
+impl TagMismatch {
+    pub fn describe(&self) -> String {
+        format!(
+            "`{}@{}` in `{}` is not `{}`, which points to `{}`",
+            self.action, self.sha, self.file, self.tag, self.tag_sha
+        )
+    }
+}
+
+// Pins that do not match their tag, and the pins that could not be checked
+// because a lookup failed
+#[derive(Debug, Default)]
+pub struct TagVerification {
+    pub mismatches: Vec<TagMismatch>,
+    pub errors: Vec<String>,
+}
+
+// Verifies pinned actions against the tags of their repositories.
+// Results are cached per action repository and SHA so the same pin
+// found in several files or repositories is only checked once, and
+// tags are resolved once per action repository.
+pub struct PinVerifier<'a> {
+    lookup: &'a (dyn TagLookup + Sync),
+    cache: HashMap<String, PinStatus>,
+    tags: HashMap<String, Option<String>>,
+}
+
+impl<'a> PinVerifier<'a> {
+    pub fn new(lookup: &'a (dyn TagLookup + Sync)) -> Self {
+        PinVerifier {
+            lookup,
+            cache: HashMap::new(),
+            tags: HashMap::new(),
+        }
+    }
+
+    // Check that the new SHA of every <pinned> action is the commit of the tag kept
+    // in its ratchet comment, or an ancestor of it. Pins without a tag and tags that
+    // can not be resolved are not checked. A failed lookup is recorded and the
+    // other pins are still checked.
+    pub async fn find_tag_mismatches(&mut self, pinned: &[PinnedAction]) -> TagVerification {
+        let mut verification = TagVerification::default();
+        for action in pinned {
+            let (Some(tag), Some(action_ref)) = (&action.tag, ActionRef::parse(&action.action))
+            else {
+                continue;
+            };
+            if RefKind::of(&format!("{}@{}", action.action, action.new)) != RefKind::Sha {
+                continue;
+            }
+            let (owner, repo) = (action_ref.owner, action_ref.name);
+            let key = format!("{}/{}@{}", owner, repo, tag).to_lowercase();
+            let tag_sha = match self.tags.get(&key) {
+                Some(tag_sha) => tag_sha.clone(),
+                None => {
+                    let tag_sha = match self.lookup.resolve_ref(owner, repo, tag).await {
+                        Ok(tag_sha) => tag_sha,
+                        Err(e) => {
+                            verification.errors.push(format!(
+                                "{}@{} in {}: {}",
+                                action.action, tag, action.file, e
+                            ));
+                            continue;
+                        }
+                    };
+                    debug!("Tag {} resolved to {:?}", key, tag_sha);
+                    self.tags.insert(key, tag_sha.clone());
+                    tag_sha
+                }
+            };
+            let Some(tag_sha) = tag_sha else {
+                warn!(
+                    "Could not resolve {}@{}, not verifying its pin in {}",
+                    action.action, tag, action.file
+                );
+                continue;
+            };
+            if tag_sha.eq_ignore_ascii_case(&action.new) {
+                continue;
+            }
+            match self
+                .lookup
+                .is_reachable_from(owner, repo, &action.new, tag)
+                .await
+            {
+                Ok(true) => continue,
+                Ok(false) => {}
+                Err(e) => {
+                    verification.errors.push(format!(
+                        "{}@{} in {}: {}",
+                        action.action, action.new, action.file, e
+                    ));
+                    continue;
+                }
+            }
+            let mismatch = TagMismatch {
+                file: action.file.clone(),
+                action: action.action.clone(),
+                tag: tag.clone(),
+                sha: action.new.clone(),
+                tag_sha,
+            };
+            warn!("pin does not match its tag: {}", mismatch.describe());
+            verification.mismatches.push(mismatch);
+        }
+        verification
+    }
+
+    pub async fn verify(
+        &mut self,
+        action: &ActionUse,
+    ) -> Result<PinStatus, Box<dyn std::error::Error>> {
+        let (owner, repo, sha) = action
+            .parts()
+            .ok_or_else(|| format!("{} is not a pinned action", action.uses))?;
+        let key = format!("{}/{}@{}", owner, repo, sha);
+        if let Some(status) = self.cache.get(&key) {
+            return Ok(*status);
+        }
+
+        let status = match self.lookup.list_tags(owner, repo).await? {
+            None => PinStatus::Inaccessible,
+            Some(tags) if tags.iter().any(|tag| tag.sha == sha) => PinStatus::Tagged,
+            Some(tags) => {
+                let mut status = PinStatus::Floating;
+                for tag in tags.iter().take(MAX_COMPARED_TAGS) {
+                    if self
+                        .lookup
+                        .is_reachable_from(owner, repo, sha, &tag.name)
+                        .await?
+                    {
+                        status = PinStatus::ReachableFromTag;
+                        break;
+                    }
+                }
+                status
+            }
+        };
+
+        debug!("Pin {} verified as {:?}", key, status);
+        self.cache.insert(key, status);
+        Ok(status)
+    }
+
+    // Verify all <actions> and return the ones that are floating commit pins
+    pub async fn find_floating_pins(
+        &mut self,
+        actions: &[ActionUse],
+    ) -> Result<Vec<ActionUse>, Box<dyn std::error::Error>> {
+        let mut floating = Vec::new();
+        for action in actions {
+            if self.verify(action).await? == PinStatus::Floating {
+                warn!(
+                    "floating commit pin: {} in {}:{} is not reachable from any tag",
+                    action.uses, action.file, action.line
+                );
+                floating.push(action.clone());
+            }
+        }
+        Ok(floating)
+    }
+}
+
+// Section appended to the PR body listing the floating commit pins
+pub fn render_floating_pins(floating: &[ActionUse]) -> String {
+    if floating.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n### :warning: Floating commit pins\n\nThe following actions are pinned to a commit that is not reachable from any tag of the action repository. Please verify these pins.\n\n",
+    );
+    for action in floating {
+        section.push_str(&format!(
+            "- `{}` in `{}` line {}\n",
+            action.uses, action.file, action.line
+        ));
+    }
+    section
+}
+
+// Section appended to the PR body listing the pins that do not match their tag
+pub fn render_tag_mismatches(mismatches: &[TagMismatch]) -> String {
+    if mismatches.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from(
+        "\n\n### :warning: Pins that do not match their tag\n\nThe following pins are not the commit of the tag in their ratchet comment, nor an ancestor of it. Please verify these pins.\n\n",
+    );
+    for mismatch in mismatches {
+        section.push_str(&format!("- {}\n", mismatch.describe()));
+    }
+    section
+}
+
+// Action whose source repository is archived, missing or moved
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SourceIssue {
+    // owner/repo of the action
+    pub action: String,
+    #[serde(flatten)]
+    pub status: SourceStatus,
+}
+
+impl SourceIssue {
+    // One line description, used in the PR body and the changes digest
+    pub fn describe(&self) -> String {
+        match &self.status {
+            SourceStatus::Archived => format!(
+                "⚠️ `{}` is archived — consider a maintained fork",
+                self.action
+            ),
+            SourceStatus::Missing => format!(
+                "⚠️ `{}` no longer exists or is not accessible — workflows cannot resolve it",
+                self.action
+            ),
+            SourceStatus::Moved { moved_to } => format!(
+                "`{}` moved to `{}` — plan a follow-up to update the reference",
+                self.action, moved_to
+            ),
+            SourceStatus::Active => format!("`{}` is active", self.action),
+        }
+    }
+}
+
+// Checks the repositories that actions are loaded from for --verify-sources.
+// Every action repository is looked up once per run.
+pub struct SourceVerifier<'a> {
+    lookup: &'a (dyn TagLookup + Sync),
+    cache: HashMap<String, SourceStatus>,
+}
+
+impl<'a> SourceVerifier<'a> {
+    pub fn new(lookup: &'a (dyn TagLookup + Sync)) -> Self {
+        SourceVerifier {
+            lookup,
+            cache: HashMap::new(),
+        }
+    }
+
+    // Check the repositories of <actions>, return the ones that are not active
+    // in the order they are first used
+    pub async fn find_source_issues(
+        &mut self,
+        actions: &[ActionUse],
+    ) -> Result<Vec<SourceIssue>, Box<dyn std::error::Error>> {
+        let mut issues: Vec<SourceIssue> = Vec::new();
+        for action in actions {
+            let Some(action_ref) = ActionRef::parse(&action.uses) else {
+                continue;
+            };
+            let name = format!("{}/{}", action_ref.owner, action_ref.name);
+            let key = name.to_lowercase();
+            let status = match self.cache.get(&key) {
+                Some(status) => status.clone(),
+                None => {
+                    let status = self
+                        .lookup
+                        .repository_status(action_ref.owner, action_ref.name)
+                        .await?;
+                    debug!("Action source {} is {:?}", name, status);
+                    self.cache.insert(key, status.clone());
+                    status
+                }
+            };
+            if status == SourceStatus::Active
+                || issues
+                    .iter()
+                    .any(|issue| issue.action.eq_ignore_ascii_case(&name))
+            {
+                continue;
+            }
+            let issue = SourceIssue {
+                action: name,
+                status,
+            };
+            warn!("action source: {}", issue.describe());
+            issues.push(issue);
+        }
+        Ok(issues)
+    }
+}
+
+// Section appended to the PR body listing the action sources that need attention
+pub fn render_source_issues(issues: &[SourceIssue]) -> String {
+    if issues.is_empty() {
+        return String::new();
+    }
+    let mut section = String::from("\n\n### :warning: Action sources\n\n");
+    for issue in issues {
+        section.push_str(&format!("- {}\n", issue.describe()));
+    }
+    section
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::github::{MockTagLookup, TagRef};
+    use mockall::predicate::*;
+
+    const SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+
+    fn pinned() -> ActionUse {
+        ActionUse {
+            file: ".github/workflows/ci.yml".to_string(),
+            line: 17,
+            uses: format!("actions/checkout@{}", SHA),
+            excluded: false,
+        }
+    }
+
+    fn tags(sha: &str) -> Vec<TagRef> {
+        vec![TagRef {
+            name: "v3".to_string(),
+            sha: sha.to_string(),
+        }]
+    }
+
+    #[tokio::test]
+    async fn test_verify_tagged_pin() {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_list_tags()
+            .with(eq("actions"), eq("checkout"))
+            .times(1)
+            .returning(|_, _| Ok(Some(tags(SHA))));
+
+        let mut verifier = PinVerifier::new(&lookup);
+        assert_eq!(verifier.verify(&pinned()).await.unwrap(), PinStatus::Tagged);
+        // Second lookup is served from the cache
+        assert_eq!(verifier.verify(&pinned()).await.unwrap(), PinStatus::Tagged);
+    }
+
+    #[tokio::test]
+    async fn test_verify_reachable_pin() {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_list_tags()
+            .returning(|_, _| Ok(Some(tags("0000000000000000000000000000000000000000"))));
+        lookup
+            .expect_is_reachable_from()
+            .with(eq("actions"), eq("checkout"), eq(SHA), eq("v3"))
+            .times(1)
+            .returning(|_, _, _, _| Ok(true));
+
+        let mut verifier = PinVerifier::new(&lookup);
+        assert_eq!(
+            verifier.verify(&pinned()).await.unwrap(),
+            PinStatus::ReachableFromTag
+        );
+    }
+
+    #[tokio::test]
+    async fn test_verify_floating_pin() {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_list_tags()
+            .times(1)
+            .returning(|_, _| Ok(Some(tags("0000000000000000000000000000000000000000"))));
+        lookup
+            .expect_is_reachable_from()
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+
+        let mut verifier = PinVerifier::new(&lookup);
+        let floating = verifier
+            .find_floating_pins(&[pinned(), pinned()])
+            .await
+            .unwrap();
+        assert_eq!(floating.len(), 2);
+        assert!(render_floating_pins(&floating).contains("Floating commit pins"));
+    }
+
+    #[tokio::test]
+    async fn test_verify_inaccessible_repository() {
+        let mut lookup = MockTagLookup::new();
+        lookup.expect_list_tags().returning(|_, _| Ok(None));
+        lookup.expect_is_reachable_from().never();
+
+        let mut verifier = PinVerifier::new(&lookup);
+        assert_eq!(
+            verifier.verify(&pinned()).await.unwrap(),
+            PinStatus::Inaccessible
+        );
+        assert!(verifier
+            .find_floating_pins(&[pinned()])
+            .await
+            .unwrap()
+            .is_empty());
+    }
+
+    fn pinned_action(file: &str, action: &str, tag: Option<&str>) -> PinnedAction {
+        PinnedAction {
+            file: file.to_string(),
+            action: action.to_string(),
+            old: String::from("v3"),
+            new: SHA.to_string(),
+            tag: tag.map(String::from),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_tag_mismatches() {
+        let mut lookup = MockTagLookup::new();
+        // Each action and tag is resolved once, whatever the number of files using it
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("checkout"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Ok(Some(SHA.to_string())));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("github"), eq("codeql-action"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Ok(Some("0".repeat(40))));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("setup-node"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Ok(Some("1".repeat(40))));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("cache"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Ok(None));
+        // An ancestor of the tag is accepted
+        lookup
+            .expect_is_reachable_from()
+            .with(eq("actions"), eq("setup-node"), eq(SHA), eq("v3"))
+            .returning(|_, _, _, _| Ok(true));
+        lookup
+            .expect_is_reachable_from()
+            .with(eq("github"), eq("codeql-action"), eq(SHA), eq("v3"))
+            .times(1)
+            .returning(|_, _, _, _| Ok(false));
+
+        let mut verifier = PinVerifier::new(&lookup);
+        let verification = verifier
+            .find_tag_mismatches(&[
+                pinned_action("ci.yml", "actions/checkout", Some("v3")),
+                pinned_action("release.yml", "actions/checkout", Some("v3")),
+                pinned_action("ci.yml", "actions/setup-node", Some("v3")),
+                pinned_action("ci.yml", "github/codeql-action/init", Some("v3")),
+                pinned_action("ci.yml", "actions/cache", Some("v3")),
+                pinned_action("ci.yml", "actions/upload-artifact", None),
+            ])
+            .await;
+        assert!(verification.errors.is_empty());
+        let mismatches = verification.mismatches;
+        assert_eq!(
+            mismatches,
+            [TagMismatch {
+                file: String::from("ci.yml"),
+                action: String::from("github/codeql-action/init"),
+                tag: String::from("v3"),
+                sha: SHA.to_string(),
+                tag_sha: "0".repeat(40),
+            }]
+        );
+        assert_eq!(
+            render_tag_mismatches(&mismatches),
+            format!("\n\n### :warning: Pins that do not match their tag\n\nThe following pins are not the commit of the tag in their ratchet comment, nor an ancestor of it. Please verify these pins.\n\n- `github/codeql-action/init@{}` in `ci.yml` is not `v3`, which points to `{}`\n", SHA, "0".repeat(40))
+        );
+        assert!(render_tag_mismatches(&[]).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_failed_lookup_does_not_stop_tag_verification() {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("checkout"), eq("v3"))
+            .times(1)
+            .returning(|_, _, _| Err(Box::from("API rate limit exceeded")));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("cache"), eq("v3"))
+            .returning(|_, _, _| Ok(Some("0".repeat(40))));
+        lookup
+            .expect_resolve_ref()
+            .with(eq("actions"), eq("setup-node"), eq("v3"))
+            .returning(|_, _, _| Ok(Some("1".repeat(40))));
+        lookup
+            .expect_is_reachable_from()
+            .with(eq("actions"), eq("cache"), eq(SHA), eq("v3"))
+            .returning(|_, _, _, _| Ok(false));
+        lookup
+            .expect_is_reachable_from()
+            .with(eq("actions"), eq("setup-node"), eq(SHA), eq("v3"))
+            .returning(|_, _, _, _| Err(Box::from("Server error")));
+
+        let mut verifier = PinVerifier::new(&lookup);
+        let verification = verifier
+            .find_tag_mismatches(&[
+                pinned_action("ci.yml", "actions/checkout", Some("v3")),
+                pinned_action("ci.yml", "actions/setup-node", Some("v3")),
+                pinned_action("ci.yml", "actions/cache", Some("v3")),
+            ])
+            .await;
+        assert_eq!(
+            verification.errors,
+            [
+                String::from("actions/checkout@v3 in ci.yml: API rate limit exceeded"),
+                format!("actions/setup-node@{} in ci.yml: Server error", SHA),
+            ]
+        );
+        // The pins after the failed lookups are still checked
+        assert_eq!(verification.mismatches.len(), 1);
+        assert_eq!(verification.mismatches[0].action, "actions/cache");
+    }
+
+    fn action(uses: &str) -> ActionUse {
+        ActionUse {
+            file: ".github/workflows/ci.yml".to_string(),
+            line: 3,
+            uses: uses.to_string(),
+            excluded: false,
+        }
+    }
+
+    fn source_lookup(status: SourceStatus) -> MockTagLookup {
+        let mut lookup = MockTagLookup::new();
+        lookup
+            .expect_repository_status()
+            .with(eq("actions"), eq("foo"))
+            .times(1)
+            .returning(move |_, _| Ok(status.clone()));
+        lookup
+    }
+
+    #[tokio::test]
+    async fn test_existing_source() {
+        let lookup = source_lookup(SourceStatus::Active);
+        let mut verifier = SourceVerifier::new(&lookup);
+        let issues = verifier
+            .find_source_issues(&[
+                action("actions/foo@v1"),
+                action(&format!("actions/foo@{}", SHA)),
+            ])
+            .await
+            .unwrap();
+        assert!(issues.is_empty());
+        assert!(render_source_issues(&issues).is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_archived_source() {
+        let lookup = source_lookup(SourceStatus::Archived);
+        let mut verifier = SourceVerifier::new(&lookup);
+        let issues = verifier
+            .find_source_issues(&[action("actions/foo@v1"), action("actions/foo/sub@v1")])
+            .await
+            .unwrap();
+        assert_eq!(
+            issues,
+            vec![SourceIssue {
+                action: String::from("actions/foo"),
+                status: SourceStatus::Archived,
+            }]
+        );
+        assert_eq!(
+            render_source_issues(&issues),
+            "\n\n### :warning: Action sources\n\n- ⚠️ `actions/foo` is archived — consider a maintained fork\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deleted_source() {
+        let lookup = source_lookup(SourceStatus::Missing);
+        let mut verifier = SourceVerifier::new(&lookup);
+        let issues = verifier
+            .find_source_issues(&[action("actions/foo@v1")])
+            .await
+            .unwrap();
+        assert_eq!(issues[0].status, SourceStatus::Missing);
+        assert!(issues[0].describe().contains("no longer exists"));
+        // Later repositories of the run are served from the cache
+        let issues = verifier
+            .find_source_issues(&[action("Actions/Foo@v2")])
+            .await
+            .unwrap();
+        assert_eq!(issues.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_redirected_source() {
+        let lookup = source_lookup(SourceStatus::Moved {
+            moved_to: String::from("new-owner/foo"),
+        });
+        let mut verifier = SourceVerifier::new(&lookup);
+        let issues = verifier
+            .find_source_issues(&[
+                action("actions/foo@v1"),
+                action("./local"),
+                action("docker://alpine"),
+            ])
+            .await
+            .unwrap();
+        assert_eq!(issues.len(), 1);
+        assert_eq!(
+            issues[0].describe(),
+            "`actions/foo` moved to `new-owner/foo` — plan a follow-up to update the reference"
+        );
+        assert_eq!(
+            serde_json::to_value(&issues[0]).unwrap(),
+            serde_json::json!({"action": "actions/foo", "status": "moved", "moved_to": "new-owner/foo"})
+        );
+    }
+}
@@ -0,0 +1,894 @@
+use std::{ops::Range, path::Path};
+
+use glob::{MatchOptions, Pattern};
+use serde_yaml::Value;
+
+// Comment ratchet writes on lines it must leave alone
+const RATCHET_EXCLUDE: &str = "ratchet:exclude";
+
+// What the reference of a `uses:` points at
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RefKind {
+    // Full 40 character commit SHA
+    Sha,
+    // Version like v4, v4.1.2 or 1.0
+    Tag,
+    // Any other ref, usually a branch like main
+    Branch,
+    // docker://image:tag
+    Docker,
+    // ./path/to/action in the same repository
+    Local,
+    // Expression like ${{ matrix.action }}, only known at run time
+    Expression,
+    // Remote action without @ref
+    Missing,
+}
+
+impl RefKind {
+    // Classify the value of a `uses:` key
+    pub fn of(uses: &str) -> Self {
+        if uses.contains("${{") {
+            return RefKind::Expression;
+        }
+        if uses.starts_with("docker://") {
+            return RefKind::Docker;
+        }
+        if uses.starts_with("./") || uses.starts_with("../") {
+            return RefKind::Local;
+        }
+        match uses.rsplit_once('@') {
+            None => RefKind::Missing,
+            Some((_, reference)) if is_sha(reference) => RefKind::Sha,
+            Some((_, reference)) if is_version(reference) => RefKind::Tag,
+            Some(_) => RefKind::Branch,
+        }
+    }
+
+    // A reference is pinned when it points at a full commit SHA.
+    // Local actions and docker images are reported as pinned as they are out of scope.
+    pub fn is_pinned(self) -> bool {
+        matches!(self, RefKind::Sha | RefKind::Local | RefKind::Docker)
+    }
+}
+
+pub fn is_sha(reference: &str) -> bool {
+    reference.len() == 40 && reference.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+pub fn is_version(reference: &str) -> bool {
+    let version = reference.strip_prefix('v').unwrap_or(reference);
+    version.starts_with(|c: char| c.is_ascii_digit())
+}
+
+// Parts of a remote action reference: owner/name[/path][@reference]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ActionRef<'a> {
+    pub owner: &'a str,
+    pub name: &'a str,
+    // Sub directory of an action or reusable workflow, like init in github/codeql-action/init
+    pub path: Option<&'a str>,
+    pub reference: Option<&'a str>,
+}
+
+impl<'a> ActionRef<'a> {
+    // Returns None for local actions, docker images and expressions
+    pub fn parse(uses: &'a str) -> Option<Self> {
+        if matches!(
+            RefKind::of(uses),
+            RefKind::Local | RefKind::Docker | RefKind::Expression
+        ) {
+            return None;
+        }
+        let (action, reference) = match uses.rsplit_once('@') {
+            Some((action, reference)) => (action, Some(reference)),
+            None => (uses, None),
+        };
+        let mut segments = action.splitn(3, '/');
+        let owner = segments.next().filter(|owner| !owner.is_empty())?;
+        let name = segments.next().filter(|name| !name.is_empty())?;
+        Some(ActionRef {
+            owner,
+            name,
+            path: segments.next(),
+            reference,
+        })
+    }
+
+    // The action without its reference: owner/name[/path]
+    pub fn action(&self) -> String {
+        match self.path {
+            Some(path) => format!("{}/{}/{}", self.owner, self.name, path),
+            None => format!("{}/{}", self.owner, self.name),
+        }
+    }
+}
+
+// A workflow line with a `uses:` key, either a step or a job calling a reusable workflow.
+// The key may be anywhere in the document, like under an `x-templates:` key defining a
+// step once as a YAML anchor.
+// Spans are byte ranges into the line, which allows rewriting one component while
+// keeping indentation, quoting, the line ending and the rest of the line as they are.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UsesLine<'a> {
+    text: &'a str,
+    // End of the line without its line ending
+    end: usize,
+    // Line number in the file, starting at 1
+    pub line: usize,
+    // Bytes of leading whitespace
+    pub indent: usize,
+    // The value of the key, without quotes
+    pub span: Range<usize>,
+    pub quote: Option<char>,
+    // Position of the # starting the trailing comment
+    comment_start: Option<usize>,
+}
+
+impl<'a> UsesLine<'a> {
+    // Parse a single line, returns None when it has no `uses:` key with a value. An
+    // anchor of the key or the value is skipped, an alias like `uses: *checkout` has no
+    // value of its own: it is pinned along with the line of its anchor.
+    pub fn parse(raw: &'a str, line: usize) -> Option<Self> {
+        let text = raw.trim_end_matches(['\n', '\r']);
+        let content = text.trim_start();
+        let indent = text.len() - content.len();
+        let item = content.strip_prefix('-').map_or(content, str::trim_start);
+        let after_key = skip_anchor(item).strip_prefix("uses:")?;
+        let value = skip_anchor(after_key.trim_start());
+        if value.starts_with('*') {
+            return None;
+        }
+        let value_offset = text.len() - value.len();
+        let rest = &text[value_offset..];
+
+        let (span, quote, tail) = match rest.chars().next() {
+            Some(quote @ ('"' | '\'')) => {
+                let close = rest[1..].find(quote)? + 1;
+                (
+                    value_offset + 1..value_offset + close,
+                    Some(quote),
+                    value_offset + close + 1,
+                )
+            }
+            _ => {
+                let end = find_comment(rest).unwrap_or(rest.len());
+                let value = rest[..end].trim_end();
+                (
+                    value_offset..value_offset + value.len(),
+                    None,
+                    value_offset + value.len(),
+                )
+            }
+        };
+        if span.is_empty() {
+            return None;
+        }
+        let comment_start = find_comment(&text[tail..]).map(|index| tail + index);
+        Some(UsesLine {
+            text: raw,
+            end: text.len(),
+            line,
+            indent,
+            span,
+            quote,
+            comment_start,
+        })
+    }
+
+    // The value of the `uses:` key, like actions/checkout@v4
+    pub fn uses(&self) -> &'a str {
+        &self.text[self.span.clone()]
+    }
+
+    pub fn kind(&self) -> RefKind {
+        RefKind::of(self.uses())
+    }
+
+    // The value without its reference, the part before the @. Identifies the action
+    // when pairing two versions of a workflow.
+    pub fn action_key(&self) -> &'a str {
+        let uses = self.uses();
+        uses.rsplit_once('@').map_or(uses, |(action, _)| action)
+    }
+
+    // Pinned to something that can not move: a commit SHA, or the digest of a docker
+    // image like docker://alpine@sha256:...
+    pub fn is_immutable(&self) -> bool {
+        match self.kind() {
+            RefKind::Sha => true,
+            RefKind::Docker => self.uses().contains("@sha256:"),
+            _ => false,
+        }
+    }
+
+    pub fn action_ref(&self) -> Option<ActionRef<'a>> {
+        ActionRef::parse(self.uses())
+    }
+
+    // Text of the trailing comment without the #, trimmed
+    pub fn comment(&self) -> Option<&'a str> {
+        self.comment_start
+            .map(|start| self.text[start + 1..self.end].trim())
+            .filter(|comment| !comment.is_empty())
+    }
+
+    // The `uses:` value ratchet pinned, kept in its comment like
+    // `# ratchet:actions/checkout@v4`. Text after it, like a note, is not part of it.
+    pub fn ratchet_original(&self) -> Option<&'a str> {
+        self.comment()?
+            .strip_prefix("ratchet:")?
+            .split_whitespace()
+            .next()
+            .filter(|original| *original != "exclude")
+    }
+
+    // The line carries a `ratchet:exclude` comment
+    pub fn is_excluded(&self) -> bool {
+        self.comment()
+            .is_some_and(|comment| comment.contains(RATCHET_EXCLUDE))
+    }
+
+    // The line without its trailing comment
+    pub fn code(&self) -> &'a str {
+        match self.comment_start {
+            Some(start) => self.text[..start].trim_end(),
+            None => &self.text[..self.end],
+        }
+    }
+
+    // Render the line with its trailing comment replaced by <comment>, or without it
+    // when None. The whitespace before the # is kept, so comments aligned in a column
+    // stay aligned. Whitespace after the comment is dropped, the line ending is kept.
+    pub fn with_comment(&self, comment: Option<&str>) -> String {
+        let ending = &self.text[self.end..];
+        match (self.comment_start, comment) {
+            (Some(start), Some(comment)) => {
+                format!("{}# {}{}", &self.text[..start], comment, ending)
+            }
+            (None, Some(comment)) => format!("{} # {}{}", self.code().trim_end(), comment, ending),
+            (_, None) => format!("{}{}", self.code().trim_end(), ending),
+        }
+    }
+
+    // Render the line with <reference> after the @, everything else unchanged.
+    // A reference is appended to actions without one.
+    pub fn with_ref(&self, reference: &str) -> String {
+        let uses = self.uses();
+        let start = match uses.rfind('@') {
+            Some(index) => self.span.start + index + 1,
+            None => self.span.end,
+        };
+        let separator = if uses.contains('@') { "" } else { "@" };
+        format!(
+            "{}{}{}{}",
+            &self.text[..start],
+            separator,
+            reference,
+            &self.text[self.span.end..]
+        )
+    }
+}
+
+// <text> without a leading anchor like `&checkout_step `
+fn skip_anchor(text: &str) -> &str {
+    match text.strip_prefix('&') {
+        Some(anchor) => anchor
+            .find(char::is_whitespace)
+            .map_or("", |end| anchor[end..].trim_start()),
+        None => text,
+    }
+}
+
+// Position of a # that starts a YAML comment: at the start or after whitespace
+fn find_comment(text: &str) -> Option<usize> {
+    text.char_indices()
+        .find(|(index, c)| {
+            *c == '#' && (*index == 0 || text[..*index].ends_with(char::is_whitespace))
+        })
+        .map(|(index, _)| index)
+}
+
+// Every `uses:` line of a workflow file
+pub fn parse_workflow(content: &str) -> Vec<UsesLine<'_>> {
+    content
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| UsesLine::parse(line, index + 1))
+        .collect()
+}
+
+// Whether two versions of a workflow line only differ in the trailing comment of a
+// `uses:` reference pinned to the same SHA or image digest, like
+// `# ratchet:org/action@v4` vs `# ratchet:org/action@v4.1.2` written by different
+// ratchet versions
+pub fn is_comment_only_change(old: &str, new: &str) -> bool {
+    match (UsesLine::parse(old, 0), UsesLine::parse(new, 0)) {
+        (Some(old), Some(new)) => {
+            old.is_immutable() && old.code() == new.code() && old.comment() != new.comment()
+        }
+        _ => false,
+    }
+}
+
+// --only-actions and --exclude-actions: whether the pin of <action> (the part of a
+// `uses:` value before the @) is kept. Globs like my-org/* match regardless of letter
+// case, against the action and against the owner/name repository it lives in.
+pub fn is_action_selected(action: &str, only: &[Pattern], exclude: &[Pattern]) -> bool {
+    let options = MatchOptions {
+        case_sensitive: false,
+        ..MatchOptions::new()
+    };
+    let repository = action.splitn(3, '/').take(2).collect::<Vec<_>>().join("/");
+    let matches = |patterns: &[Pattern]| {
+        patterns.iter().any(|pattern| {
+            pattern.matches_with(action, options) || pattern.matches_with(&repository, options)
+        })
+    };
+    (only.is_empty() || matches(only)) && !matches(exclude)
+}
+
+// Kind of CI file ratchet pins, decides which references it may rewrite
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PinFileKind {
+    // GitHub Actions workflow or composite action
+    GitHubActions,
+    // .gitlab-ci.yml or a file under .gitlab
+    GitLabCi,
+    // File under .circleci
+    CircleCi,
+    // cloudbuild.yml or cloudbuild.yaml
+    CloudBuild,
+}
+
+impl PinFileKind {
+    // Classify a file by its path relative to the repository root
+    pub fn of(path: &Path) -> Self {
+        let name = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or_default();
+        let under = |dir: &str| path.components().any(|part| part.as_os_str() == dir);
+        if name.starts_with(".gitlab-ci.") || under(".gitlab") {
+            PinFileKind::GitLabCi
+        } else if under(".circleci") {
+            PinFileKind::CircleCi
+        } else if name.starts_with("cloudbuild.") {
+            PinFileKind::CloudBuild
+        } else {
+            PinFileKind::GitHubActions
+        }
+    }
+
+    // Whether ratchet may rewrite the value at <path>, the keys leading to it joined
+    // with dots like jobs.test.container.image, and everything nested below it.
+    // <value> is the value before ratchet ran.
+    pub fn is_reference_key(self, path: &str, value: &Value) -> bool {
+        let mut keys = path.rsplit('.');
+        let name = keys.next().unwrap_or_default();
+        let parent = keys.next();
+        let grandparent = keys.next();
+        match self {
+            // The image of a job container or service container is pinned to its
+            // digest like a `uses:`, so is the `container: node:20` short form
+            PinFileKind::GitHubActions => {
+                name == "uses"
+                    || (name == "image"
+                        && (parent == Some("container") || grandparent == Some("services")))
+                    || (name == "container" && value.is_string())
+            }
+            PinFileKind::GitLabCi => matches!(name, "image" | "services" | "include"),
+            PinFileKind::CircleCi => matches!(name, "image" | "orbs"),
+            PinFileKind::CloudBuild => name == "name",
+        }
+    }
+}
+
+// Check that <new> is the <kind> file <old> with at most the values of its reference
+// keys changed (`uses` and container images for a workflow), the only edit ratchet is
+// supposed to make.
+// Returns what else differs, or why <new> does not parse. An <old> that does not parse
+// itself is not checked.
+pub fn verify_only_references_changed(
+    kind: PinFileKind,
+    old: &str,
+    new: &str,
+) -> Result<(), String> {
+    let old: Value = match serde_yaml::from_str(old) {
+        Ok(old) => old,
+        Err(_) => return Ok(()),
+    };
+    let new: Value = serde_yaml::from_str(new).map_err(|e| format!("invalid YAML: {}", e))?;
+    compare_structure(&old, &new, "", kind, false)
+}
+
+fn compare_structure(
+    old: &Value,
+    new: &Value,
+    path: &str,
+    kind: PinFileKind,
+    is_reference: bool,
+) -> Result<(), String> {
+    let at = |path: &str| {
+        if path.is_empty() {
+            String::from("the document")
+        } else {
+            path.to_string()
+        }
+    };
+    match (old, new) {
+        (Value::String(_), Value::String(_)) if is_reference => Ok(()),
+        (Value::Mapping(old), Value::Mapping(new)) => {
+            for key in old.keys().chain(new.keys()) {
+                let name = match key {
+                    Value::String(name) => name.clone(),
+                    other => serde_yaml::to_string(other)
+                        .unwrap_or_default()
+                        .trim()
+                        .to_string(),
+                };
+                let child = if path.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", path, name)
+                };
+                match (old.get(key), new.get(key)) {
+                    (Some(old), Some(new)) => compare_structure(
+                        old,
+                        new,
+                        &child,
+                        kind,
+                        is_reference || kind.is_reference_key(&child, old),
+                    )?,
+                    (Some(_), None) => return Err(format!("{} was removed", child)),
+                    (None, _) => return Err(format!("{} was added", child)),
+                }
+            }
+            Ok(())
+        }
+        (Value::Sequence(old), Value::Sequence(new)) => {
+            if old.len() != new.len() {
+                return Err(format!(
+                    "{} has {} items instead of {}",
+                    at(path),
+                    new.len(),
+                    old.len()
+                ));
+            }
+            for (index, (old, new)) in old.iter().zip(new).enumerate() {
+                compare_structure(
+                    old,
+                    new,
+                    &format!("{}[{}]", path, index),
+                    kind,
+                    is_reference,
+                )?;
+            }
+            Ok(())
+        }
+        (Value::Tagged(old), Value::Tagged(new)) if old.tag == new.tag => {
+            compare_structure(&old.value, &new.value, path, kind, is_reference)
+        }
+        (old, new) if old == new => Ok(()),
+        _ => Err(format!("{} changed", at(path))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHA: &str = "f43a0e5ff2bd294095638e18286ca9a3d1956744";
+
+    fn parse(text: &str) -> UsesLine<'_> {
+        UsesLine::parse(text, 1).unwrap()
+    }
+
+    #[test]
+    fn test_ref_kinds() {
+        let cases = [
+            (format!("actions/checkout@{}", SHA), RefKind::Sha),
+            (String::from("actions/checkout@v4"), RefKind::Tag),
+            (String::from("actions/checkout@v4.1.2"), RefKind::Tag),
+            (String::from("actions/checkout@1.0"), RefKind::Tag),
+            (String::from("actions/checkout@main"), RefKind::Branch),
+            (String::from("actions/checkout@release/v4"), RefKind::Branch),
+            (String::from("actions/checkout@f43a0e5"), RefKind::Branch),
+            (String::from("docker://alpine:3.19"), RefKind::Docker),
+            (String::from("./.github/actions/local"), RefKind::Local),
+            (String::from("${{ matrix.action }}@v1"), RefKind::Expression),
+            (
+                String::from("actions/checkout@${{ inputs.ref }}"),
+                RefKind::Expression,
+            ),
+            (String::from("actions/checkout"), RefKind::Missing),
+        ];
+        for (uses, kind) in cases {
+            assert_eq!(RefKind::of(&uses), kind, "{}", uses);
+        }
+    }
+
+    #[test]
+    fn test_pinned_kinds() {
+        assert!(RefKind::Sha.is_pinned());
+        assert!(RefKind::Local.is_pinned());
+        assert!(RefKind::Docker.is_pinned());
+        for kind in [
+            RefKind::Tag,
+            RefKind::Branch,
+            RefKind::Expression,
+            RefKind::Missing,
+        ] {
+            assert!(!kind.is_pinned(), "{:?}", kind);
+        }
+    }
+
+    #[test]
+    fn test_action_ref() {
+        assert_eq!(
+            ActionRef::parse("github/codeql-action/init@v3"),
+            Some(ActionRef {
+                owner: "github",
+                name: "codeql-action",
+                path: Some("init"),
+                reference: Some("v3"),
+            })
+        );
+        let workflow = ActionRef::parse("org/shared/.github/workflows/ci.yml@main").unwrap();
+        assert_eq!(workflow.path, Some(".github/workflows/ci.yml"));
+        assert_eq!(workflow.action(), "org/shared/.github/workflows/ci.yml");
+        assert_eq!(
+            ActionRef::parse("actions/checkout").unwrap().reference,
+            None
+        );
+        assert_eq!(ActionRef::parse("./.github/actions/local"), None);
+        assert_eq!(ActionRef::parse("docker://alpine:3.19"), None);
+        assert_eq!(ActionRef::parse("${{ matrix.action }}@v1"), None);
+        assert_eq!(ActionRef::parse("checkout@v4"), None);
+    }
+
+    #[test]
+    fn test_verify_only_uses_changed() {
+        let old = "on: push\njobs:\n  build:\n    steps:\n      - uses: actions/checkout@v4\n        with:\n          fetch-depth: 0\n      - run: make\n";
+        let pinned = old.replace(
+            "checkout@v4",
+            &format!("checkout@{} # ratchet:actions/checkout@v4", SHA),
+        );
+        assert_eq!(
+            verify_only_references_changed(PinFileKind::GitHubActions, old, &pinned),
+            Ok(())
+        );
+        // Formatting and comments are not part of the structure
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                old,
+                &pinned.replace("\n      - run", "\n\n      - run")
+            ),
+            Ok(())
+        );
+
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                old,
+                &pinned.replace("fetch-depth: 0", "fetch-depth: 1")
+            ),
+            Err(String::from("jobs.build.steps[0].with.fetch-depth changed"))
+        );
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                old,
+                &pinned.replace("      - run: make\n", "")
+            ),
+            Err(String::from("jobs.build.steps has 1 items instead of 2"))
+        );
+        // Orphaned key of a broken step
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                old,
+                &pinned.replace("      - run: make", "        id: make\n      - run: make")
+            ),
+            Err(String::from("jobs.build.steps[0].id was added"))
+        );
+        let duplicate = pinned.replace(
+            "      - run: make",
+            "        with:\n          ref: main\n      - run: make",
+        );
+        assert!(
+            verify_only_references_changed(PinFileKind::GitHubActions, old, &duplicate)
+                .unwrap_err()
+                .starts_with("invalid YAML: ")
+        );
+        assert_eq!(
+            verify_only_references_changed(PinFileKind::GitHubActions, "{ broken", &pinned),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn test_verify_only_references_changed_gitlab() {
+        let old = "image: node:20\nservices:\n  - postgres:15\ntest:\n  image:\n    name: ruby:3.3\n  script: make\n";
+        let pinned = old
+            .replace("node:20", "node@sha256:abc # ratchet:node:20")
+            .replace("postgres:15", "postgres@sha256:def # ratchet:postgres:15")
+            .replace("ruby:3.3", "ruby@sha256:fed # ratchet:ruby:3.3");
+        assert_eq!(
+            verify_only_references_changed(PinFileKind::GitLabCi, old, &pinned),
+            Ok(())
+        );
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitLabCi,
+                old,
+                &pinned.replace("script: make", "script: make all")
+            ),
+            Err(String::from("test.script changed"))
+        );
+        // `image` is not a reference of a GitHub workflow
+        assert_eq!(
+            verify_only_references_changed(PinFileKind::GitHubActions, old, &pinned),
+            Err(String::from("image changed"))
+        );
+    }
+
+    #[test]
+    fn test_pin_file_kind() {
+        let cases = [
+            (".github/workflows/ci.yml", PinFileKind::GitHubActions),
+            (
+                ".github/actions/setup/action.yml",
+                PinFileKind::GitHubActions,
+            ),
+            (".gitlab-ci.yml", PinFileKind::GitLabCi),
+            (".gitlab/ci/build.yml", PinFileKind::GitLabCi),
+            (".circleci/config.yml", PinFileKind::CircleCi),
+            ("cloudbuild.yaml", PinFileKind::CloudBuild),
+        ];
+        for (path, kind) in cases {
+            assert_eq!(PinFileKind::of(Path::new(path)), kind, "{}", path);
+        }
+    }
+
+    #[test]
+    fn test_action_key() {
+        assert_eq!(
+            parse("  - uses: actions/checkout@v4 # ratchet:exclude").action_key(),
+            "actions/checkout"
+        );
+        assert_eq!(
+            parse("    uses: 'github/codeql-action/init@abc'").action_key(),
+            "github/codeql-action/init"
+        );
+        assert_eq!(
+            parse("- uses: ./.github/actions/local").action_key(),
+            "./.github/actions/local"
+        );
+    }
+
+    #[test]
+    fn test_parse_step_and_job_uses() {
+        let step = parse("      - uses: actions/checkout@v3");
+        assert_eq!(step.uses(), "actions/checkout@v3");
+        assert_eq!(step.indent, 6);
+        assert_eq!(step.span, 14..33);
+
+        let named = parse("        uses: actions/setup-node@v3");
+        assert_eq!(named.uses(), "actions/setup-node@v3");
+        assert_eq!(named.indent, 8);
+
+        let job = parse("    uses: org/shared/.github/workflows/ci.yml@v1");
+        assert_eq!(job.uses(), "org/shared/.github/workflows/ci.yml@v1");
+        assert_eq!(job.kind(), RefKind::Tag);
+
+        let compact = parse("-   uses: actions/checkout@v3");
+        assert_eq!(compact.uses(), "actions/checkout@v3");
+
+        assert_eq!(UsesLine::parse("        run: yarn test", 1), None);
+        assert_eq!(UsesLine::parse("        uses:", 1), None);
+        assert_eq!(UsesLine::parse("        uses: \"\"", 1), None);
+        assert_eq!(UsesLine::parse("  # uses: actions/checkout@v3", 1), None);
+    }
+
+    #[test]
+    fn test_parse_anchors_and_aliases() {
+        let anchored = parse("    uses: &setup_node actions/setup-node@v3 # ratchet:exclude");
+        assert_eq!(anchored.uses(), "actions/setup-node@v3");
+        assert_eq!(anchored.action_key(), "actions/setup-node");
+        assert!(anchored.is_excluded());
+        assert_eq!(
+            anchored.with_ref(SHA),
+            format!(
+                "    uses: &setup_node actions/setup-node@{} # ratchet:exclude",
+                SHA
+            )
+        );
+
+        let anchored_key = parse("  - &checkout uses: 'actions/checkout@v3'");
+        assert_eq!(anchored_key.uses(), "actions/checkout@v3");
+        assert_eq!(anchored_key.quote, Some('\''));
+
+        assert_eq!(UsesLine::parse("      - uses: *setup_node", 1), None);
+        assert_eq!(UsesLine::parse("    uses: &setup_node", 1), None);
+        assert_eq!(UsesLine::parse("      - <<: *checkout_step", 1), None);
+    }
+
+    #[test]
+    fn test_parse_quoting_styles() {
+        for line in [
+            "      - uses: \"actions/checkout@v3\"",
+            "      - uses: 'actions/checkout@v3'",
+            "      - uses: \"actions/checkout@v3\" # ratchet:exclude",
+            "      - uses: 'actions/checkout@v3'# trailing",
+            "      - uses:   actions/checkout@v3   ",
+        ] {
+            assert_eq!(parse(line).uses(), "actions/checkout@v3", "{}", line);
+        }
+        assert_eq!(parse("  uses: \"actions/checkout@v3\"").quote, Some('"'));
+        assert_eq!(parse("  uses: 'actions/checkout@v3'").quote, Some('\''));
+        assert_eq!(parse("  uses: actions/checkout@v3").quote, None);
+        // An unterminated quote is not a valid value
+        assert_eq!(UsesLine::parse("  uses: \"actions/checkout@v3", 1), None);
+    }
+
+    #[test]
+    fn test_parse_comment_variations() {
+        let pinned = format!(
+            "      - uses: actions/checkout@{} # ratchet:actions/checkout@v3",
+            SHA
+        );
+        let ratchet = parse(&pinned);
+        assert_eq!(ratchet.comment(), Some("ratchet:actions/checkout@v3"));
+        assert!(!ratchet.is_excluded());
+
+        let excluded = parse("      - uses: actions/checkout@v3 # ratchet:exclude");
+        assert!(excluded.is_excluded());
+
+        let free = parse("      - uses: actions/checkout@v3 #pinned later, see #12");
+        assert_eq!(free.comment(), Some("pinned later, see #12"));
+
+        // A # inside the value does not start a comment
+        let anchor = parse("      - uses: org/action@feature#1");
+        assert_eq!(anchor.uses(), "org/action@feature#1");
+        assert_eq!(anchor.comment(), None);
+
+        let empty = parse("      - uses: actions/checkout@v3 #");
+        assert_eq!(empty.comment(), None);
+        assert_eq!(empty.code(), "      - uses: actions/checkout@v3");
+
+        let crlf = parse("      - uses: actions/checkout@v3 # ratchet:exclude\r\n");
+        assert_eq!(crlf.comment(), Some("ratchet:exclude"));
+    }
+
+    #[test]
+    fn test_with_ref_preserves_the_rest_of_the_line() {
+        let line = "      - uses: \"actions/checkout@v3\"   # ratchet:actions/checkout@v3";
+        assert_eq!(
+            parse(line).with_ref(SHA),
+            format!(
+                "      - uses: \"actions/checkout@{}\"   # ratchet:actions/checkout@v3",
+                SHA
+            )
+        );
+        assert_eq!(
+            parse("    uses: actions/checkout\n").with_ref("v4"),
+            "    uses: actions/checkout@v4\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_workflow() {
+        let content = "jobs:\n  build:\n    steps:\n      - uses: actions/checkout@v3\n      - run: echo uses: nothing\n      - name: node\n        uses: actions/setup-node@v3\n  shared:\n    uses: org/shared/.github/workflows/ci.yml@main\n";
+        let lines: Vec<(usize, &str)> = parse_workflow(content)
+            .iter()
+            .map(|line| (line.line, line.uses()))
+            .collect();
+        assert_eq!(
+            lines,
+            [
+                (4, "actions/checkout@v3"),
+                (7, "actions/setup-node@v3"),
+                (9, "org/shared/.github/workflows/ci.yml@main"),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_is_action_selected() {
+        let patterns = |globs: &[&str]| -> Vec<Pattern> {
+            globs
+                .iter()
+                .map(|glob| Pattern::new(glob).unwrap())
+                .collect()
+        };
+        assert!(is_action_selected("actions/checkout", &[], &[]));
+        let exclude = patterns(&["my-org/*", "actions/cache"]);
+        assert!(!is_action_selected("My-Org/deploy", &[], &exclude));
+        assert!(!is_action_selected("actions/cache/restore", &[], &exclude));
+        assert!(is_action_selected("actions/checkout", &[], &exclude));
+        let only = patterns(&["actions/*"]);
+        assert!(is_action_selected("actions/setup-node", &only, &[]));
+        assert!(!is_action_selected("docker/login-action", &only, &[]));
+        assert!(!is_action_selected("actions/cache", &only, &exclude));
+    }
+
+    #[test]
+    fn test_is_comment_only_change() {
+        let line = |reference: &str, comment: &str| {
+            format!(
+                "      - uses: actions/checkout@{} # ratchet:{}\n",
+                reference, comment
+            )
+        };
+        assert!(is_comment_only_change(
+            &line(SHA, "actions/checkout@v4"),
+            &line(SHA, "actions/checkout@v4.1.2")
+        ));
+        assert!(!is_comment_only_change(
+            &line(SHA, "actions/checkout@v4"),
+            &line(SHA, "actions/checkout@v4")
+        ));
+        assert!(!is_comment_only_change(
+            &line(SHA, "actions/checkout@v4"),
+            &line(
+                "0ad4b8fadaa221de15dcec353f45205ec38ea70b",
+                "actions/checkout@v4.1.2"
+            )
+        ));
+        assert!(!is_comment_only_change(
+            &line("v4", "actions/checkout@v4"),
+            &line("v4", "actions/checkout@v4.1.2")
+        ));
+        assert!(!is_comment_only_change(
+            "# a comment\n",
+            "# another comment\n"
+        ));
+
+        let docker = |reference: &str, comment: &str| {
+            format!(
+                "      - uses: docker://alpine{} # ratchet:{}\n",
+                reference, comment
+            )
+        };
+        assert!(is_comment_only_change(
+            &docker("@sha256:abc", "docker://alpine:3.19"),
+            &docker("@sha256:abc", "docker://alpine:3.19.1")
+        ));
+        assert!(!is_comment_only_change(
+            &docker(":3.19", "docker://alpine:3.19"),
+            &docker(":3.19", "docker://alpine:3.19.1")
+        ));
+    }
+
+    #[test]
+    fn test_verify_only_references_changed_container_images() {
+        let old = "jobs:\n  test:\n    container:\n      image: node:20\n      options: --cpus 1\n    services:\n      redis:\n        image: redis:7\n  lint:\n    container: node:20\n";
+        let pinned = old
+            .replace("node:20", "node@sha256:abc # ratchet:node:20")
+            .replace("redis:7", "redis@sha256:def # ratchet:redis:7");
+        assert_eq!(
+            verify_only_references_changed(PinFileKind::GitHubActions, old, &pinned),
+            Ok(())
+        );
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                old,
+                &pinned.replace("--cpus 1", "--cpus 2")
+            ),
+            Err(String::from("jobs.test.container.options changed"))
+        );
+        // Only the image of a container is a reference
+        let env = "jobs:\n  test:\n    env:\n      image: node:20\n";
+        assert_eq!(
+            verify_only_references_changed(
+                PinFileKind::GitHubActions,
+                env,
+                &env.replace("node:20", "node@sha256:abc")
+            ),
+            Err(String::from("jobs.test.env.image changed"))
+        );
+    }
+}